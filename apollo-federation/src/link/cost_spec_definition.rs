@@ -0,0 +1,5 @@
+use apollo_compiler::name;
+use apollo_compiler::Name;
+
+pub(crate) const COST_DIRECTIVE_NAME_IN_SPEC: Name = name!("cost");
+pub(crate) const COST_LIST_SIZE_DIRECTIVE_NAME_IN_SPEC: Name = name!("listSize");
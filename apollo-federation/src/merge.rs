@@ -36,6 +36,8 @@ use indexmap::IndexSet;
 use itertools::Itertools;
 
 use crate::error::FederationError;
+use crate::link::cost_spec_definition::COST_DIRECTIVE_NAME_IN_SPEC;
+use crate::link::cost_spec_definition::COST_LIST_SIZE_DIRECTIVE_NAME_IN_SPEC;
 use crate::link::federation_spec_definition::FEDERATION_EXTERNAL_DIRECTIVE_NAME_IN_SPEC;
 use crate::link::federation_spec_definition::FEDERATION_FIELDS_ARGUMENT_NAME;
 use crate::link::federation_spec_definition::FEDERATION_FROM_ARGUMENT_NAME;
@@ -59,6 +61,7 @@ type MergeError = String;
 struct Merger {
     errors: Vec<MergeError>,
     composition_hints: Vec<MergeWarning>,
+    needs_join_directive: bool,
 }
 
 pub struct MergeSuccess {
@@ -120,6 +123,7 @@ impl Merger {
         Merger {
             composition_hints: Vec::new(),
             errors: Vec::new(),
+            needs_join_directive: false,
         }
     }
     fn merge(&mut self, subgraphs: ValidFederationSubgraphs) -> Result<MergeSuccess, MergeFailure> {
@@ -228,6 +232,10 @@ impl Merger {
             }
         }
 
+        if self.needs_join_directive {
+            add_join_directive(&mut supergraph);
+        }
+
         if self.errors.is_empty() {
             // TODO: validate here and extend `MergeFailure` to propagate validation errors
             let supergraph = Valid::assume_valid(supergraph);
@@ -375,13 +383,15 @@ impl Merger {
             .map(|link| link.directive_name_in_schema(&FEDERATION_KEY_DIRECTIVE_NAME_IN_SPEC))
             .unwrap_or(FEDERATION_KEY_DIRECTIVE_NAME_IN_SPEC);
 
+        let cost_directive_names = cost_directive_names(metadata);
+
         let existing_type = types
             .entry(interface_name.clone())
             .or_insert(copy_interface_type(interface_name, interface));
         if let ExtendedType::Interface(intf) = existing_type {
             let key_directives = interface.directives.get_all(&key_directive_name);
             let join_type_directives =
-                join_type_applied_directive(subgraph_name, key_directives, false);
+                join_type_applied_directive(subgraph_name.clone(), key_directives, false);
             let mutable_intf = intf.make_mut();
             mutable_intf.directives.extend(join_type_directives);
 
@@ -405,6 +415,18 @@ impl Merger {
                         // TODO process directives
                     }
                 }
+
+                let join_cost_directives = self.join_cost_applied_directives(
+                    &subgraph_name,
+                    &cost_directive_names,
+                    field.directives.iter(),
+                );
+                if let Some(supergraph_field) = mutable_intf.fields.get_mut(field_name) {
+                    supergraph_field
+                        .make_mut()
+                        .directives
+                        .extend(join_cost_directives.into_iter().map(Node::new));
+                }
             }
         } else {
             // TODO conflict on type
@@ -448,6 +470,8 @@ impl Merger {
             .map(|link| link.directive_name_in_schema(&FEDERATION_OVERRIDE_DIRECTIVE_NAME_IN_SPEC))
             .unwrap_or(FEDERATION_OVERRIDE_DIRECTIVE_NAME_IN_SPEC);
 
+        let cost_directive_names = cost_directive_names(metadata);
+
         let is_interface_object = object.directives.has(&interface_object_directive_name);
         let existing_type = types
             .entry(object_name.clone())
@@ -462,6 +486,14 @@ impl Merger {
                 join_type_applied_directive(subgraph_name.clone(), key_directives, false);
             let mutable_object = obj.make_mut();
             mutable_object.directives.extend(join_type_directives);
+            let join_cost_directives = self.join_cost_applied_directives(
+                &subgraph_name,
+                &cost_directive_names,
+                object.directives.iter().map(|directive| &directive.node),
+            );
+            mutable_object
+                .directives
+                .extend(join_cost_directives.into_iter().map(Component::new));
             self.merge_descriptions(&mut mutable_object.description, &object.description);
             object.implements_interfaces.iter().for_each(|intf_name| {
                 // IndexSet::insert deduplicates
@@ -550,6 +582,16 @@ impl Merger {
                     .directives
                     .push(Node::new(join_field_directive));
 
+                let join_cost_directives = self.join_cost_applied_directives(
+                    &subgraph_name,
+                    &cost_directive_names,
+                    field.directives.iter(),
+                );
+                supergraph_field
+                    .make_mut()
+                    .directives
+                    .extend(join_cost_directives.into_iter().map(Node::new));
+
                 // TODO: implement needsJoinField to avoid adding join__field when unnecessary
                 // https://github.com/apollographql/federation/blob/0d8a88585d901dff6844fdce1146a4539dec48df/composition-js/src/merging/merge.ts#L1648
             }
@@ -563,6 +605,32 @@ impl Merger {
         // TODO merge fields
     }
 
+    /// Carries the `@cost` and `@listSize` directives of a subgraph element into the supergraph
+    /// as `@join__directive` applications, so that demand control can use them
+    fn join_cost_applied_directives<'a>(
+        &mut self,
+        subgraph_name: &Name,
+        cost_directive_names: &[(Name, Name)],
+        directives: impl Iterator<Item = &'a Node<Directive>>,
+    ) -> Vec<Directive> {
+        let join_directives = directives
+            .filter_map(|directive| {
+                let (name_in_spec, _) = cost_directive_names
+                    .iter()
+                    .find(|(_, name_in_schema)| *name_in_schema == directive.name)?;
+                Some(join_directive_applied_directive(
+                    subgraph_name.clone(),
+                    name_in_spec,
+                    directive,
+                ))
+            })
+            .collect::<Vec<_>>();
+        if !join_directives.is_empty() {
+            self.needs_join_directive = true;
+        }
+        join_directives
+    }
+
     fn merge_union_type(
         &mut self,
         types: &mut IndexMap<NamedType, ExtendedType>,
@@ -822,6 +890,59 @@ fn join_type_applied_directive<'a>(
         .collect::<Vec<Component<Directive>>>()
 }
 
+/// The names of the `@cost` and `@listSize` directives in the specification and in a subgraph.
+/// They can be imported from the cost specification or from the federation specification.
+fn cost_directive_names(metadata: &Option<&LinksMetadata>) -> Vec<(Name, Name)> {
+    let link = metadata.and_then(|m| {
+        m.by_identity
+            .get(&Identity::cost_identity())
+            .or_else(|| m.by_identity.get(&Identity::federation_identity()))
+    });
+    [
+        COST_DIRECTIVE_NAME_IN_SPEC,
+        COST_LIST_SIZE_DIRECTIVE_NAME_IN_SPEC,
+    ]
+    .into_iter()
+    .map(|name_in_spec| {
+        let name_in_schema = link
+            .map(|link| link.directive_name_in_schema(&name_in_spec))
+            .unwrap_or(name_in_spec.clone());
+        (name_in_spec, name_in_schema)
+    })
+    .collect()
+}
+
+/// @join__directive(graphs: [SUBGRAPH], name: "cost", args: {weight: 5})
+fn join_directive_applied_directive(
+    subgraph_name: Name,
+    name_in_spec: &Name,
+    directive: &Directive,
+) -> Directive {
+    Directive {
+        name: name!("join__directive"),
+        arguments: vec![
+            Node::new(Argument {
+                name: name!("graphs"),
+                value: Node::new(Value::List(vec![Node::new(Value::Enum(subgraph_name))])),
+            }),
+            Node::new(Argument {
+                name: name!("name"),
+                value: name_in_spec.as_str().into(),
+            }),
+            Node::new(Argument {
+                name: name!("args"),
+                value: Node::new(Value::Object(
+                    directive
+                        .arguments
+                        .iter()
+                        .map(|argument| (argument.name.clone(), argument.value.clone()))
+                        .collect(),
+                )),
+            }),
+        ],
+    }
+}
+
 fn join_implements_applied_directive(
     subgraph_name: Name,
     intf_name: &Name,
@@ -1057,6 +1178,86 @@ fn add_core_feature_join(
     supergraph.types.insert(name, join_graph_enum_type.into());
 }
 
+/// Adds the `@join__directive` definition to the supergraph, which requires version 0.4 of the
+/// join specification
+fn add_join_directive(supergraph: &mut Schema) {
+    for link in supergraph
+        .schema_definition
+        .make_mut()
+        .directives
+        .iter_mut()
+        .filter(|directive| directive.name == "link")
+    {
+        for argument in link.make_mut().arguments.iter_mut() {
+            if argument.name == "url"
+                && argument.value.as_str() == Some("https://specs.apollo.dev/join/v0.3")
+            {
+                argument.make_mut().value = "https://specs.apollo.dev/join/v0.4".into();
+            }
+        }
+    }
+
+    // scalar DirectiveArguments
+    let join_directive_arguments_name = name!("join__DirectiveArguments");
+    let join_directive_arguments_scalar = ExtendedType::Scalar(Node::new(ScalarType {
+        directives: Default::default(),
+        name: join_directive_arguments_name.clone(),
+        description: None,
+    }));
+    supergraph.types.insert(
+        join_directive_arguments_name,
+        join_directive_arguments_scalar,
+    );
+
+    let join_directive_directive_definition = join_directive_directive_definition();
+    supergraph.directive_definitions.insert(
+        join_directive_directive_definition.name.clone(),
+        Node::new(join_directive_directive_definition),
+    );
+}
+
+/// directive @directive(
+///   graphs: [Graph!],
+///   name: String!,
+///   args: DirectiveArguments
+/// ) repeatable on SCHEMA | OBJECT | INTERFACE | FIELD_DEFINITION
+fn join_directive_directive_definition() -> DirectiveDefinition {
+    DirectiveDefinition {
+        name: name!("join__directive"),
+        description: None,
+        arguments: vec![
+            Node::new(InputValueDefinition {
+                name: name!("graphs"),
+                description: None,
+                directives: Default::default(),
+                ty: ty!([join__Graph!]).into(),
+                default_value: None,
+            }),
+            Node::new(InputValueDefinition {
+                name: name!("name"),
+                description: None,
+                directives: Default::default(),
+                ty: ty!(String!).into(),
+                default_value: None,
+            }),
+            Node::new(InputValueDefinition {
+                name: name!("args"),
+                description: None,
+                directives: Default::default(),
+                ty: ty!(join__DirectiveArguments).into(),
+                default_value: None,
+            }),
+        ],
+        locations: vec![
+            DirectiveLocation::Schema,
+            DirectiveLocation::Object,
+            DirectiveLocation::Interface,
+            DirectiveLocation::FieldDefinition,
+        ],
+        repeatable: true,
+    }
+}
+
 /// directive @enumValue(graph: join__Graph!) repeatable on ENUM_VALUE
 fn join_enum_value_directive_definition() -> DirectiveDefinition {
     DirectiveDefinition {
@@ -1507,4 +1708,46 @@ mod tests {
 
         assert_snapshot!(schema.serialize());
     }
+
+    #[test]
+    fn test_cost_directives() {
+        let sdl = r#"
+            directive @cost(weight: Int!) on ARGUMENT_DEFINITION | ENUM | FIELD_DEFINITION | INPUT_FIELD_DEFINITION | OBJECT | SCALAR
+            directive @listSize(assumedSize: Int, slicingArguments: [String!], sizedFields: [String!], requireOneSlicingArgument: Boolean = true) on FIELD_DEFINITION
+
+            type Query {
+              products(first: Int): [Product] @listSize(slicingArguments: ["first"])
+              node: Node
+            }
+
+            interface Node {
+              id: ID! @cost(weight: 2)
+            }
+
+            type Product implements Node @cost(weight: 5) {
+              id: ID!
+              name: String @cost(weight: 10)
+            }
+        "#;
+
+        let mut subgraphs = ValidFederationSubgraphs::new();
+        subgraphs
+            .add(ValidFederationSubgraph {
+                name: "products".to_string(),
+                url: "".to_string(),
+                schema: ValidFederationSchema::new(
+                    Schema::parse_and_validate(sdl, "./products.graphql").unwrap(),
+                )
+                .unwrap(),
+            })
+            .unwrap();
+
+        let result = merge_federation_subgraphs(subgraphs).unwrap();
+
+        let schema = result.schema.into_inner();
+        let validation = schema.clone().validate();
+        assert!(validation.is_ok(), "{:?}", validation);
+
+        assert_snapshot!(schema.serialize());
+    }
 }
@@ -11,6 +11,7 @@ use serde_json_bytes::Map;
 use serde_json_bytes::Value as JSON;
 
 use super::helpers::json_type_name;
+use super::methods::apply_method;
 use super::parser::*;
 
 pub trait ApplyTo {
@@ -85,7 +86,7 @@ impl Hash for ApplyToError {
 }
 
 impl ApplyToError {
-    fn new(message: &str, path: &[JSON]) -> Self {
+    pub(super) fn new(message: &str, path: &[JSON]) -> Self {
         Self(json!({
             "message": message,
             "path": JSON::Array(path.to_vec()),
@@ -141,7 +142,9 @@ impl ApplyTo for JSONSelection {
         errors: &mut IndexSet<ApplyToError>,
     ) -> Option<JSON> {
         if let JSON::Array(array) = data {
-            return self.apply_to_array(array, vars, input_path, errors);
+            if !matches!(self, Self::Path(path) if starts_with_method(path)) {
+                return self.apply_to_array(array, vars, input_path, errors);
+            }
         }
 
         match self {
@@ -242,6 +245,17 @@ impl ApplyTo for NamedSelection {
     }
 }
 
+// Methods are applied to the whole value selected by the preceding path steps,
+// so an array reaching a ->method (for example in $->first or list->first) is
+// not mapped element by element like it is for other path steps.
+pub(super) fn starts_with_method(path: &PathSelection) -> bool {
+    match path {
+        PathSelection::Method(..) => true,
+        PathSelection::Var(var_name, tail) if var_name == "$" => starts_with_method(tail),
+        _ => false,
+    }
+}
+
 impl ApplyTo for PathSelection {
     fn apply_to_path(
         &self,
@@ -251,7 +265,9 @@ impl ApplyTo for PathSelection {
         errors: &mut IndexSet<ApplyToError>,
     ) -> Option<JSON> {
         if let JSON::Array(array) = data {
-            return self.apply_to_array(array, vars, input_path, errors);
+            if !starts_with_method(self) {
+                return self.apply_to_array(array, vars, input_path, errors);
+            }
         }
 
         match self {
@@ -294,6 +310,10 @@ impl ApplyTo for PathSelection {
                     Key::Index(index) => data.get(index),
                 } {
                     tail.apply_to_path(child, vars, input_path, errors)
+                } else if matches!(tail.as_ref(), Self::Method(name, _, _) if name == "default") {
+                    // A missing property followed by ->default(...) is not an
+                    // error, but rather the reason to use the default value.
+                    tail.apply_to_path(&JSON::Null, vars, input_path, errors)
                 } else {
                     errors.insert(ApplyToError::new(
                         format!(
@@ -311,6 +331,12 @@ impl ApplyTo for PathSelection {
 
                 result
             }
+            Self::Method(name, args, tail) => {
+                input_path.push(json!(format!("->{name}")));
+                let result = apply_method(name, args.as_ref(), data, vars, input_path, errors);
+                input_path.pop();
+                result.and_then(|value| tail.apply_to_path(&value, vars, input_path, errors))
+            }
             Self::Selection(selection) => {
                 // If data is not an object here, this recursive apply_to_path
                 // call will handle the error.
@@ -325,6 +351,61 @@ impl ApplyTo for PathSelection {
     }
 }
 
+impl ApplyTo for JSLiteral {
+    fn apply_to_path(
+        &self,
+        data: &JSON,
+        vars: &IndexMap<String, JSON>,
+        input_path: &mut Vec<JSON>,
+        errors: &mut IndexSet<ApplyToError>,
+    ) -> Option<JSON> {
+        match self {
+            Self::String(string) => Some(JSON::String(string.clone().into())),
+            Self::Number(number) => {
+                // The JSNumber grammar allows forms like -1. and .5 that JSON
+                // does not, so they are normalized before parsing.
+                let normalized = number.trim_end_matches('.').replacen("-.", "-0.", 1);
+                let normalized = if normalized.starts_with('.') {
+                    format!("0{normalized}")
+                } else {
+                    normalized
+                };
+                match serde_json::from_str::<serde_json::Number>(&normalized) {
+                    Ok(number) => Some(JSON::Number(number)),
+                    Err(_) => {
+                        errors.insert(ApplyToError::new(
+                            format!("Invalid number {number}").as_str(),
+                            input_path,
+                        ));
+                        None
+                    }
+                }
+            }
+            Self::Bool(boolean) => Some(JSON::Bool(*boolean)),
+            Self::Null => Some(JSON::Null),
+            Self::Object(properties) => {
+                let mut output = Map::new();
+                for (key, value) in properties {
+                    if let Some(value) = value.apply_to_path(data, vars, input_path, errors) {
+                        output.insert(key.clone(), value);
+                    }
+                }
+                Some(JSON::Object(output))
+            }
+            Self::Array(items) => Some(JSON::Array(
+                items
+                    .iter()
+                    .map(|item| {
+                        item.apply_to_path(data, vars, input_path, errors)
+                            .unwrap_or(JSON::Null)
+                    })
+                    .collect(),
+            )),
+            Self::Path(path) => path.apply_to_path(data, vars, input_path, errors),
+        }
+    }
+}
+
 impl ApplyTo for SubSelection {
     fn apply_to_path(
         &self,
@@ -1309,4 +1390,142 @@ mod tests {
             (Some(json!(123)), vec![],),
         );
     }
+
+    #[test]
+    fn test_apply_to_methods() {
+        let data = json!({
+            "people": [
+                { "name": "Ada", "kind": "ADMIN", "age": "36" },
+                { "name": "Alan", "kind": "USER", "age": null },
+            ],
+            "tags": ["a", "b", "c"],
+            "label": "hello",
+            "count": 3,
+            "missing": null,
+        });
+
+        assert_eq!(
+            selection!("names: people->map(.name)").apply_to(&data),
+            (Some(json!({ "names": ["Ada", "Alan"] })), vec![]),
+        );
+        assert_eq!(
+            selection!("$.people->first.name").apply_to(&data),
+            (Some(json!("Ada")), vec![]),
+        );
+        assert_eq!(
+            selection!("$.people->last { name }").apply_to(&data),
+            (Some(json!({ "name": "Alan" })), vec![]),
+        );
+        assert_eq!(
+            selection!("$.tags->slice(1)").apply_to(&data),
+            (Some(json!(["b", "c"])), vec![]),
+        );
+        assert_eq!(
+            selection!("$.tags->slice(0, -1)->join(', ')").apply_to(&data),
+            (Some(json!("a, b")), vec![]),
+        );
+        assert_eq!(
+            selection!("$.label->slice(1, 3)").apply_to(&data),
+            (Some(json!("el")), vec![]),
+        );
+        assert_eq!(
+            selection!("$.label->first").apply_to(&data),
+            (Some(json!("h")), vec![]),
+        );
+        assert_eq!(
+            selection!("$.people->map(.kind->match({ ADMIN: 'Admin' }, 'Guest'))").apply_to(&data),
+            (Some(json!(["Admin", "Guest"])), vec![]),
+        );
+        assert_eq!(
+            selection!("$.people->map(.age->default(0)->toNumber)").apply_to(&data),
+            (Some(json!([36, 0])), vec![]),
+        );
+        assert_eq!(
+            selection!("$.count->toString").apply_to(&data),
+            (Some(json!("3")), vec![]),
+        );
+        assert_eq!(
+            selection!("$.missing->default('none')").apply_to(&data),
+            (Some(json!("none")), vec![]),
+        );
+        assert_eq!(
+            selection!("$.absent->default('none')").apply_to(&data),
+            (Some(json!("none")), vec![]),
+        );
+        assert_eq!(
+            selection!("$->echo({ label: .label, list: [$.count, true, null, 1.5] })")
+                .apply_to(&data),
+            (
+                Some(json!({ "label": "hello", "list": [3, true, null, 1.5] })),
+                vec![],
+            ),
+        );
+        assert_eq!(
+            selection!("$args.id->toString").apply_with_vars(
+                &data,
+                &IndexMap::from([("$args".to_string(), json!({ "id": 42 }))]),
+            ),
+            (Some(json!("42")), vec![]),
+        );
+    }
+
+    #[test]
+    fn test_apply_to_method_errors() {
+        let data = json!({
+            "people": [{ "kind": "ROBOT" }],
+            "label": "hello",
+            "count": 3,
+        });
+
+        assert_eq!(
+            selection!("$.count->first").apply_to(&data),
+            (
+                None,
+                vec![ApplyToError::from_json(&json!({
+                    "message": "Method ->first cannot be applied to number",
+                    "path": ["count", "->first"],
+                }))],
+            ),
+        );
+        assert_eq!(
+            selection!("$.label->slice('a')").apply_to(&data),
+            (
+                None,
+                vec![ApplyToError::from_json(&json!({
+                    "message": "Method ->slice requires integer arguments",
+                    "path": ["label", "->slice"],
+                }))],
+            ),
+        );
+        assert_eq!(
+            selection!("$.label->join(1, 2)").apply_to(&data),
+            (
+                None,
+                vec![ApplyToError::from_json(&json!({
+                    "message": "Method ->join requires one argument",
+                    "path": ["label", "->join"],
+                }))],
+            ),
+        );
+        assert_eq!(
+            selection!("$.people->map(.kind->match({ ADMIN: 'Admin' }))").apply_to(&data),
+            (
+                Some(json!([null])),
+                vec![ApplyToError::from_json(&json!({
+                    "message": "Method ->match found no match for \"ROBOT\"",
+                    "path": ["people", "->map", "kind", "->match"],
+                }))],
+            ),
+        );
+        assert_eq!(
+            selection!("$.label->toNumber").apply_to(&data),
+            (
+                None,
+                vec![ApplyToError::from_json(&json!({
+                    "message": "Method ->toNumber cannot convert \"hello\" to a number",
+                    "path": ["label", "->toNumber"],
+                }))],
+            ),
+        );
+    }
 }
@@ -102,6 +102,10 @@ impl From<PathSelection> for Vec<GraphQLSelection> {
                 let tail = *tail;
                 tail.into()
             }
+            PathSelection::Method(_, _, tail) => {
+                let tail = *tail;
+                tail.into()
+            }
             PathSelection::Selection(selection) => {
                 GraphQLSelections::from(selection).valid_selections()
             }
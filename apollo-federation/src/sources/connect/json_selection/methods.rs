@@ -0,0 +1,653 @@
+/// Implementations of the `->method` path steps of JSONSelection syntax. Each
+/// method takes the value selected by the preceding path steps, along with its
+/// (optional) arguments, and produces a new value, or reports an ApplyToError
+/// and produces None when the method cannot be applied.
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use serde_json_bytes::Value as JSON;
+
+use super::apply_to::ApplyTo;
+use super::apply_to::ApplyToError;
+use super::helpers::json_type_name;
+use super::parser::JSLiteral;
+use super::parser::MethodArgs;
+use super::shape::ComputeShape;
+use super::shape::Shape;
+
+// The methods recognized by the parser. Using any other method name is a parse
+// error, so typos are caught before any data is processed.
+const METHODS: &[&str] = &[
+    "echo", "map", "first", "last", "slice", "join", "match", "default", "toString", "toNumber",
+];
+
+pub(super) fn is_known_method(name: &str) -> bool {
+    METHODS.contains(&name)
+}
+
+pub(super) fn apply_method(
+    name: &str,
+    args: Option<&MethodArgs>,
+    data: &JSON,
+    vars: &IndexMap<String, JSON>,
+    input_path: &mut Vec<JSON>,
+    errors: &mut IndexSet<ApplyToError>,
+) -> Option<JSON> {
+    let args = args.map(|args| args.0.as_slice()).unwrap_or_default();
+    let mut method = Method {
+        name,
+        args,
+        vars,
+        input_path,
+        errors,
+    };
+
+    match name {
+        "echo" => {
+            let [value] = method.expect_args::<1>()?;
+            method.eval(value, data)
+        }
+        "map" => {
+            let [value] = method.expect_args::<1>()?;
+            match data {
+                JSON::Array(array) => Some(JSON::Array(
+                    array
+                        .iter()
+                        .map(|element| method.eval(value, element).unwrap_or(JSON::Null))
+                        .collect(),
+                )),
+                _ => method.eval(value, data),
+            }
+        }
+        "first" | "last" => {
+            method.expect_args::<0>()?;
+            let first = name == "first";
+            match data {
+                JSON::Array(array) => {
+                    if first {
+                        array.first().cloned()
+                    } else {
+                        array.last().cloned()
+                    }
+                }
+                JSON::String(string) => {
+                    let string = string.as_str();
+                    let char = if first {
+                        string.chars().next()
+                    } else {
+                        string.chars().last()
+                    };
+                    char.map(|char| JSON::String(char.to_string().into()))
+                }
+                _ => method.error_for_type(data),
+            }
+        }
+        "slice" => {
+            if args.is_empty() || args.len() > 2 {
+                return method.error("requires one or two arguments");
+            }
+            let start = method.eval_index(&args[0], data)?;
+            let end = match args.get(1) {
+                Some(end) => Some(method.eval_index(end, data)?),
+                None => None,
+            };
+            match data {
+                JSON::Array(array) => {
+                    let (start, end) = slice_bounds(start, end, array.len());
+                    Some(JSON::Array(array[start..end].to_vec()))
+                }
+                JSON::String(string) => {
+                    let chars: Vec<char> = string.as_str().chars().collect();
+                    let (start, end) = slice_bounds(start, end, chars.len());
+                    Some(JSON::String(
+                        chars[start..end].iter().collect::<String>().into(),
+                    ))
+                }
+                _ => method.error_for_type(data),
+            }
+        }
+        "join" => {
+            let [separator] = method.expect_args::<1>()?;
+            let Some(JSON::String(separator)) = method.eval(separator, data) else {
+                return method.error("requires a string argument");
+            };
+            let JSON::Array(array) = data else {
+                return method.error_for_type(data);
+            };
+            let mut parts = Vec::with_capacity(array.len());
+            for element in array {
+                match element {
+                    JSON::Null => parts.push(String::new()),
+                    JSON::Array(_) | JSON::Object(_) => {
+                        return method.error(&format!(
+                            "cannot join array elements of type {}",
+                            json_type_name(element)
+                        ));
+                    }
+                    _ => parts.push(to_string(element)),
+                }
+            }
+            Some(JSON::String(parts.join(separator.as_str()).into()))
+        }
+        "match" => {
+            if args.is_empty() || args.len() > 2 {
+                return method.error("requires one or two arguments");
+            }
+            let Some(JSON::Object(cases)) = method.eval(&args[0], data) else {
+                return method.error("requires an object argument");
+            };
+            if matches!(data, JSON::Array(_) | JSON::Object(_)) {
+                return method.error_for_type(data);
+            }
+            match cases.get(to_string(data).as_str()) {
+                Some(value) => Some(value.clone()),
+                None => match args.get(1) {
+                    Some(default) => method.eval(default, data),
+                    None => method.error(&format!("found no match for {}", data)),
+                },
+            }
+        }
+        "default" => {
+            let [default] = method.expect_args::<1>()?;
+            match data {
+                JSON::Null => method.eval(default, data),
+                _ => Some(data.clone()),
+            }
+        }
+        "toString" => {
+            method.expect_args::<0>()?;
+            Some(JSON::String(to_string(data).into()))
+        }
+        "toNumber" => {
+            method.expect_args::<0>()?;
+            match data {
+                JSON::Number(_) => Some(data.clone()),
+                JSON::String(string) => {
+                    match serde_json::from_str::<serde_json::Number>(string.as_str().trim()) {
+                        Ok(number) => Some(JSON::Number(number)),
+                        Err(_) => method.error(&format!(
+                            "cannot convert {} to a number",
+                            JSON::String(string.clone())
+                        )),
+                    }
+                }
+                JSON::Bool(boolean) => Some(JSON::Number((*boolean as u8).into())),
+                _ => method.error_for_type(data),
+            }
+        }
+        _ => method.error("is not a known method"),
+    }
+}
+
+// The static counterpart of apply_method, computing the shape of the output of
+// a method from the shape of its input value, for JSONSelection::output_shape.
+pub(super) fn method_shape(name: &str, args: Option<&MethodArgs>, input: &Shape) -> Shape {
+    let args = args.map(|args| args.0.as_slice()).unwrap_or_default();
+    let arg_shape = |index: usize, input: &Shape| {
+        args.get(index)
+            .map_or(Shape::Unknown, |arg| arg.compute_shape(input))
+    };
+
+    match name {
+        "echo" => arg_shape(0, input),
+        "map" => match input {
+            Shape::Array(element) => Shape::Array(Box::new(arg_shape(0, element))),
+            _ => arg_shape(0, input),
+        },
+        "first" | "last" => match input {
+            Shape::Array(element) => (**element).clone(),
+            Shape::String => Shape::String,
+            _ => Shape::Unknown,
+        },
+        "slice" => match input {
+            Shape::Array(_) | Shape::String => input.clone(),
+            _ => Shape::Unknown,
+        },
+        "join" | "toString" => Shape::String,
+        "toNumber" => Shape::Number,
+        "match" => match args.first() {
+            Some(JSLiteral::Object(cases)) => Shape::one(
+                cases
+                    .iter()
+                    .map(|(_, value)| value.compute_shape(input))
+                    .chain(args.get(1).map(|default| default.compute_shape(input))),
+            ),
+            // The cases are only known at runtime.
+            _ => Shape::Unknown,
+        },
+        "default" => match input {
+            Shape::Unknown => Shape::Unknown,
+            Shape::Null => arg_shape(0, input),
+            _ => Shape::one([input.non_null(), arg_shape(0, input)]),
+        },
+        _ => Shape::Unknown,
+    }
+}
+
+// Evaluation context shared by the arms of apply_method.
+struct Method<'a, 'b> {
+    name: &'a str,
+    args: &'a [JSLiteral],
+    vars: &'a IndexMap<String, JSON>,
+    input_path: &'b mut Vec<JSON>,
+    errors: &'b mut IndexSet<ApplyToError>,
+}
+
+impl<'a, 'b> Method<'a, 'b> {
+    fn expect_args<const N: usize>(&mut self) -> Option<&'a [JSLiteral; N]> {
+        match self.args.try_into() {
+            Ok(args) => Some(args),
+            Err(_) => match N {
+                0 => self.error("does not take arguments"),
+                1 => self.error("requires one argument"),
+                _ => self.error(&format!("requires {} arguments", N)),
+            },
+        }
+    }
+
+    // Arguments are evaluated against the method's input value, so $ refers to
+    // that value within argument paths, while $args, $this, etc. refer to the
+    // usual variables.
+    fn eval(&mut self, arg: &JSLiteral, data: &JSON) -> Option<JSON> {
+        arg.apply_to_path(data, self.vars, self.input_path, self.errors)
+    }
+
+    fn eval_index(&mut self, arg: &JSLiteral, data: &JSON) -> Option<i64> {
+        match self.eval(arg, data) {
+            Some(JSON::Number(number)) if number.is_i64() => number.as_i64(),
+            _ => self.error("requires integer arguments"),
+        }
+    }
+
+    fn error_for_type<T>(&mut self, data: &JSON) -> Option<T> {
+        self.error(&format!("cannot be applied to {}", json_type_name(data)))
+    }
+
+    fn error<T>(&mut self, message: &str) -> Option<T> {
+        self.errors.insert(ApplyToError::new(
+            format!("Method ->{} {}", self.name, message).as_str(),
+            self.input_path,
+        ));
+        None
+    }
+}
+
+// Negative indices count back from the end, as with JavaScript's
+// Array.prototype.slice, and out-of-range indices are clamped.
+fn slice_bounds(start: i64, end: Option<i64>, len: usize) -> (usize, usize) {
+    let clamp = |index: i64| -> usize {
+        if index < 0 {
+            len.saturating_sub(index.unsigned_abs() as usize)
+        } else {
+            (index as usize).min(len)
+        }
+    };
+    let start = clamp(start);
+    let end = end.map_or(len, clamp);
+    (start, end.max(start))
+}
+
+// Strings are converted without their JSON quotes, and everything else is
+// converted to its JSON representation.
+fn to_string(data: &JSON) -> String {
+    match data {
+        JSON::String(string) => string.as_str().to_string(),
+        _ => data.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::selection;
+
+    // Applies the given method path steps to the value of the "value" key.
+    fn apply(methods: &str, value: JSON) -> (Option<JSON>, Vec<ApplyToError>) {
+        let source = format!("$.value{methods}");
+        selection!(source.as_str()).apply_to(&json!({ "value": value }))
+    }
+
+    fn method_error(name: &str, message: &str) -> (Option<JSON>, Vec<ApplyToError>) {
+        (
+            None,
+            vec![ApplyToError::new(
+                &format!("Method ->{name} {message}"),
+                &[json!("value"), json!(format!("->{name}"))],
+            )],
+        )
+    }
+
+    #[test]
+    fn test_is_known_method() {
+        for name in METHODS {
+            assert!(is_known_method(name));
+        }
+        assert!(!is_known_method("size"));
+        assert!(!is_known_method("tostring"));
+    }
+
+    #[test]
+    fn test_echo() {
+        assert_eq!(
+            apply("->echo('hello')", json!(1)),
+            (Some(json!("hello")), vec![])
+        );
+        assert_eq!(
+            apply("->echo([$, $])", json!(1)),
+            (Some(json!([1, 1])), vec![])
+        );
+        assert_eq!(
+            apply("->echo", json!(1)),
+            method_error("echo", "requires one argument")
+        );
+        assert_eq!(
+            apply("->echo(1, 2)", json!(1)),
+            method_error("echo", "requires one argument")
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        assert_eq!(
+            apply("->map($->toString)", json!([1, true, null])),
+            (Some(json!(["1", "true", "null"])), vec![])
+        );
+        assert_eq!(apply("->map(.a)", json!([])), (Some(json!([])), vec![]));
+        // Values other than arrays are mapped as a single element.
+        assert_eq!(
+            apply("->map(.a)", json!({ "a": 1 })),
+            (Some(json!(1)), vec![])
+        );
+        assert_eq!(
+            apply("->map", json!([1])),
+            method_error("map", "requires one argument")
+        );
+    }
+
+    #[test]
+    fn test_first_and_last() {
+        assert_eq!(apply("->first", json!([1, 2, 3])), (Some(json!(1)), vec![]));
+        assert_eq!(apply("->last", json!([1, 2, 3])), (Some(json!(3)), vec![]));
+        assert_eq!(apply("->first", json!([])), (None, vec![]));
+        assert_eq!(apply("->last", json!([])), (None, vec![]));
+        assert_eq!(apply("->first", json!("épée")), (Some(json!("é")), vec![]));
+        assert_eq!(apply("->last", json!("épée")), (Some(json!("e")), vec![]));
+        assert_eq!(apply("->first", json!("")), (None, vec![]));
+        assert_eq!(
+            apply("->first", json!(3)),
+            method_error("first", "cannot be applied to number")
+        );
+        assert_eq!(
+            apply("->last", json!({ "a": 1 })),
+            method_error("last", "cannot be applied to object")
+        );
+        assert_eq!(
+            apply("->last", json!(null)),
+            method_error("last", "cannot be applied to null")
+        );
+        assert_eq!(
+            apply("->first(1)", json!([1])),
+            method_error("first", "does not take arguments")
+        );
+    }
+
+    #[test]
+    fn test_slice() {
+        let array = json!([1, 2, 3, 4]);
+        assert_eq!(
+            apply("->slice(1)", array.clone()),
+            (Some(json!([2, 3, 4])), vec![])
+        );
+        assert_eq!(
+            apply("->slice(1, 3)", array.clone()),
+            (Some(json!([2, 3])), vec![])
+        );
+        assert_eq!(
+            apply("->slice(-2)", array.clone()),
+            (Some(json!([3, 4])), vec![])
+        );
+        assert_eq!(
+            apply("->slice(0, -1)", array.clone()),
+            (Some(json!([1, 2, 3])), vec![])
+        );
+        assert_eq!(
+            apply("->slice(-10, 10)", array.clone()),
+            (Some(json!([1, 2, 3, 4])), vec![])
+        );
+        assert_eq!(
+            apply("->slice(3, 1)", array.clone()),
+            (Some(json!([])), vec![])
+        );
+        assert_eq!(
+            apply("->slice(1, -1)", json!("épée")),
+            (Some(json!("pé")), vec![])
+        );
+        assert_eq!(
+            apply("->slice(5)", json!("épée")),
+            (Some(json!("")), vec![])
+        );
+        assert_eq!(
+            apply("->slice", array.clone()),
+            method_error("slice", "requires one or two arguments")
+        );
+        assert_eq!(
+            apply("->slice(0, 1, 2)", array.clone()),
+            method_error("slice", "requires one or two arguments")
+        );
+        assert_eq!(
+            apply("->slice('1')", array.clone()),
+            method_error("slice", "requires integer arguments")
+        );
+        assert_eq!(
+            apply("->slice(0, 1.5)", array),
+            method_error("slice", "requires integer arguments")
+        );
+        assert_eq!(
+            apply("->slice(0)", json!(3)),
+            method_error("slice", "cannot be applied to number")
+        );
+        assert_eq!(
+            apply("->slice(0)", json!({ "a": 1 })),
+            method_error("slice", "cannot be applied to object")
+        );
+    }
+
+    #[test]
+    fn test_slice_bounds() {
+        assert_eq!(slice_bounds(0, None, 3), (0, 3));
+        assert_eq!(slice_bounds(1, Some(2), 3), (1, 2));
+        assert_eq!(slice_bounds(-1, None, 3), (2, 3));
+        assert_eq!(slice_bounds(-5, Some(-1), 3), (0, 2));
+        assert_eq!(slice_bounds(5, Some(10), 3), (3, 3));
+        assert_eq!(slice_bounds(2, Some(1), 3), (2, 2));
+        assert_eq!(slice_bounds(0, None, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(
+            apply("->join(', ')", json!(["a", 1, 2.5, true, null])),
+            (Some(json!("a, 1, 2.5, true, ")), vec![])
+        );
+        assert_eq!(apply("->join(',')", json!([])), (Some(json!("")), vec![]));
+        assert_eq!(
+            apply("->join(1)", json!(["a"])),
+            method_error("join", "requires a string argument")
+        );
+        assert_eq!(
+            apply("->join(',')", json!(["a", ["b"]])),
+            method_error("join", "cannot join array elements of type array")
+        );
+        assert_eq!(
+            apply("->join(',')", json!([{ "a": 1 }])),
+            method_error("join", "cannot join array elements of type object")
+        );
+        assert_eq!(
+            apply("->join(',')", json!("abc")),
+            method_error("join", "cannot be applied to string")
+        );
+        assert_eq!(
+            apply("->join", json!(["a"])),
+            method_error("join", "requires one argument")
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        let cases = "{ A: 'a', \"1\": 'one', \"true\": 'yes', \"null\": 'none' }";
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!("A")),
+            (Some(json!("a")), vec![])
+        );
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!(1)),
+            (Some(json!("one")), vec![])
+        );
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!(true)),
+            (Some(json!("yes")), vec![])
+        );
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!(null)),
+            (Some(json!("none")), vec![])
+        );
+        assert_eq!(
+            apply(&format!("->match({cases}, $)"), json!("B")),
+            (Some(json!("B")), vec![])
+        );
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!("B")),
+            method_error("match", "found no match for \"B\"")
+        );
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!(["A"])),
+            method_error("match", "cannot be applied to array")
+        );
+        assert_eq!(
+            apply(&format!("->match({cases})"), json!({ "A": 1 })),
+            method_error("match", "cannot be applied to object")
+        );
+        assert_eq!(
+            apply("->match('A')", json!("A")),
+            method_error("match", "requires an object argument")
+        );
+        assert_eq!(
+            apply("->match", json!("A")),
+            method_error("match", "requires one or two arguments")
+        );
+        assert_eq!(
+            apply(&format!("->match({cases}, 1, 2)"), json!("A")),
+            method_error("match", "requires one or two arguments")
+        );
+    }
+
+    #[test]
+    fn test_default() {
+        assert_eq!(
+            apply("->default('none')", json!(null)),
+            (Some(json!("none")), vec![])
+        );
+        assert_eq!(
+            apply("->default('none')", json!("some")),
+            (Some(json!("some")), vec![])
+        );
+        // Falsy values other than null are kept.
+        assert_eq!(
+            apply("->default(1)", json!(false)),
+            (Some(json!(false)), vec![])
+        );
+        assert_eq!(apply("->default(1)", json!(0)), (Some(json!(0)), vec![]));
+        assert_eq!(apply("->default(1)", json!("")), (Some(json!("")), vec![]));
+        assert_eq!(
+            apply("->default", json!(null)),
+            method_error("default", "requires one argument")
+        );
+    }
+
+    #[test]
+    fn test_to_string() {
+        assert_eq!(
+            apply("->toString", json!("text")),
+            (Some(json!("text")), vec![])
+        );
+        assert_eq!(
+            apply("->toString", json!(1.5)),
+            (Some(json!("1.5")), vec![])
+        );
+        assert_eq!(
+            apply("->toString", json!(false)),
+            (Some(json!("false")), vec![])
+        );
+        assert_eq!(
+            apply("->toString", json!(null)),
+            (Some(json!("null")), vec![])
+        );
+        assert_eq!(
+            apply("->toString", json!({ "a": [1, "b"] })),
+            (Some(json!("{\"a\":[1,\"b\"]}")), vec![])
+        );
+        assert_eq!(
+            apply("->toString('x')", json!(1)),
+            method_error("toString", "does not take arguments")
+        );
+    }
+
+    #[test]
+    fn test_to_number() {
+        assert_eq!(apply("->toNumber", json!(3)), (Some(json!(3)), vec![]));
+        assert_eq!(
+            apply("->toNumber", json!(" 42 ")),
+            (Some(json!(42)), vec![])
+        );
+        assert_eq!(
+            apply("->toNumber", json!("-1.5")),
+            (Some(json!(-1.5)), vec![])
+        );
+        assert_eq!(apply("->toNumber", json!(true)), (Some(json!(1)), vec![]));
+        assert_eq!(apply("->toNumber", json!(false)), (Some(json!(0)), vec![]));
+        assert_eq!(
+            apply("->toNumber", json!("")),
+            method_error("toNumber", "cannot convert \"\" to a number")
+        );
+        assert_eq!(
+            apply("->toNumber", json!("12px")),
+            method_error("toNumber", "cannot convert \"12px\" to a number")
+        );
+        assert_eq!(
+            apply("->toNumber", json!(null)),
+            method_error("toNumber", "cannot be applied to null")
+        );
+        assert_eq!(
+            apply("->toNumber", json!([1])),
+            method_error("toNumber", "cannot be applied to array")
+        );
+        assert_eq!(
+            apply("->toNumber(10)", json!("1")),
+            method_error("toNumber", "does not take arguments")
+        );
+    }
+
+    #[test]
+    fn test_method_shape() {
+        let array = Shape::Array(Box::new(Shape::Int));
+        assert_eq!(method_shape("first", None, &array), Shape::Int);
+        assert_eq!(method_shape("last", None, &Shape::String), Shape::String);
+        assert_eq!(method_shape("first", None, &Shape::Int), Shape::Unknown);
+        assert_eq!(method_shape("slice", None, &array), array);
+        assert_eq!(method_shape("slice", None, &Shape::Bool), Shape::Unknown);
+        assert_eq!(method_shape("join", None, &array), Shape::String);
+        assert_eq!(method_shape("toString", None, &array), Shape::String);
+        assert_eq!(
+            method_shape("toNumber", None, &Shape::String),
+            Shape::Number
+        );
+        // The cases of ->match are only known when given as an object literal.
+        assert_eq!(method_shape("match", None, &Shape::String), Shape::Unknown);
+        assert_eq!(
+            method_shape("default", None, &Shape::Unknown),
+            Shape::Unknown
+        );
+        assert_eq!(method_shape("unknown", None, &Shape::Int), Shape::Unknown);
+    }
+}
@@ -1,11 +1,18 @@
 mod apply_to;
 mod graphql;
 mod helpers;
+mod methods;
 mod parser;
 mod pretty;
+mod selection_set;
+mod shape;
+mod validate;
 
 pub use apply_to::*;
 pub use parser::*;
+pub use shape::Shape;
+pub use validate::ShapeError;
+pub use validate::ShapeErrorKind;
 // Pretty code is currently only used in tests, so this cfg is to suppress the
 // unused lint warning. If pretty code is needed in not test code, feel free to
 // remove the `#[cfg(test)]`.
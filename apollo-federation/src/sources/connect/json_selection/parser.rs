@@ -1,13 +1,17 @@
 use std::fmt::Display;
 
 use nom::branch::alt;
+use nom::bytes::complete::tag;
 use nom::character::complete::char;
+use nom::character::complete::digit0;
+use nom::character::complete::digit1;
 use nom::character::complete::one_of;
 use nom::combinator::all_consuming;
 use nom::combinator::map;
 use nom::combinator::opt;
 use nom::combinator::recognize;
 use nom::multi::many0;
+use nom::multi::separated_list0;
 use nom::sequence::delimited;
 use nom::sequence::pair;
 use nom::sequence::preceded;
@@ -17,6 +21,7 @@ use serde::Serialize;
 use serde_json_bytes::Value as JSON;
 
 use super::helpers::spaces_or_comments;
+use super::methods::is_known_method;
 
 // JSONSelection     ::= NakedSubSelection | PathSelection
 // NakedSubSelection ::= NamedSelection* StarSelection?
@@ -203,6 +208,9 @@ pub enum PathSelection {
     // the selection to a JSON value easier.
     Var(String, Box<PathSelection>),
     Key(Key, Box<PathSelection>),
+    // A ->method invocation, with its optional arguments, applied to the value
+    // selected by the preceding steps.
+    Method(String, Option<MethodArgs>, Box<PathSelection>),
     Selection(SubSelection),
     Empty,
 }
@@ -269,6 +277,23 @@ impl PathSelection {
             )));
         }
 
+        // The ->method case is applicable at any depth except the beginning of
+        // the PathSelection, since methods need a value to be applied to.
+        if let Ok((suffix, (name, args))) = tuple((
+            preceded(tuple((spaces_or_comments, tag("->"))), parse_identifier),
+            opt(MethodArgs::parse),
+        ))(input)
+        {
+            if !is_known_method(&name) {
+                return Err(nom::Err::Failure(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Verify,
+                )));
+            }
+            let (input, rest) = Self::parse_with_depth(suffix, depth + 1)?;
+            return Ok((input, Self::Method(name, args, Box::new(rest))));
+        }
+
         // If the PathSelection has a SubSelection, it must appear at the end of
         // a non-empty path.
         if let Ok((suffix, selection)) = SubSelection::parse(input) {
@@ -312,6 +337,7 @@ impl PathSelection {
         match self {
             PathSelection::Var(_, path) => path.next_subselection(),
             PathSelection::Key(_, path) => path.next_subselection(),
+            PathSelection::Method(_, _, path) => path.next_subselection(),
             PathSelection::Selection(sub) => Some(sub),
             PathSelection::Empty => None,
         }
@@ -322,12 +348,124 @@ impl PathSelection {
         match self {
             PathSelection::Var(_, path) => path.next_mut_subselection(),
             PathSelection::Key(_, path) => path.next_mut_subselection(),
+            PathSelection::Method(_, _, path) => path.next_mut_subselection(),
             PathSelection::Selection(sub) => Some(sub),
             PathSelection::Empty => None,
         }
     }
 }
 
+// MethodArgs ::= "(" (JSLiteral ("," JSLiteral)*)? ")"
+
+#[derive(Debug, PartialEq, Clone, Serialize, Default)]
+pub struct MethodArgs(pub(super) Vec<JSLiteral>);
+
+impl MethodArgs {
+    fn parse(input: &str) -> IResult<&str, Self> {
+        delimited(
+            tuple((spaces_or_comments, char('('), spaces_or_comments)),
+            separated_list0(char(','), JSLiteral::parse),
+            tuple((spaces_or_comments, char(')'), spaces_or_comments)),
+        )(input)
+        .map(|(input, args)| (input, Self(args)))
+    }
+}
+
+// JSLiteral   ::= JSPrimitive | JSObject | JSArray | PathSelection
+// JSPrimitive ::= StringLiteral | JSNumber | "true" | "false" | "null"
+// JSObject    ::= "{" (JSProperty ("," JSProperty)*)? "}"
+// JSProperty  ::= Key ":" JSLiteral
+// JSArray     ::= "[" (JSLiteral ("," JSLiteral)*)? "]"
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum JSLiteral {
+    String(String),
+    // Numbers are kept as their source text, so they can be reprinted exactly.
+    Number(String),
+    Bool(bool),
+    Null,
+    Object(Vec<(String, JSLiteral)>),
+    Array(Vec<JSLiteral>),
+    Path(PathSelection),
+}
+
+impl JSLiteral {
+    pub(crate) fn parse(input: &str) -> IResult<&str, Self> {
+        delimited(
+            spaces_or_comments,
+            alt((
+                map(parse_string_literal, Self::String),
+                map(parse_number, Self::Number),
+                Self::parse_object,
+                Self::parse_array,
+                map(PathSelection::parse, Self::Path),
+                Self::parse_keyword,
+            )),
+            spaces_or_comments,
+        )(input)
+    }
+
+    fn parse_keyword(input: &str) -> IResult<&str, Self> {
+        let (suffix, keyword) = parse_identifier(input)?;
+        match keyword.as_str() {
+            "true" => Ok((suffix, Self::Bool(true))),
+            "false" => Ok((suffix, Self::Bool(false))),
+            "null" => Ok((suffix, Self::Null)),
+            _ => Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::IsNot,
+            ))),
+        }
+    }
+
+    fn parse_object(input: &str) -> IResult<&str, Self> {
+        delimited(
+            tuple((char('{'), spaces_or_comments)),
+            separated_list0(
+                char(','),
+                map(
+                    tuple((
+                        spaces_or_comments,
+                        Key::parse,
+                        spaces_or_comments,
+                        char(':'),
+                        JSLiteral::parse,
+                    )),
+                    |(_, key, _, _, value)| (key.as_string(), value),
+                ),
+            ),
+            tuple((spaces_or_comments, char('}'))),
+        )(input)
+        .map(|(input, properties)| (input, Self::Object(properties)))
+    }
+
+    fn parse_array(input: &str) -> IResult<&str, Self> {
+        delimited(
+            tuple((char('['), spaces_or_comments)),
+            separated_list0(char(','), JSLiteral::parse),
+            tuple((spaces_or_comments, char(']'))),
+        )(input)
+        .map(|(input, items)| (input, Self::Array(items)))
+    }
+}
+
+// JSNumber    ::= "-"? (UnsignedInt ("." [0-9]*)? | "." [0-9]+)
+// UnsignedInt ::= "0" | [1-9] NO_SPACE [0-9]*
+
+fn parse_number(input: &str) -> IResult<&str, String> {
+    recognize(tuple((
+        opt(char('-')),
+        alt((
+            recognize(pair(
+                alt((tag("0"), recognize(pair(one_of("123456789"), digit0)))),
+                opt(pair(char('.'), digit0)),
+            )),
+            recognize(pair(char('.'), digit1)),
+        )),
+    )))(input)
+    .map(|(input, number)| (input, number.to_string()))
+}
+
 // SubSelection ::= "{" NakedSubSelection "}"
 
 #[derive(Debug, PartialEq, Clone, Serialize, Default)]
@@ -1381,6 +1519,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_path_selection_methods() {
+        {
+            let expected = PathSelection::Key(
+                Key::Field("list".to_string()),
+                Box::new(PathSelection::Method(
+                    "first".to_string(),
+                    None,
+                    Box::new(PathSelection::Key(
+                        Key::Field("name".to_string()),
+                        Box::new(PathSelection::Empty),
+                    )),
+                )),
+            );
+            check_path_selection("list->first.name", expected.clone());
+            check_path_selection("list -> first .name", expected.clone());
+            check_path_selection(".list->first.name", expected.clone());
+        }
+
+        check_path_selection(
+            "$.list->slice(1, -2.5)",
+            PathSelection::Var(
+                "$".to_string(),
+                Box::new(PathSelection::Key(
+                    Key::Field("list".to_string()),
+                    Box::new(PathSelection::Method(
+                        "slice".to_string(),
+                        Some(MethodArgs(vec![
+                            JSLiteral::Number("1".to_string()),
+                            JSLiteral::Number("-2.5".to_string()),
+                        ])),
+                        Box::new(PathSelection::Empty),
+                    )),
+                )),
+            ),
+        );
+
+        check_path_selection(
+            "kind->match({ DOG: 'Dog', 'big cat': [true, null] }, $args.fallback)",
+            PathSelection::Key(
+                Key::Field("kind".to_string()),
+                Box::new(PathSelection::Method(
+                    "match".to_string(),
+                    Some(MethodArgs(vec![
+                        JSLiteral::Object(vec![
+                            ("DOG".to_string(), JSLiteral::String("Dog".to_string())),
+                            (
+                                "big cat".to_string(),
+                                JSLiteral::Array(vec![JSLiteral::Bool(true), JSLiteral::Null]),
+                            ),
+                        ]),
+                        JSLiteral::Path(PathSelection::Var(
+                            "$args".to_string(),
+                            Box::new(PathSelection::Key(
+                                Key::Field("fallback".to_string()),
+                                Box::new(PathSelection::Empty),
+                            )),
+                        )),
+                    ])),
+                    Box::new(PathSelection::Empty),
+                )),
+            ),
+        );
+
+        // Methods need a value to be applied to, and unknown methods are
+        // rejected.
+        assert!(PathSelection::parse("->first").is_err());
+        assert!(PathSelection::parse("list->nope").is_err());
+        assert!(JSONSelection::parse("list->nope").is_err());
+    }
+
     #[test]
     fn test_subselection() {
         assert_eq!(
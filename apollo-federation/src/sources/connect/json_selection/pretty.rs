@@ -5,7 +5,9 @@
 //! pretty printing trait which is then implemented on the various sub types
 //! of the JSONSelection tree.
 
+use crate::sources::connect::json_selection::JSLiteral;
 use crate::sources::connect::json_selection::JSONSelection;
+use crate::sources::connect::json_selection::MethodArgs;
 use crate::sources::connect::json_selection::NamedSelection;
 use crate::sources::connect::json_selection::PathSelection;
 use crate::sources::connect::json_selection::StarSelection;
@@ -101,6 +103,18 @@ impl PrettyPrintable for PathSelection {
                 result.push_str(key.dotted().as_str());
                 result.push_str(rest.as_str());
             }
+            PathSelection::Method(name, args, path) => {
+                let rest = path.pretty_print_with_indentation(true, indentation);
+                result.push_str("->");
+                result.push_str(name.as_str());
+                if let Some(args) = args {
+                    result.push_str(
+                        args.pretty_print_with_indentation(true, indentation)
+                            .as_str(),
+                    );
+                }
+                result.push_str(rest.as_str());
+            }
             PathSelection::Selection(sub) => {
                 let sub = sub.pretty_print_with_indentation(true, indentation);
                 result.push(' ');
@@ -113,6 +127,97 @@ impl PrettyPrintable for PathSelection {
     }
 }
 
+impl PrettyPrintable for MethodArgs {
+    fn pretty_print_with_indentation(&self, inline: bool, indentation: usize) -> String {
+        let mut result = String::new();
+
+        if !inline {
+            result.push_str(indent_chars(indentation).as_str());
+        }
+
+        let args = self
+            .0
+            .iter()
+            .map(|arg| arg.pretty_print_with_indentation(true, indentation))
+            .collect::<Vec<_>>();
+        result.push('(');
+        result.push_str(args.join(", ").as_str());
+        result.push(')');
+
+        result
+    }
+}
+
+impl PrettyPrintable for JSLiteral {
+    fn pretty_print_with_indentation(&self, inline: bool, indentation: usize) -> String {
+        let mut result = String::new();
+
+        if !inline {
+            result.push_str(indent_chars(indentation).as_str());
+        }
+
+        // Literals are always printed on a single line, since they are usually
+        // short method arguments.
+        match self {
+            JSLiteral::String(string) => {
+                let quoted = serde_json_bytes::Value::String(string.clone().into()).to_string();
+                result.push_str(quoted.as_str());
+            }
+            JSLiteral::Number(number) => result.push_str(number.as_str()),
+            JSLiteral::Bool(boolean) => result.push_str(if *boolean { "true" } else { "false" }),
+            JSLiteral::Null => result.push_str("null"),
+            JSLiteral::Object(properties) => {
+                let properties = properties
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = value.pretty_print_with_indentation(true, indentation);
+                        if is_identifier(key) {
+                            format!("{key}: {value}")
+                        } else {
+                            let quoted =
+                                serde_json_bytes::Value::String(key.clone().into()).to_string();
+                            format!("{quoted}: {value}")
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if properties.is_empty() {
+                    result.push_str("{}");
+                } else {
+                    result.push_str("{ ");
+                    result.push_str(properties.join(", ").as_str());
+                    result.push_str(" }");
+                }
+            }
+            JSLiteral::Array(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.pretty_print_with_indentation(true, indentation))
+                    .collect::<Vec<_>>();
+                result.push('[');
+                result.push_str(items.join(", ").as_str());
+                result.push(']');
+            }
+            JSLiteral::Path(path) => {
+                result.push_str(
+                    path.pretty_print_with_indentation(true, indentation)
+                        .as_str(),
+                );
+            }
+        }
+
+        result
+    }
+}
+
+/// Helper method to check whether an object key can be printed without quotes
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 impl PrettyPrintable for NamedSelection {
     fn pretty_print_with_indentation(&self, inline: bool, indentation: usize) -> String {
         let mut result = String::new();
@@ -287,6 +392,10 @@ mod tests {
             ".first",
             ".a.b.c.d.e",
             ".one.two.three {\n  a\n  b\n}",
+            // Method
+            "$.list->slice(0, -1)->join(\", \")",
+            ".kind->match({ DOG: \"Dog\", \"big cat\": [true, null] }, $args.fallback)",
+            ".items->map({ id: .id }) {\n  id\n}",
         ];
         for path in paths {
             let (unmatched, path_selection) = PathSelection::parse(path).unwrap();
@@ -0,0 +1,408 @@
+//! Narrowing of a JSONSelection to the fields requested by a GraphQL operation.
+//! A connector selection usually maps a whole REST payload to every field of
+//! its GraphQL type, while an operation may only request a few of them, so the
+//! narrowed selection skips the unrequested subtrees when it is applied, and
+//! produces its output under the response keys (aliases) of the operation.
+
+use apollo_compiler::executable::ExecutableDocument;
+use apollo_compiler::executable::Field;
+use apollo_compiler::executable::Selection;
+use apollo_compiler::executable::SelectionSet;
+use indexmap::IndexMap;
+
+use super::parser::*;
+
+const TYPENAME: &str = "__typename";
+
+impl JSONSelection {
+    // Returns a copy of this selection that only selects the fields requested
+    // by selection_set, which must be a selection set of the GraphQL type that
+    // this selection produces, taken from document. Each requested field is
+    // selected once for each of its response keys, with the nested selection
+    // narrowed to the subfields requested under that key.
+    //
+    // Fields inside fragments are all selected, regardless of their type
+    // conditions, since the concrete type of the output is only known once the
+    // selection has been applied. For the same reason, a __typename selection
+    // is always kept, so abstract types can be resolved from the output.
+    pub fn apply_selection_set(
+        &self,
+        document: &ExecutableDocument,
+        selection_set: &SelectionSet,
+    ) -> Self {
+        let selection_sets = [selection_set];
+        match self {
+            Self::Named(selection) => Self::Named(selection.narrow(document, &selection_sets)),
+            Self::Path(path) => Self::Path(path.narrow(document, &selection_sets)),
+        }
+    }
+}
+
+impl PathSelection {
+    fn narrow(&self, document: &ExecutableDocument, selection_sets: &[&SelectionSet]) -> Self {
+        match self {
+            Self::Var(name, tail) => Self::Var(
+                name.clone(),
+                Box::new(tail.narrow(document, selection_sets)),
+            ),
+            Self::Key(key, tail) => {
+                Self::Key(key.clone(), Box::new(tail.narrow(document, selection_sets)))
+            }
+            Self::Method(name, args, tail) => Self::Method(
+                name.clone(),
+                args.clone(),
+                Box::new(tail.narrow(document, selection_sets)),
+            ),
+            Self::Selection(selection) => {
+                Self::Selection(selection.narrow(document, selection_sets))
+            }
+            // Without a SubSelection, the whole value is selected, and nothing
+            // can be skipped.
+            Self::Empty => Self::Empty,
+        }
+    }
+}
+
+impl SubSelection {
+    fn narrow(&self, document: &ExecutableDocument, selection_sets: &[&SelectionSet]) -> Self {
+        if selection_sets
+            .iter()
+            .all(|selection_set| selection_set.selections.is_empty())
+        {
+            // The field is a leaf of the operation, such as a custom scalar
+            // holding an object, so its whole value is requested.
+            return self.clone();
+        }
+
+        let mut requested = IndexMap::new();
+        for selection_set in selection_sets {
+            collect_fields(document, selection_set, &mut requested);
+        }
+
+        let mut narrowed = SubSelection::default();
+
+        if !requested.contains_key(TYPENAME) {
+            if let Some(typename) = self.find(TYPENAME) {
+                narrowed.selections.push(typename.clone());
+            }
+        }
+
+        for (response_key, fields) in &requested {
+            let name = fields[0].name.as_str();
+            let selection_sets = fields
+                .iter()
+                .map(|field| &field.selection_set)
+                .collect::<Vec<_>>();
+
+            if let Some(selection) = self.find(name) {
+                narrowed
+                    .selections
+                    .push(selection.narrow(document, response_key, &selection_sets));
+                continue;
+            }
+
+            match &self.star {
+                // Any unselected property may be selected by an unaliased *
+                // selection, so it is selected explicitly instead.
+                Some(StarSelection(None, selection)) => {
+                    narrowed.selections.push(NamedSelection::Field(
+                        alias_for(response_key, name),
+                        name.to_string(),
+                        selection
+                            .as_ref()
+                            .map(|selection| selection.narrow(document, &selection_sets)),
+                    ));
+                }
+                Some(StarSelection(Some(alias), selection))
+                    if alias.name == name && narrowed.star.is_none() =>
+                {
+                    narrowed.star = Some(StarSelection(
+                        Some(Alias::new(response_key)),
+                        selection.clone(),
+                    ));
+                }
+                // The field is not produced by this selection, so it must be
+                // resolved some other way.
+                _ => {}
+            }
+        }
+
+        narrowed
+    }
+
+    fn find(&self, name: &str) -> Option<&NamedSelection> {
+        self.selections
+            .iter()
+            .find(|selection| selection.name() == name)
+    }
+}
+
+impl NamedSelection {
+    // Narrows the selection of a single field of the operation, producing its
+    // output under the response key of the field instead of its name.
+    fn narrow(
+        &self,
+        document: &ExecutableDocument,
+        response_key: &str,
+        selection_sets: &[&SelectionSet],
+    ) -> Self {
+        match self {
+            Self::Field(_, name, selection) => Self::Field(
+                alias_for(response_key, name),
+                name.clone(),
+                selection
+                    .as_ref()
+                    .map(|selection| selection.narrow(document, selection_sets)),
+            ),
+            Self::Quoted(_, name, selection) => Self::Quoted(
+                Alias::new(response_key),
+                name.clone(),
+                selection
+                    .as_ref()
+                    .map(|selection| selection.narrow(document, selection_sets)),
+            ),
+            Self::Path(_, path) => Self::Path(
+                Alias::new(response_key),
+                path.narrow(document, selection_sets),
+            ),
+            Self::Group(_, selection) => Self::Group(
+                Alias::new(response_key),
+                selection.narrow(document, selection_sets),
+            ),
+        }
+    }
+}
+
+fn alias_for(response_key: &str, name: &str) -> Option<Alias> {
+    if response_key == name {
+        None
+    } else {
+        Some(Alias::new(response_key))
+    }
+}
+
+// Collects the fields of a selection set by response key, including the fields
+// of all its fragments. Fields with the same response key always have the same
+// name in a valid operation, and their selection sets are merged.
+fn collect_fields<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a SelectionSet,
+    fields: &mut IndexMap<&'a str, Vec<&'a Field>>,
+) {
+    for selection in &selection_set.selections {
+        match selection {
+            Selection::Field(field) => {
+                fields
+                    .entry(field.response_key().as_str())
+                    .or_default()
+                    .push(field.as_ref());
+            }
+            Selection::InlineFragment(fragment) => {
+                collect_fields(document, &fragment.selection_set, fields);
+            }
+            Selection::FragmentSpread(spread) => {
+                if let Some(fragment) = document.fragments.get(&spread.fragment_name) {
+                    collect_fields(document, &fragment.selection_set, fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apollo_compiler::Schema;
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::selection;
+    use crate::sources::connect::ApplyTo;
+
+    const SCHEMA: &str = r#"
+        type Query {
+            user: User
+            users: [User]
+            pet: Pet
+        }
+
+        type User {
+            id: ID!
+            name: String
+            email: String
+            address: Address
+            friends: [User]
+            extra: String
+            tags: JSON
+        }
+
+        type Address {
+            street: String
+            city: String
+            zip: String
+        }
+
+        interface Pet {
+            name: String
+        }
+
+        type Dog implements Pet {
+            name: String
+            barks: Boolean
+        }
+
+        type Cat implements Pet {
+            name: String
+            meows: Boolean
+        }
+
+        scalar JSON
+    "#;
+
+    // Narrows the selection by the selection set of the root field of query.
+    fn narrow(selection: &JSONSelection, query: &str) -> JSONSelection {
+        let schema = Schema::parse_and_validate(SCHEMA, "schema.graphql").unwrap();
+        let document =
+            ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let operation = document.anonymous_operation.as_ref().unwrap();
+        let Some(Selection::Field(field)) = operation.selection_set.selections.first() else {
+            panic!("expected a root field");
+        };
+        selection.apply_selection_set(&document, &field.selection_set)
+    }
+
+    #[test]
+    fn test_skips_unrequested_fields() {
+        let selection = selection!("id name email address { street city zip } friends { id name }");
+        assert_eq!(
+            narrow(&selection, "{ user { id address { city } } }"),
+            selection!("id address { city }"),
+        );
+        assert_eq!(
+            narrow(&selection, "{ users { name friends { id } } }"),
+            selection!("name friends { id }"),
+        );
+    }
+
+    #[test]
+    fn test_maps_aliases() {
+        let selection = selection!(
+            r#"
+            id
+            name: username
+            email: 'e-mail'
+            address: location { city: town zip }
+            friends: .friendIds { id: $ }
+            "#
+        );
+        assert_eq!(
+            narrow(
+                &selection,
+                "{ user { userId: id name fullName: name mail: email home: address { city } friends { friendId: id } } }",
+            ),
+            selection!(
+                r#"
+                userId: id
+                name: username
+                fullName: username
+                mail: 'e-mail'
+                home: location { city: town }
+                friends: .friendIds { friendId: $ }
+                "#
+            ),
+        );
+
+        let (value, errors) = narrow(&selection, "{ user { userId: id fullName: name } }")
+            .apply_to(&json!({ "id": 1, "username": "ada", "location": { "town": "x" } }));
+        assert_eq!(value, Some(json!({ "userId": 1, "fullName": "ada" })));
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_merges_fragments() {
+        let selection = selection!("id name email address { street city zip }");
+        assert_eq!(
+            narrow(
+                &selection,
+                r#"
+                {
+                    user {
+                        ... on User { id address { city } }
+                        ...UserFields
+                    }
+                }
+                fragment UserFields on User { address { zip } }
+                "#,
+            ),
+            selection!("id address { city zip }"),
+        );
+    }
+
+    #[test]
+    fn test_keeps_typename() {
+        let selection = selection!(
+            r#"
+            __typename: kind->match({ "dog": "Dog", "cat": "Cat" })
+            name
+            barks
+            meows
+            "#
+        );
+        assert_eq!(
+            narrow(&selection, "{ pet { ... on Dog { barks } } }"),
+            selection!(
+                r#"
+                __typename: kind->match({ "dog": "Dog", "cat": "Cat" })
+                barks
+                "#
+            ),
+        );
+        assert_eq!(
+            narrow(&selection, "{ pet { name type: __typename } }"),
+            selection!(
+                r#"
+                __typename: kind->match({ "dog": "Dog", "cat": "Cat" })
+                name
+                type: kind->match({ "dog": "Dog", "cat": "Cat" })
+                "#
+            ),
+        );
+
+        // Without a __typename selection, __typename is left to the caller.
+        assert_eq!(
+            narrow(&selection!("id name"), "{ user { __typename id } }"),
+            selection!("id"),
+        );
+    }
+
+    #[test]
+    fn test_star_selections() {
+        assert_eq!(
+            narrow(&selection!("id *"), "{ user { id name mail: email } }"),
+            selection!("id name mail: email"),
+        );
+        assert_eq!(
+            narrow(&selection!("id extra: *"), "{ user { id extra } }"),
+            selection!("id extra: *"),
+        );
+        assert_eq!(
+            narrow(&selection!("id extra: *"), "{ user { id } }"),
+            selection!("id"),
+        );
+    }
+
+    #[test]
+    fn test_path_selections() {
+        let selection = selection!(".data.user { id name email }");
+        assert_eq!(
+            narrow(&selection, "{ user { name } }"),
+            selection!(".data.user { name }"),
+        );
+
+        // Leaf fields keep their whole selection.
+        let selection = selection!("id tags: labels { name color }");
+        assert_eq!(
+            narrow(&selection, "{ user { tags } }"),
+            selection!("tags: labels { name color }"),
+        );
+    }
+}
@@ -0,0 +1,420 @@
+/// Static inference of the output shape of a JSONSelection. Since the input
+/// JSON is only known at runtime, the shape is determined by the selection
+/// syntax itself (object keys, literal values, method results), and any part
+/// of the output that is copied from the input data has the Shape::Unknown
+/// shape.
+use indexmap::IndexMap;
+
+use super::apply_to::starts_with_method;
+use super::methods::method_shape;
+use super::parser::*;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Shape {
+    // Any JSON value, including null, because it depends on the input data.
+    Unknown,
+    Null,
+    Bool,
+    String,
+    Int,
+    Float,
+    // A number that may or may not be an integer, such as the result of the
+    // ->toNumber method.
+    Number,
+    // An object with known keys, where rest is the shape of the values of any
+    // other keys, as selected by an unaliased * selection. When rest is None,
+    // the object has no other keys.
+    Object {
+        fields: IndexMap<String, Shape>,
+        rest: Option<Box<Shape>>,
+    },
+    Array(Box<Shape>),
+    // A value that may have any one of several shapes, such as a string or
+    // null. Use Shape::one to construct this case, so nested alternatives are
+    // flattened and duplicates removed.
+    One(Vec<Shape>),
+}
+
+impl Shape {
+    pub fn one(shapes: impl IntoIterator<Item = Shape>) -> Self {
+        let mut alternatives: Vec<Shape> = vec![];
+        for shape in shapes {
+            let flattened = match shape {
+                Shape::One(inner) => inner,
+                shape => vec![shape],
+            };
+            for shape in flattened {
+                if shape == Shape::Unknown {
+                    // Any value is possible, so the other alternatives do not
+                    // narrow the shape.
+                    return Shape::Unknown;
+                }
+                if !alternatives.contains(&shape) {
+                    alternatives.push(shape);
+                }
+            }
+        }
+        match alternatives.len() {
+            0 => Shape::Unknown,
+            1 => alternatives.remove(0),
+            _ => Shape::One(alternatives),
+        }
+    }
+
+    // Whether null is known to be a possible value. Shape::Unknown values may
+    // also be null, but that cannot be determined statically.
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            Shape::Null => true,
+            Shape::One(alternatives) => alternatives.contains(&Shape::Null),
+            _ => false,
+        }
+    }
+
+    pub fn non_null(&self) -> Shape {
+        match self {
+            Shape::One(alternatives) => Shape::one(
+                alternatives
+                    .iter()
+                    .filter(|shape| **shape != Shape::Null)
+                    .cloned(),
+            ),
+            shape => shape.clone(),
+        }
+    }
+
+    // The shape of the value of the given property of a value of this shape.
+    fn field(&self, name: &str) -> Shape {
+        match self {
+            Shape::Object { fields, rest } => fields
+                .get(name)
+                .or(rest.as_deref())
+                .cloned()
+                .unwrap_or(Shape::Unknown),
+            Shape::One(alternatives) => {
+                Shape::one(alternatives.iter().map(|shape| shape.field(name)))
+            }
+            _ => Shape::Unknown,
+        }
+    }
+
+    // A short description of the shape, for use in error messages.
+    pub(super) fn describe(&self) -> String {
+        match self {
+            Shape::Unknown => "any value".to_string(),
+            Shape::Null => "null".to_string(),
+            Shape::Bool => "a boolean".to_string(),
+            Shape::String => "a string".to_string(),
+            Shape::Int => "an integer".to_string(),
+            Shape::Float => "a float".to_string(),
+            Shape::Number => "a number".to_string(),
+            Shape::Object { .. } => "an object".to_string(),
+            Shape::Array(_) => "an array".to_string(),
+            Shape::One(alternatives) => alternatives
+                .iter()
+                .map(|shape| shape.describe())
+                .collect::<Vec<_>>()
+                .join(" or "),
+        }
+    }
+}
+
+impl JSONSelection {
+    // Computes the shape of the output of applying this selection to any
+    // input. Like ApplyTo::apply_to, a selection applied to an array input
+    // produces an array of results, but since the input is unknown, the shape
+    // only describes a single result in that case.
+    pub fn output_shape(&self) -> Shape {
+        self.compute_shape(&Shape::Unknown)
+    }
+}
+
+// ComputeShape mirrors the ApplyTo trait, computing the shape of the output of
+// each part of a JSONSelection from the shape of its input.
+pub(super) trait ComputeShape {
+    fn compute_shape(&self, input: &Shape) -> Shape;
+}
+
+impl ComputeShape for JSONSelection {
+    fn compute_shape(&self, input: &Shape) -> Shape {
+        match self {
+            Self::Named(selection) => selection.compute_shape(input),
+            Self::Path(path) => path.compute_shape(input),
+        }
+    }
+}
+
+impl ComputeShape for NamedSelection {
+    fn compute_shape(&self, input: &Shape) -> Shape {
+        match self {
+            Self::Field(_, name, selection) | Self::Quoted(_, name, selection) => {
+                let value = input.field(name);
+                match selection {
+                    Some(selection) => selection.compute_shape(&value),
+                    None => value,
+                }
+            }
+            Self::Path(_, path) => path.compute_shape(input),
+            Self::Group(_, selection) => selection.compute_shape(input),
+        }
+    }
+}
+
+impl ComputeShape for PathSelection {
+    fn compute_shape(&self, input: &Shape) -> Shape {
+        if let Shape::Array(element) = input {
+            if !starts_with_method(self) {
+                return Shape::Array(Box::new(self.compute_shape(element)));
+            }
+        }
+
+        match self {
+            Self::Var(var_name, tail) => {
+                if var_name == "$" {
+                    tail.compute_shape(input)
+                } else {
+                    // Variables such as $args are only known at runtime.
+                    tail.compute_shape(&Shape::Unknown)
+                }
+            }
+            Self::Key(key, tail) => tail.compute_shape(&input.field(&key.as_string())),
+            Self::Method(name, args, tail) => {
+                tail.compute_shape(&method_shape(name, args.as_ref(), input))
+            }
+            Self::Selection(selection) => selection.compute_shape(input),
+            Self::Empty => input.clone(),
+        }
+    }
+}
+
+impl ComputeShape for JSLiteral {
+    fn compute_shape(&self, input: &Shape) -> Shape {
+        match self {
+            Self::String(_) => Shape::String,
+            Self::Number(number) => {
+                if number.contains('.') {
+                    Shape::Float
+                } else {
+                    Shape::Int
+                }
+            }
+            Self::Bool(_) => Shape::Bool,
+            Self::Null => Shape::Null,
+            Self::Object(properties) => Shape::Object {
+                fields: properties
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.compute_shape(input)))
+                    .collect(),
+                rest: None,
+            },
+            Self::Array(items) => Shape::Array(Box::new(Shape::one(
+                items.iter().map(|item| item.compute_shape(input)),
+            ))),
+            Self::Path(path) => path.compute_shape(input),
+        }
+    }
+}
+
+impl ComputeShape for SubSelection {
+    fn compute_shape(&self, input: &Shape) -> Shape {
+        if let Shape::Array(element) = input {
+            return Shape::Array(Box::new(self.compute_shape(element)));
+        }
+
+        let mut fields = IndexMap::new();
+        for selection in &self.selections {
+            fields.insert(selection.name().to_string(), selection.compute_shape(input));
+        }
+
+        let mut rest = None;
+        if let Some(StarSelection(alias, selection)) = &self.star {
+            // The keys matched by the * selection are only known at runtime,
+            // but the shape of their values is known if there is a
+            // SubSelection.
+            let value = selection.as_ref().map_or(Shape::Unknown, |selection| {
+                selection.compute_shape(&Shape::Unknown)
+            });
+            match alias {
+                Some(alias) => {
+                    fields.insert(
+                        alias.name.clone(),
+                        Shape::Object {
+                            fields: IndexMap::new(),
+                            rest: Some(Box::new(value)),
+                        },
+                    );
+                }
+                None => rest = Some(Box::new(value)),
+            }
+        }
+
+        Shape::Object { fields, rest }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection;
+
+    fn object<const N: usize>(fields: [(&str, Shape); N]) -> Shape {
+        Shape::Object {
+            fields: fields
+                .into_iter()
+                .map(|(key, shape)| (key.to_string(), shape))
+                .collect(),
+            rest: None,
+        }
+    }
+
+    #[test]
+    fn test_named_selection_shapes() {
+        assert_eq!(
+            selection!("id name").output_shape(),
+            object([("id", Shape::Unknown), ("name", Shape::Unknown)]),
+        );
+
+        assert_eq!(
+            selection!("id author: user { id name } meta: { created }").output_shape(),
+            object([
+                ("id", Shape::Unknown),
+                (
+                    "author",
+                    object([("id", Shape::Unknown), ("name", Shape::Unknown)]),
+                ),
+                ("meta", object([("created", Shape::Unknown)])),
+            ]),
+        );
+
+        assert_eq!(
+            selection!("title: 'book title' { text }").output_shape(),
+            object([("title", object([("text", Shape::Unknown)]))]),
+        );
+
+        assert_eq!(
+            selection!("city: address.city").output_shape(),
+            object([("city", Shape::Unknown)]),
+        );
+    }
+
+    #[test]
+    fn test_star_selection_shapes() {
+        assert_eq!(
+            selection!("id *").output_shape(),
+            Shape::Object {
+                fields: IndexMap::from_iter([("id".to_string(), Shape::Unknown)]),
+                rest: Some(Box::new(Shape::Unknown)),
+            },
+        );
+
+        assert_eq!(
+            selection!("id others: * { name }").output_shape(),
+            object([
+                ("id", Shape::Unknown),
+                (
+                    "others",
+                    Shape::Object {
+                        fields: IndexMap::new(),
+                        rest: Some(Box::new(object([("name", Shape::Unknown)]))),
+                    },
+                ),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_path_selection_shapes() {
+        assert_eq!(selection!(".data").output_shape(), Shape::Unknown);
+        assert_eq!(selection!("$args.id").output_shape(), Shape::Unknown);
+        assert_eq!(
+            selection!(".data { id }").output_shape(),
+            object([("id", Shape::Unknown)]),
+        );
+        assert_eq!(
+            selection!("$.data.items { id }").output_shape(),
+            object([("id", Shape::Unknown)]),
+        );
+    }
+
+    #[test]
+    fn test_method_shapes() {
+        assert_eq!(
+            selection!(
+                r#"
+                id: id->toString
+                count: count->toNumber
+                csv: tags->join(",")
+                name: name->default("anonymous")
+                kind: kind->match({ "a": "A", "b": null })
+                score: score->match({ "low": 1, "high": 2.5 }, 0)
+                flags: $->echo([true, false])
+                first: $->echo([1, 2])->first
+                pair: $->echo({ a: 1, b: "b" })
+                b: $->echo({ a: 1, b: "b" }).b
+                tags: tags->map(.name)
+                names: $->echo(["a", null])->map($->default(""))
+                "#
+            )
+            .output_shape(),
+            object([
+                ("id", Shape::String),
+                ("count", Shape::Number),
+                ("csv", Shape::String),
+                ("name", Shape::Unknown),
+                ("kind", Shape::One(vec![Shape::String, Shape::Null])),
+                ("score", Shape::One(vec![Shape::Int, Shape::Float]),),
+                ("flags", Shape::Array(Box::new(Shape::Bool))),
+                ("first", Shape::Int),
+                ("pair", object([("a", Shape::Int), ("b", Shape::String)])),
+                ("b", Shape::String),
+                ("tags", Shape::Unknown),
+                ("names", Shape::Array(Box::new(Shape::String))),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_array_literal_shapes() {
+        assert_eq!(
+            selection!("$->echo([{ id: 1 }, { id: 2 }]) { id }").output_shape(),
+            Shape::Array(Box::new(object([("id", Shape::Int)]))),
+        );
+        assert_eq!(
+            selection!("$->echo([{ id: 1 }, { id: 2 }]).id").output_shape(),
+            Shape::Array(Box::new(Shape::Int)),
+        );
+        assert_eq!(
+            selection!("$->echo([1, 'two', null])").output_shape(),
+            Shape::Array(Box::new(Shape::One(vec![
+                Shape::Int,
+                Shape::String,
+                Shape::Null,
+            ]))),
+        );
+        assert_eq!(
+            selection!("$->echo([1, 2, 3])->slice(1)").output_shape(),
+            Shape::Array(Box::new(Shape::Int)),
+        );
+    }
+
+    #[test]
+    fn test_one() {
+        assert_eq!(Shape::one([]), Shape::Unknown);
+        assert_eq!(Shape::one([Shape::Int]), Shape::Int);
+        assert_eq!(Shape::one([Shape::Int, Shape::Int]), Shape::Int);
+        assert_eq!(
+            Shape::one([
+                Shape::Int,
+                Shape::one([Shape::String, Shape::Null]),
+                Shape::String,
+            ]),
+            Shape::One(vec![Shape::Int, Shape::String, Shape::Null]),
+        );
+        assert_eq!(Shape::one([Shape::Int, Shape::Unknown]), Shape::Unknown);
+
+        let nullable = Shape::one([Shape::String, Shape::Null]);
+        assert!(nullable.is_nullable());
+        assert_eq!(nullable.non_null(), Shape::String);
+        assert!(!Shape::Unknown.is_nullable());
+    }
+}
@@ -0,0 +1,476 @@
+/// Validation of the output shape of a JSONSelection against the GraphQL type
+/// it is expected to produce, such as the return type of a field annotated
+/// with `@connect(selection: ...)`.
+use std::ops::Range;
+
+use apollo_compiler::ast::Type;
+use apollo_compiler::name;
+use apollo_compiler::schema::ExtendedType;
+use apollo_compiler::Schema;
+
+use super::helpers::spaces_or_comments;
+use super::parser::*;
+use super::shape::Shape;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeErrorKind {
+    // A field of the GraphQL type is not selected.
+    MissingField,
+    // The selection produces a key that is not a field of the GraphQL type.
+    ExtraField,
+    // The selected value cannot have the GraphQL type of its field.
+    TypeMismatch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapeError {
+    pub kind: ShapeErrorKind,
+    pub message: String,
+    // The output keys leading to the selection with the error, which is the
+    // whole selection when the path is empty.
+    pub path: Vec<String>,
+    // The byte range of the selection string to report the error at, which
+    // covers the last key of the path that could be found in the string.
+    pub range: Option<Range<usize>>,
+}
+
+impl JSONSelection {
+    // Checks that the output of this selection, which was parsed from source,
+    // can be a value of the GraphQL type ty. Parts of the output that depend on
+    // the input data cannot be checked statically, so they are assumed valid.
+    pub fn validate_shape(&self, source: &str, schema: &Schema, ty: &Type) -> Vec<ShapeError> {
+        let mut validator = Validator {
+            schema,
+            path: vec![],
+            errors: vec![],
+        };
+        validator.check(&self.output_shape(), ty);
+        validator
+            .errors
+            .into_iter()
+            .map(|(kind, message, path)| {
+                let range = locate(source, self, &path);
+                ShapeError {
+                    kind,
+                    message,
+                    path,
+                    range,
+                }
+            })
+            .collect()
+    }
+}
+
+struct Validator<'a> {
+    schema: &'a Schema,
+    path: Vec<String>,
+    errors: Vec<(ShapeErrorKind, String, Vec<String>)>,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&mut self, shape: &Shape, ty: &Type) {
+        match shape {
+            Shape::Unknown => {}
+            Shape::Null => {
+                if ty.is_non_null() {
+                    self.mismatch(shape, ty);
+                }
+            }
+            Shape::One(alternatives) => {
+                for alternative in alternatives {
+                    self.check(alternative, ty);
+                }
+            }
+            Shape::Array(element) => match ty {
+                Type::List(inner) | Type::NonNullList(inner) => self.check(element, inner),
+                // Custom scalars such as JSON can represent arrays.
+                Type::Named(name) | Type::NonNullNamed(name) => {
+                    if !self.is_custom_scalar(name.as_str()) {
+                        self.mismatch(shape, ty);
+                    }
+                }
+            },
+            _ => match ty {
+                // A selection applied to an array input produces an array, so
+                // any shape not known to be an array may also be a list.
+                Type::List(inner) | Type::NonNullList(inner) => self.check(shape, inner),
+                Type::Named(name) | Type::NonNullNamed(name) => {
+                    if !self.matches_named_type(shape, name.as_str()) {
+                        self.mismatch(shape, ty);
+                    }
+                }
+            },
+        }
+    }
+
+    // Returns false if the shape cannot be a value of the named type. Errors
+    // found in the fields of objects are reported separately, so this still
+    // returns true in that case.
+    fn matches_named_type(&mut self, shape: &Shape, name: &str) -> bool {
+        let schema = self.schema;
+        let Some(definition) = schema.types.get(name) else {
+            // Undefined types are reported by schema validation.
+            return true;
+        };
+
+        match definition {
+            ExtendedType::Scalar(_) => match name {
+                "Int" => matches!(shape, Shape::Int | Shape::Number),
+                "Float" => matches!(shape, Shape::Int | Shape::Float | Shape::Number),
+                "String" => matches!(shape, Shape::String),
+                "Boolean" => matches!(shape, Shape::Bool),
+                "ID" => matches!(shape, Shape::String | Shape::Int),
+                // Custom scalars can represent any value.
+                _ => true,
+            },
+            ExtendedType::Enum(_) => matches!(shape, Shape::String),
+            ExtendedType::Object(object) => {
+                let fields = object
+                    .fields
+                    .iter()
+                    .map(|(name, field)| {
+                        (name.as_str(), &field.ty, field.directives.has("connect"))
+                    })
+                    .collect::<Vec<_>>();
+                self.check_fields(shape, name, &fields)
+            }
+            ExtendedType::Interface(interface) => {
+                let fields = interface
+                    .fields
+                    .iter()
+                    .map(|(name, field)| {
+                        (name.as_str(), &field.ty, field.directives.has("connect"))
+                    })
+                    .collect::<Vec<_>>();
+                self.check_fields(shape, name, &fields)
+            }
+            ExtendedType::Union(union) => {
+                // Since the member type of the output is only known at runtime,
+                // it is enough for the shape to match any member type.
+                matches!(shape, Shape::Object { .. })
+                    && union.members.iter().any(|member| {
+                        let mut validator = Validator {
+                            schema,
+                            path: vec![],
+                            errors: vec![],
+                        };
+                        validator.matches_named_type(shape, member.name.as_str())
+                            && validator.errors.is_empty()
+                    })
+            }
+            ExtendedType::InputObject(_) => false,
+        }
+    }
+
+    fn check_fields(
+        &mut self,
+        shape: &Shape,
+        type_name: &str,
+        fields: &[(&str, &Type, bool)],
+    ) -> bool {
+        let Shape::Object {
+            fields: selected,
+            rest,
+        } = shape
+        else {
+            return false;
+        };
+
+        for (key, value) in selected {
+            self.path.push(key.clone());
+            if key == "__typename" {
+                self.check(value, &Type::NonNullNamed(name!("String")));
+            } else if let Some((_, ty, _)) =
+                fields.iter().find(|(name, _, _)| *name == key.as_str())
+            {
+                self.check(value, ty);
+            } else {
+                self.error(
+                    ShapeErrorKind::ExtraField,
+                    format!("Field `{key}` does not exist on type `{type_name}`"),
+                );
+            }
+            self.path.pop();
+        }
+
+        // Any field may be selected by a * selection, and fields with their own
+        // @connect directive are resolved separately.
+        if rest.is_none() {
+            for (name, _, has_connect) in fields {
+                if !has_connect && !selected.contains_key(*name) {
+                    self.error(
+                        ShapeErrorKind::MissingField,
+                        format!(
+                            "{} does not select field `{type_name}.{name}`",
+                            self.describe_path()
+                        ),
+                    );
+                }
+            }
+        }
+
+        true
+    }
+
+    fn is_custom_scalar(&self, name: &str) -> bool {
+        matches!(self.schema.types.get(name), Some(ExtendedType::Scalar(scalar)) if !scalar.is_built_in())
+    }
+
+    fn mismatch(&mut self, shape: &Shape, ty: &Type) {
+        self.error(
+            ShapeErrorKind::TypeMismatch,
+            format!(
+                "{} produces {}, which is not a valid `{ty}` value",
+                self.describe_path(),
+                shape.describe()
+            ),
+        );
+    }
+
+    fn describe_path(&self) -> String {
+        if self.path.is_empty() {
+            "The selection".to_string()
+        } else {
+            format!("Selection `{}`", self.path.join("."))
+        }
+    }
+
+    fn error(&mut self, kind: ShapeErrorKind, message: String) {
+        let error = (kind, message, self.path.clone());
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+}
+
+// The parsed JSONSelection does not record source locations, so the output
+// keys of the path are found by parsing the source again, one NamedSelection
+// at a time, descending into the SubSelection of each matching key.
+fn locate(source: &str, selection: &JSONSelection, path: &[String]) -> Option<Range<usize>> {
+    let offset = |text: &str| text.as_ptr() as usize - source.as_ptr() as usize;
+
+    let (trimmed, _) = spaces_or_comments(source).ok()?;
+    let mut range = offset(trimmed)..source.trim_end().len();
+
+    let mut text = match selection {
+        JSONSelection::Named(_) => Some(source),
+        JSONSelection::Path(path) => path
+            .next_subselection()
+            .and_then(|selection| subselection_text(source, selection)),
+    };
+
+    for key in path {
+        let Some((named_text, named)) = text.and_then(|text| find_named_selection(text, key))
+        else {
+            break;
+        };
+        // Every NamedSelection starts with its output key, either as an alias
+        // or as the field name.
+        let start = offset(named_text);
+        range = start..start + key.len();
+        text = named
+            .next_subselection()
+            .and_then(|selection| subselection_text(named_text, selection));
+    }
+
+    Some(range)
+}
+
+// Returns the source text of the NamedSelection with the given output key in
+// text, which contains a sequence of NamedSelections.
+fn find_named_selection<'a>(text: &'a str, key: &str) -> Option<(&'a str, NamedSelection)> {
+    let mut text = text;
+    loop {
+        let (start, _) = spaces_or_comments(text).ok()?;
+        let (rest, named) = NamedSelection::parse(start).ok()?;
+        if named.name() == key {
+            return Some((&start[..start.len() - rest.len()], named));
+        }
+        text = rest;
+    }
+}
+
+// Returns the text following the opening { of the given SubSelection, which
+// must end the text. Any { characters before it belong to method arguments.
+fn subselection_text<'a>(text: &'a str, selection: &SubSelection) -> Option<&'a str> {
+    text.char_indices()
+        .filter(|(_, c)| *c == '{')
+        .find_map(|(i, _)| match SubSelection::parse(&text[i..]) {
+            Ok((rest, parsed)) if rest.is_empty() && parsed == *selection => Some(&text[i + 1..]),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection;
+
+    const SCHEMA: &str = r#"
+        scalar JSON
+
+        enum Role { ADMIN USER }
+
+        type Query {
+            user: User
+            users: [User!]!
+        }
+
+        type User {
+            id: ID!
+            name: String
+            age: Int
+            role: Role
+            address: Address
+            metadata: JSON
+            posts: [Post] @connect
+        }
+
+        type Address {
+            street: String
+            city: String!
+        }
+
+        type Post {
+            title: String
+        }
+
+        union Result = User | Post
+
+        directive @connect on FIELD_DEFINITION
+    "#;
+
+    fn validate(source: &str, ty: &str) -> Vec<ShapeError> {
+        let schema = Schema::parse_and_validate(SCHEMA, "schema.graphql").unwrap();
+        let ty = Type::parse(ty, "type.graphql").unwrap();
+        selection!(source).validate_shape(source, &schema, &ty)
+    }
+
+    fn messages(errors: &[ShapeError]) -> Vec<&str> {
+        errors.iter().map(|error| error.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_valid_selections() {
+        let user = "id name age role address { street city } metadata";
+        assert_eq!(validate(user, "User"), vec![]);
+        assert_eq!(validate(user, "[User!]!"), vec![]);
+        assert_eq!(validate(&format!(".data {{ {user} }}"), "User"), vec![]);
+        assert_eq!(validate("id *", "User"), vec![]);
+        assert_eq!(validate(&format!("__typename {user}"), "User"), vec![]);
+        assert_eq!(
+            validate(
+                "id: id->toString name: $->echo('name') age: age->toNumber role: role->match({ a: 'ADMIN' }, null) address { street city } metadata: $->echo([1, 2])",
+                "User",
+            ),
+            vec![],
+        );
+        assert_eq!(validate("title", "Result"), vec![]);
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let errors = validate("id name address { city }", "User");
+        assert_eq!(
+            messages(&errors),
+            vec![
+                "Selection `address` does not select field `Address.street`",
+                "The selection does not select field `User.age`",
+                "The selection does not select field `User.role`",
+                "The selection does not select field `User.metadata`",
+            ],
+        );
+        assert!(errors
+            .iter()
+            .all(|error| error.kind == ShapeErrorKind::MissingField));
+        assert_eq!(errors[0].path, vec!["address".to_string()]);
+        assert_eq!(errors[0].range, Some(8..15));
+        assert_eq!(errors[1].path, Vec::<String>::new());
+        assert_eq!(errors[1].range, Some(0..24));
+    }
+
+    #[test]
+    fn test_extra_fields() {
+        let source = "id name age role metadata address { street city zip: postcode }\n  email";
+        let errors = validate(source, "User");
+        assert_eq!(
+            messages(&errors),
+            vec![
+                "Field `zip` does not exist on type `Address`",
+                "Field `email` does not exist on type `User`",
+            ],
+        );
+        assert!(errors
+            .iter()
+            .all(|error| error.kind == ShapeErrorKind::ExtraField));
+        assert_eq!(errors[0].path, vec!["address", "zip"]);
+        assert_eq!(&source[errors[0].range.clone().unwrap()], "zip");
+        assert_eq!(errors[1].path, vec!["email"]);
+        assert_eq!(&source[errors[1].range.clone().unwrap()], "email");
+    }
+
+    #[test]
+    fn test_type_mismatches() {
+        let source = r#"
+            id: $->echo(true)
+            name: $->echo({ first: "Ada" })
+            age: $->echo(1.5)
+            role: $->echo(1)
+            address: street
+            metadata
+        "#;
+        let errors = validate(source, "User");
+        assert_eq!(
+            messages(&errors),
+            vec![
+                "Selection `id` produces a boolean, which is not a valid `ID!` value",
+                "Selection `name` produces an object, which is not a valid `String` value",
+                "Selection `age` produces a float, which is not a valid `Int` value",
+                "Selection `role` produces an integer, which is not a valid `Role` value",
+            ],
+        );
+        assert!(errors
+            .iter()
+            .all(|error| error.kind == ShapeErrorKind::TypeMismatch));
+        assert_eq!(&source[errors[2].range.clone().unwrap()], "age");
+
+        assert_eq!(
+            messages(&validate(
+                "id: $->echo(null) name age role address metadata",
+                "User"
+            )),
+            vec!["Selection `id` produces null, which is not a valid `ID!` value"],
+        );
+        assert_eq!(
+            messages(&validate(
+                "id name: $->echo(['a']) age role address metadata",
+                "User"
+            )),
+            vec!["Selection `name` produces an array, which is not a valid `String` value"],
+        );
+        assert_eq!(
+            messages(&validate("id name", "String")),
+            vec!["The selection produces an object, which is not a valid `String` value"],
+        );
+    }
+
+    #[test]
+    fn test_nested_locations() {
+        let source = "id name age role metadata\naddress: .location { street city: $->echo({ a: 1 }) { a } }";
+        let errors = validate(source, "User");
+        assert_eq!(
+            messages(&errors),
+            vec![
+                "Selection `address.city` produces an object, which is not a valid `String!` value"
+            ],
+        );
+        let range = errors[0].range.clone().unwrap();
+        assert_eq!(&source[range.clone()], "city");
+        assert_eq!(range.start, source.find("city").unwrap());
+
+        let source = ".user { id name age role metadata address { street city: $->echo(1) } }";
+        let errors = validate(source, "User");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(&source[errors[0].range.clone().unwrap()], "city");
+    }
+}
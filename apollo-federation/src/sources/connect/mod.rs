@@ -8,5 +8,8 @@ pub use json_selection::ApplyToError;
 pub use json_selection::JSONSelection;
 pub use json_selection::Key;
 pub use json_selection::PathSelection;
+pub use json_selection::Shape;
+pub use json_selection::ShapeError;
+pub use json_selection::ShapeErrorKind;
 pub use json_selection::SubSelection;
 pub use url_path_template::URLPathTemplate;
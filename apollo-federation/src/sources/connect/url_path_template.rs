@@ -6,10 +6,13 @@ use itertools::Itertools;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::char;
+use nom::character::complete::digit1;
 use nom::character::complete::one_of;
+use nom::combinator::map_res;
 use nom::combinator::opt;
 use nom::combinator::recognize;
 use nom::multi::many0;
+use nom::multi::separated_list1;
 use nom::sequence::pair;
 use nom::sequence::preceded;
 use nom::sequence::tuple;
@@ -41,6 +44,7 @@ pub struct ParameterValue {
 pub enum ValuePart {
     Text(String),
     Var(VariableExpression),
+    Expression(Expression),
 }
 
 #[derive(Debug, PartialEq, Clone, Default)]
@@ -64,26 +68,76 @@ pub struct VariableExpression {
     required: bool,
 }
 
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Expression {
+    // An RFC 6570 expression, such as {+path}, {#section}, {/segments*} or
+    // {?ids*,limit}, whose operator determines how the values of its variables
+    // are joined and percent-encoded.
+    operator: Operator,
+    vars: Vec<VarSpec>,
+
+    // Like VariableExpression variables, the variables of expressions in the
+    // URL path are required, except in {?var} and {&var} expressions, which
+    // expand to query parameters. Undefined (missing or null) variables of
+    // optional expressions are left out of the expansion.
+    required: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Operator {
+    // {var}
+    #[default]
+    Simple,
+    // {+var}
+    Reserved,
+    // {#var}
+    Fragment,
+    // {.var}
+    Label,
+    // {/var}
+    PathSegment,
+    // {;var}
+    PathParameter,
+    // {?var}
+    Query,
+    // {&var}
+    QueryContinuation,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct VarSpec {
+    var_path: String,
+
+    // The * modifier expands each element of a list value, or each entry of an
+    // object value, as a separate item of the expression.
+    explode: bool,
+
+    // The :N modifier keeps only the first N characters of a string value.
+    prefix: Option<usize>,
+}
+
 impl URLPathTemplate {
     // Top-level parsing entry point for URLPathTemplate syntax.
     pub fn parse(input: &str) -> Result<URLPathTemplate, String> {
-        let mut prefix_suffix = input.splitn(2, '?');
-        let path_prefix = prefix_suffix.next();
-        let query_suffix = prefix_suffix.next();
+        // The ? and / separators may also appear as operators inside {...}
+        // expressions, like {?ids*} or {/path*}, so only separators outside
+        // of expressions split the template.
+        let (path_prefix, query_suffix) = match find_outside_braces(input, '?') {
+            Some(index) => (&input[..index], Some(&input[index + 1..])),
+            None => (input, None),
+        };
         let mut path = vec![];
 
-        if let Some(path_prefix) = path_prefix {
-            for path_part in path_prefix.split('/') {
-                if !path_part.is_empty() {
-                    path.push(ParameterValue::parse(path_part, true)?);
-                }
+        for path_part in split_outside_braces(path_prefix, '/') {
+            if !path_part.is_empty() {
+                path.push(ParameterValue::parse(path_part, true)?);
             }
         }
 
         let mut query = IndexMap::new();
 
         if let Some(query_suffix) = query_suffix {
-            for query_part in query_suffix.split('&') {
+            for query_part in split_outside_braces(query_suffix, '&') {
                 if let Some((key, value)) = query_part.split_once('=') {
                     query.insert(key.to_string(), ParameterValue::parse(value, false)?);
                 }
@@ -101,7 +155,10 @@ impl URLPathTemplate {
         let mut path = String::new();
         if let Some(var_map) = vars.as_object() {
             for (path_position, param_value) in self.path.iter().enumerate() {
-                path.push('/');
+                // A {/var} expression provides its own / separator.
+                if !param_value.starts_with_path_segment() {
+                    path.push('/');
+                }
 
                 if let Some(value) = param_value.interpolate(var_map)? {
                     path.push_str(value.as_str());
@@ -122,7 +179,9 @@ impl URLPathTemplate {
                 }
             }
             if !params.is_empty() {
-                path.push('?');
+                // A {?var} expression in the path may have started the query
+                // string already.
+                path.push(if path.contains('?') { '&' } else { '?' });
                 path.push_str(&params.join("&"));
             }
         } else {
@@ -142,7 +201,23 @@ impl URLPathTemplate {
     // variables from the path and return them as a JSON object.
     #[allow(dead_code)]
     fn extract_vars(&self, path: &str) -> Result<JSON, String> {
-        let concrete_template = URLPathTemplate::parse(path)?;
+        let mut concrete_template = URLPathTemplate::parse(path)?;
+
+        // A {+var}, {#var} or {/var} expression in the last path segment may
+        // expand to several segments, which are matched against it together.
+        if concrete_template.path.len() > self.path.len()
+            && self.path.last().is_some_and(ParameterValue::spans_segments)
+        {
+            let tail = concrete_template.path.split_off(self.path.len() - 1);
+            let mut parts = vec![];
+            for (i, segment) in tail.into_iter().enumerate() {
+                if i > 0 {
+                    parts.push(ValuePart::Text("/".to_string()));
+                }
+                parts.extend(segment.parts);
+            }
+            concrete_template.path.push(ParameterValue { parts });
+        }
 
         if concrete_template.path.len() != self.path.len() {
             return Err(format!(
@@ -155,7 +230,16 @@ impl URLPathTemplate {
         let mut var_map = Map::new();
 
         for (i, path_value) in self.path.iter().enumerate() {
-            for (var_path, value) in path_value.extract_vars(&concrete_template.path[i])? {
+            let concrete_value = &concrete_template.path[i];
+            let vars = if path_value.starts_with_path_segment() {
+                // The / separator of the segment belongs to the expression.
+                let mut parts = vec![ValuePart::Text("/".to_string())];
+                parts.extend(concrete_value.parts.iter().cloned());
+                path_value.extract_vars(&ParameterValue { parts })?
+            } else {
+                path_value.extract_vars(concrete_value)?
+            };
+            for (var_path, value) in vars {
                 var_map.insert(var_path, value);
             }
         }
@@ -184,9 +268,51 @@ impl URLPathTemplate {
             }
         }
 
+        // Variables of {?var} and {&var} expressions are extracted from the
+        // name=value pairs of the concrete query string, since exploded lists
+        // repeat the same name for each element.
+        let query_expressions = self.query_expressions();
+        if !query_expressions.is_empty() {
+            let query_string = path.split_once('?').map_or("", |(_, query)| query);
+            let query_string = query_string
+                .split_once('#')
+                .map_or(query_string, |(query, _)| query);
+            let pairs = query_string
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (percent_decode(name), value)
+                })
+                .collect::<Vec<_>>();
+
+            let mut known_names: HashSet<&str> = self.query.keys().map(String::as_str).collect();
+            for expression in &query_expressions {
+                known_names.extend(expression.vars.iter().map(|var| var.var_path.as_str()));
+            }
+
+            for expression in query_expressions {
+                expression.extract_named(&pairs, &known_names, &mut var_map);
+            }
+        }
+
         Ok(JSON::Object(var_map))
     }
 
+    fn query_expressions(&self) -> Vec<&Expression> {
+        self.path
+            .iter()
+            .chain(self.query.values())
+            .flat_map(|param_value| &param_value.parts)
+            .filter_map(|part| match part {
+                ValuePart::Expression(expression) if expression.operator.is_query() => {
+                    Some(expression)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn required_parameters(&self) -> Vec<String> {
         let mut parameters = HashSet::new();
         for param_value in &self.path {
@@ -246,10 +372,7 @@ impl ParameterValue {
             remaining = suffix;
 
             if let Some((var, suffix)) = remaining.split_once('}') {
-                parts.push(ValuePart::Var(VariableExpression::parse(
-                    var,
-                    required_by_default,
-                )?));
+                parts.push(ValuePart::parse_expression(var, required_by_default)?);
                 remaining = suffix;
             } else {
                 return Err(format!(
@@ -266,19 +389,25 @@ impl ParameterValue {
         // Enforce that variable expressions must be separated by nonempty text
         // delimiters, though the parameter value may start or end with variable
         // expressions without preceding/following text.
+        // Expressions whose operator starts their expansion with a delimiter,
+        // like {.ext} or {?ids}, may directly follow another expression.
         let mut prev_part_was_var = false;
         for part in &parts {
-            if let ValuePart::Var(_) = part {
-                if prev_part_was_var {
-                    return Err(format!(
-                        "Ambiguous adjacent variable expressions in {}",
-                        input,
-                    ));
+            let delimited = match part {
+                ValuePart::Text(_) => {
+                    prev_part_was_var = false;
+                    continue;
                 }
-                prev_part_was_var = true;
-            } else {
-                prev_part_was_var = false;
+                ValuePart::Var(_) => false,
+                ValuePart::Expression(expression) => !expression.operator.first().is_empty(),
+            };
+            if prev_part_was_var && !delimited {
+                return Err(format!(
+                    "Ambiguous adjacent variable expressions in {}",
+                    input,
+                ));
             }
+            prev_part_was_var = true;
         }
 
         Ok(ParameterValue { parts })
@@ -288,6 +417,7 @@ impl ParameterValue {
         let mut value = String::new();
         let mut missing_vars = vec![];
         let mut some_vars_required = false;
+        let mut missing_expression = false;
 
         for part in &self.parts {
             match part {
@@ -304,6 +434,10 @@ impl ParameterValue {
                         some_vars_required = true;
                     }
                 }
+                ValuePart::Expression(expression) => match expression.expand(vars) {
+                    Some(expansion) => value.push_str(&expansion),
+                    None => missing_expression = true,
+                },
             }
         }
 
@@ -325,6 +459,12 @@ impl ParameterValue {
             }
         }
 
+        // A required expression with an undefined variable leaves the
+        // parameter value incomplete.
+        if missing_expression {
+            return Ok(None);
+        }
+
         Ok(Some(value))
     }
 
@@ -339,11 +479,14 @@ impl ParameterValue {
                 ValuePart::Var(var) => {
                     return Err(format!("Unexpected variable expression {{{}}}", var));
                 }
+                ValuePart::Expression(expression) => {
+                    return Err(format!("Unexpected variable expression {{{}}}", expression));
+                }
             });
         }
 
         let mut concrete_suffix = concrete_text.as_str();
-        let mut pending_var: Option<&VariableExpression> = None;
+        let mut pending_part: Option<&ValuePart> = None;
         let mut output = Map::new();
 
         fn add_var_value(
@@ -356,27 +499,49 @@ impl ParameterValue {
                 let mut values = vec![];
                 for value in value.split(separator) {
                     if !value.is_empty() {
-                        values.push(JSON::String(ByteString::from(value)));
+                        values.push(JSON::String(ByteString::from(percent_decode(value))));
                     }
                 }
                 output.insert(key, JSON::Array(values));
             } else if !value.is_empty() {
-                output.insert(key, JSON::String(ByteString::from(value)));
+                output.insert(key, JSON::String(ByteString::from(percent_decode(value))));
+            }
+        }
+
+        fn add_part_value(part: &ValuePart, value: &str, output: &mut Map<ByteString, JSON>) {
+            match part {
+                ValuePart::Var(var) => add_var_value(var, value, output),
+                ValuePart::Expression(expression) => expression.extract(value, output),
+                ValuePart::Text(_) => {}
             }
         }
 
+        // Returns the length of the pending part's value at the start of
+        // suffix, which ends where the delimiter is found, skipping the
+        // delimiter that an expression operator starts with.
+        fn pending_len(part: &ValuePart, suffix: &str, delimiter: &str) -> Option<usize> {
+            let skip = match part {
+                ValuePart::Expression(expression)
+                    if suffix.starts_with(expression.operator.first()) =>
+                {
+                    expression.operator.first().len()
+                }
+                _ => 0,
+            };
+            suffix[skip..].find(delimiter).map(|start| start + skip)
+        }
+
         for part in &self.parts {
             match part {
                 ValuePart::Text(text) => {
-                    if let Some(var) = pending_var {
-                        if let Some(start) = concrete_suffix.find(text) {
-                            add_var_value(var, &concrete_suffix[..start], &mut output);
+                    if let Some(pending) = pending_part.take() {
+                        if let Some(start) = pending_len(pending, concrete_suffix, text) {
+                            add_part_value(pending, &concrete_suffix[..start], &mut output);
                             concrete_suffix = &concrete_suffix[start..];
                         } else {
-                            add_var_value(var, concrete_suffix, &mut output);
+                            add_part_value(pending, concrete_suffix, &mut output);
                             concrete_suffix = "";
                         }
-                        pending_var = None;
                     }
 
                     if concrete_suffix.starts_with(text) {
@@ -388,29 +553,67 @@ impl ParameterValue {
                         ));
                     }
                 }
-                ValuePart::Var(var) => {
-                    if let Some(pending) = pending_var {
-                        return Err(format!(
-                            "Ambiguous adjacent variable expressions {} and {} in parameter value {}",
-                            pending, var, concrete_text
-                        ));
-                    } else {
-                        // This variable's value will be extracted from the
-                        // concrete URL by the ValuePart::Text branch above, on
-                        // the next iteration of the for loop.
-                        pending_var = Some(var);
+                // The variables of {?var} and {&var} expressions are extracted
+                // from the query string by URLPathTemplate::extract_vars.
+                ValuePart::Expression(expression) if expression.operator.is_query() => {}
+                ValuePart::Var(_) | ValuePart::Expression(_) => {
+                    let delimiter = match part {
+                        ValuePart::Expression(expression) => expression.operator.first(),
+                        _ => "",
+                    };
+                    if let Some(pending) = pending_part {
+                        if delimiter.is_empty() {
+                            return Err(format!(
+                                "Ambiguous adjacent variable expressions {} and {} in parameter value {}",
+                                pending, part, concrete_text
+                            ));
+                        }
+                        // The pending value ends where this expression's
+                        // operator delimiter begins.
+                        let end = pending_len(pending, concrete_suffix, delimiter)
+                            .unwrap_or(concrete_suffix.len());
+                        add_part_value(pending, &concrete_suffix[..end], &mut output);
+                        concrete_suffix = &concrete_suffix[end..];
                     }
+                    // This part's value will be extracted from the concrete
+                    // URL on a later iteration of the for loop, once the text
+                    // following it is known.
+                    pending_part = Some(part);
                 }
             }
         }
 
-        if let Some(var) = pending_var {
-            add_var_value(var, concrete_suffix, &mut output);
+        if let Some(pending) = pending_part {
+            add_part_value(pending, concrete_suffix, &mut output);
         }
 
         Ok(output)
     }
 
+    fn starts_with_path_segment(&self) -> bool {
+        matches!(
+            self.parts.first(),
+            Some(ValuePart::Expression(Expression {
+                operator: Operator::PathSegment,
+                ..
+            }))
+        )
+    }
+
+    // Whether the value of this parameter may contain / characters, so it can
+    // match several segments of a concrete path.
+    fn spans_segments(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part,
+                ValuePart::Expression(Expression {
+                    operator: Operator::Reserved | Operator::Fragment | Operator::PathSegment,
+                    ..
+                })
+            )
+        })
+    }
+
     fn required_parameters(&self) -> Vec<String> {
         let mut parameters = vec![];
         for part in &self.parts {
@@ -421,6 +624,11 @@ impl ParameterValue {
                         parameters.push(var.var_path.clone());
                     }
                 }
+                ValuePart::Expression(expression) => {
+                    if expression.required {
+                        parameters.extend(expression.vars.iter().map(|var| var.var_path.clone()));
+                    }
+                }
             }
         }
         parameters
@@ -453,6 +661,11 @@ impl Display for ValuePart {
                 var.fmt(f)?;
                 f.write_str("}")?;
             }
+            ValuePart::Expression(expression) => {
+                f.write_str("{")?;
+                expression.fmt(f)?;
+                f.write_str("}")?;
+            }
         }
         Ok(())
     }
@@ -495,12 +708,14 @@ impl VariableExpression {
 
     fn interpolate(&self, vars: &Map<ByteString, JSON>) -> Result<Option<String>, String> {
         let var_path_bytes = ByteString::from(self.var_path.as_str());
-        if let Some(child_value) = vars.get(&var_path_bytes) {
+        // Null values are treated as missing, so optional query parameters
+        // are dropped rather than generated as the text null.
+        if let Some(child_value) = vars.get(&var_path_bytes).filter(|value| !value.is_null()) {
             if let Some(separator) = &self.batch_separator {
                 if let JSON::Array(array) = child_value {
                     let mut value_strings = vec![];
                     for value in array {
-                        value_strings.push(self.value_as_string(value));
+                        value_strings.push(percent_encode(&value_as_string(value), false));
                     }
                     if value_strings.is_empty() {
                         return Ok(None);
@@ -510,7 +725,7 @@ impl VariableExpression {
                 }
                 // Fall through to handle non-array values as single batch inputs.
             }
-            Ok(Some(self.value_as_string(child_value)))
+            Ok(Some(percent_encode(&value_as_string(child_value), false)))
         } else if self.required {
             return Err(format!(
                 "Missing required variable {} in {}",
@@ -521,16 +736,6 @@ impl VariableExpression {
             return Ok(None);
         }
     }
-
-    fn value_as_string(&self, value: &JSON) -> String {
-        // Need to remove quotes from string values, since the quotes don't
-        // belong in the URL.
-        if let JSON::String(string) = value {
-            string.as_str().to_string()
-        } else {
-            value.to_string()
-        }
-    }
 }
 
 impl Display for VariableExpression {
@@ -547,6 +752,491 @@ impl Display for VariableExpression {
     }
 }
 
+impl ValuePart {
+    // Parses the contents of a {...} expression, which is an RFC 6570
+    // expression when it starts with an operator or uses a syntax that
+    // VariableExpression does not support, like {x,y} or {ids*}.
+    fn parse_expression(input: &str, required_by_default: bool) -> Result<Self, String> {
+        if input.starts_with(|c| Operator::from_char(c).is_some()) {
+            return Expression::parse(input, required_by_default).map(ValuePart::Expression);
+        }
+        match VariableExpression::parse(input, required_by_default) {
+            Ok(var) => Ok(ValuePart::Var(var)),
+            Err(err) => Expression::parse(input, required_by_default)
+                .map(ValuePart::Expression)
+                .map_err(|_| err),
+        }
+    }
+}
+
+impl Expression {
+    fn parse(input: &str, required_by_default: bool) -> Result<Self, String> {
+        tuple((
+            opt(one_of("+#./;?&")),
+            separated_list1(char(','), nom_parse_var_spec),
+        ))(input)
+        .map_err(|err| format!("Error parsing variable expression {}: {}", input, err))
+        .and_then(|(remaining, (operator, vars))| {
+            if remaining.is_empty() {
+                let operator = operator.and_then(Operator::from_char).unwrap_or_default();
+                Ok(Expression {
+                    operator,
+                    vars,
+                    required: required_by_default && !operator.is_query(),
+                })
+            } else {
+                Err(format!(
+                    "Unexpected trailing characters {} in variable expression {}",
+                    remaining, input
+                ))
+            }
+        })
+    }
+
+    // Expands the defined variables of the expression following RFC 6570,
+    // returning an empty string when none of them are defined. Returns None
+    // if the expression is required and one of its variables is undefined.
+    fn expand(&self, vars: &Map<ByteString, JSON>) -> Option<String> {
+        let mut items = vec![];
+        for var in &self.vars {
+            match vars
+                .get(&ByteString::from(var.var_path.as_str()))
+                .and_then(|value| self.expand_var(var, value))
+            {
+                Some(item) => items.push(item),
+                None if self.required => return None,
+                None => {}
+            }
+        }
+
+        if items.is_empty() {
+            Some(String::new())
+        } else {
+            Some(format!(
+                "{}{}",
+                self.operator.first(),
+                items.join(self.operator.separator())
+            ))
+        }
+    }
+
+    fn expand_var(&self, var: &VarSpec, value: &JSON) -> Option<String> {
+        let operator = self.operator;
+        let encode =
+            |value: &JSON| percent_encode(&value_as_string(value), operator.allows_reserved());
+        let name = var.var_path.as_str();
+
+        match value {
+            JSON::Null => None,
+            JSON::Array(array) => {
+                let items = array
+                    .iter()
+                    .filter(|item| !item.is_null())
+                    .map(encode)
+                    .collect::<Vec<_>>();
+                if items.is_empty() {
+                    None
+                } else if var.explode {
+                    Some(
+                        items
+                            .iter()
+                            .map(|item| self.named_value(name, item))
+                            .join(operator.separator()),
+                    )
+                } else {
+                    Some(self.named_value(name, &items.join(",")))
+                }
+            }
+            JSON::Object(object) => {
+                let entries = object
+                    .iter()
+                    .filter(|(_, value)| !value.is_null())
+                    .map(|(key, value)| {
+                        (
+                            percent_encode(key.as_str(), operator.allows_reserved()),
+                            encode(value),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                if entries.is_empty() {
+                    None
+                } else if var.explode {
+                    Some(
+                        entries
+                            .iter()
+                            .map(|(key, value)| format!("{}={}", key, value))
+                            .join(operator.separator()),
+                    )
+                } else {
+                    let flattened = entries
+                        .iter()
+                        .map(|(key, value)| format!("{},{}", key, value))
+                        .join(",");
+                    Some(self.named_value(name, &flattened))
+                }
+            }
+            _ => {
+                let mut string = value_as_string(value);
+                if let Some(prefix) = var.prefix {
+                    string = string.chars().take(prefix).collect();
+                }
+                Some(self.named_value(name, &percent_encode(&string, operator.allows_reserved())))
+            }
+        }
+    }
+
+    fn named_value(&self, name: &str, value: &str) -> String {
+        if !self.operator.named() {
+            value.to_string()
+        } else if value.is_empty() {
+            format!("{}{}", name, self.operator.if_empty())
+        } else {
+            format!("{}={}", name, value)
+        }
+    }
+
+    // Extracts the variables of the expression from its expansion, the
+    // inverse of expand. Lists and objects are recovered from the unencoded
+    // separators between their elements, and all values are strings.
+    fn extract(&self, text: &str, output: &mut Map<ByteString, JSON>) {
+        let Some(text) = text.strip_prefix(self.operator.first()) else {
+            return;
+        };
+
+        if self.operator.named() {
+            let pairs = text
+                .split(self.operator.separator())
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (percent_decode(name), value)
+                })
+                .collect::<Vec<_>>();
+            let names: HashSet<&str> = self.vars.iter().map(|var| var.var_path.as_str()).collect();
+            self.extract_named(&pairs, &names, output);
+            return;
+        }
+
+        if text.is_empty() {
+            return;
+        }
+
+        if let [var] = self.vars.as_slice() {
+            let value = if var.explode {
+                let items = text.split(self.operator.separator()).collect::<Vec<_>>();
+                if items.iter().all(|item| item.contains('=')) {
+                    JSON::Object(
+                        items
+                            .iter()
+                            .filter_map(|item| item.split_once('='))
+                            .map(|(key, value)| {
+                                (
+                                    ByteString::from(percent_decode(key)),
+                                    JSON::String(ByteString::from(percent_decode(value))),
+                                )
+                            })
+                            .collect(),
+                    )
+                } else {
+                    JSON::Array(
+                        items
+                            .iter()
+                            .map(|item| JSON::String(ByteString::from(percent_decode(item))))
+                            .collect(),
+                    )
+                }
+            } else {
+                self.extract_value(text)
+            };
+            output.insert(ByteString::from(var.var_path.as_str()), value);
+            return;
+        }
+
+        // With several variables, each item of the expansion is assigned to
+        // the variables in order.
+        for (var, item) in self.vars.iter().zip(text.split(self.operator.separator())) {
+            output.insert(
+                ByteString::from(var.var_path.as_str()),
+                self.extract_value(item),
+            );
+        }
+    }
+
+    // Extracts the variables of a named expression ({;var}, {?var} or {&var})
+    // from name=value pairs, whose names are already decoded. An exploded
+    // variable without a pair of its own name collects the pairs that do not
+    // belong to any of known_names as the entries of an object.
+    fn extract_named(
+        &self,
+        pairs: &[(String, &str)],
+        known_names: &HashSet<&str>,
+        output: &mut Map<ByteString, JSON>,
+    ) {
+        for var in &self.vars {
+            let name = var.var_path.as_str();
+            let values = pairs
+                .iter()
+                .filter(|(key, _)| key == name)
+                .map(|(_, value)| *value)
+                .collect::<Vec<_>>();
+
+            let value = if var.explode {
+                if values.is_empty() {
+                    let entries = pairs
+                        .iter()
+                        .filter(|(key, _)| !known_names.contains(key.as_str()))
+                        .map(|(key, value)| {
+                            (
+                                ByteString::from(key.as_str()),
+                                JSON::String(ByteString::from(percent_decode(value))),
+                            )
+                        })
+                        .collect::<Map<_, _>>();
+                    if entries.is_empty() {
+                        continue;
+                    }
+                    JSON::Object(entries)
+                } else {
+                    JSON::Array(
+                        values
+                            .iter()
+                            .map(|value| JSON::String(ByteString::from(percent_decode(value))))
+                            .collect(),
+                    )
+                }
+            } else if let Some(value) = values.first() {
+                self.extract_value(value)
+            } else {
+                continue;
+            };
+
+            output.insert(ByteString::from(name), value);
+        }
+    }
+
+    // Values of operators that encode reserved characters can only contain
+    // unencoded commas as the separators of list elements.
+    fn extract_value(&self, value: &str) -> JSON {
+        if !self.operator.allows_reserved() && value.contains(',') {
+            JSON::Array(
+                value
+                    .split(',')
+                    .map(|item| JSON::String(ByteString::from(percent_decode(item))))
+                    .collect(),
+            )
+        } else {
+            JSON::String(ByteString::from(percent_decode(value)))
+        }
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(operator) = self.operator.as_char() {
+            write!(f, "{}", operator)?;
+        }
+        for (i, var) in self.vars.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            var.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Operator {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Self::Reserved),
+            '#' => Some(Self::Fragment),
+            '.' => Some(Self::Label),
+            '/' => Some(Self::PathSegment),
+            ';' => Some(Self::PathParameter),
+            '?' => Some(Self::Query),
+            '&' => Some(Self::QueryContinuation),
+            _ => None,
+        }
+    }
+
+    fn as_char(&self) -> Option<char> {
+        match self {
+            Self::Simple => None,
+            Self::Reserved => Some('+'),
+            Self::Fragment => Some('#'),
+            Self::Label => Some('.'),
+            Self::PathSegment => Some('/'),
+            Self::PathParameter => Some(';'),
+            Self::Query => Some('?'),
+            Self::QueryContinuation => Some('&'),
+        }
+    }
+
+    // The expansion rules of each operator, from appendix A of RFC 6570.
+
+    fn first(&self) -> &'static str {
+        match self {
+            Self::Simple | Self::Reserved => "",
+            Self::Fragment => "#",
+            Self::Label => ".",
+            Self::PathSegment => "/",
+            Self::PathParameter => ";",
+            Self::Query => "?",
+            Self::QueryContinuation => "&",
+        }
+    }
+
+    fn separator(&self) -> &'static str {
+        match self {
+            Self::Simple | Self::Reserved | Self::Fragment => ",",
+            Self::Label => ".",
+            Self::PathSegment => "/",
+            Self::PathParameter => ";",
+            Self::Query | Self::QueryContinuation => "&",
+        }
+    }
+
+    fn named(&self) -> bool {
+        matches!(
+            self,
+            Self::PathParameter | Self::Query | Self::QueryContinuation
+        )
+    }
+
+    fn if_empty(&self) -> &'static str {
+        match self {
+            Self::Query | Self::QueryContinuation => "=",
+            _ => "",
+        }
+    }
+
+    fn allows_reserved(&self) -> bool {
+        matches!(self, Self::Reserved | Self::Fragment)
+    }
+
+    fn is_query(&self) -> bool {
+        matches!(self, Self::Query | Self::QueryContinuation)
+    }
+}
+
+impl Display for VarSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.var_path)?;
+        if self.explode {
+            f.write_str("*")?;
+        }
+        if let Some(prefix) = self.prefix {
+            write!(f, ":{}", prefix)?;
+        }
+        Ok(())
+    }
+}
+
+fn value_as_string(value: &JSON) -> String {
+    // Need to remove quotes from string values, since the quotes don't
+    // belong in the URL.
+    if let JSON::String(string) = value {
+        string.as_str().to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// Percent-encodes every character outside the unreserved set of RFC 3986, also
+// keeping reserved characters and existing %XX triplets when allow_reserved.
+fn percent_encode(value: &str, allow_reserved: bool) -> String {
+    const RESERVED: &[u8] = b":/?#[]@!$&'()*+,;=";
+    let bytes = value.as_bytes();
+    let mut encoded = String::with_capacity(value.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if byte.is_ascii_alphanumeric()
+            || b"-._~".contains(&byte)
+            || (allow_reserved && RESERVED.contains(&byte))
+        {
+            encoded.push(byte as char);
+        } else if allow_reserved
+            && byte == b'%'
+            && bytes
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        {
+            encoded.push_str(&value[i..i + 3]);
+            i += 3;
+            continue;
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+        i += 1;
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn find_outside_braces(input: &str, separator: char) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in input.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            _ if c == separator && depth == 0 => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_outside_braces(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut remaining = input;
+    while let Some(index) = find_outside_braces(remaining, separator) {
+        parts.push(&remaining[..index]);
+        remaining = &remaining[index + separator.len_utf8()..];
+    }
+    parts.push(remaining);
+    parts
+}
+
+fn nom_parse_var_spec(input: &str) -> IResult<&str, VarSpec> {
+    let (input, var_path) = nom_parse_identifier_path(input)?;
+    let (input, explode) = opt(char('*'))(input)?;
+    let (input, prefix) = if explode.is_some() {
+        (input, None)
+    } else {
+        opt(preceded(char(':'), map_res(digit1, str::parse::<usize>)))(input)?
+    };
+    Ok((
+        input,
+        VarSpec {
+            var_path,
+            explode: explode.is_some(),
+            prefix,
+        },
+    ))
+}
+
 fn nom_parse_identifier_possible_namespace(input: &str) -> IResult<&str, &str> {
     recognize(alt((tag("$this"), nom_parse_identifier)))(input)
 }
@@ -1805,5 +2495,328 @@ mod tests {
                 .required_parameters(),
             vec!["$this.bar", "$this.id"],
         );
+
+        // Expressions are required in the path, except for query expressions.
+        assert_eq!(
+            URLPathTemplate::parse("/files/{+path}{/ids*}{.ext}{?limit}?q={q}{&page}")
+                .unwrap()
+                .required_parameters(),
+            vec!["ext", "ids", "path"],
+        );
+    }
+
+    #[test]
+    fn test_parse_expressions() {
+        assert_eq!(
+            URLPathTemplate::parse("/files{/path*}{?ids*,limit:2}"),
+            Ok(URLPathTemplate {
+                path: vec![ParameterValue {
+                    parts: vec![
+                        ValuePart::Text("files".to_string()),
+                        ValuePart::Expression(Expression {
+                            operator: Operator::PathSegment,
+                            vars: vec![VarSpec {
+                                var_path: "path".to_string(),
+                                explode: true,
+                                ..Default::default()
+                            }],
+                            required: true,
+                        }),
+                        ValuePart::Expression(Expression {
+                            operator: Operator::Query,
+                            vars: vec![
+                                VarSpec {
+                                    var_path: "ids".to_string(),
+                                    explode: true,
+                                    ..Default::default()
+                                },
+                                VarSpec {
+                                    var_path: "limit".to_string(),
+                                    prefix: Some(2),
+                                    ..Default::default()
+                                },
+                            ],
+                            required: false,
+                        }),
+                    ],
+                }],
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(
+            URLPathTemplate::parse("/position/{x,y}?q={q}{&page}"),
+            Ok(URLPathTemplate {
+                path: vec![
+                    ParameterValue {
+                        parts: vec![ValuePart::Text("position".to_string())],
+                    },
+                    ParameterValue {
+                        parts: vec![ValuePart::Expression(Expression {
+                            operator: Operator::Simple,
+                            vars: vec![
+                                VarSpec {
+                                    var_path: "x".to_string(),
+                                    ..Default::default()
+                                },
+                                VarSpec {
+                                    var_path: "y".to_string(),
+                                    ..Default::default()
+                                },
+                            ],
+                            required: true,
+                        })],
+                    },
+                ],
+                query: IndexMap::from([(
+                    "q".to_string(),
+                    ParameterValue {
+                        parts: vec![
+                            ValuePart::Var(VariableExpression {
+                                var_path: "q".to_string(),
+                                ..Default::default()
+                            }),
+                            ValuePart::Expression(Expression {
+                                operator: Operator::QueryContinuation,
+                                vars: vec![VarSpec {
+                                    var_path: "page".to_string(),
+                                    ..Default::default()
+                                }],
+                                required: false,
+                            }),
+                        ],
+                    },
+                )]),
+            }),
+        );
+
+        assert_eq!(
+            URLPathTemplate::parse("/{+a}{b}"),
+            Err("Ambiguous adjacent variable expressions in {+a}{b}".to_string()),
+        );
+        assert_eq!(
+            URLPathTemplate::parse("/{a}{+b}"),
+            Err("Ambiguous adjacent variable expressions in {a}{+b}".to_string()),
+        );
+        assert_eq!(
+            URLPathTemplate::parse("/{a*b}"),
+            Err("Unexpected trailing characters *b in variable expression a*b".to_string()),
+        );
+        assert!(URLPathTemplate::parse("/{?}").is_err());
+        assert!(URLPathTemplate::parse("/{#a,}").is_err());
+
+        for template in [
+            "/files{/path*}{?ids*,limit:2}",
+            "/repos/{owner}/{+path}",
+            "/docs/{page}{#section}",
+            "/search?q={q}{&page,limit}",
+            "/items{;id,color}",
+            "/file/{name}{.ext}",
+            "/{x,y}",
+        ] {
+            let parsed = URLPathTemplate::parse(template).unwrap();
+            assert_eq!(URLPathTemplate::parse(&parsed.to_string()), Ok(parsed));
+        }
+    }
+
+    #[test]
+    fn test_generate_expressions() {
+        // The examples of RFC 6570, within URL paths.
+        let vars = json!({
+            "var": "value",
+            "hello": "Hello World!",
+            "path": "foo/bar",
+            "list": ["red", "green", "blue"],
+            "keys": { "semi": ";", "dot": ".", "comma": "," },
+            "x": 1024,
+            "y": 768,
+            "empty": "",
+        });
+
+        for (template, expected) in [
+            ("/{var}", "/value"),
+            ("/{hello}", "/Hello%20World%21"),
+            ("/{var:3}", "/val"),
+            ("/{+hello}", "/Hello%20World!"),
+            ("/{+path}/here", "/foo/bar/here"),
+            ("/{x,y}", "/1024,768"),
+            ("/{list*}", "/red,green,blue"),
+            ("/{keys*}", "/semi=%3B,dot=.,comma=%2C"),
+            ("/index{#var}", "/index#value"),
+            ("/index{#hello}", "/index#Hello%20World!"),
+            ("/file{.list}", "/file.red,green,blue"),
+            ("/file{.list*}", "/file.red.green.blue"),
+            ("/files{/list*}", "/files/red/green/blue"),
+            ("{/var,x}", "/value/1024"),
+            ("/map{;x,y,empty}", "/map;x=1024;y=768;empty"),
+            ("/map{;list*}", "/map;list=red;list=green;list=blue"),
+            ("/search{?x,y,empty}", "/search?x=1024&y=768&empty="),
+            ("/search{?list}", "/search?list=red,green,blue"),
+            ("/search{?list*}", "/search?list=red&list=green&list=blue"),
+            ("/search{?keys*}", "/search?semi=%3B&dot=.&comma=%2C"),
+            ("/search{?keys}", "/search?keys=semi,%3B,dot,.,comma,%2C"),
+            ("/search{?undef}", "/search"),
+            ("/search?fixed=yes{&x}", "/search?fixed=yes&x=1024"),
+            ("/search{?x}?limit={y}", "/search?x=1024&limit=768"),
+            ("/search{?undef}?limit={y}", "/search?limit=768"),
+        ] {
+            assert_eq!(
+                URLPathTemplate::parse(template)
+                    .unwrap()
+                    .generate_path(&vars),
+                Ok(expected.to_string()),
+                "{}",
+                template,
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_null_values() {
+        let template = URLPathTemplate::parse("/users{?ids*,limit}?q={q}").unwrap();
+        assert_eq!(
+            template.generate_path(&json!({ "ids": null, "limit": 10, "q": null })),
+            Ok("/users?limit=10".to_string()),
+        );
+        assert_eq!(
+            template.generate_path(&json!({ "ids": [1, null, 2], "q": "abc" })),
+            Ok("/users?ids=1&ids=2&q=abc".to_string()),
+        );
+        assert_eq!(
+            template.generate_path(&json!({ "ids": [], "limit": null })),
+            Ok("/users".to_string()),
+        );
+
+        // Expressions in the path are required.
+        for (template, vars) in [
+            ("/files{/path*}", json!({})),
+            ("/files{/path*}", json!({ "path": [] })),
+            ("/files/{+path}", json!({ "path": null })),
+            ("/files/{name}{.ext}", json!({ "name": "a" })),
+            ("/position/{x,y}", json!({ "x": 1 })),
+        ] {
+            let template = URLPathTemplate::parse(template).unwrap();
+            assert_eq!(
+                template.generate_path(&vars),
+                Err(format!(
+                    "Incomplete path parameter {} at position {} with variables {}",
+                    template.path.last().unwrap(),
+                    template.path.len() - 1,
+                    vars,
+                )),
+            );
+        }
+
+        assert_eq!(
+            URLPathTemplate::parse("/users/{id}")
+                .unwrap()
+                .generate_path(&json!({ "id": null })),
+            Err(r#"Missing required variable id in {"id":null}"#.to_string()),
+        );
+    }
+
+    #[test]
+    fn test_percent_encoding() {
+        let template = URLPathTemplate::parse("/users/{id}?q={q}&tags={tag,...}").unwrap();
+        assert_eq!(
+            template.generate_path(&json!({
+                "id": "a/b",
+                "q": "x y&z=é",
+                "tag": ["1,2", "3"],
+            })),
+            Ok("/users/a%2Fb?q=x%20y%26z%3D%C3%A9&tags=1%2C2,3".to_string()),
+        );
+        assert_eq!(
+            template.extract_vars("/users/a%2Fb?q=x%20y%26z%3D%C3%A9&tags=1%2C2,3"),
+            Ok(json!({
+                "id": "a/b",
+                "q": "x y&z=é",
+                "tag": ["1,2", "3"],
+            })),
+        );
+
+        // Reserved expansion keeps reserved characters and existing %XX
+        // triplets, but still encodes other characters.
+        assert_eq!(
+            URLPathTemplate::parse("/{+path}")
+                .unwrap()
+                .generate_path(&json!({ "path": "a/b%20c d?e" })),
+            Ok("/a/b%20c%20d?e".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_expression_round_trips() {
+        for (template, expected_path, vars) in [
+            (
+                "/files{/path*}",
+                "/files/a/b%20c",
+                json!({ "path": ["a", "b c"] }),
+            ),
+            ("{/segments*}", "/a/b", json!({ "segments": ["a", "b"] })),
+            (
+                "/repos/{owner}/{+path}",
+                "/repos/apollo/docs/intro.md",
+                json!({ "owner": "apollo", "path": "docs/intro.md" }),
+            ),
+            (
+                "/docs/{page}{#section}",
+                "/docs/intro#getting%20started",
+                json!({ "page": "intro", "section": "getting started" }),
+            ),
+            (
+                "/users{?ids*,limit}",
+                "/users?ids=1&ids=2&limit=10",
+                json!({ "ids": ["1", "2"], "limit": "10" }),
+            ),
+            (
+                "/search{?filter*}",
+                "/search?name=a%20b&tag=x",
+                json!({ "filter": { "name": "a b", "tag": "x" } }),
+            ),
+            (
+                "/items{;id,color}",
+                "/items;id=1;color=red",
+                json!({ "id": "1", "color": "red" }),
+            ),
+            (
+                "/search?q={q}{&page,limit}",
+                "/search?q=a%26b&page=2&limit=5",
+                json!({ "q": "a&b", "page": "2", "limit": "5" }),
+            ),
+            (
+                "/file/{name}{.ext}",
+                "/file/report.pdf",
+                json!({ "name": "report", "ext": "pdf" }),
+            ),
+            (
+                "/tags{?tags}",
+                "/tags?tags=a,b%20c",
+                json!({ "tags": ["a", "b c"] }),
+            ),
+            (
+                "/position/{x,y}",
+                "/position/1,2",
+                json!({ "x": "1", "y": "2" }),
+            ),
+        ] {
+            let template = URLPathTemplate::parse(template).unwrap();
+            assert_eq!(template.generate_path(&vars), Ok(expected_path.to_string()));
+            assert_eq!(template.extract_vars(expected_path), Ok(vars));
+        }
+
+        // Undefined variables are absent from the extracted variables.
+        assert_eq!(
+            URLPathTemplate::parse("/users{?ids*,limit}")
+                .unwrap()
+                .extract_vars("/users?limit=5"),
+            Ok(json!({ "limit": "5" })),
+        );
+        assert_eq!(
+            URLPathTemplate::parse("/files{/path*}")
+                .unwrap()
+                .extract_vars("/files"),
+            Ok(json!({})),
+        );
     }
 }
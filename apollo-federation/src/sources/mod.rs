@@ -1 +1 @@
-mod connect;
+pub mod connect;
@@ -9,6 +9,7 @@ use fred::interfaces::EventInterface;
 use fred::mocks::Mocks;
 use fred::prelude::ClientLike;
 use fred::prelude::KeysInterface;
+use fred::prelude::LuaInterface;
 use fred::prelude::RedisClient;
 use fred::prelude::RedisError;
 use fred::prelude::RedisErrorKind;
@@ -40,6 +41,51 @@ const SUPPORTED_REDIS_SCHEMES: [&str; 6] = [
     "rediss-sentinel",
 ];
 
+/// Adds the members in `ARGV[2..]` to the set at `KEYS[1]`, and extends its expiration to
+/// `ARGV[1]` seconds if it is longer than the current one. Running it as a script ensures that the
+/// set never exists without its expiration
+const ADD_TO_SET_SCRIPT: &str = r#"
+for i = 2, #ARGV do
+    redis.call('SADD', KEYS[1], ARGV[i])
+end
+local ttl = tonumber(ARGV[1])
+if ttl > 0 then
+    local current = redis.call('TTL', KEYS[1])
+    if current == -1 or current < ttl then
+        redis.call('EXPIRE', KEYS[1], ttl)
+    end
+end
+"#;
+
+/// Removes the set at `KEYS[1]` and returns its members. Running it as a script ensures that
+/// members added concurrently are either returned or kept in the set
+const TAKE_SET_SCRIPT: &str = r#"
+local members = redis.call('SMEMBERS', KEYS[1])
+redis.call('DEL', KEYS[1])
+return members
+"#;
+
+/// Adds `ARGV[1]` to the counter at `KEYS[1]` and returns its new value. A counter without
+/// expiration expires after `ARGV[2]` seconds, in the same script so that it cannot be left
+/// without expiration
+const INCR_SCRIPT: &str = r#"
+local count = redis.call('INCRBY', KEYS[1], ARGV[1])
+if redis.call('TTL', KEYS[1]) == -1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[2])
+end
+return count
+"#;
+
+/// Converts a TTL to a number of seconds for `EXPIRE`, rounded up so that sub-second TTLs do not
+/// become 0, which would delete the key
+pub(crate) fn ttl_secs(ttl: Duration) -> i64 {
+    let mut secs = ttl.as_secs();
+    if ttl.subsec_nanos() > 0 || secs == 0 {
+        secs += 1;
+    }
+    secs as i64
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub(crate) struct RedisKey<K>(pub(crate) K)
 where
@@ -365,6 +411,18 @@ impl RedisCacheStorage {
         }
     }
 
+    /// Removes the namespace prefix from a key returned by `scan` or `take_set_members`
+    pub(crate) fn strip_namespace(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => key
+                .strip_prefix(namespace.as_str())
+                .and_then(|key| key.strip_prefix(':'))
+                .unwrap_or(key)
+                .to_string(),
+            None => key.to_string(),
+        }
+    }
+
     pub(crate) async fn get<K: KeyType, V: ValueType>(
         &self,
         key: RedisKey<K>,
@@ -562,23 +620,144 @@ impl RedisCacheStorage {
     }
 
     pub(crate) async fn delete<K: KeyType>(&self, keys: Vec<RedisKey<K>>) -> Option<u32> {
+        if self.is_cluster {
+            // like in `get_multiple`, a DEL cannot span multiple hash slots in a cluster, so the
+            // keys are grouped by slot and each group is deleted separately
+            let mut h: HashMap<u16, Vec<String>> = HashMap::new();
+            for key in keys {
+                let key = key.to_string();
+                h.entry(ClusterRouting::hash_key(key.as_bytes()))
+                    .or_default()
+                    .push(key);
+            }
+
+            let results = futures::future::join_all(
+                h.into_values().map(|keys| self.inner.del::<u32, _>(keys)),
+            )
+            .await;
+
+            let mut deleted = 0;
+            let mut failed = false;
+            for result in results {
+                match result {
+                    Ok(count) => deleted += count,
+                    Err(e) => {
+                        if !e.is_not_found() {
+                            tracing::error!(error = %e, "redis del error");
+                        }
+                        failed = true;
+                    }
+                }
+            }
+            (!failed).then_some(deleted)
+        } else {
+            self.inner
+                .del(keys)
+                .await
+                .map_err(|e| {
+                    if !e.is_not_found() {
+                        tracing::error!(error = %e, "redis del error");
+                    }
+                    e
+                })
+                .ok()
+        }
+    }
+
+    /// Adds members to the set stored at `key`. The members are stored with the namespace prefix,
+    /// so they can be used directly as keys to delete.
+    ///
+    /// The set expires after `ttl`, unless it already had a longer expiration
+    pub(crate) async fn add_to_set<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        members: Vec<String>,
+        ttl: Option<Duration>,
+    ) {
+        let key = self.make_key(key);
+        // the first argument is the TTL in seconds, 0 if the set does not expire
+        let ttl = ttl
+            .as_ref()
+            .or(self.ttl.as_ref())
+            .map(|ttl| ttl_secs(*ttl))
+            .unwrap_or_default();
+        let args = std::iter::once(ttl.to_string())
+            .chain(
+                members
+                    .into_iter()
+                    .map(|member| self.make_key(RedisKey(member))),
+            )
+            .collect::<Vec<_>>();
+        if let Err(e) = self
+            .inner
+            .eval::<(), _, _, _>(ADD_TO_SET_SCRIPT, key, args)
+            .await
+        {
+            tracing::error!(error = %e, "redis add to set error");
+        }
+    }
+
+    /// Removes the set stored at `key` and returns its members
+    pub(crate) async fn take_set_members<K: KeyType>(&self, key: RedisKey<K>) -> Vec<String> {
+        let key = self.make_key(key);
         self.inner
-            .del(keys)
+            .eval::<Vec<String>, _, _, _>(TAKE_SET_SCRIPT, key, Vec::<String>::new())
             .await
             .map_err(|e| {
-                if !e.is_not_found() {
-                    tracing::error!(error = %e, "redis del error");
-                }
+                tracing::error!(error = %e, "redis take set members error");
+                e
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adds `amount` to the counter stored at `key` and returns its new value. A new counter
+    /// expires after `ttl`, rounded up to the second
+    pub(crate) async fn incr<K: KeyType>(
+        &self,
+        key: RedisKey<K>,
+        amount: i64,
+        ttl: Duration,
+    ) -> Option<i64> {
+        let key = self.make_key(key);
+        self.inner
+            .eval::<i64, _, _, _>(INCR_SCRIPT, key, vec![amount, ttl_secs(ttl)])
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis incr error");
+                e
+            })
+            .ok()
+    }
+
+    /// Runs a Lua script on `keys`, with the namespace prefix, and `args`
+    pub(crate) async fn eval<K: KeyType, R: FromRedis>(
+        &self,
+        script: &'static str,
+        keys: Vec<RedisKey<K>>,
+        args: Vec<String>,
+    ) -> Option<R> {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.make_key(key))
+            .collect::<Vec<_>>();
+        self.inner
+            .eval::<R, _, _, _>(script, keys, args)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "redis eval error");
                 e
             })
             .ok()
     }
 
+    /// Scans for the keys matching `pattern`, in the namespace of this storage. The returned keys
+    /// contain the namespace prefix, so they can be used directly as keys to delete.
     pub(crate) fn scan(
         &self,
         pattern: String,
         count: Option<u32>,
     ) -> Pin<Box<dyn Stream<Item = Result<ScanResult, RedisError>> + Send>> {
+        let pattern = self.make_key(RedisKey(pattern));
         if self.is_cluster {
             Box::pin(self.inner.scan_cluster(pattern, count, None))
         } else {
@@ -589,10 +768,33 @@ impl RedisCacheStorage {
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
     use std::time::SystemTime;
 
     use url::Url;
 
+    #[test]
+    fn ttl_secs_rounds_up() {
+        assert_eq!(super::ttl_secs(Duration::from_millis(100)), 1);
+        assert_eq!(super::ttl_secs(Duration::ZERO), 1);
+        assert_eq!(super::ttl_secs(Duration::from_secs(2)), 2);
+        assert_eq!(super::ttl_secs(Duration::from_millis(2500)), 3);
+    }
+
+    #[tokio::test]
+    async fn strips_the_namespace_of_keys() {
+        let mut storage = super::RedisCacheStorage::from_mocks(Arc::new(fred::mocks::Echo))
+            .await
+            .unwrap();
+        assert_eq!(storage.strip_namespace("ns:version:1"), "ns:version:1");
+
+        storage.namespace = Some(Arc::new("ns".to_string()));
+        assert_eq!(storage.strip_namespace("ns:version:1"), "version:1");
+        assert_eq!(storage.strip_namespace("version:1"), "version:1");
+        assert_eq!(storage.strip_namespace("nsversion:1"), "nsversion:1");
+    }
+
     #[test]
     fn ensure_invalid_payload_serialization_doesnt_fail() {
         #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
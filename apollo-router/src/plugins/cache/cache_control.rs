@@ -40,8 +40,8 @@ pub(crate) struct CacheControl {
     no_transform: bool,
     #[serde(skip_serializing_if = "is_false", default)]
     immutable: bool,
-    #[serde(skip_serializing_if = "is_false", default)]
-    stale_if_error: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    stale_if_error: Option<u32>,
 }
 
 fn is_false(b: &bool) -> bool {
@@ -72,7 +72,7 @@ impl Default for CacheControl {
             must_understand: false,
             no_transform: false,
             immutable: false,
-            stale_if_error: false,
+            stale_if_error: None,
         }
     }
 }
@@ -134,9 +134,11 @@ impl CacheControl {
                     ("immutable", None) => {
                         result.immutable = true;
                     }
-                    ("stale-if-error", None) => {
-                        result.stale_if_error = true;
+                    ("stale-if-error", Some(v)) => {
+                        result.stale_if_error = Some(v.parse()?);
                     }
+                    // RFC 5861 requires a value, without it the directive has no effect
+                    ("stale-if-error", None) => {}
                     _ => {
                         return Err("invalid Cache-Control header value".into());
                     }
@@ -190,7 +192,7 @@ impl CacheControl {
             prev = true;
         }
         if self.no_cache {
-            write!(&mut s, "{}no_cache", if prev { "," } else { "" },)?;
+            write!(&mut s, "{}no-cache", if prev { "," } else { "" },)?;
             prev = true;
         }
         if self.must_revalidate {
@@ -225,8 +227,13 @@ impl CacheControl {
             write!(&mut s, "{}immutable", if prev { "," } else { "" },)?;
             prev = true;
         }
-        if self.stale_if_error {
-            write!(&mut s, "{}stale-if-error", if prev { "," } else { "" },)?;
+        if let Some(sie) = self.stale_if_error {
+            write!(
+                &mut s,
+                "{}stale-if-error={}",
+                if prev { "," } else { "" },
+                sie
+            )?;
         }
         headers.insert(CACHE_CONTROL, HeaderValue::from_str(&s)?);
 
@@ -239,7 +246,17 @@ impl CacheControl {
         Ok(())
     }
 
-    pub(super) fn no_store() -> Self {
+    /// Returns a copy of this `CacheControl` with its TTL replaced by `ttl`
+    pub(crate) fn with_ttl(&self, ttl: Duration) -> Self {
+        CacheControl {
+            max_age: Some(ttl.as_secs() as u32),
+            s_max_age: None,
+            age: None,
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn no_store() -> Self {
         CacheControl {
             no_store: true,
             ..Default::default()
@@ -299,7 +316,15 @@ impl CacheControl {
             must_understand: self.must_understand || other.must_understand,
             no_transform: self.no_transform || other.no_transform,
             immutable: self.immutable || other.immutable,
-            stale_if_error: self.stale_if_error || other.stale_if_error,
+            stale_if_error: match (self.stale_if_error, other.stale_if_error) {
+                (None, None) => None,
+                (None, Some(ttl)) => Some(other.update_ttl(ttl, now)),
+                (Some(ttl), None) => Some(self.update_ttl(ttl, now)),
+                (Some(ttl1), Some(ttl2)) => Some(std::cmp::min(
+                    self.update_ttl(ttl1, now),
+                    other.update_ttl(ttl2, now),
+                )),
+            },
         }
     }
 
@@ -357,11 +382,39 @@ impl CacheControl {
         let elapsed = self.elapsed();
         let expired = self.ttl().map(|ttl| ttl < elapsed).unwrap_or(false);
 
-        // FIXME: we don't honor stale-while-revalidate yet
-        // !expired || self.stale_while_revalidate
         !expired && !self.no_store
     }
 
+    /// Time in seconds during which the data can still be used after it expired, with
+    /// `stale-while-revalidate` or `stale-if-error`. The data must be stored for that long
+    /// after its TTL
+    pub(crate) fn stale_time(&self) -> u32 {
+        std::cmp::max(
+            self.stale_while_revalidate.unwrap_or(0),
+            self.stale_if_error.unwrap_or(0),
+        )
+    }
+
+    /// Returns true if the data expired, but can still be used while it is refreshed in the background
+    pub(crate) fn can_use_stale_while_revalidate(&self) -> bool {
+        self.can_use_stale_inner(self.stale_while_revalidate, now_epoch_seconds())
+    }
+
+    /// Returns true if the data expired, but can still be used if refreshing it fails
+    pub(crate) fn can_use_stale_if_error(&self) -> bool {
+        self.can_use_stale_inner(self.stale_if_error, now_epoch_seconds())
+    }
+
+    fn can_use_stale_inner(&self, stale_time: Option<u32>, now: u64) -> bool {
+        match (self.ttl(), stale_time) {
+            (Some(ttl), Some(stale_time)) if !self.no_store && !self.must_revalidate => {
+                let elapsed = self.elapsed_inner(now);
+                ttl < elapsed && elapsed <= ttl.saturating_add(stale_time)
+            }
+            _ => false,
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn remaining_time(&self, now: u64) -> Option<u32> {
         self.ttl().map(|ttl| {
@@ -456,4 +509,62 @@ mod tests {
         assert!(merged.private);
         assert!(merged.can_use());
     }
+
+    #[test]
+    fn stale_directives() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("max-age=60,stale-while-revalidate=30,stale-if-error=300"),
+        );
+        let cache_control = CacheControl::new(&headers, None).unwrap();
+        assert_eq!(cache_control.stale_time(), 300);
+
+        let mut headers = HeaderMap::new();
+        cache_control.to_headers(&mut headers).unwrap();
+        assert_eq!(
+            headers.get(CACHE_CONTROL).unwrap(),
+            "max-age=60,stale-while-revalidate=30,stale-if-error=300"
+        );
+    }
+
+    #[test]
+    fn can_use_stale() {
+        let now = now_epoch_seconds();
+
+        let cache_control = CacheControl {
+            created: now - 80,
+            max_age: Some(60),
+            stale_while_revalidate: Some(30),
+            stale_if_error: Some(300),
+            ..Default::default()
+        };
+        assert!(!cache_control.can_use());
+        assert!(cache_control.can_use_stale_inner(cache_control.stale_while_revalidate, now));
+        assert!(cache_control.can_use_stale_inner(cache_control.stale_if_error, now));
+
+        // past the stale-while-revalidate window
+        let cache_control = CacheControl {
+            created: now - 100,
+            ..cache_control
+        };
+        assert!(!cache_control.can_use_stale_inner(cache_control.stale_while_revalidate, now));
+        assert!(cache_control.can_use_stale_inner(cache_control.stale_if_error, now));
+
+        // fresh data is not stale
+        let cache_control = CacheControl {
+            created: now - 10,
+            ..cache_control
+        };
+        assert!(cache_control.can_use());
+        assert!(!cache_control.can_use_stale_inner(cache_control.stale_while_revalidate, now));
+
+        // must-revalidate forbids using stale data
+        let cache_control = CacheControl {
+            created: now - 80,
+            must_revalidate: true,
+            ..cache_control
+        };
+        assert!(!cache_control.can_use_stale_inner(cache_control.stale_if_error, now));
+    }
 }
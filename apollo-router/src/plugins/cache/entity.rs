@@ -7,10 +7,10 @@ use std::time::Duration;
 
 use http::header;
 use http::header::CACHE_CONTROL;
+use multimap::MultiMap;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json_bytes::from_value;
 use serde_json_bytes::ByteString;
 use serde_json_bytes::Value;
 use sha2::Digest;
@@ -24,15 +24,22 @@ use tracing::Instrument;
 use tracing::Level;
 
 use super::cache_control::CacheControl;
+use super::entity_key::EntityKeys;
 use super::invalidation::Invalidation;
-use super::invalidation::InvalidationOrigin;
+use super::invalidation_endpoint::InvalidationEndpointConfig;
+use super::invalidation_endpoint::InvalidationService;
+use super::invalidation_endpoint::SubgraphInvalidationConfig;
 use super::metrics::CacheMetricContextKey;
 use super::metrics::CacheMetricsService;
+use super::root_fields::RootField;
+use super::root_fields::RootQuery;
+use super::storage::EntityStorage;
+use super::storage::SubgraphStorage;
+use super::storage::DEFAULT_IN_MEMORY_MAX_TTL;
 use crate::batching::BatchQuery;
 use crate::cache::redis::RedisCacheStorage;
-use crate::cache::redis::RedisKey;
-use crate::cache::redis::RedisValue;
 use crate::configuration::subgraph::SubgraphConfiguration;
+use crate::configuration::InMemoryCache;
 use crate::configuration::RedisCache;
 use crate::error::FetchError;
 use crate::graphql;
@@ -49,21 +56,30 @@ use crate::services::subgraph;
 use crate::services::supergraph;
 use crate::spec::TYPENAME;
 use crate::Context;
+use crate::Endpoint;
+use crate::ListenAddr;
 
 pub(crate) const ENTITIES: &str = "_entities";
 pub(crate) const REPRESENTATIONS: &str = "representations";
 pub(crate) const CONTEXT_CACHE_KEY: &str = "apollo_entity_cache::key";
+/// Response header containing the space separated cache tags of a subgraph response
+pub(crate) const SURROGATE_KEY: &str = "surrogate-key";
+/// Key of the subgraph response extension containing the list of cache tags of the response
+pub(crate) const CACHE_TAGS_EXTENSION: &str = "cacheTags";
 
 register_plugin!("apollo", "preview_entity_cache", EntityCache);
 
 #[derive(Clone)]
 pub(crate) struct EntityCache {
-    storage: Option<RedisCacheStorage>,
+    storage: EntityStorage,
     subgraphs: Arc<SubgraphConfiguration<Subgraph>>,
     entity_type: Option<String>,
     enabled: bool,
     metrics: Metrics,
     private_queries: Arc<RwLock<HashSet<String>>>,
+    revalidating: Revalidating,
+    endpoint_config: Option<Arc<InvalidationEndpointConfig>>,
+    entity_keys: Arc<EntityKeys>,
     pub(crate) invalidation: Invalidation,
 }
 
@@ -71,13 +87,24 @@ pub(crate) struct EntityCache {
 #[derive(Clone, Debug, JsonSchema, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub(crate) struct Config {
-    redis: RedisCache,
+    /// Redis configuration. If it is not set, the entity cache only uses the in memory caches
+    redis: Option<RedisCache>,
     /// Enable or disable the entity caching feature
     #[serde(default)]
     enabled: bool,
 
     subgraph: SubgraphConfiguration<Subgraph>,
 
+    /// Invalidation endpoint configuration
+    invalidation: Option<InvalidationEndpointConfig>,
+
+    /// Maximum time an entry is kept in the in memory caches when Redis is configured (default: 5s).
+    /// Invalidations only remove entries from the in memory caches of the router receiving them, so
+    /// the other routers can serve invalidated entries for this long
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "Option<String>", default)]
+    in_memory_max_ttl: Option<Duration>,
+
     /// Entity caching evaluation metrics
     #[serde(default)]
     metrics: Metrics,
@@ -95,6 +122,40 @@ pub(crate) struct Subgraph {
 
     /// Context key used to separate cache sections per user
     pub(crate) private_id: Option<String>,
+
+    /// Invalidation configuration
+    pub(crate) invalidation: Option<SubgraphInvalidationConfig>,
+
+    /// In memory cache configuration for this subgraph. The in memory cache is used in front of Redis,
+    /// or alone if Redis is not configured
+    pub(crate) in_memory: Option<InMemoryCache>,
+
+    /// Root fields caching configuration
+    pub(crate) root_fields: Option<RootFields>,
+}
+
+/// Configuration for caching the root fields of queries separately
+#[derive(Clone, Debug, Default, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) struct RootFields {
+    /// Cache each root field of the queries sent to this subgraph separately, instead of whole responses
+    #[serde(default)]
+    pub(crate) enabled: bool,
+
+    /// Per field configuration, by root field name
+    #[serde(default)]
+    pub(crate) fields: HashMap<String, RootFieldConfig>,
+}
+
+/// Configuration for caching a root field
+#[derive(Clone, Debug, Default, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) struct RootFieldConfig {
+    /// expiration for this field, overrides the `Cache-Control` header in subgraph responses
+    pub(crate) ttl: Option<Ttl>,
+
+    /// activates caching for this field (default: true)
+    pub(crate) enabled: Option<bool>,
 }
 
 /// Per subgraph configuration for entity caching
@@ -145,26 +206,36 @@ impl Plugin for EntityCache {
             .query
             .as_ref()
             .map(|q| q.name.to_string());
-        let required_to_start = init.config.redis.required_to_start;
-        // we need to explicitely disable TTL reset because it is managed directly by this plugin
-        let mut redis_config = init.config.redis.clone();
-        redis_config.reset_ttl = false;
-        let storage = match RedisCacheStorage::new(redis_config).await {
-            Ok(storage) => Some(storage),
-            Err(e) => {
-                tracing::error!(
-                    cache = "entity",
-                    e,
-                    "could not open connection to Redis for caching",
-                );
-                if required_to_start {
-                    return Err(e);
+        let redis = match init.config.redis.clone() {
+            Some(mut redis_config) => {
+                let required_to_start = redis_config.required_to_start;
+                // we need to explicitely disable TTL reset because it is managed directly by this plugin
+                redis_config.reset_ttl = false;
+                match RedisCacheStorage::new(redis_config).await {
+                    Ok(storage) => Some(storage),
+                    Err(e) => {
+                        tracing::error!(
+                            cache = "entity",
+                            e,
+                            "could not open connection to Redis for caching",
+                        );
+                        if required_to_start {
+                            return Err(e);
+                        }
+                        None
+                    }
                 }
-                None
             }
+            None => None,
         };
 
-        if init.config.redis.ttl.is_none()
+        if init
+            .config
+            .redis
+            .as_ref()
+            .and_then(|redis| redis.ttl)
+            .is_none()
+            && init.config.subgraph.all.ttl.is_none()
             && init
                 .config
                 .subgraph
@@ -177,7 +248,14 @@ impl Plugin for EntityCache {
                 .into());
         }
 
-        let invalidation = Invalidation::new(storage.clone()).await?;
+        let storage = EntityStorage::new(
+            redis,
+            init.config
+                .in_memory_max_ttl
+                .unwrap_or(DEFAULT_IN_MEMORY_MAX_TTL),
+        );
+        let entity_keys = Arc::new(EntityKeys::from_supergraph(&init.supergraph_schema));
+        let invalidation = Invalidation::new(storage.clone(), entity_keys.clone());
 
         Ok(Self {
             storage,
@@ -186,6 +264,9 @@ impl Plugin for EntityCache {
             subgraphs: Arc::new(init.config.subgraph),
             metrics: init.config.metrics,
             private_queries: Arc::new(RwLock::new(HashSet::new())),
+            revalidating: Default::default(),
+            endpoint_config: init.config.invalidation.map(Arc::new),
+            entity_keys,
             invalidation,
         })
     }
@@ -207,12 +288,61 @@ impl Plugin for EntityCache {
             .boxed()
     }
 
-    fn subgraph_service(
-        &self,
-        name: &str,
-        mut service: subgraph::BoxService,
-    ) -> subgraph::BoxService {
-        let storage = match self.storage.clone() {
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let invalidation = self.invalidation.clone();
+        let subgraph_name = name.to_string();
+        let allow_cross_subgraph = self
+            .subgraphs
+            .get(name)
+            .invalidation
+            .as_ref()
+            .is_some_and(|config| config.allow_cross_subgraph);
+        ServiceBuilder::new()
+            // subgraphs can request invalidations in their response extensions, whether their
+            // responses are cached or not, so this is handled outside of the cache service
+            .map_response(move |mut response: subgraph::Response| {
+                invalidation.handle_response_extensions(
+                    &subgraph_name,
+                    allow_cross_subgraph,
+                    &mut response,
+                );
+                // the cache tags were already consumed if the response was stored, but they
+                // must not leak to the client when caching is disabled for this request
+                response
+                    .response
+                    .body_mut()
+                    .extensions
+                    .remove(CACHE_TAGS_EXTENSION);
+                response
+            })
+            .service(self.cache_service(name, service))
+            .boxed()
+    }
+
+    fn web_endpoints(&self) -> MultiMap<ListenAddr, Endpoint> {
+        let mut map = MultiMap::new();
+        if self.enabled {
+            if let Some(config) = &self.endpoint_config {
+                let endpoint = Endpoint::from_router_service(
+                    config.path.clone(),
+                    InvalidationService::new(self.subgraphs.clone(), self.invalidation.clone())
+                        .boxed(),
+                );
+                map.insert(config.listen.clone(), endpoint);
+            }
+        }
+        map
+    }
+}
+
+impl EntityCache {
+    fn cache_service(&self, name: &str, mut service: subgraph::BoxService) -> subgraph::BoxService {
+        let subgraph_config = self.subgraphs.get(name);
+        let storage = match self.storage.subgraph(
+            name,
+            subgraph_config.in_memory.as_ref().map(|c| c.limit),
+            subgraph_config.ttl.clone().map(|t| t.0),
+        ) {
             Some(storage) => storage,
             None => {
                 return ServiceBuilder::new()
@@ -246,6 +376,12 @@ impl Plugin for EntityCache {
                 // if the top level `enabled` is true but there is no other configuration, caching is enabled for this plugin
                 .unwrap_or(true);
         let private_id = self.subgraphs.get(name).private_id.clone();
+        let root_fields = self
+            .subgraphs
+            .get(name)
+            .root_fields
+            .clone()
+            .filter(|root_fields| root_fields.enabled);
 
         let name = name.to_string();
 
@@ -276,10 +412,12 @@ impl Plugin for EntityCache {
                     entity_type: self.entity_type.clone(),
                     name: name.to_string(),
                     storage,
+                    entity_keys: self.entity_keys.clone(),
                     subgraph_ttl,
                     private_queries,
+                    revalidating: self.revalidating.clone(),
                     private_id,
-                    invalidation: self.invalidation.clone(),
+                    root_fields,
                 })));
             tower::util::BoxService::new(inner)
         } else {
@@ -298,9 +436,7 @@ impl Plugin for EntityCache {
                 .boxed()
         }
     }
-}
 
-impl EntityCache {
     #[cfg(test)]
     pub(crate) async fn with_mocks(
         storage: RedisCacheStorage,
@@ -309,9 +445,26 @@ impl EntityCache {
     where
         Self: Sized,
     {
-        let invalidation = Invalidation::new(Some(storage.clone())).await?;
-        Ok(Self {
-            storage: Some(storage),
+        Ok(Self::with_storage(
+            EntityStorage::new(Some(storage), DEFAULT_IN_MEMORY_MAX_TTL),
+            subgraphs,
+        ))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_in_memory(subgraphs: HashMap<String, Subgraph>) -> Self {
+        Self::with_storage(
+            EntityStorage::new(None, DEFAULT_IN_MEMORY_MAX_TTL),
+            subgraphs,
+        )
+    }
+
+    #[cfg(test)]
+    fn with_storage(storage: EntityStorage, subgraphs: HashMap<String, Subgraph>) -> Self {
+        let entity_keys = Arc::new(EntityKeys::default());
+        let invalidation = Invalidation::new(storage.clone(), entity_keys.clone());
+        Self {
+            storage,
             entity_type: None,
             enabled: true,
             subgraphs: Arc::new(SubgraphConfiguration {
@@ -320,8 +473,23 @@ impl EntityCache {
             }),
             metrics: Metrics::default(),
             private_queries: Default::default(),
+            revalidating: Default::default(),
+            endpoint_config: None,
+            entity_keys,
             invalidation,
-        })
+        }
+    }
+
+    /// Reads the entity keys from a supergraph, as `new` does
+    #[cfg(test)]
+    pub(crate) fn with_supergraph(mut self, supergraph: &str) -> Self {
+        let schema = apollo_compiler::Schema::parse_and_validate(supergraph, "supergraph.graphql")
+            .unwrap_or_else(|invalid| {
+                apollo_compiler::validation::Valid::assume_valid(invalid.partial)
+            });
+        self.entity_keys = Arc::new(EntityKeys::from_supergraph(&schema));
+        self.invalidation = Invalidation::new(self.storage.clone(), self.entity_keys.clone());
+        self
     }
 }
 
@@ -330,11 +498,13 @@ struct InnerCacheService {
     service: subgraph::BoxService,
     name: String,
     entity_type: Option<String>,
-    storage: RedisCacheStorage,
+    storage: SubgraphStorage,
+    entity_keys: Arc<EntityKeys>,
     subgraph_ttl: Option<Duration>,
     private_queries: Arc<RwLock<HashSet<String>>>,
+    revalidating: Revalidating,
     private_id: Option<String>,
-    invalidation: Invalidation,
+    root_fields: Option<RootFields>,
 }
 
 impl Service<subgraph::Request> for CacheService {
@@ -398,6 +568,12 @@ impl InnerCacheService {
             .contains_key(REPRESENTATIONS)
         {
             if request.operation_kind == OperationKind::Query {
+                if let Some(root_query) = self.root_query(&request) {
+                    return self
+                        .call_root_fields(request, root_query, query, is_known_private, private_id)
+                        .await;
+                }
+
                 let mut cache_hit: HashMap<String, CacheHitMiss> = HashMap::new();
                 match cache_lookup_root(
                     self.name.clone(),
@@ -430,10 +606,11 @@ impl InnerCacheService {
                         );
 
                         let mut response = self.service.call(request).await?;
+                        let tags = cache_tags(&mut response);
 
                         let cache_control =
                             if response.response.headers().contains_key(CACHE_CONTROL) {
-                                CacheControl::new(response.response.headers(), self.storage.ttl)?
+                                CacheControl::new(response.response.headers(), self.storage.ttl())?
                             } else {
                                 let mut c = CacheControl::default();
                                 c.no_store = true;
@@ -454,19 +631,6 @@ impl InnerCacheService {
                             }
                         }
 
-                        if let Some(invalidation_extensions) = response
-                            .response
-                            .body_mut()
-                            .extensions
-                            .remove("invalidation")
-                        {
-                            self.handle_invalidation(
-                                InvalidationOrigin::Extensions,
-                                invalidation_extensions,
-                            )
-                            .await;
-                        }
-
                         if cache_control.should_store() {
                             cache_store_root_from_response(
                                 self.storage,
@@ -474,6 +638,7 @@ impl InnerCacheService {
                                 &response,
                                 cache_control,
                                 root_cache_key,
+                                tags,
                             )
                             .await?;
                         }
@@ -482,26 +647,13 @@ impl InnerCacheService {
                     }
                 }
             } else {
-                let mut response = self.service.call(request).await?;
-                if let Some(invalidation_extensions) = response
-                    .response
-                    .body_mut()
-                    .extensions
-                    .remove("invalidation")
-                {
-                    self.handle_invalidation(
-                        InvalidationOrigin::Extensions,
-                        invalidation_extensions,
-                    )
-                    .await;
-                }
-
-                Ok(response)
+                self.service.call(request).await
             }
         } else {
             match cache_lookup_entities(
                 self.name.clone(),
                 self.storage.clone(),
+                &self.entity_keys,
                 is_known_private,
                 private_id.as_deref(),
                 request,
@@ -509,13 +661,32 @@ impl InnerCacheService {
             .instrument(tracing::info_span!("cache.entity.lookup"))
             .await?
             {
-                ControlFlow::Break(response) => Ok(response),
+                ControlFlow::Break((response, revalidation)) => {
+                    if let Some(revalidation) = revalidation {
+                        self.revalidate(revalidation, is_known_private, private_id);
+                    }
+                    Ok(response)
+                }
                 ControlFlow::Continue((request, cache_result)) => {
-                    let mut response = self.service.call(request).await?;
+                    let context = request.context.clone();
+                    let subgraph_name = request.subgraph_name.clone();
+                    let mut response = match self.service.call(request).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            // the subgraph request failed, for example with a timeout
+                            return match stale_if_error_entities(&self.name, &cache_result.0) {
+                                Some(entities) => {
+                                    stale_if_error_response(entities, subgraph_name, context)
+                                }
+                                None => Err(e),
+                            };
+                        }
+                    };
+                    let tags = cache_tags(&mut response);
 
                     let mut cache_control =
                         if response.response.headers().contains_key(CACHE_CONTROL) {
-                            CacheControl::new(response.response.headers(), self.storage.ttl)?
+                            CacheControl::new(response.response.headers(), self.storage.ttl())?
                         } else {
                             CacheControl::no_store()
                         };
@@ -528,19 +699,6 @@ impl InnerCacheService {
                         self.private_queries.write().await.insert(query.to_string());
                     }
 
-                    if let Some(invalidation_extensions) = response
-                        .response
-                        .body_mut()
-                        .extensions
-                        .remove("invalidation")
-                    {
-                        self.handle_invalidation(
-                            InvalidationOrigin::Extensions,
-                            invalidation_extensions,
-                        )
-                        .await;
-                    }
-
                     cache_store_entities_from_response(
                         self.storage,
                         self.subgraph_ttl,
@@ -549,6 +707,7 @@ impl InnerCacheService {
                         cache_result.0,
                         is_known_private,
                         private_id,
+                        tags,
                     )
                     .await?;
 
@@ -560,6 +719,340 @@ impl InnerCacheService {
         }
     }
 
+    /// Splits the query in root fields, if root fields caching is enabled for this subgraph
+    fn root_query(&self, request: &subgraph::Request) -> Option<RootQuery> {
+        self.root_fields.as_ref()?;
+        let body = request.subgraph_request.body();
+        RootQuery::parse(
+            body.query.as_deref()?,
+            body.operation_name.as_deref(),
+            &body.variables,
+        )
+    }
+
+    /// Caches each root field of a query separately, and only requests from the subgraph the
+    /// fields that were not found in the cache
+    async fn call_root_fields(
+        mut self,
+        mut request: subgraph::Request,
+        root_query: RootQuery,
+        query: String,
+        is_known_private: bool,
+        private_id: Option<String>,
+    ) -> Result<subgraph::Response, BoxError> {
+        let root_fields = self.root_fields.take().unwrap_or_default();
+        let additional_data_hash = hash_root_field_data(
+            request.subgraph_request.body(),
+            &request.context,
+            &request.authorization,
+        );
+        let keys: Vec<Option<String>> = root_query
+            .fields
+            .iter()
+            .map(|field| {
+                root_fields
+                    .fields
+                    .get(&field.name)
+                    .and_then(|config| config.enabled)
+                    .unwrap_or(true)
+                    .then(|| {
+                        extract_cache_key_root_field(
+                            &self.name,
+                            self.entity_type.as_deref(),
+                            field,
+                            &additional_data_hash,
+                            is_known_private,
+                            private_id.as_deref(),
+                        )
+                    })
+            })
+            .collect();
+
+        let mut found = self
+            .storage
+            .get_multiple(keys.iter().flatten().cloned().collect())
+            .instrument(tracing::info_span!("cache.entity.lookup"))
+            .await
+            .into_iter();
+        let cached: Vec<Option<CacheEntry>> = keys
+            .iter()
+            .map(|key| {
+                key.as_ref()
+                    .and_then(|_| found.next().flatten())
+                    .filter(|entry| entry.control.can_use())
+            })
+            .collect();
+
+        let hit = cached.iter().flatten().count();
+        let mut cache_hit: HashMap<String, CacheHitMiss> = HashMap::new();
+        cache_hit.insert(
+            "Query".to_string(),
+            CacheHitMiss {
+                hit,
+                miss: keys.iter().flatten().count() - hit,
+            },
+        );
+        let _ = request.context.insert(
+            CacheMetricContextKey::new(request.subgraph_name.clone().unwrap_or_default()),
+            CacheSubgraph(cache_hit),
+        );
+
+        let cached_control =
+            cached
+                .iter()
+                .flatten()
+                .fold(None, |merged: Option<CacheControl>, entry| {
+                    Some(match merged {
+                        None => entry.control.clone(),
+                        Some(merged) => merged.merge(&entry.control),
+                    })
+                });
+
+        if cached.iter().all(Option::is_some) {
+            let mut data = Object::default();
+            for (field, entry) in root_query.fields.iter().zip(cached) {
+                if let Some(entry) = entry {
+                    data.insert(field.response_key.as_str(), entry.data);
+                }
+            }
+
+            let mut response = subgraph::Response::builder()
+                .data(data)
+                .extensions(Object::new())
+                .context(request.context)
+                .and_subgraph_name(request.subgraph_name.clone())
+                .build();
+            cached_control
+                .unwrap_or_default()
+                .to_headers(response.response.headers_mut())?;
+
+            return Ok(response);
+        }
+
+        // only request the fields that were not found in the cache
+        if cached_control.is_some() {
+            let (subset, used_variables) = root_query.subset(|index| cached[index].is_none());
+            let body = request.subgraph_request.body_mut();
+            body.query = Some(subset);
+            body.variables
+                .retain(|name, _| used_variables.contains(name.as_str()));
+        }
+
+        let mut response = self.service.call(request).await?;
+        let tags = cache_tags(&mut response);
+
+        let cache_control = if response.response.headers().contains_key(CACHE_CONTROL) {
+            CacheControl::new(response.response.headers(), self.storage.ttl())?
+        } else {
+            CacheControl::no_store()
+        };
+
+        let mut should_store = cache_control.should_store();
+        let mut update_key_private = None;
+        if cache_control.private() && !is_known_private {
+            // we did not know in advance that this was a query with a private scope, so we update the cache keys
+            self.private_queries.write().await.insert(query);
+            match private_id {
+                Some(id) => update_key_private = Some(id),
+                // the response has a private scope but we don't have a way to differentiate users, so we do not store the response in cache
+                None => should_store = false,
+            }
+        }
+
+        let body = response.response.body();
+        // errors without a path cannot be attributed to a field
+        if should_store && body.errors.iter().all(|error| error.path.is_some()) {
+            if let Some(Value::Object(data)) = body.data.as_ref() {
+                let mut to_insert = Vec::new();
+                for ((field, key), entry) in root_query.fields.iter().zip(&keys).zip(&cached) {
+                    let key = match (key, entry) {
+                        (Some(key), None) => key,
+                        _ => continue,
+                    };
+                    let field_path = Path(vec![PathElement::Key(field.response_key.clone(), None)]);
+                    let has_errors = body.errors.iter().any(|error| {
+                        error
+                            .path
+                            .as_ref()
+                            .map(|path| path.starts_with(&field_path))
+                            .unwrap_or(false)
+                    });
+                    let value = match data.get(field.response_key.as_str()) {
+                        Some(value) if !has_errors => value,
+                        _ => continue,
+                    };
+
+                    let field_ttl = root_fields
+                        .fields
+                        .get(&field.name)
+                        .and_then(|config| config.ttl.as_ref())
+                        .map(|ttl| ttl.0);
+                    let (control, ttl) = match field_ttl {
+                        Some(ttl) => (cache_control.with_ttl(ttl), Some(ttl)),
+                        None => (
+                            cache_control.clone(),
+                            cache_control
+                                .ttl()
+                                .map(|secs| Duration::from_secs(secs as u64))
+                                .or(self.subgraph_ttl),
+                        ),
+                    };
+                    // keep the data for the time it can be used stale
+                    let ttl = ttl.map(|ttl| ttl + Duration::from_secs(control.stale_time() as u64));
+                    let key = match update_key_private.as_ref() {
+                        Some(id) => format!("{key}:{id}"),
+                        None => key.clone(),
+                    };
+
+                    to_insert.push((
+                        key,
+                        CacheEntry {
+                            control,
+                            data: value.clone(),
+                            tags: tags.clone(),
+                        },
+                        ttl,
+                    ));
+                }
+
+                if !to_insert.is_empty() {
+                    let storage = self.storage.clone();
+                    let span = tracing::info_span!("cache.entity.store");
+                    tokio::spawn(
+                        async move {
+                            for (key, entry, ttl) in to_insert {
+                                storage.insert(key, entry, ttl).await;
+                            }
+                        }
+                        .instrument(span),
+                    );
+                }
+            }
+        }
+
+        // add the cached fields to the response, in the order of the query
+        if let Some(cached_control) = cached_control {
+            if let Some(Value::Object(data)) = response.response.body_mut().data.as_mut() {
+                let mut new_data = Object::default();
+                for (field, entry) in root_query.fields.iter().zip(cached) {
+                    let value = match entry {
+                        Some(entry) => Some(entry.data),
+                        None => data.remove(field.response_key.as_str()),
+                    };
+                    if let Some(value) = value {
+                        new_data.insert(field.response_key.as_str(), value);
+                    }
+                }
+                *data = new_data;
+            }
+
+            cache_control
+                .merge(&cached_control)
+                .to_headers(response.response.headers_mut())?;
+        }
+
+        Ok(response)
+    }
+
+    /// Refreshes in the background the entities that were served with `stale-while-revalidate`.
+    /// Entities that are already being refreshed by another request are left out
+    fn revalidate(
+        mut self,
+        revalidation: Revalidation,
+        is_known_private: bool,
+        private_id: Option<String>,
+    ) {
+        let Revalidation {
+            mut request,
+            results,
+        } = revalidation;
+        let Some(representations) = request
+            .subgraph_request
+            .body_mut()
+            .variables
+            .get_mut(REPRESENTATIONS)
+            .and_then(|value| value.as_array_mut())
+        else {
+            return;
+        };
+        let (new_representations, results): (Vec<Value>, Vec<IntermediateResult>) = {
+            let mut revalidating = self.revalidating.lock();
+            representations
+                .drain(..)
+                .zip(results)
+                .filter(|(_, result)| revalidating.insert(result.key.clone()))
+                .unzip()
+        };
+        if results.is_empty() {
+            return;
+        }
+        request
+            .subgraph_request
+            .body_mut()
+            .variables
+            .insert(REPRESENTATIONS, new_representations.into());
+        let guard = RevalidationGuard {
+            revalidating: self.revalidating.clone(),
+            keys: results.iter().map(|result| result.key.clone()).collect(),
+        };
+
+        let span = tracing::info_span!("cache.entity.revalidate");
+        tokio::spawn(
+            async move {
+                // the keys can be revalidated again once this task ends
+                let _guard = guard;
+                let mut response = match self.service.call(request).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        tracing::error!(
+                            subgraph = self.name,
+                            error = %e,
+                            message = "could not revalidate stale entities",
+                        );
+                        return;
+                    }
+                };
+                let tags = cache_tags(&mut response);
+
+                let cache_control = if response.response.headers().contains_key(CACHE_CONTROL) {
+                    match CacheControl::new(response.response.headers(), self.storage.ttl()) {
+                        Ok(cache_control) => cache_control,
+                        Err(e) => {
+                            tracing::error!(
+                                subgraph = self.name,
+                                error = %e,
+                                message = "could not parse the Cache-Control header",
+                            );
+                            return;
+                        }
+                    }
+                } else {
+                    CacheControl::no_store()
+                };
+
+                if let Err(e) = cache_store_entities_from_response(
+                    self.storage,
+                    self.subgraph_ttl,
+                    &mut response,
+                    cache_control,
+                    results,
+                    is_known_private,
+                    private_id,
+                    tags,
+                )
+                .await
+                {
+                    tracing::error!(
+                        subgraph = self.name,
+                        error = %e,
+                        message = "could not store revalidated entities",
+                    );
+                }
+            }
+            .instrument(span),
+        );
+    }
+
     fn get_private_id(&self, context: &Context) -> Option<String> {
         self.private_id.as_ref().and_then(|key| {
             context.get_json_value(key).and_then(|value| {
@@ -571,26 +1064,12 @@ impl InnerCacheService {
             })
         })
     }
-
-    async fn handle_invalidation(
-        &mut self,
-        origin: InvalidationOrigin,
-        invalidation_extensions: Value,
-    ) {
-        if let Ok(requests) = from_value(invalidation_extensions) {
-            if let Err(e) = self.invalidation.invalidate(origin, requests).await {
-                tracing::error!(error = %e,
-                   message = "could not invalidate entity cache entries",
-                );
-            }
-        }
-    }
 }
 
 async fn cache_lookup_root(
     name: String,
     entity_type_opt: Option<&str>,
-    cache: RedisCacheStorage,
+    cache: SubgraphStorage,
     is_known_private: bool,
     private_id: Option<&str>,
     mut request: subgraph::Request,
@@ -608,28 +1087,25 @@ async fn cache_lookup_root(
         private_id,
     );
 
-    let cache_result: Option<RedisValue<CacheEntry>> = cache.get(RedisKey(key.clone())).await;
+    let cache_result: Option<CacheEntry> = cache.get(key.clone()).await;
 
     match cache_result {
         Some(value) => {
-            if value.0.control.can_use() {
-                let control = value.0.control.clone();
+            if value.control.can_use() {
+                let control = value.control.clone();
                 request
                     .context
                     .extensions()
-                    .with_lock(|mut lock| lock.insert(control));
+                    .with_lock(|mut lock| lock.insert(control.clone()));
 
                 let mut response = subgraph::Response::builder()
-                    .data(value.0.data)
+                    .data(value.data)
                     .extensions(Object::new())
                     .context(request.context)
                     .and_subgraph_name(request.subgraph_name.clone())
                     .build();
 
-                value
-                    .0
-                    .control
-                    .to_headers(response.response.headers_mut())?;
+                control.to_headers(response.response.headers_mut())?;
                 Ok(ControlFlow::Break(response))
             } else {
                 Ok(ControlFlow::Continue((request, key)))
@@ -641,17 +1117,49 @@ async fn cache_lookup_root(
 
 struct EntityCacheResults(Vec<IntermediateResult>, Option<CacheControl>);
 
+/// Entities served with `stale-while-revalidate`, that must be refreshed in the background
+struct Revalidation {
+    request: subgraph::Request,
+    results: Vec<IntermediateResult>,
+}
+
+/// Cache keys of the entities being refreshed in the background
+type Revalidating = Arc<parking_lot::Mutex<HashSet<String>>>;
+
+/// Removes the keys of a revalidation from the keys being refreshed when it ends
+struct RevalidationGuard {
+    revalidating: Revalidating,
+    keys: Vec<String>,
+}
+
+impl Drop for RevalidationGuard {
+    fn drop(&mut self) {
+        let mut revalidating = self.revalidating.lock();
+        for key in &self.keys {
+            revalidating.remove(key);
+        }
+    }
+}
+
 async fn cache_lookup_entities(
     name: String,
-    cache: RedisCacheStorage,
+    cache: SubgraphStorage,
+    entity_keys: &EntityKeys,
     is_known_private: bool,
     private_id: Option<&str>,
     mut request: subgraph::Request,
-) -> Result<ControlFlow<subgraph::Response, (subgraph::Request, EntityCacheResults)>, BoxError> {
+) -> Result<
+    ControlFlow<
+        (subgraph::Response, Option<Revalidation>),
+        (subgraph::Request, EntityCacheResults),
+    >,
+    BoxError,
+> {
     let body = request.subgraph_request.body_mut();
 
     let keys = extract_cache_keys(
         &name,
+        entity_keys,
         &request.query_hash,
         body,
         &request.context,
@@ -660,25 +1168,7 @@ async fn cache_lookup_entities(
         private_id,
     )?;
 
-    let cache_result: Vec<Option<CacheEntry>> = cache
-        .get_multiple(keys.iter().map(|k| RedisKey(k.clone())).collect::<Vec<_>>())
-        .await
-        .map(|res| {
-            res.into_iter()
-                .map(|r| r.map(|v: RedisValue<CacheEntry>| v.0))
-                .map(|v| match v {
-                    None => None,
-                    Some(v) => {
-                        if v.control.can_use() {
-                            Some(v)
-                        } else {
-                            None
-                        }
-                    }
-                })
-                .collect()
-        })
-        .unwrap_or_else(|| std::iter::repeat(None).take(keys.len()).collect());
+    let cache_result: Vec<Option<CacheEntry>> = cache.get_multiple(keys.clone()).await;
 
     let representations = body
         .variables
@@ -686,7 +1176,7 @@ async fn cache_lookup_entities(
         .and_then(|value| value.as_array_mut())
         .expect("we already checked that representations exist");
     // remove from representations the entities we already obtained from the cache
-    let (new_representations, cache_result, cache_control) =
+    let (new_representations, cache_result, cache_control, stale) =
         filter_representations(&name, representations, keys, cache_result, &request.context)?;
 
     if !new_representations.is_empty() {
@@ -698,6 +1188,20 @@ async fn cache_lookup_entities(
             EntityCacheResults(cache_result, cache_control),
         )))
     } else {
+        let revalidation = if stale.is_empty() {
+            None
+        } else {
+            let (representations, results): (Vec<Value>, Vec<IntermediateResult>) =
+                stale.into_iter().unzip();
+            let mut request = request.clone();
+            request
+                .subgraph_request
+                .body_mut()
+                .variables
+                .insert(REPRESENTATIONS, representations.into());
+            Some(Revalidation { request, results })
+        };
+
         let entities = cache_result
             .into_iter()
             .filter_map(|res| res.cache_entry)
@@ -717,10 +1221,65 @@ async fn cache_lookup_entities(
             .unwrap_or_default()
             .to_headers(response.response.headers_mut())?;
 
-        Ok(ControlFlow::Break(response))
+        Ok(ControlFlow::Break((response, revalidation)))
     }
 }
 
+/// Gets the entities from the cache, using the stale entries allowed by `stale-if-error` for
+/// the ones that were requested from the subgraph. Returns `None` if one of them is missing
+fn stale_if_error_entities(
+    subgraph_name: &str,
+    results: &[IntermediateResult],
+) -> Option<Vec<Value>> {
+    let entities = results
+        .iter()
+        .map(|result| {
+            result
+                .cache_entry
+                .as_ref()
+                .or(result.stale_entry.as_ref())
+                .map(|entry| entry.data.clone())
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    for result in results.iter().filter(|result| result.cache_entry.is_none()) {
+        record_stale_hit(subgraph_name, &result.typename, "error");
+    }
+
+    Some(entities)
+}
+
+fn stale_if_error_response(
+    entities: Vec<Value>,
+    subgraph_name: Option<String>,
+    context: Context,
+) -> Result<subgraph::Response, BoxError> {
+    let mut data = Object::default();
+    data.insert(ENTITIES, entities.into());
+
+    let mut response = subgraph::Response::builder()
+        .data(data)
+        .extensions(Object::new())
+        .and_subgraph_name(subgraph_name)
+        .context(context)
+        .build();
+    // stale data must not be cached further
+    CacheControl::no_store().to_headers(response.response.headers_mut())?;
+
+    Ok(response)
+}
+
+fn record_stale_hit(subgraph_name: &str, typename: &str, reason: &'static str) {
+    u64_counter!(
+        "apollo.router.operations.entity.cache.stale",
+        "Entity cache counter for stale entities used instead of fresh ones",
+        1u64,
+        "subgraph.name" = subgraph_name.to_string(),
+        "entity.type" = typename.to_string(),
+        "reason" = reason
+    );
+}
+
 fn update_cache_control(context: &Context, cache_control: &CacheControl) {
     context.extensions().with_lock(|mut lock| {
         if let Some(c) = lock.get_mut::<CacheControl>() {
@@ -733,23 +1292,29 @@ fn update_cache_control(context: &Context, cache_control: &CacheControl) {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct CacheEntry {
-    control: CacheControl,
-    data: Value,
+pub(crate) struct CacheEntry {
+    pub(crate) control: CacheControl,
+    pub(crate) data: Value,
+    /// Cache tags that can be used to invalidate this entry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tags: Vec<String>,
 }
 
 async fn cache_store_root_from_response(
-    cache: RedisCacheStorage,
+    cache: SubgraphStorage,
     subgraph_ttl: Option<Duration>,
     response: &subgraph::Response,
     cache_control: CacheControl,
     cache_key: String,
+    tags: Vec<String>,
 ) -> Result<(), BoxError> {
     if let Some(data) = response.response.body().data.as_ref() {
+        // keep the data for the time it can be used stale
         let ttl: Option<Duration> = cache_control
             .ttl()
             .map(|secs| Duration::from_secs(secs as u64))
-            .or(subgraph_ttl);
+            .or(subgraph_ttl)
+            .map(|ttl| ttl + Duration::from_secs(cache_control.stale_time() as u64));
 
         if response.response.body().errors.is_empty() && cache_control.should_store() {
             let span = tracing::info_span!("cache.entity.store");
@@ -757,11 +1322,12 @@ async fn cache_store_root_from_response(
             tokio::spawn(async move {
                 cache
                     .insert(
-                        RedisKey(cache_key),
-                        RedisValue(CacheEntry {
+                        cache_key,
+                        CacheEntry {
                             control: cache_control,
                             data,
-                        }),
+                            tags,
+                        },
                         ttl,
                     )
                     .instrument(span)
@@ -773,14 +1339,16 @@ async fn cache_store_root_from_response(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn cache_store_entities_from_response(
-    cache: RedisCacheStorage,
+    cache: SubgraphStorage,
     subgraph_ttl: Option<Duration>,
     response: &mut subgraph::Response,
     cache_control: CacheControl,
     mut result_from_cache: Vec<IntermediateResult>,
     is_known_private: bool,
     private_id: Option<String>,
+    tags: Vec<String>,
 ) -> Result<(), BoxError> {
     let mut data = response.response.body_mut().data.take();
 
@@ -804,6 +1372,7 @@ async fn cache_store_entities_from_response(
                 .ok_or_else(|| FetchError::MalformedResponse {
                     reason: "expected an array of entities".to_string(),
                 })?,
+            response.subgraph_name.as_deref().unwrap_or_default(),
             &response.response.body().errors,
             cache,
             subgraph_ttl,
@@ -811,6 +1380,7 @@ async fn cache_store_entities_from_response(
             &mut result_from_cache,
             update_key_private,
             should_cache_private,
+            tags,
         )
         .await?;
 
@@ -819,11 +1389,59 @@ async fn cache_store_entities_from_response(
             .map(|o| o.insert(ENTITIES, new_entities.into()));
         response.response.body_mut().data = data;
         response.response.body_mut().errors = new_errors;
+    } else {
+        // the subgraph did not return any entity
+        if !response.response.body().errors.is_empty() {
+            let subgraph_name = response.subgraph_name.clone().unwrap_or_default();
+            if let Some(entities) = stale_if_error_entities(&subgraph_name, &result_from_cache) {
+                let mut object = Object::default();
+                object.insert(ENTITIES, entities.into());
+                data = Some(Value::Object(object));
+                response.response.body_mut().errors = Vec::new();
+            }
+        }
+        response.response.body_mut().data = data;
     }
 
     Ok(())
 }
 
+/// Gets the cache tags of a subgraph response, from the `Surrogate-Key` header and the `cacheTags`
+/// extension. The extension is removed from the response.
+pub(crate) fn cache_tags(response: &mut subgraph::Response) -> Vec<String> {
+    let mut tags: Vec<String> = response
+        .response
+        .headers()
+        .get_all(SURROGATE_KEY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split_whitespace())
+        .map(|tag| tag.to_string())
+        .collect();
+
+    if let Some(extension) = response
+        .response
+        .body_mut()
+        .extensions
+        .remove(CACHE_TAGS_EXTENSION)
+    {
+        match serde_json_bytes::from_value::<Vec<String>>(extension) {
+            Ok(extension_tags) => tags.extend(extension_tags),
+            Err(e) => {
+                tracing::error!(
+                    subgraph = response.subgraph_name.as_deref().unwrap_or_default(),
+                    error = %e,
+                    message = "could not deserialize the cache tags from the subgraph response extensions",
+                );
+            }
+        }
+    }
+
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
 pub(crate) fn hash_vary_headers(headers: &http::HeaderMap) -> String {
     let mut digest = Sha256::new();
 
@@ -857,6 +1475,14 @@ pub(crate) fn hash_query(query_hash: &QueryHash, body: &graphql::Request) -> Str
     hex::encode(digest.finalize().as_slice())
 }
 
+// hash the entity key (the key fields of the representation, see `EntityKeys::key`)
+pub(crate) fn hash_entity_key(key: &Value) -> Result<String, serde_json::Error> {
+    // We have to hash the key because it can contains PII
+    let mut digest = Sha256::new();
+    digest.update(serde_json::to_vec(key)?);
+    Ok(hex::encode(digest.finalize().as_slice()))
+}
+
 pub(crate) fn hash_additional_data(
     body: &mut graphql::Request,
     context: &Context,
@@ -872,6 +1498,30 @@ pub(crate) fn hash_additional_data(
         body.variables.insert(repr_key, representations);
     }
 
+    hash_request_data(&mut digest, body, context, cache_key);
+
+    hex::encode(digest.finalize().as_slice())
+}
+
+// the variables used by a root field are already part of its hash, so this only hashes the
+// authorization status and the cache key from the context
+fn hash_root_field_data(
+    body: &graphql::Request,
+    context: &Context,
+    cache_key: &CacheKeyMetadata,
+) -> String {
+    let mut digest = Sha256::new();
+    hash_request_data(&mut digest, body, context, cache_key);
+
+    hex::encode(digest.finalize().as_slice())
+}
+
+fn hash_request_data(
+    digest: &mut Sha256,
+    body: &graphql::Request,
+    context: &Context,
+    cache_key: &CacheKeyMetadata,
+) {
     digest.update(serde_json::to_vec(cache_key).unwrap());
 
     if let Ok(Some(cache_data)) = context.get::<&str, Object>(CONTEXT_CACHE_KEY) {
@@ -886,8 +1536,6 @@ pub(crate) fn hash_additional_data(
             digest.update(serde_json::to_vec(v).unwrap())
         }
     }
-
-    hex::encode(digest.finalize().as_slice())
 }
 
 // build a cache key for the root operation
@@ -928,9 +1576,43 @@ fn extract_cache_key_root(
     key
 }
 
+// build a cache key for a root field
+fn extract_cache_key_root_field(
+    subgraph_name: &str,
+    entity_type_opt: Option<&str>,
+    field: &RootField,
+    additional_data_hash: &str,
+    is_known_private: bool,
+    private_id: Option<&str>,
+) -> String {
+    let entity_type = entity_type_opt.unwrap_or("Query");
+
+    // the cache key is written to easily find keys matching a prefix for deletion:
+    // - subgraph name: subgraph name
+    // - entity type: root query type
+    // - field: field name
+    // - hash: field selection, arguments and variables
+    // - additional data: separate cache entries depending on info like authorization status
+    let mut key = String::new();
+    let _ = write!(
+        &mut key,
+        "subgraph:{subgraph_name}:type:{entity_type}:field:{}:hash:{}:data:{additional_data_hash}",
+        field.name, field.hash
+    );
+
+    if is_known_private {
+        if let Some(id) = private_id {
+            let _ = write!(&mut key, ":{id}");
+        }
+    }
+    key
+}
+
 // build a list of keys to get from the cache in one query
+#[allow(clippy::too_many_arguments)]
 fn extract_cache_keys(
     subgraph_name: &str,
+    entity_keys: &EntityKeys,
     query_hash: &QueryHash,
     body: &mut graphql::Request,
     context: &Context,
@@ -960,10 +1642,8 @@ fn extract_cache_keys(
 
         let typename = opt_type.as_str().unwrap_or("-");
 
-        // We have to hash the representation because it can contains PII
-        let mut digest = Sha256::new();
-        digest.update(serde_json::to_string(&representation).unwrap().as_bytes());
-        let hashed_entity_key = hex::encode(digest.finalize().as_slice());
+        let hashed_entity_key =
+            hash_entity_key(&entity_keys.key(subgraph_name, typename, representation))?;
 
         // the cache key is written to easily find keys matching a prefix for deletion:
         // - subgraph name: caching is done per subgraph
@@ -972,7 +1652,7 @@ fn extract_cache_keys(
         // - query hash: invalidate the entry for a specific query and operation name
         // - additional data: separate cache entries depending on info like authorization status
         let mut key = String::new();
-        let _ = write!(&mut key,  "subgraph:{subgraph_name}:{typename}:{hashed_entity_key}:{query_hash}:{additional_data_hash}");
+        let _ = write!(&mut key,  "subgraph:{subgraph_name}:type:{typename}:entity:{hashed_entity_key}:hash:{query_hash}:data:{additional_data_hash}");
         if is_known_private {
             if let Some(id) = private_id {
                 let _ = write!(&mut key, ":{id}");
@@ -992,6 +1672,8 @@ struct IntermediateResult {
     key: String,
     typename: String,
     cache_entry: Option<CacheEntry>,
+    /// expired entry that can be used with `stale-if-error` if the subgraph request fails
+    stale_entry: Option<CacheEntry>,
 }
 
 // build a new list of representations without the ones we got from the cache
@@ -1002,12 +1684,28 @@ fn filter_representations(
     keys: Vec<String>,
     mut cache_result: Vec<Option<CacheEntry>>,
     context: &Context,
-) -> Result<(Vec<Value>, Vec<IntermediateResult>, Option<CacheControl>), BoxError> {
+) -> Result<
+    (
+        Vec<Value>,
+        Vec<IntermediateResult>,
+        Option<CacheControl>,
+        Vec<(Value, IntermediateResult)>,
+    ),
+    BoxError,
+> {
     let mut new_representations: Vec<Value> = Vec::new();
     let mut result = Vec::new();
+    let mut stale = Vec::new();
     let mut cache_hit: HashMap<String, CacheHitMiss> = HashMap::new();
     let mut cache_control = None;
 
+    // stale entries are only served with `stale-while-revalidate` if it avoids a subgraph request
+    let revalidate = cache_result.iter().all(|entry| {
+        entry.as_ref().is_some_and(|entry| {
+            entry.control.can_use() || entry.control.can_use_stale_while_revalidate()
+        })
+    });
+
     for ((mut representation, key), mut cache_entry) in representations
         .drain(..)
         .zip(keys)
@@ -1022,9 +1720,30 @@ fn filter_representations(
 
         let typename = opt_type.as_str().unwrap_or("-").to_string();
 
-        // do not use that cache entry if it is stale
+        let mut stale_entry = None;
         if let Some(false) = cache_entry.as_ref().map(|c| c.control.can_use()) {
-            cache_entry = None;
+            if revalidate {
+                // serve the stale entry, and refresh it in the background
+                record_stale_hit(subgraph_name, &typename, "revalidate");
+                let mut representation = representation.clone();
+                representation
+                    .as_object_mut()
+                    .map(|o| o.insert(TYPENAME, opt_type.clone()));
+                stale.push((
+                    representation,
+                    IntermediateResult {
+                        key: key.clone(),
+                        typename: typename.clone(),
+                        cache_entry: None,
+                        stale_entry: None,
+                    },
+                ));
+            } else {
+                // do not use that cache entry if it is stale, unless the subgraph request fails
+                stale_entry = cache_entry
+                    .take()
+                    .filter(|c| c.control.can_use_stale_if_error());
+            }
         }
         match cache_entry.as_ref() {
             None => {
@@ -1048,6 +1767,7 @@ fn filter_representations(
             key,
             typename,
             cache_entry,
+            stale_entry,
         });
     }
 
@@ -1056,25 +1776,29 @@ fn filter_representations(
         CacheSubgraph(cache_hit),
     );
 
-    Ok((new_representations, result, cache_control))
+    Ok((new_representations, result, cache_control, stale))
 }
 
 // fill in the entities for the response
 #[allow(clippy::too_many_arguments)]
 async fn insert_entities_in_result(
     entities: &mut Vec<Value>,
+    subgraph_name: &str,
     errors: &[Error],
-    cache: RedisCacheStorage,
+    cache: SubgraphStorage,
     subgraph_ttl: Option<Duration>,
     cache_control: CacheControl,
     result: &mut Vec<IntermediateResult>,
     update_key_private: Option<String>,
     should_cache_private: bool,
+    tags: Vec<String>,
 ) -> Result<(Vec<Value>, Vec<Error>), BoxError> {
+    // keep the data for the time it can be used stale
     let ttl: Option<Duration> = cache_control
         .ttl()
         .map(|secs| Duration::from_secs(secs as u64))
-        .or(subgraph_ttl);
+        .or(subgraph_ttl)
+        .map(|ttl| ttl + Duration::from_secs(cache_control.stale_time() as u64));
 
     let mut new_entities = Vec::new();
     let mut new_errors = Vec::new();
@@ -1091,6 +1815,7 @@ async fn insert_entities_in_result(
             mut key,
             typename,
             cache_entry,
+            stale_entry,
         },
     ) in result.drain(..).enumerate()
     {
@@ -1106,14 +1831,11 @@ async fn insert_entities_in_result(
                             reason: "invalid number of entities".to_string(),
                         })?;
 
-                *inserted_types.entry(typename).or_default() += 1;
-
                 if let Some(ref id) = update_key_private {
                     key = format!("{key}:{id}");
                 }
 
-                let mut has_errors = false;
-                for error in errors.iter().filter(|e| {
+                let entity_errors = errors.iter().filter(|e| {
                     e.path
                         .as_ref()
                         .map(|path| {
@@ -1123,7 +1845,19 @@ async fn insert_entities_in_result(
                             ]))
                         })
                         .unwrap_or(false)
-                }) {
+                });
+                let has_errors = entity_errors.clone().next().is_some();
+
+                if has_errors {
+                    if let Some(stale_entry) = stale_entry {
+                        // replace the entity and its errors with the stale data
+                        record_stale_hit(subgraph_name, &typename, "error");
+                        new_entities.push(stale_entry.data);
+                        continue;
+                    }
+                }
+
+                for error in entity_errors {
                     // update the entity index, because it does not match with the original one
                     let mut e = error.clone();
                     if let Some(path) = e.path.as_mut() {
@@ -1131,16 +1865,18 @@ async fn insert_entities_in_result(
                     }
 
                     new_errors.push(e);
-                    has_errors = true;
                 }
 
+                *inserted_types.entry(typename).or_default() += 1;
+
                 if !has_errors && cache_control.should_store() && should_cache_private {
                     to_insert.push((
-                        RedisKey(key),
-                        RedisValue(CacheEntry {
+                        key,
+                        CacheEntry {
                             control: cache_control.clone(),
                             data: value.clone(),
-                        }),
+                            tags: tags.clone(),
+                        },
                     ));
                 }
 
@@ -1153,10 +1889,7 @@ async fn insert_entities_in_result(
         let span = tracing::info_span!("cache_store");
 
         tokio::spawn(async move {
-            cache
-                .insert_multiple(&to_insert, ttl)
-                .instrument(span)
-                .await;
+            cache.insert_multiple(to_insert, ttl).instrument(span).await;
         });
     }
 
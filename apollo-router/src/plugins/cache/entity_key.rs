@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use apollo_compiler::ast::Value as AstValue;
+use apollo_compiler::executable::Selection;
+use apollo_compiler::executable::SelectionSet;
+use apollo_compiler::schema::ExtendedType;
+use apollo_compiler::validation::Valid;
+use apollo_compiler::Name;
+use apollo_compiler::Parser;
+use apollo_compiler::Schema;
+use serde_json_bytes::ByteString;
+use serde_json_bytes::Map;
+use serde_json_bytes::Value;
+
+const JOIN_TYPE_DIRECTIVE: &str = "join__type";
+const JOIN_GRAPH_DIRECTIVE: &str = "join__graph";
+const JOIN_GRAPH_ENUM: &str = "join__Graph";
+
+/// The `@key` field sets of each entity type, per subgraph.
+///
+/// Entity representations sent to a subgraph contain the fields of one of its keys, but also the
+/// fields required by `@requires`, in the order chosen by the query planner. The cache key of an
+/// entity is computed from its key fields only, sorted by name, so that an invalidation request
+/// can target an entity from its key alone.
+///
+/// An entity is cached under a single key. When a type has several keys, and a representation
+/// contains the fields of more than one of them, the first of those keys in the supergraph is
+/// used, so an invalidation request must use that key to remove the entity.
+#[derive(Debug, Default)]
+pub(crate) struct EntityKeys {
+    keys: HashMap<(String, String), Vec<SelectionSet>>,
+}
+
+impl EntityKeys {
+    /// Reads the keys from the `@join__type` directives of a supergraph
+    pub(crate) fn from_supergraph(schema: &Valid<Schema>) -> Self {
+        let graphs = graph_names(schema);
+        let mut keys: HashMap<(String, String), Vec<SelectionSet>> = HashMap::new();
+
+        for (type_name, ty) in &schema.types {
+            let directives = match ty {
+                ExtendedType::Object(object) => &object.directives,
+                ExtendedType::Interface(interface) => &interface.directives,
+                _ => continue,
+            };
+            for directive in directives.get_all(JOIN_TYPE_DIRECTIVE) {
+                let subgraph = match directive
+                    .argument_by_name("graph")
+                    .map(|graph| graph.as_ref())
+                {
+                    Some(AstValue::Enum(graph)) => match graphs.get(graph) {
+                        Some(subgraph) => subgraph,
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                let Some(key) = directive
+                    .argument_by_name("key")
+                    .and_then(|key| key.as_str())
+                else {
+                    continue;
+                };
+                let field_set = match Parser::new().parse_field_set(
+                    schema,
+                    type_name.clone(),
+                    key,
+                    "",
+                ) {
+                    Ok(field_set) => field_set,
+                    Err(errors) => {
+                        tracing::warn!(
+                                "invalid @key field set on type {type_name} in subgraph {subgraph}, its entities will be cached with their whole representation: {}",
+                                errors.errors
+                            );
+                        continue;
+                    }
+                };
+                keys.entry((subgraph.clone(), type_name.to_string()))
+                    .or_default()
+                    .push(field_set.selection_set);
+            }
+        }
+
+        Self { keys }
+    }
+
+    /// Returns the key of an entity representation (without its `__typename`): the fields of the
+    /// first key of the type that are all present in the representation, with object fields
+    /// sorted by name. If the keys of the type are not known, or if none of them match, the
+    /// whole representation is used, with sorted object fields.
+    pub(crate) fn key(&self, subgraph: &str, type_name: &str, representation: &Value) -> Value {
+        self.keys
+            .get(&(subgraph.to_string(), type_name.to_string()))
+            .and_then(|field_sets| {
+                field_sets
+                    .iter()
+                    .find_map(|field_set| select(field_set, representation))
+            })
+            .unwrap_or_else(|| canonicalize(representation))
+    }
+}
+
+// Selects the fields of the field set in value, sorted by name, or returns None if one of them is
+// missing
+fn select(selection_set: &SelectionSet, value: &Value) -> Option<Value> {
+    match value {
+        Value::Object(object) => {
+            let mut fields = Vec::new();
+            collect_fields(selection_set, &mut fields);
+            fields.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+            let mut selected = Map::new();
+            for (name, selection_set) in fields {
+                let field = object.get(name.as_str())?;
+                let field = if selection_set.selections.is_empty() {
+                    canonicalize(field)
+                } else {
+                    select(selection_set, field)?
+                };
+                selected.insert(ByteString::from(name.as_str()), field);
+            }
+            Some(Value::Object(selected))
+        }
+        Value::Array(array) => array
+            .iter()
+            .map(|element| select(selection_set, element))
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array),
+        _ => None,
+    }
+}
+
+// the fields of inline fragments are part of the same object in the representation
+fn collect_fields<'a>(
+    selection_set: &'a SelectionSet,
+    fields: &mut Vec<(&'a Name, &'a SelectionSet)>,
+) {
+    for selection in &selection_set.selections {
+        match selection {
+            Selection::Field(field) => fields.push((&field.name, &field.selection_set)),
+            Selection::InlineFragment(fragment) => collect_fields(&fragment.selection_set, fields),
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+/// Sorts the fields of the objects in value by name
+fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut fields = object.iter().collect::<Vec<_>>();
+            fields.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+            Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name.clone(), canonicalize(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(array) => Value::Array(array.iter().map(canonicalize).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Subgraph names, keyed by their `join__Graph` enum value
+fn graph_names(schema: &Schema) -> HashMap<Name, String> {
+    let Some(ExtendedType::Enum(graphs)) = schema.types.get(JOIN_GRAPH_ENUM) else {
+        return HashMap::new();
+    };
+    graphs
+        .values
+        .iter()
+        .filter_map(|(value, definition)| {
+            let name = definition
+                .directives
+                .get(JOIN_GRAPH_DIRECTIVE)?
+                .argument_by_name("name")?
+                .as_str()?;
+            Some((value.clone(), name.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        directive @join__type(graph: join__Graph!, key: join__FieldSet) repeatable on OBJECT | INTERFACE
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+        scalar join__FieldSet
+        enum join__Graph {
+            PRODUCTS @join__graph(name: "products", url: "http://localhost:4001")
+            REVIEWS @join__graph(name: "reviews", url: "http://localhost:4002")
+        }
+        type Query {
+            product: Product
+        }
+        type Product
+            @join__type(graph: PRODUCTS, key: "upc")
+            @join__type(graph: REVIEWS, key: "sku organization { id region }")
+            @join__type(graph: REVIEWS, key: "upc") {
+            upc: String
+            sku: String
+            weight: Int
+            organization: Organization
+        }
+        type Organization {
+            id: ID
+            region: String
+        }
+    "#;
+
+    #[test]
+    fn selects_key_fields() {
+        let schema = Schema::parse_and_validate(SCHEMA, "schema.graphql").unwrap();
+        let keys = EntityKeys::from_supergraph(&schema);
+
+        // @requires fields are left out, and fields are sorted
+        assert_eq!(
+            keys.key("products", "Product", &json!({ "weight": 1, "upc": "1" })),
+            json!({ "upc": "1" })
+        );
+        assert_eq!(
+            keys.key(
+                "reviews",
+                "Product",
+                &json!({ "organization": { "region": "eu", "id": "2" }, "weight": 1, "sku": "a" })
+            )
+            .to_string(),
+            json!({ "organization": { "id": "2", "region": "eu" }, "sku": "a" }).to_string()
+        );
+        // the first key whose fields are all present is used
+        assert_eq!(
+            keys.key("reviews", "Product", &json!({ "upc": "1", "sku": "a" })),
+            json!({ "upc": "1" })
+        );
+        // unknown types use the whole representation
+        assert_eq!(
+            keys.key(
+                "products",
+                "Other",
+                &json!({ "b": 1, "a": { "d": 2, "c": 3 } })
+            )
+            .to_string(),
+            json!({ "a": { "c": 3, "d": 2 }, "b": 1 }).to_string()
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_keys() {
+        let schema = Schema::parse_and_validate(
+            SCHEMA.replace(
+                r#"@join__type(graph: PRODUCTS, key: "upc")"#,
+                r#"@join__type(graph: PRODUCTS, key: "upc { id }")"#,
+            ),
+            "schema.graphql",
+        )
+        .unwrap();
+        let keys = EntityKeys::from_supergraph(&schema);
+
+        assert_eq!(
+            keys.key("products", "Product", &json!({ "weight": 1, "upc": "1" }))
+                .to_string(),
+            json!({ "upc": "1", "weight": 1 }).to_string()
+        );
+        assert_eq!(
+            keys.key("reviews", "Product", &json!({ "weight": 1, "upc": "1" })),
+            json!({ "upc": "1" })
+        );
+    }
+}
@@ -1,95 +1,160 @@
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Instant;
 
 use fred::types::Scanner;
-use futures::SinkExt;
 use futures::StreamExt;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json_bytes::from_value;
 use serde_json_bytes::Value;
 use tower::BoxError;
 use tracing::Instrument;
 
-use crate::cache::redis::RedisCacheStorage;
+use super::entity::hash_entity_key;
+use super::entity_key::EntityKeys;
+use super::storage::tag_key;
+use super::storage::EntityStorage;
 use crate::cache::redis::RedisKey;
-use crate::notification::Handle;
-use crate::notification::HandleStream;
-use crate::Notify;
+use crate::services::subgraph;
+
+/// Key of the subgraph response extension used to send invalidation requests
+pub(crate) const INVALIDATION_EXTENSION: &str = "invalidation";
 
 #[derive(Clone)]
 pub(crate) struct Invalidation {
-    enabled: bool,
-    handle: Handle<InvalidationTopic, (InvalidationOrigin, Vec<InvalidationRequest>)>,
+    storage: EntityStorage,
+    entity_keys: Arc<EntityKeys>,
 }
 
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
-pub(crate) struct InvalidationTopic;
-
-#[derive(Clone, Debug)]
-#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
 pub(crate) enum InvalidationOrigin {
     Endpoint,
     Extensions,
 }
 
+impl InvalidationOrigin {
+    fn as_str(&self) -> &'static str {
+        match self {
+            InvalidationOrigin::Endpoint => "endpoint",
+            InvalidationOrigin::Extensions => "extensions",
+        }
+    }
+}
+
 impl Invalidation {
-    pub(crate) async fn new(storage: Option<RedisCacheStorage>) -> Result<Self, BoxError> {
-        let mut notify = Notify::new(None, None, None);
-        let (handle, _b) = notify.create_or_subscribe(InvalidationTopic, false).await?;
-        let enabled = storage.is_some();
-        if let Some(storage) = storage {
-            let h = handle.clone();
-
-            tokio::task::spawn(async move { start(storage, h.into_stream()).await });
+    pub(crate) fn new(storage: EntityStorage, entity_keys: Arc<EntityKeys>) -> Self {
+        Self {
+            storage,
+            entity_keys,
         }
-        Ok(Self { enabled, handle })
     }
 
+    /// Executes a batch of invalidation requests and returns the number of deleted keys
     pub(crate) async fn invalidate(
-        &mut self,
+        &self,
         origin: InvalidationOrigin,
         requests: Vec<InvalidationRequest>,
-    ) -> Result<(), BoxError> {
-        if self.enabled {
-            let mut sink = self.handle.clone().into_sink();
-            sink.send((origin, requests)).await.map_err(|e| e.message)?;
-        }
-
-        Ok(())
-    }
-}
-
-async fn start(
-    storage: RedisCacheStorage,
-    mut handle: HandleStream<InvalidationTopic, (InvalidationOrigin, Vec<InvalidationRequest>)>,
-) {
-    while let Some((origin, requests)) = handle.next().await {
-        let origin = match origin {
-            InvalidationOrigin::Endpoint => "endpoint",
-            InvalidationOrigin::Extensions => "extensions",
-        };
+    ) -> Result<u64, BoxError> {
+        let origin = origin.as_str();
         u64_counter!(
             "apollo.router.operations.entity.invalidation.event",
             "Entity cache received a batch of invalidation requests",
             1u64,
             "origin" = origin
         );
-        handle_request_batch(&storage, origin, requests)
+
+        let count = handle_request_batch(&self.storage, &self.entity_keys, origin, requests)
             .instrument(tracing::info_span!(
                 "cache.invalidation.batch",
                 "origin" = origin
             ))
-            .await
+            .await;
+
+        u64_histogram!(
+            "apollo.router.cache.invalidation.keys",
+            "Number of invalidated keys.",
+            count
+        );
+
+        Ok(count)
+    }
+
+    /// Removes the `invalidation` entry from the subgraph response extensions, and
+    /// executes the invalidation requests it contains in the background. Unless
+    /// `allow_cross_subgraph` is set, only the requests targeting `subgraph_name` are executed
+    pub(crate) fn handle_response_extensions(
+        &self,
+        subgraph_name: &str,
+        allow_cross_subgraph: bool,
+        response: &mut subgraph::Response,
+    ) {
+        let invalidation_extensions = match response
+            .response
+            .body_mut()
+            .extensions
+            .remove(INVALIDATION_EXTENSION)
+        {
+            Some(invalidation_extensions) => invalidation_extensions,
+            None => return,
+        };
+
+        let requests: Vec<InvalidationRequest> = match from_value(invalidation_extensions) {
+            Ok(requests) => requests,
+            Err(e) => {
+                tracing::error!(
+                    subgraph = subgraph_name,
+                    error = %e,
+                    message = "could not deserialize the invalidation requests from the subgraph response extensions",
+                );
+                return;
+            }
+        };
+
+        let requests: Vec<InvalidationRequest> = requests
+            .into_iter()
+            .filter(|request| {
+                let allowed = allow_cross_subgraph || request.subgraph() == Some(subgraph_name);
+                if !allowed {
+                    tracing::warn!(
+                        subgraph = subgraph_name,
+                        request = ?request,
+                        message = "ignoring an invalidation request targeting other subgraphs in the response extensions",
+                    );
+                }
+                allowed
+            })
+            .collect();
+
+        if requests.is_empty() {
+            return;
+        }
+
+        let invalidation = self.clone();
+        // do not delay the subgraph response while the cache entries are deleted
+        tokio::spawn(async move {
+            if let Err(e) = invalidation
+                .invalidate(InvalidationOrigin::Extensions, requests)
+                .await
+            {
+                tracing::error!(error = %e,
+                   message = "could not invalidate entity cache entries",
+                );
+            }
+        });
     }
 }
 
 async fn handle_request_batch(
-    storage: &RedisCacheStorage,
+    storage: &EntityStorage,
+    entity_keys: &EntityKeys,
     origin: &'static str,
     requests: Vec<InvalidationRequest>,
-) {
+) -> u64 {
+    let mut count = 0;
     for request in requests {
         let start = Instant::now();
-        handle_request(storage, origin, &request)
+        count += handle_request(storage, entity_keys, origin, &request)
             .instrument(tracing::info_span!("cache.invalidation.request"))
             .await;
         f64_histogram!(
@@ -98,23 +163,55 @@ async fn handle_request_batch(
             start.elapsed().as_secs_f64()
         );
     }
+    count
 }
 
 async fn handle_request(
-    storage: &RedisCacheStorage,
+    storage: &EntityStorage,
+    entity_keys: &EntityKeys,
     origin: &'static str,
     request: &InvalidationRequest,
-) {
-    let key_prefix = request.key_prefix();
-    let subgraph = request.subgraph();
+) -> u64 {
+    let subgraph = match request.subgraph() {
+        Some(subgraph) => subgraph.to_string(),
+        None => return handle_tag_request(storage, entity_keys, origin, request).await,
+    };
+    let key_prefix = match request.key_prefix(entity_keys) {
+        Ok(key_prefix) => key_prefix,
+        Err(e) => {
+            tracing::error!(error = %e, message = "could not compute the invalidation key");
+            return 0;
+        }
+    };
     tracing::debug!(
         "got invalidation request: {request:?}, will scan for: {}",
         key_prefix
     );
 
+    // a key can be present in both tiers, so we count the distinct deleted keys, without the
+    // Redis namespace prefix
+    let mut deleted_keys: HashSet<String> = storage
+        .remove_in_memory(&subgraph, key_prefix.trim_end_matches('*'))
+        .await
+        .into_iter()
+        .collect();
+    if !deleted_keys.is_empty() {
+        u64_counter!(
+            "apollo.router.operations.entity.invalidation.entry",
+            "Entity cache counter for invalidated entries",
+            1u64,
+            "origin" = origin,
+            "subgraph.name" = subgraph.clone()
+        );
+    }
+
+    let redis = match storage.redis() {
+        Some(redis) => redis,
+        None => return deleted_keys.len() as u64,
+    };
+
     // FIXME: configurable batch size
-    let mut stream = storage.scan(key_prefix.clone(), Some(10));
-    let mut count = 0u64;
+    let mut stream = redis.scan(key_prefix.clone(), Some(10));
 
     while let Some(res) = stream.next().await {
         match res {
@@ -131,12 +228,14 @@ async fn handle_request(
                     let keys = keys
                         .iter()
                         .filter_map(|k| k.as_str())
-                        .map(|k| RedisKey(k.to_string()))
+                        .map(|k| k.to_string())
                         .collect::<Vec<_>>();
                     if !keys.is_empty() {
                         tracing::debug!("deleting keys: {keys:?}");
-                        count += keys.len() as u64;
-                        storage.delete(keys).await;
+                        redis
+                            .delete(keys.iter().cloned().map(RedisKey).collect())
+                            .await;
+                        deleted_keys.extend(keys.iter().map(|key| redis.strip_namespace(key)));
 
                         u64_counter!(
                             "apollo.router.operations.entity.invalidation.entry",
@@ -151,11 +250,53 @@ async fn handle_request(
         }
     }
 
-    u64_histogram!(
-        "apollo.router.cache.invalidation.keys",
-        "Number of invalidated keys.",
-        count
-    );
+    deleted_keys.len() as u64
+}
+
+/// Invalidates the entries covered by a cache tag, in all subgraphs
+async fn handle_tag_request(
+    storage: &EntityStorage,
+    entity_keys: &EntityKeys,
+    origin: &'static str,
+    request: &InvalidationRequest,
+) -> u64 {
+    let tag_key = match request.key_prefix(entity_keys) {
+        Ok(tag_key) => tag_key,
+        Err(e) => {
+            tracing::error!(error = %e, message = "could not compute the invalidation key");
+            return 0;
+        }
+    };
+    tracing::debug!("got invalidation request: {request:?}, will delete keys from: {tag_key}");
+
+    let mut deleted_keys: HashSet<String> = match request {
+        InvalidationRequest::Tag { tag } => storage.remove_tagged_in_memory(tag).await,
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .collect();
+
+    if let Some(redis) = storage.redis() {
+        let keys = redis.take_set_members(RedisKey(tag_key)).await;
+        if !keys.is_empty() {
+            tracing::debug!("deleting keys: {keys:?}");
+            redis
+                .delete(keys.iter().cloned().map(RedisKey).collect())
+                .await;
+            deleted_keys.extend(keys.iter().map(|key| redis.strip_namespace(key)));
+        }
+    }
+
+    if !deleted_keys.is_empty() {
+        u64_counter!(
+            "apollo.router.operations.entity.invalidation.entry",
+            "Entity cache counter for invalidated entries",
+            1u64,
+            "origin" = origin
+        );
+    }
+
+    deleted_keys.len() as u64
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -168,34 +309,49 @@ pub(crate) enum InvalidationRequest {
         subgraph: String,
         r#type: String,
     },
+    /// `key` contains the `@key` fields of the entity in this subgraph, in any order. Other
+    /// fields of the representation, like the fields required by `@requires`, are ignored
     Entity {
         subgraph: String,
         r#type: String,
         key: Value,
     },
+    /// Invalidates the entries stored from subgraph responses carrying this cache tag, in
+    /// the `Surrogate-Key` header or in the `cacheTags` extension
+    Tag {
+        tag: String,
+    },
 }
 
 impl InvalidationRequest {
-    fn key_prefix(&self) -> String {
-        match self {
+    pub(crate) fn key_prefix(&self, entity_keys: &EntityKeys) -> Result<String, serde_json::Error> {
+        Ok(match self {
             InvalidationRequest::Subgraph { subgraph } => {
                 format!("subgraph:{subgraph}*",)
             }
             InvalidationRequest::Type { subgraph, r#type } => {
                 format!("subgraph:{subgraph}:type:{type}*",)
             }
-            _ => {
-                todo!()
+            InvalidationRequest::Entity {
+                subgraph,
+                r#type,
+                key,
+            } => {
+                let entity_key = hash_entity_key(&entity_keys.key(subgraph, r#type, key))?;
+                format!("subgraph:{subgraph}:type:{type}:entity:{entity_key}*")
             }
-        }
+            // tagged keys are not found by prefix, but through a set indexing them
+            InvalidationRequest::Tag { tag } => tag_key(tag),
+        })
     }
 
-    fn subgraph(&self) -> String {
+    /// Subgraph targeted by the request, or `None` if it can target all subgraphs
+    pub(crate) fn subgraph(&self) -> Option<&str> {
         match self {
-            InvalidationRequest::Subgraph { subgraph } => subgraph.clone(),
-            _ => {
-                todo!()
-            }
+            InvalidationRequest::Subgraph { subgraph }
+            | InvalidationRequest::Type { subgraph, .. }
+            | InvalidationRequest::Entity { subgraph, .. } => Some(subgraph),
+            InvalidationRequest::Tag { .. } => None,
         }
     }
 }
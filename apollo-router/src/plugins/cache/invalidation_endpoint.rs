@@ -0,0 +1,303 @@
+use std::sync::Arc;
+use std::task::Poll;
+
+use bytes::Buf;
+use futures::future::BoxFuture;
+use http::header::AUTHORIZATION;
+use http::header::CONTENT_TYPE;
+use http::HeaderValue;
+use http::Method;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tower::BoxError;
+use tower::Service;
+use tracing_futures::Instrument;
+
+use super::entity::Subgraph;
+use super::invalidation::Invalidation;
+use super::invalidation::InvalidationOrigin;
+use super::invalidation::InvalidationRequest;
+use crate::configuration::subgraph::SubgraphConfiguration;
+use crate::services::router;
+use crate::services::router::body::RouterBody;
+use crate::ListenAddr;
+
+/// Configuration of the invalidation endpoint
+#[derive(Clone, Debug, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) struct InvalidationEndpointConfig {
+    /// Path on which the invalidation endpoint listens
+    pub(crate) path: String,
+    /// Listen address of the invalidation endpoint
+    #[serde(default = "default_listen_addr")]
+    pub(crate) listen: ListenAddr,
+}
+
+fn default_listen_addr() -> ListenAddr {
+    ListenAddr::SocketAddr("127.0.0.1:4000".parse().expect("valid ListenAddr"))
+}
+
+/// Per subgraph invalidation configuration
+#[derive(Clone, Debug, Default, JsonSchema, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) struct SubgraphInvalidationConfig {
+    /// Accept invalidation requests targeting this subgraph on the invalidation endpoint
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    /// Key that must be sent in the `Authorization` header of invalidation requests targeting this subgraph
+    #[serde(default)]
+    pub(crate) shared_key: String,
+    /// Accept invalidation requests targeting other subgraphs, or cache tags, in the `invalidation`
+    /// extension of the responses of this subgraph. By default, a subgraph can only invalidate its
+    /// own entries
+    #[serde(default)]
+    pub(crate) allow_cross_subgraph: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct InvalidationResponse {
+    pub(crate) count: u64,
+}
+
+#[derive(Clone)]
+pub(crate) struct InvalidationService {
+    subgraphs: Arc<SubgraphConfiguration<Subgraph>>,
+    invalidation: Invalidation,
+}
+
+impl InvalidationService {
+    pub(crate) fn new(
+        subgraphs: Arc<SubgraphConfiguration<Subgraph>>,
+        invalidation: Invalidation,
+    ) -> Self {
+        Self {
+            subgraphs,
+            invalidation,
+        }
+    }
+}
+
+impl Service<router::Request> for InvalidationService {
+    type Response = router::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: router::Request) -> Self::Future {
+        let subgraphs = self.subgraphs.clone();
+        let invalidation = self.invalidation.clone();
+        Box::pin(
+            async move {
+                let (parts, body) = req.router_request.into_parts();
+                if parts.method != Method::POST {
+                    return error_response(
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "only POST requests are accepted",
+                        req.context,
+                    );
+                }
+
+                let requests = Into::<RouterBody>::into(body)
+                    .to_bytes()
+                    .await
+                    .map_err(|e| format!("failed to get the request body: {e}"))
+                    .and_then(|bytes| {
+                        serde_json::from_reader::<_, Vec<InvalidationRequest>>(bytes.reader())
+                            .map_err(|err| {
+                                format!("failed to deserialize the request body into JSON: {err}")
+                            })
+                    });
+                let requests = match requests {
+                    Ok(requests) => requests,
+                    Err(err) => {
+                        return error_response(StatusCode::BAD_REQUEST, err, req.context);
+                    }
+                };
+
+                let shared_key = parts
+                    .headers
+                    .get(AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default();
+                if !requests
+                    .iter()
+                    .all(|request| is_authorized(&subgraphs, request.subgraph(), shared_key))
+                {
+                    return error_response(
+                        StatusCode::UNAUTHORIZED,
+                        "invalid authorization header",
+                        req.context,
+                    );
+                }
+
+                match invalidation
+                    .invalidate(InvalidationOrigin::Endpoint, requests)
+                    .await
+                {
+                    Ok(count) => Ok(router::Response {
+                        response: http::Response::builder()
+                            .status(StatusCode::ACCEPTED)
+                            .header(
+                                CONTENT_TYPE,
+                                HeaderValue::from_static(mime::APPLICATION_JSON.essence_str()),
+                            )
+                            .body(serde_json::to_string(&InvalidationResponse { count })?.into())
+                            .map_err(BoxError::from)?,
+                        context: req.context,
+                    }),
+                    Err(err) => error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        err.to_string(),
+                        req.context,
+                    ),
+                }
+            }
+            .instrument(tracing::info_span!("invalidation_endpoint")),
+        )
+    }
+}
+
+/// Requests targeting a subgraph use its configuration, while tag requests can affect all
+/// subgraphs, so they use the `all` configuration
+fn is_authorized(
+    subgraphs: &SubgraphConfiguration<Subgraph>,
+    subgraph_name: Option<&str>,
+    shared_key: &str,
+) -> bool {
+    let config = match subgraph_name {
+        Some(subgraph_name) => subgraphs.get(subgraph_name),
+        None => &subgraphs.all,
+    };
+    match config.invalidation.as_ref() {
+        Some(config) if config.enabled && !config.shared_key.is_empty() => {
+            // Hash the keys to sha256 to mitigate timing attacks
+            Sha256::digest(shared_key.as_bytes()) == Sha256::digest(config.shared_key.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+fn error_response(
+    status: StatusCode,
+    message: impl Into<String>,
+    context: crate::Context,
+) -> Result<router::Response, BoxError> {
+    Ok(router::Response {
+        response: http::Response::builder()
+            .status(status)
+            .body(message.into().into())
+            .map_err(BoxError::from)?,
+        context,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugins::cache::storage::EntityStorage;
+    use crate::plugins::cache::storage::DEFAULT_IN_MEMORY_MAX_TTL;
+
+    fn service() -> InvalidationService {
+        let subgraphs = SubgraphConfiguration {
+            all: Subgraph::default(),
+            subgraphs: [(
+                "products".to_string(),
+                Subgraph {
+                    invalidation: Some(SubgraphInvalidationConfig {
+                        enabled: true,
+                        shared_key: "products_key".to_string(),
+                    }),
+                    ..Default::default()
+                },
+            )]
+            .into_iter()
+            .collect::<HashMap<_, _>>(),
+        };
+        InvalidationService::new(
+            Arc::new(subgraphs),
+            Invalidation::new(
+                EntityStorage::new(None, DEFAULT_IN_MEMORY_MAX_TTL),
+                Default::default(),
+            ),
+        )
+    }
+
+    fn request(method: Method, shared_key: &str, body: serde_json::Value) -> router::Request {
+        http::Request::builder()
+            .method(method)
+            .uri("http://localhost:4000/invalidation")
+            .header(AUTHORIZATION, shared_key)
+            .body(router::Body::from(serde_json::to_vec(&body).unwrap()))
+            .unwrap()
+            .into()
+    }
+
+    #[tokio::test]
+    async fn invalidation_endpoint() {
+        let body = serde_json::json!([{
+            "kind": "entity",
+            "subgraph": "products",
+            "type": "Product",
+            "key": { "upc": "1" }
+        }]);
+
+        let response = service()
+            .oneshot(request(Method::POST, "products_key", body.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::ACCEPTED);
+        let bytes = hyper::body::to_bytes(response.response.into_body())
+            .await
+            .unwrap();
+        let response: InvalidationResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(response.count, 0);
+
+        let response = service()
+            .oneshot(request(Method::POST, "wrong_key", body.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = service()
+            .oneshot(request(Method::GET, "products_key", body))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn invalidation_endpoint_subgraph_not_enabled() {
+        // the "reviews" subgraph has no invalidation configuration, so the whole batch is rejected
+        let body = serde_json::json!([
+            { "kind": "subgraph", "subgraph": "products" },
+            { "kind": "subgraph", "subgraph": "reviews" }
+        ]);
+
+        let response = service()
+            .oneshot(request(Method::POST, "products_key", body))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = service()
+            .oneshot(request(
+                Method::POST,
+                "products_key",
+                serde_json::json!({ "kind": "subgraph" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+    }
+}
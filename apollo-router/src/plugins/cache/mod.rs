@@ -1,6 +1,10 @@
 pub(crate) mod cache_control;
 pub(crate) mod entity;
+pub(crate) mod entity_key;
 pub(crate) mod invalidation;
+pub(crate) mod invalidation_endpoint;
 pub(crate) mod metrics;
+pub(crate) mod root_fields;
+pub(crate) mod storage;
 #[cfg(test)]
 pub(crate) mod tests;
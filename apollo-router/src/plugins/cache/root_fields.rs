@@ -0,0 +1,309 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use apollo_compiler::ast;
+use apollo_compiler::Node;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::json_ext::Object;
+
+/// A root query split in its root fields, so that they can be cached separately
+pub(crate) struct RootQuery {
+    document: ast::Document,
+    operation: Node<ast::OperationDefinition>,
+    pub(crate) fields: Vec<RootField>,
+}
+
+/// A field selected at the root of a query
+pub(crate) struct RootField {
+    /// Name of the field
+    pub(crate) name: String,
+    /// Key of the field in the response data: its alias if it has one, or its name
+    pub(crate) response_key: String,
+    /// Hash of the field selection, with its arguments, directives, sub selections and the
+    /// values of the variables it uses
+    pub(crate) hash: String,
+}
+
+impl RootQuery {
+    /// Returns `None` if the query cannot be split: if it is not a valid query, or if its root
+    /// selection set contains fragments or several selections with the same response key
+    pub(crate) fn parse(
+        query: &str,
+        operation_name: Option<&str>,
+        variables: &Object,
+    ) -> Option<Self> {
+        let document = ast::Document::parse(query, "query.graphql").ok()?;
+        let fragments: HashMap<&str, &Node<ast::FragmentDefinition>> = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                ast::Definition::FragmentDefinition(fragment) => {
+                    Some((fragment.name.as_str(), fragment))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut operations = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                ast::Definition::OperationDefinition(operation) => Some(operation),
+                _ => None,
+            })
+            .filter(|operation| match operation_name {
+                Some(name) => operation.name.as_ref().map(|n| n.as_str()) == Some(name),
+                None => true,
+            });
+        let operation = operations.next()?.clone();
+        if operations.next().is_some() || operation.operation_type != ast::OperationType::Query {
+            return None;
+        }
+
+        let mut response_keys = HashSet::new();
+        let mut fields = Vec::new();
+        for selection in &operation.selection_set {
+            let field = match selection {
+                ast::Selection::Field(field) => field,
+                _ => return None,
+            };
+            let response_key = field.alias.as_ref().unwrap_or(&field.name).to_string();
+            if !response_keys.insert(response_key.clone()) {
+                return None;
+            }
+
+            let mut used_fragments = BTreeSet::new();
+            let mut used_variables = BTreeSet::new();
+            collect_selection(
+                selection,
+                &fragments,
+                &mut used_fragments,
+                &mut used_variables,
+            );
+
+            let mut digest = Sha256::new();
+            digest.update(field.to_string().as_bytes());
+            for name in &used_fragments {
+                if let Some(fragment) = fragments.get(name.as_str()) {
+                    digest.update(fragment.to_string().as_bytes());
+                }
+            }
+            for name in &used_variables {
+                let definition = operation
+                    .variables
+                    .iter()
+                    .find(|definition| definition.name.as_str() == name);
+                if let Some(definition) = definition {
+                    digest.update(definition.to_string().as_bytes());
+                }
+                digest.update(name.as_bytes());
+                digest
+                    .update(serde_json::to_vec(&variables.get(name.as_str())).unwrap_or_default());
+            }
+
+            fields.push(RootField {
+                name: field.name.to_string(),
+                response_key,
+                hash: hex::encode(digest.finalize().as_slice()),
+            });
+        }
+
+        Some(Self {
+            document,
+            operation,
+            fields,
+        })
+    }
+
+    /// Generates a query selecting only the root fields for which `keep` returns true, along with
+    /// the fragments and variables they use. Returns the query, and the names of the variables
+    /// that are still used
+    pub(crate) fn subset(&self, keep: impl Fn(usize) -> bool) -> (String, HashSet<String>) {
+        let fragments: HashMap<&str, &Node<ast::FragmentDefinition>> = self
+            .document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                ast::Definition::FragmentDefinition(fragment) => {
+                    Some((fragment.name.as_str(), fragment))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut operation = self.operation.clone();
+        let operation = operation.make_mut();
+        operation.selection_set = operation
+            .selection_set
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| keep(*index))
+            .map(|(_, selection)| selection.clone())
+            .collect();
+
+        let mut used_fragments = BTreeSet::new();
+        let mut used_variables = BTreeSet::new();
+        for selection in &operation.selection_set {
+            collect_selection(
+                selection,
+                &fragments,
+                &mut used_fragments,
+                &mut used_variables,
+            );
+        }
+        for directive in &operation.directives.0 {
+            for argument in &directive.arguments {
+                collect_value(&argument.value, &mut used_variables);
+            }
+        }
+        operation
+            .variables
+            .retain(|definition| used_variables.contains(definition.name.as_str()));
+
+        let mut document = ast::Document::new();
+        document
+            .definitions
+            .push(ast::Definition::OperationDefinition(Node::new(
+                operation.clone(),
+            )));
+        for name in &used_fragments {
+            if let Some(fragment) = fragments.get(name.as_str()) {
+                document
+                    .definitions
+                    .push(ast::Definition::FragmentDefinition((*fragment).clone()));
+            }
+        }
+
+        (
+            document.serialize().no_indent().to_string(),
+            used_variables.into_iter().collect(),
+        )
+    }
+}
+
+fn collect_selection(
+    selection: &ast::Selection,
+    fragments: &HashMap<&str, &Node<ast::FragmentDefinition>>,
+    used_fragments: &mut BTreeSet<String>,
+    used_variables: &mut BTreeSet<String>,
+) {
+    match selection {
+        ast::Selection::Field(field) => {
+            for argument in &field.arguments {
+                collect_value(&argument.value, used_variables);
+            }
+            collect_directives(&field.directives, used_variables);
+            for selection in &field.selection_set {
+                collect_selection(selection, fragments, used_fragments, used_variables);
+            }
+        }
+        ast::Selection::InlineFragment(fragment) => {
+            collect_directives(&fragment.directives, used_variables);
+            for selection in &fragment.selection_set {
+                collect_selection(selection, fragments, used_fragments, used_variables);
+            }
+        }
+        ast::Selection::FragmentSpread(spread) => {
+            collect_directives(&spread.directives, used_variables);
+            // a fragment is only visited once, even if it is recursive
+            if used_fragments.insert(spread.fragment_name.to_string()) {
+                if let Some(fragment) = fragments.get(spread.fragment_name.as_str()) {
+                    collect_directives(&fragment.directives, used_variables);
+                    for selection in &fragment.selection_set {
+                        collect_selection(selection, fragments, used_fragments, used_variables);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn collect_directives(directives: &ast::DirectiveList, used_variables: &mut BTreeSet<String>) {
+    for directive in &directives.0 {
+        for argument in &directive.arguments {
+            collect_value(&argument.value, used_variables);
+        }
+    }
+}
+
+fn collect_value(value: &ast::Value, used_variables: &mut BTreeSet<String>) {
+    match value {
+        ast::Value::Variable(name) => {
+            used_variables.insert(name.to_string());
+        }
+        ast::Value::List(values) => {
+            for value in values {
+                collect_value(value, used_variables);
+            }
+        }
+        ast::Value::Object(fields) => {
+            for (_, value) in fields {
+                collect_value(value, used_variables);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    #[test]
+    fn split_root_fields() {
+        let query = "query($first: Int, $id: ID!, $withName: Boolean!) { topProducts(first: $first) { ...ProductFields } me: user(id: $id) { id name @include(if: $withName) } __typename } fragment ProductFields on Product { upc name }";
+        let variables = json!({ "first": 5, "id": "1", "withName": true });
+        let root_query = RootQuery::parse(query, None, variables.as_object().unwrap()).unwrap();
+
+        let fields = root_query
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.response_key.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                ("topProducts", "topProducts"),
+                ("user", "me"),
+                ("__typename", "__typename")
+            ]
+        );
+
+        // the hash depends on the values of the variables used by the field
+        let other_variables = json!({ "first": 5, "id": "2", "withName": true });
+        let other_query =
+            RootQuery::parse(query, None, other_variables.as_object().unwrap()).unwrap();
+        assert_eq!(root_query.fields[0].hash, other_query.fields[0].hash);
+        assert_ne!(root_query.fields[1].hash, other_query.fields[1].hash);
+
+        let (subset, used_variables) = root_query.subset(|index| index == 0);
+        assert_eq!(
+            subset,
+            "query($first: Int) { topProducts(first: $first) { ...ProductFields } } fragment ProductFields on Product { upc name }"
+        );
+        assert_eq!(used_variables, HashSet::from(["first".to_string()]));
+
+        let (subset, used_variables) = root_query.subset(|index| index == 1);
+        assert_eq!(
+            subset,
+            "query($id: ID!, $withName: Boolean!) { me: user(id: $id) { id name @include(if: $withName) } }"
+        );
+        assert_eq!(used_variables.len(), 2);
+    }
+
+    #[test]
+    fn unsupported_root_queries() {
+        let variables = Object::new();
+        assert!(RootQuery::parse("mutation { a }", None, &variables).is_none());
+        assert!(RootQuery::parse("{ ... on Query { a } }", None, &variables).is_none());
+        assert!(RootQuery::parse("{ a a }", None, &variables).is_none());
+        assert!(RootQuery::parse("{ a", None, &variables).is_none());
+        assert!(RootQuery::parse("query A { a } query B { b }", None, &variables).is_none());
+        assert!(RootQuery::parse("query A { a } query B { b }", Some("B"), &variables).is_some());
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use lru::LruCache;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use super::entity::CacheEntry;
+use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+use crate::cache::redis::RedisValue;
+use crate::cache::storage::InMemoryCache;
+
+#[derive(Clone, Debug)]
+pub(crate) struct InMemoryEntry {
+    entry: CacheEntry,
+    expires_at: Option<Instant>,
+}
+
+impl InMemoryEntry {
+    fn new(entry: CacheEntry, ttl: Option<Duration>) -> Self {
+        Self {
+            entry,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Instant::now())
+            .unwrap_or(false)
+    }
+}
+
+/// Maximum time an entry is kept in the in memory tier when Redis is configured, unless the
+/// configuration overrides it
+pub(crate) const DEFAULT_IN_MEMORY_MAX_TTL: Duration = Duration::from_secs(5);
+
+/// Reverse index from a cache tag to the (subgraph name, key) pairs of the in memory entries it covers
+type InMemoryTags = Arc<parking_lot::Mutex<HashMap<String, HashSet<(String, String)>>>>;
+
+/// Redis key of the set containing the keys covered by a cache tag
+pub(crate) fn tag_key(tag: &str) -> String {
+    format!("tag:{tag}")
+}
+
+/// Adds an in memory entry to the tag index
+fn tag(tags: &InMemoryTags, subgraph_name: &str, key: &str, entry: &CacheEntry) {
+    if entry.tags.is_empty() {
+        return;
+    }
+
+    let mut index = tags.lock();
+    for tag in &entry.tags {
+        index
+            .entry(tag.clone())
+            .or_default()
+            .insert((subgraph_name.to_string(), key.to_string()));
+    }
+}
+
+/// Removes an in memory entry from the tag index, once it is evicted or invalidated
+fn untag(tags: &InMemoryTags, subgraph_name: &str, key: &str, entry: &InMemoryEntry) {
+    if entry.entry.tags.is_empty() {
+        return;
+    }
+
+    let mut index = tags.lock();
+    let tagged = (subgraph_name.to_string(), key.to_string());
+    for tag in &entry.entry.tags {
+        if let Some(keys) = index.get_mut(tag) {
+            keys.remove(&tagged);
+            if keys.is_empty() {
+                index.remove(tag);
+            }
+        }
+    }
+}
+
+/// Storage of the entity cache
+///
+/// It is made of an optional in memory tier, with one LRU cache per subgraph, in front of an
+/// optional Redis tier shared by all subgraphs.
+///
+/// Invalidations only reach the in memory tier of the router instance receiving them, while Redis
+/// is shared by all instances. When Redis is configured, entries are kept in memory for at most
+/// `in_memory_max_ttl`, so that the other instances see invalidations after that delay.
+#[derive(Clone)]
+pub(crate) struct EntityStorage {
+    redis: Option<RedisCacheStorage>,
+    in_memory: Arc<parking_lot::Mutex<HashMap<String, InMemoryCache<String, InMemoryEntry>>>>,
+    in_memory_max_ttl: Duration,
+    tags: InMemoryTags,
+}
+
+impl EntityStorage {
+    pub(crate) fn new(redis: Option<RedisCacheStorage>, in_memory_max_ttl: Duration) -> Self {
+        Self {
+            redis,
+            in_memory: Default::default(),
+            in_memory_max_ttl,
+            tags: Default::default(),
+        }
+    }
+
+    pub(crate) fn redis(&self) -> Option<&RedisCacheStorage> {
+        self.redis.as_ref()
+    }
+
+    /// Returns the storage used by a subgraph, or `None` if there is no tier available for it
+    ///
+    /// The in memory tier of a subgraph is created on the first call, with `limit` entries.
+    pub(crate) fn subgraph(
+        &self,
+        subgraph_name: &str,
+        limit: Option<NonZeroUsize>,
+        subgraph_ttl: Option<Duration>,
+    ) -> Option<SubgraphStorage> {
+        let in_memory = limit.map(|limit| {
+            self.in_memory
+                .lock()
+                .entry(subgraph_name.to_string())
+                .or_insert_with(|| Arc::new(Mutex::new(LruCache::new(limit))))
+                .clone()
+        });
+
+        if in_memory.is_none() && self.redis.is_none() {
+            return None;
+        }
+
+        Some(SubgraphStorage {
+            subgraph_name: subgraph_name.to_string(),
+            redis: self.redis.clone(),
+            in_memory,
+            in_memory_max_ttl: self.redis.as_ref().map(|_| self.in_memory_max_ttl),
+            subgraph_ttl,
+            tags: self.tags.clone(),
+        })
+    }
+
+    /// Removes from the in memory tier of a subgraph all the keys starting with `prefix`
+    ///
+    /// Returns the removed keys
+    pub(crate) async fn remove_in_memory(&self, subgraph_name: &str, prefix: &str) -> Vec<String> {
+        let in_memory = match self.in_memory.lock().get(subgraph_name) {
+            Some(in_memory) => in_memory.clone(),
+            None => return Vec::new(),
+        };
+
+        let mut in_memory = in_memory.lock().await;
+        let keys = in_memory
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            if let Some(entry) = in_memory.pop(key) {
+                untag(&self.tags, subgraph_name, key, &entry);
+            }
+        }
+
+        keys
+    }
+
+    /// Removes from the in memory tiers all the keys covered by a cache tag
+    ///
+    /// Returns the removed keys
+    pub(crate) async fn remove_tagged_in_memory(&self, tag: &str) -> Vec<String> {
+        let tagged = match self.tags.lock().remove(tag) {
+            Some(tagged) => tagged,
+            None => return Vec::new(),
+        };
+
+        let mut keys = Vec::new();
+        for (subgraph_name, key) in tagged {
+            let in_memory = self.in_memory.lock().get(&subgraph_name).cloned();
+            if let Some(in_memory) = in_memory {
+                if let Some(entry) = in_memory.lock().await.pop(&key) {
+                    // the entry can be covered by other tags
+                    untag(&self.tags, &subgraph_name, &key, &entry);
+                    keys.push(key);
+                }
+            }
+        }
+
+        keys
+    }
+}
+
+/// Storage tiers used by the entity cache for one subgraph
+#[derive(Clone)]
+pub(crate) struct SubgraphStorage {
+    subgraph_name: String,
+    redis: Option<RedisCacheStorage>,
+    in_memory: Option<InMemoryCache<String, InMemoryEntry>>,
+    /// Set only when Redis is configured
+    in_memory_max_ttl: Option<Duration>,
+    subgraph_ttl: Option<Duration>,
+    tags: InMemoryTags,
+}
+
+impl SubgraphStorage {
+    /// Default TTL from the Redis configuration
+    pub(crate) fn ttl(&self) -> Option<Duration> {
+        self.redis.as_ref().and_then(|redis| redis.ttl())
+    }
+
+    /// TTL of an entry in the in memory tier, capped by `in_memory_max_ttl`
+    fn in_memory_ttl(&self, ttl: Option<Duration>) -> Option<Duration> {
+        match (ttl, self.in_memory_max_ttl) {
+            (Some(ttl), Some(max_ttl)) => Some(ttl.min(max_ttl)),
+            (ttl, max_ttl) => ttl.or(max_ttl),
+        }
+    }
+
+    pub(crate) async fn get(&self, key: String) -> Option<CacheEntry> {
+        self.get_multiple(vec![key]).await.pop().flatten()
+    }
+
+    /// Looks up the keys in the in memory tier first, then in Redis for the ones that were not
+    /// found. Values obtained from Redis are added to the in memory tier.
+    ///
+    /// The result has the same length and order as `keys`
+    pub(crate) async fn get_multiple(&self, keys: Vec<String>) -> Vec<Option<CacheEntry>> {
+        let mut result: Vec<Option<CacheEntry>> = match self.in_memory.as_ref() {
+            Some(in_memory) => {
+                let mut in_memory = in_memory.lock().await;
+                keys.iter()
+                    .map(|key| match in_memory.get(key) {
+                        Some(value) if value.is_expired() => {
+                            if let Some(entry) = in_memory.pop(key) {
+                                untag(&self.tags, &self.subgraph_name, key, &entry);
+                            }
+                            None
+                        }
+                        Some(value) => Some(value.entry.clone()),
+                        None => None,
+                    })
+                    .collect()
+            }
+            None => std::iter::repeat(None).take(keys.len()).collect(),
+        };
+
+        let redis = match self.redis.as_ref() {
+            Some(redis) => redis,
+            None => return result,
+        };
+
+        let (indexes, missing): (Vec<usize>, Vec<RedisKey<String>>) = keys
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| result[*index].is_none())
+            .map(|(index, key)| (index, RedisKey(key.clone())))
+            .unzip();
+        if missing.is_empty() {
+            return result;
+        }
+
+        let values: Vec<Option<RedisValue<CacheEntry>>> = match redis.get_multiple(missing).await {
+            Some(values) => values,
+            None => return result,
+        };
+
+        let mut in_memory = match self.in_memory.as_ref() {
+            Some(in_memory) => Some(in_memory.lock().await),
+            None => None,
+        };
+        for (index, value) in indexes.into_iter().zip(values) {
+            if let Some(RedisValue(entry)) = value {
+                if let Some(in_memory) = in_memory.as_mut() {
+                    // only keep the entry in memory for the time it has left in Redis
+                    let ttl = self.in_memory_ttl(
+                        entry
+                            .control
+                            .ttl()
+                            .map(|ttl| Duration::from_secs(ttl as u64))
+                            .or(self.subgraph_ttl)
+                            .or_else(|| self.ttl())
+                            .map(|ttl| {
+                                (ttl + Duration::from_secs(entry.control.stale_time() as u64))
+                                    .saturating_sub(Duration::from_secs(
+                                        entry.control.elapsed() as u64
+                                    ))
+                            }),
+                    );
+                    let key = &keys[index];
+                    if let Some((key, evicted)) =
+                        in_memory.push(key.clone(), InMemoryEntry::new(entry.clone(), ttl))
+                    {
+                        untag(&self.tags, &self.subgraph_name, &key, &evicted);
+                    }
+                    tag(&self.tags, &self.subgraph_name, key, &entry);
+                }
+                result[index] = Some(entry);
+            }
+        }
+
+        result
+    }
+
+    pub(crate) async fn insert(&self, key: String, value: CacheEntry, ttl: Option<Duration>) {
+        self.insert_multiple(vec![(key, value)], ttl).await
+    }
+
+    /// Inserts entries in all the tiers, and adds them to the index of each of their cache tags
+    pub(crate) async fn insert_multiple(
+        &self,
+        data: Vec<(String, CacheEntry)>,
+        ttl: Option<Duration>,
+    ) {
+        if let Some(in_memory) = self.in_memory.as_ref() {
+            let memory_ttl = self.in_memory_ttl(ttl.or(self.subgraph_ttl).or_else(|| self.ttl()));
+            let mut in_memory = in_memory.lock().await;
+            for (key, value) in &data {
+                let entry = InMemoryEntry::new(value.clone(), memory_ttl);
+                // remove the replaced or evicted entry from the tag index
+                if let Some((key, evicted)) = in_memory.push(key.clone(), entry) {
+                    untag(&self.tags, &self.subgraph_name, &key, &evicted);
+                }
+                tag(&self.tags, &self.subgraph_name, key, value);
+            }
+        }
+
+        if let Some(redis) = self.redis.as_ref() {
+            let mut tagged: HashMap<&str, Vec<String>> = HashMap::new();
+            for (key, value) in &data {
+                for tag in &value.tags {
+                    tagged.entry(tag.as_str()).or_default().push(key.clone());
+                }
+            }
+            for (tag, keys) in tagged {
+                redis.add_to_set(RedisKey(tag_key(tag)), keys, ttl).await;
+            }
+
+            let data = data
+                .into_iter()
+                .map(|(key, value)| (RedisKey(key), RedisValue(value)))
+                .collect::<Vec<_>>();
+            if let [(key, value)] = &data[..] {
+                redis.insert(key.clone(), value.clone(), ttl).await;
+            } else {
+                redis.insert_multiple(&data, ttl).await;
+            }
+        }
+    }
+}
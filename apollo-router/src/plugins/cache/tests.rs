@@ -1,5 +1,9 @@
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
 use fred::error::RedisErrorKind;
@@ -10,12 +14,33 @@ use fred::prelude::RedisValue;
 use http::header::CACHE_CONTROL;
 use http::HeaderValue;
 use parking_lot::Mutex;
+use serde_json_bytes::json;
+use tower::service_fn;
+use tower::BoxError;
 use tower::ServiceExt;
 
+use super::cache_control::CacheControl;
+use super::entity::cache_tags;
+use super::entity::CacheEntry;
 use super::entity::EntityCache;
+use super::entity::RootFields;
+use super::entity::Ttl;
+use super::entity_key::EntityKeys;
+use super::invalidation::Invalidation;
+use super::invalidation::InvalidationOrigin;
+use super::invalidation::InvalidationRequest;
+use super::invalidation_endpoint::SubgraphInvalidationConfig;
+use super::storage::EntityStorage;
+use super::storage::DEFAULT_IN_MEMORY_MAX_TTL;
 use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+use crate::configuration::InMemoryCache;
+use crate::graphql;
 use crate::plugin::test::MockSubgraph;
+use crate::plugin::Plugin;
 use crate::plugins::cache::entity::Subgraph;
+use crate::query_planner::OperationKind;
+use crate::services::subgraph;
 use crate::services::supergraph;
 use crate::Context;
 use crate::MockedSubgraphs;
@@ -110,6 +135,17 @@ impl Mocks for MockStore {
                 }
                 return Ok(RedisValue::Null);
             }
+            "DEL" => {
+                let mut count = 0;
+                for key in &command.args {
+                    if let RedisValue::Bytes(key) = key {
+                        if self.map.lock().remove(key).is_some() {
+                            count += 1;
+                        }
+                    }
+                }
+                return Ok(RedisValue::Integer(count));
+            }
             //FIXME: this is not working because fred's mock never sends the response to SCAN to the client
             /*"SCAN" => {
                 let mut args_it = command.args.iter();
@@ -266,6 +302,102 @@ async fn insert() {
     insta::assert_json_snapshot!(response);
 }
 
+#[tokio::test]
+async fn insert_in_memory() {
+    let query = "query { currentUser { activeOrganization { id creatorUser { __typename id } } } }";
+
+    let subgraphs = MockedSubgraphs([
+        ("user", MockSubgraph::builder().with_json(
+                serde_json::json!{{"query":"{currentUser{activeOrganization{__typename id}}}"}},
+                serde_json::json!{{"data": {"currentUser": { "activeOrganization": {
+                    "__typename": "Organization",
+                    "id": "1"
+                } }}}}
+        ).with_header(CACHE_CONTROL, HeaderValue::from_static("public")).build()),
+        ("orga", MockSubgraph::builder().with_json(
+            serde_json::json!{{
+                "query": "query($representations:[_Any!]!){_entities(representations:$representations){...on Organization{creatorUser{__typename id}}}}",
+            "variables": {
+                "representations": [
+                    {
+                        "id": "1",
+                        "__typename": "Organization",
+                    }
+                ]
+            }}},
+            serde_json::json!{{"data": {
+                "_entities": [{
+                    "creatorUser": {
+                        "__typename": "User",
+                        "id": 2
+                    }
+                }]
+            }}}
+        ).with_header(CACHE_CONTROL, HeaderValue::from_static("public")).build())
+    ].into_iter().collect());
+
+    let in_memory = Subgraph {
+        ttl: Some(Ttl(Duration::from_secs(60))),
+        in_memory: Some(InMemoryCache {
+            limit: NonZeroUsize::new(10).unwrap(),
+        }),
+        ..Default::default()
+    };
+    // no Redis storage, the cached data is only kept in memory
+    let entity_cache = EntityCache::with_in_memory(
+        [
+            ("user".to_string(), in_memory.clone()),
+            ("orga".to_string(), in_memory),
+        ]
+        .into_iter()
+        .collect(),
+    );
+
+    let service = TestHarness::builder()
+        .configuration_json(serde_json::json!({"include_subgraph_errors": { "all": true } }))
+        .unwrap()
+        .schema(SCHEMA)
+        .extra_plugin(entity_cache.clone())
+        .extra_plugin(subgraphs)
+        .build_supergraph()
+        .await
+        .unwrap();
+
+    let request = supergraph::Request::fake_builder()
+        .query(query)
+        .context(Context::new())
+        .build()
+        .unwrap();
+    let mut response = service.oneshot(request).await.unwrap();
+
+    insta::assert_debug_snapshot!(response.response.headers().get(CACHE_CONTROL));
+    let response = response.next_response().await.unwrap();
+
+    insta::assert_json_snapshot!(response);
+
+    // Now testing without any mock subgraphs, all the data should come from the in memory cache
+    let service = TestHarness::builder()
+        .configuration_json(serde_json::json!({"include_subgraph_errors": { "all": true } }))
+        .unwrap()
+        .schema(SCHEMA)
+        .extra_plugin(entity_cache)
+        .build_supergraph()
+        .await
+        .unwrap();
+
+    let request = supergraph::Request::fake_builder()
+        .query(query)
+        .context(Context::new())
+        .build()
+        .unwrap();
+    let mut response = service.oneshot(request).await.unwrap();
+
+    insta::assert_debug_snapshot!(response.response.headers().get(CACHE_CONTROL));
+    let response = response.next_response().await.unwrap();
+
+    insta::assert_json_snapshot!(response);
+}
+
 #[tokio::test]
 async fn no_cache_control() {
     let query = "query { currentUser { activeOrganization { id creatorUser { __typename id } } } }";
@@ -401,6 +533,7 @@ async fn private() {
                 private_id: Some("sub".to_string()),
                 enabled: Some(true),
                 ttl: None,
+                ..Default::default()
             },
         ),
         (
@@ -409,6 +542,7 @@ async fn private() {
                 private_id: Some("sub".to_string()),
                 enabled: Some(true),
                 ttl: None,
+                ..Default::default()
             },
         ),
     ]
@@ -497,6 +631,709 @@ async fn private() {
     insta::assert_json_snapshot!(response);
 }
 
+#[tokio::test]
+async fn entity_invalidation_key_prefix() {
+    let query = "query { currentUser { activeOrganization { id creatorUser { __typename id } } } }";
+
+    let subgraphs = MockedSubgraphs([
+        ("user", MockSubgraph::builder().with_json(
+                serde_json::json!{{"query":"{currentUser{activeOrganization{__typename id}}}"}},
+                serde_json::json!{{"data": {"currentUser": { "activeOrganization": {
+                    "__typename": "Organization",
+                    "id": "1"
+                } }}}}
+        ).with_header(CACHE_CONTROL, HeaderValue::from_static("public")).build()),
+        ("orga", MockSubgraph::builder().with_json(
+            serde_json::json!{{
+                "query": "query($representations:[_Any!]!){_entities(representations:$representations){...on Organization{creatorUser{__typename id}}}}",
+            "variables": {
+                "representations": [
+                    {
+                        "id": "1",
+                        "__typename": "Organization",
+                    }
+                ]
+            }}},
+            serde_json::json!{{"data": {
+                "_entities": [{
+                    "creatorUser": {
+                        "__typename": "User",
+                        "id": 2
+                    }
+                }]
+            }}}
+        ).with_header(CACHE_CONTROL, HeaderValue::from_static("public")).build())
+    ].into_iter().collect());
+
+    let store = MockStore::new();
+    let map = store.map.clone();
+    let redis_cache = RedisCacheStorage::from_mocks(Arc::new(store))
+        .await
+        .unwrap();
+    let entity_cache = EntityCache::with_mocks(redis_cache.clone(), HashMap::new())
+        .await
+        .unwrap();
+
+    let service = TestHarness::builder()
+        .configuration_json(serde_json::json!({"include_subgraph_errors": { "all": true } }))
+        .unwrap()
+        .schema(SCHEMA)
+        .extra_plugin(entity_cache)
+        .extra_plugin(subgraphs)
+        .build_supergraph()
+        .await
+        .unwrap();
+
+    let request = supergraph::Request::fake_builder()
+        .query(query)
+        .context(Context::new())
+        .build()
+        .unwrap();
+    let mut response = service.oneshot(request).await.unwrap();
+    response.next_response().await.unwrap();
+    // let the spawned cache insertion tasks run
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let matching_keys = |request: InvalidationRequest| {
+        let prefix = request.key_prefix(&EntityKeys::default()).unwrap();
+        let prefix = prefix.trim_end_matches('*').to_string();
+        map.lock()
+            .keys()
+            .filter(|key| key.starts_with(prefix.as_bytes()))
+            .count()
+    };
+
+    assert_eq!(
+        matching_keys(InvalidationRequest::Entity {
+            subgraph: "orga".to_string(),
+            r#type: "Organization".to_string(),
+            key: serde_json_bytes::json!({"id": "1"}),
+        }),
+        1
+    );
+    assert_eq!(
+        matching_keys(InvalidationRequest::Entity {
+            subgraph: "orga".to_string(),
+            r#type: "Organization".to_string(),
+            key: serde_json_bytes::json!({"id": "2"}),
+        }),
+        0
+    );
+    assert_eq!(
+        matching_keys(InvalidationRequest::Type {
+            subgraph: "orga".to_string(),
+            r#type: "Organization".to_string(),
+        }),
+        1
+    );
+    assert_eq!(
+        matching_keys(InvalidationRequest::Subgraph {
+            subgraph: "user".to_string(),
+        }),
+        1
+    );
+}
+
+#[tokio::test]
+async fn entity_invalidation_by_key_fields() {
+    let supergraph = r#"
+        directive @join__type(graph: join__Graph!, key: join__FieldSet) repeatable on OBJECT | INTERFACE
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+        scalar join__FieldSet
+        enum join__Graph {
+            ORGA @join__graph(name: "orga", url: "http://localhost:4002/graphql")
+        }
+        type Query {
+            organization: Organization
+        }
+        type Organization @join__type(graph: ORGA, key: "id region") {
+            id: ID
+            region: String
+            name: String
+            slogan: String
+        }
+    "#;
+    let entity_cache = EntityCache::with_in_memory(
+        [(
+            "orga".to_string(),
+            Subgraph {
+                in_memory: Some(InMemoryCache::default()),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+    )
+    .with_supergraph(supergraph);
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let service = {
+        let calls = calls.clone();
+        service_fn(move |request: subgraph::Request| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                Ok::<_, BoxError>(
+                    subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .header(CACHE_CONTROL, "max-age=60")
+                        .data(json!({ "_entities": [{ "slogan": "hello" }] }))
+                        .build()
+                        .unwrap(),
+                )
+            }
+        })
+    };
+    // the representation contains the `name` field required by `slogan`, and the key fields are
+    // not in the order of the `@key` directive
+    let call = || {
+        entity_cache
+            .subgraph_service("orga", service.clone().boxed())
+            .oneshot(
+                subgraph::Request::fake_builder()
+                    .subgraph_name("orga")
+                    .subgraph_request(
+                        http::Request::builder()
+                            .body(
+                                graphql::Request::fake_builder()
+                                    .query("query($representations:[_Any!]!){_entities(representations:$representations){...on Organization{slogan}}}")
+                                    .variables(
+                                        json!({ "representations": [{ "__typename": "Organization", "region": "eu", "name": "Apollo", "id": "1" }] })
+                                            .as_object()
+                                            .unwrap()
+                                            .clone(),
+                                    )
+                                    .build(),
+                            )
+                            .unwrap(),
+                    )
+                    .build(),
+            )
+    };
+
+    call().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    call().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // the invalidation request only contains the key fields, in another order
+    let count = entity_cache
+        .invalidation
+        .invalidate(
+            InvalidationOrigin::Endpoint,
+            vec![InvalidationRequest::Entity {
+                subgraph: "orga".to_string(),
+                r#type: "Organization".to_string(),
+                key: json!({ "id": "1", "region": "eu" }),
+            }],
+        )
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+
+    call().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn invalidation_extensions_are_removed() {
+    let redis_cache = RedisCacheStorage::from_mocks(Arc::new(MockStore::new()))
+        .await
+        .unwrap();
+    let entity_cache = EntityCache::with_mocks(
+        redis_cache,
+        [(
+            "orga".to_string(),
+            Subgraph {
+                enabled: Some(false),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+    )
+    .await
+    .unwrap();
+
+    let call = |subgraph_name: &'static str, invalidation: serde_json::Value| {
+        let service = service_fn(move |request: subgraph::Request| {
+            let invalidation = invalidation.clone();
+            async move {
+                Ok::<_, BoxError>(
+                    subgraph::Response::fake_builder()
+                        .context(request.context)
+                        .subgraph_name(subgraph_name)
+                        .data(serde_json_bytes::json!({"updateUser": { "id": "1" }}))
+                        .extension(
+                            "invalidation",
+                            serde_json_bytes::to_value(invalidation).unwrap(),
+                        )
+                        .extension("other", "value")
+                        .build(),
+                )
+            }
+        });
+        entity_cache
+            .subgraph_service(subgraph_name, service.boxed())
+            .oneshot(
+                subgraph::Request::fake_builder()
+                    .operation_kind(OperationKind::Mutation)
+                    .subgraph_name(subgraph_name)
+                    .build(),
+            )
+    };
+
+    // caching is enabled for the `user` subgraph
+    let response = call(
+        "user",
+        serde_json::json!([{ "kind": "entity", "subgraph": "user", "type": "User", "key": { "id": "1" } }]),
+    )
+    .await
+    .unwrap();
+    let extensions = &response.response.body().extensions;
+    assert!(!extensions.contains_key("invalidation"));
+    assert_eq!(extensions.get("other").unwrap(), "value");
+
+    // caching is disabled for the `orga` subgraph, but it can still request invalidations
+    let response = call(
+        "orga",
+        serde_json::json!([{ "kind": "type", "subgraph": "orga", "type": "Organization" }]),
+    )
+    .await
+    .unwrap();
+    assert!(!response
+        .response
+        .body()
+        .extensions
+        .contains_key("invalidation"));
+
+    // invalid invalidation requests are removed too
+    let response = call("user", serde_json::json!({ "kind": "unknown" }))
+        .await
+        .unwrap();
+    assert!(!response
+        .response
+        .body()
+        .extensions
+        .contains_key("invalidation"));
+}
+
+#[tokio::test]
+async fn cross_subgraph_invalidation_extensions() {
+    let entity_cache = EntityCache::with_in_memory(
+        [
+            (
+                "orga".to_string(),
+                Subgraph {
+                    in_memory: Some(InMemoryCache::default()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "admin".to_string(),
+                Subgraph {
+                    invalidation: Some(SubgraphInvalidationConfig {
+                        allow_cross_subgraph: true,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    );
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let orga = {
+        let calls = calls.clone();
+        service_fn(move |request: subgraph::Request| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                Ok::<_, BoxError>(
+                    subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .header(CACHE_CONTROL, "max-age=60")
+                        .data(json!({ "_entities": [{ "name": "Apollo" }] }))
+                        .build()
+                        .unwrap(),
+                )
+            }
+        })
+    };
+    let call_orga = || {
+        entity_cache
+            .subgraph_service("orga", orga.clone().boxed())
+            .oneshot(entities_request())
+    };
+    let invalidate_orga = |subgraph_name: &'static str| {
+        let service = service_fn(move |request: subgraph::Request| async move {
+            Ok::<_, BoxError>(
+                subgraph::Response::fake_builder()
+                    .context(request.context)
+                    .subgraph_name(subgraph_name)
+                    .data(json!({ "updateOrganization": { "id": "1" } }))
+                    .extension(
+                        "invalidation",
+                        json!([{ "kind": "type", "subgraph": "orga", "type": "Organization" }]),
+                    )
+                    .build(),
+            )
+        });
+        entity_cache
+            .subgraph_service(subgraph_name, service.boxed())
+            .oneshot(
+                subgraph::Request::fake_builder()
+                    .operation_kind(OperationKind::Mutation)
+                    .subgraph_name(subgraph_name)
+                    .build(),
+            )
+    };
+
+    call_orga().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    call_orga().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // by default, a subgraph cannot invalidate the entries of another subgraph
+    invalidate_orga("user").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    call_orga().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // unless it is allowed in its configuration
+    invalidate_orga("admin").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    call_orga().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+fn entities_request() -> subgraph::Request {
+    subgraph::Request::fake_builder()
+        .subgraph_name("orga")
+        .subgraph_request(
+            http::Request::builder()
+                .body(
+                    graphql::Request::fake_builder()
+                        .query("query($representations:[_Any!]!){_entities(representations:$representations){...on Organization{name}}}")
+                        .variables(
+                            json!({ "representations": [{ "__typename": "Organization", "id": "1" }] })
+                                .as_object()
+                                .unwrap()
+                                .clone(),
+                        )
+                        .build(),
+                )
+                .unwrap(),
+        )
+        .build()
+}
+
+fn entity_name(response: &subgraph::Response) -> Option<&str> {
+    response
+        .response
+        .body()
+        .data
+        .as_ref()?
+        .get("_entities")?
+        .get(0)?
+        .get("name")?
+        .as_str()
+}
+
+#[tokio::test]
+async fn stale_while_revalidate() {
+    let entity_cache = EntityCache::with_in_memory(
+        [(
+            "orga".to_string(),
+            Subgraph {
+                in_memory: Some(InMemoryCache::default()),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+    );
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let service = {
+        let calls = calls.clone();
+        service_fn(move |request: subgraph::Request| {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                Ok::<_, BoxError>(
+                    subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .header(CACHE_CONTROL, "max-age=0,stale-while-revalidate=60")
+                        .data(json!({ "_entities": [{ "name": format!("v{call}") }] }))
+                        .build()
+                        .unwrap(),
+                )
+            }
+        })
+    };
+    let call = || {
+        entity_cache
+            .subgraph_service("orga", service.clone().boxed())
+            .oneshot(entities_request())
+    };
+
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v1"));
+    // let the entry expire
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // the stale entry is served at once, then refreshed in the background
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v1"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v2"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn stale_while_revalidate_once() {
+    let entity_cache = EntityCache::with_in_memory(
+        [(
+            "orga".to_string(),
+            Subgraph {
+                in_memory: Some(InMemoryCache::default()),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+    );
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let service = {
+        let calls = calls.clone();
+        service_fn(move |request: subgraph::Request| {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                // the revalidation is slow
+                if call > 1 {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                Ok::<_, BoxError>(
+                    subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .header(CACHE_CONTROL, "max-age=0,stale-while-revalidate=60")
+                        .data(json!({ "_entities": [{ "name": format!("v{call}") }] }))
+                        .build()
+                        .unwrap(),
+                )
+            }
+        })
+    };
+    let call = || {
+        entity_cache
+            .subgraph_service("orga", service.clone().boxed())
+            .oneshot(entities_request())
+    };
+
+    call().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // the entity is refreshed once while the stale entry is served
+    for _ in 0..3 {
+        let response = call().await.unwrap();
+        assert_eq!(entity_name(&response), Some("v1"));
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v2"));
+}
+
+#[tokio::test]
+async fn stale_if_error() {
+    let entity_cache = EntityCache::with_in_memory(
+        [(
+            "orga".to_string(),
+            Subgraph {
+                in_memory: Some(InMemoryCache::default()),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+    );
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let service = {
+        let calls = calls.clone();
+        service_fn(move |request: subgraph::Request| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 => Ok(subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .header(CACHE_CONTROL, "max-age=0,stale-if-error=60")
+                        .data(json!({ "_entities": [{ "name": "v1" }] }))
+                        .build()
+                        .unwrap()),
+                    1 => Err::<subgraph::Response, BoxError>("timeout".into()),
+                    _ => Ok(subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .error(
+                            graphql::Error::builder()
+                                .message("subgraph unavailable")
+                                .extension_code("UNAVAILABLE")
+                                .build(),
+                        )
+                        .build()
+                        .unwrap()),
+                }
+            }
+        })
+    };
+    let call = || {
+        entity_cache
+            .subgraph_service("orga", service.clone().boxed())
+            .oneshot(entities_request())
+    };
+
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v1"));
+    // let the entry expire
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // the subgraph request fails
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v1"));
+
+    // the subgraph returns errors
+    let response = call().await.unwrap();
+    assert_eq!(entity_name(&response), Some("v1"));
+    assert!(response.response.body().errors.is_empty());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn root_fields() {
+    let entity_cache = EntityCache::with_in_memory(
+        [(
+            "orga".to_string(),
+            Subgraph {
+                in_memory: Some(InMemoryCache::default()),
+                root_fields: Some(RootFields {
+                    enabled: true,
+                    fields: Default::default(),
+                }),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect(),
+    );
+
+    let queries = Arc::new(Mutex::new(Vec::new()));
+    let service = {
+        let queries = queries.clone();
+        service_fn(move |request: subgraph::Request| {
+            let body = request.subgraph_request.body();
+            queries.lock().push(body.query.clone().unwrap_or_default());
+            let mut data = serde_json_bytes::Map::new();
+            if body.variables.contains_key("first") {
+                data.insert("orgas", json!([{ "id": "1" }]));
+            }
+            if body.variables.contains_key("id") {
+                data.insert("me", json!({ "name": "test" }));
+            }
+            async move {
+                Ok::<_, BoxError>(
+                    subgraph::Response::fake2_builder()
+                        .context(request.context)
+                        .subgraph_name("orga")
+                        .header(CACHE_CONTROL, "public,max-age=60")
+                        .data(data)
+                        .build()
+                        .unwrap(),
+                )
+            }
+        })
+    };
+    let call = |query: &str, variables: serde_json_bytes::Value| {
+        entity_cache
+            .subgraph_service("orga", service.clone().boxed())
+            .oneshot(
+                subgraph::Request::fake_builder()
+                    .subgraph_name("orga")
+                    .subgraph_request(
+                        http::Request::builder()
+                            .body(
+                                graphql::Request::fake_builder()
+                                    .query(query)
+                                    .variables(variables.as_object().unwrap().clone())
+                                    .build(),
+                            )
+                            .unwrap(),
+                    )
+                    .build(),
+            )
+    };
+
+    let response = call(
+        "query($first: Int) { orgas(first: $first) { id } }",
+        json!({ "first": 1 }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        response.response.body().data,
+        Some(json!({ "orgas": [{ "id": "1" }] }))
+    );
+    // wait for the field to be stored
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // only the field that is not in cache is requested
+    let response = call(
+        "query($id: ID!, $first: Int) { me: user(id: $id) { name } orgas(first: $first) { id } }",
+        json!({ "first": 1, "id": "1" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        response.response.body().data,
+        Some(json!({ "me": { "name": "test" }, "orgas": [{ "id": "1" }] }))
+    );
+    assert_eq!(queries.lock().len(), 2);
+    assert!(!queries.lock()[1].contains("orgas"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // all the fields are in cache
+    let response = call(
+        "query($id: ID!, $first: Int) { orgas(first: $first) { id } me: user(id: $id) { name } }",
+        json!({ "first": 1, "id": "1" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        response.response.body().data,
+        Some(json!({ "orgas": [{ "id": "1" }], "me": { "name": "test" } }))
+    );
+    assert_eq!(queries.lock().len(), 2);
+
+    // the variables are part of the field cache key
+    call(
+        "query($first: Int) { orgas(first: $first) { id } }",
+        json!({ "first": 2 }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(queries.lock().len(), 3);
+}
+
 /*FIXME: reactivate test if we manage to make fred return the response to SCAN in mocks
 #[tokio::test(flavor = "multi_thread")]
 async fn invalidate() {
@@ -616,3 +1453,219 @@ async fn invalidate() {
     insta::assert_json_snapshot!(response);
     panic!()
 }*/
+
+#[tokio::test]
+async fn in_memory_storage() {
+    let storage = EntityStorage::new(None, DEFAULT_IN_MEMORY_MAX_TTL);
+    let entry = |data: serde_json::Value| CacheEntry {
+        control: CacheControl::default(),
+        data: serde_json_bytes::to_value(data).unwrap(),
+        tags: Vec::new(),
+    };
+
+    assert!(storage.subgraph("user", None, None).is_none());
+    let user = storage
+        .subgraph("user", NonZeroUsize::new(2), Some(Duration::from_secs(60)))
+        .unwrap();
+    let orga = storage
+        .subgraph(
+            "orga",
+            NonZeroUsize::new(2),
+            Some(Duration::from_millis(10)),
+        )
+        .unwrap();
+
+    user.insert_multiple(
+        vec![
+            (
+                "subgraph:user:type:User:entity:1".to_string(),
+                entry(serde_json::json!({"id": "1"})),
+            ),
+            (
+                "subgraph:user:type:User:entity:2".to_string(),
+                entry(serde_json::json!({"id": "2"})),
+            ),
+        ],
+        None,
+    )
+    .await;
+    orga.insert(
+        "subgraph:orga:type:Organization:entity:1".to_string(),
+        entry(serde_json::json!({"id": "1"})),
+        None,
+    )
+    .await;
+
+    let found = user
+        .get_multiple(vec![
+            "subgraph:user:type:User:entity:1".to_string(),
+            "subgraph:user:type:User:entity:3".to_string(),
+        ])
+        .await;
+    assert_eq!(found[0].as_ref().unwrap().data, json!({"id": "1"}));
+    assert!(found[1].is_none());
+
+    // the in memory cache is size limited per subgraph
+    user.insert(
+        "subgraph:user:type:User:entity:3".to_string(),
+        entry(serde_json::json!({"id": "3"})),
+        None,
+    )
+    .await;
+    assert!(user
+        .get("subgraph:user:type:User:entity:2".to_string())
+        .await
+        .is_none());
+
+    // entries expire with the subgraph TTL
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(orga
+        .get("subgraph:orga:type:Organization:entity:1".to_string())
+        .await
+        .is_none());
+
+    let invalidation = Invalidation::new(storage.clone(), Default::default());
+    let count = invalidation
+        .invalidate(
+            InvalidationOrigin::Endpoint,
+            vec![InvalidationRequest::Type {
+                subgraph: "user".to_string(),
+                r#type: "User".to_string(),
+            }],
+        )
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+    assert!(user
+        .get("subgraph:user:type:User:entity:1".to_string())
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn in_memory_ttl_is_capped_with_redis() {
+    // two router instances sharing the same Redis
+    let store = Arc::new(MockStore::new());
+    let first = EntityStorage::new(
+        Some(RedisCacheStorage::from_mocks(store.clone()).await.unwrap()),
+        Duration::from_millis(100),
+    );
+    let second = EntityStorage::new(
+        Some(RedisCacheStorage::from_mocks(store).await.unwrap()),
+        Duration::from_millis(100),
+    );
+    let entry = CacheEntry {
+        control: CacheControl::default(),
+        data: json!({"id": "1"}),
+        tags: Vec::new(),
+    };
+    let key = "subgraph:user:type:User:entity:1".to_string();
+
+    let user = first
+        .subgraph("user", NonZeroUsize::new(10), Some(Duration::from_secs(60)))
+        .unwrap();
+    user.insert(key.clone(), entry, None).await;
+
+    // the second instance invalidates the entry, which only removes it from Redis
+    second
+        .redis()
+        .unwrap()
+        .delete(vec![RedisKey(key.clone())])
+        .await;
+    assert!(user.get(key.clone()).await.is_some());
+
+    // the first instance does not keep it in memory longer than the maximum TTL
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(user.get(key).await.is_none());
+}
+
+#[tokio::test]
+async fn tag_invalidation() {
+    let storage = EntityStorage::new(None, DEFAULT_IN_MEMORY_MAX_TTL);
+    let entry = |data: serde_json_bytes::Value, tags: &[&str]| CacheEntry {
+        control: CacheControl::default(),
+        data,
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+    };
+
+    let mut response = subgraph::Response::fake2_builder()
+        .header("surrogate-key", "product:42 reviews")
+        .extension("cacheTags", json!(["product:42", "user:1"]))
+        .build()
+        .unwrap();
+    assert_eq!(
+        cache_tags(&mut response),
+        vec![
+            "product:42".to_string(),
+            "reviews".to_string(),
+            "user:1".to_string()
+        ]
+    );
+    assert!(response.response.body().extensions.is_empty());
+
+    let products = storage
+        .subgraph("products", NonZeroUsize::new(10), None)
+        .unwrap();
+    let reviews = storage
+        .subgraph("reviews", NonZeroUsize::new(10), None)
+        .unwrap();
+    products
+        .insert_multiple(
+            vec![
+                (
+                    "subgraph:products:type:Product:entity:42".to_string(),
+                    entry(json!({"upc": "42"}), &["product:42"]),
+                ),
+                (
+                    "subgraph:products:type:Product:entity:43".to_string(),
+                    entry(json!({"upc": "43"}), &["product:43"]),
+                ),
+            ],
+            None,
+        )
+        .await;
+    reviews
+        .insert(
+            "subgraph:reviews:type:Product:entity:42".to_string(),
+            entry(json!({"reviews": []}), &["product:42", "reviews"]),
+            None,
+        )
+        .await;
+
+    // the tag covers entries across subgraphs
+    let invalidation = Invalidation::new(storage.clone(), Default::default());
+    let count = invalidation
+        .invalidate(
+            InvalidationOrigin::Endpoint,
+            vec![InvalidationRequest::Tag {
+                tag: "product:42".to_string(),
+            }],
+        )
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+    assert!(products
+        .get("subgraph:products:type:Product:entity:42".to_string())
+        .await
+        .is_none());
+    assert!(products
+        .get("subgraph:products:type:Product:entity:43".to_string())
+        .await
+        .is_some());
+    assert!(reviews
+        .get("subgraph:reviews:type:Product:entity:42".to_string())
+        .await
+        .is_none());
+
+    // the entry was already removed through another tag
+    let count = invalidation
+        .invalidate(
+            InvalidationOrigin::Endpoint,
+            vec![InvalidationRequest::Tag {
+                tag: "reviews".to_string(),
+            }],
+        )
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+}
@@ -0,0 +1,444 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use apollo_compiler::ast::Value;
+use apollo_compiler::schema::ExtendedType;
+use apollo_compiler::Name;
+use apollo_compiler::Node;
+use apollo_compiler::Schema;
+use apollo_federation::sources::connect::JSONSelection;
+use apollo_federation::sources::connect::URLPathTemplate;
+use displaydoc::Display;
+use http::HeaderName;
+use http::HeaderValue;
+use http::Method;
+use thiserror::Error;
+
+use super::Config;
+
+const JOIN_DIRECTIVE: &str = "join__directive";
+const CONNECT_DIRECTIVE: &str = "connect";
+const SOURCE_DIRECTIVE: &str = "source";
+
+#[derive(Debug, Display, Error)]
+pub(crate) enum ConnectorError {
+    /// invalid @source directive "{0}" in subgraph {1}: {2}
+    InvalidSource(String, String, String),
+    /// invalid @connect directive on {0} in subgraph {1}: {2}
+    InvalidConnect(String, String, String),
+}
+
+/// The connectors of a subgraph, indexed by the type and field they resolve.
+///
+/// They are read from the `@join__directive(name: "connect")` applications carried by composition
+/// in the supergraph, with the base URLs and headers of the matching
+/// `@join__directive(name: "source")` applications on the schema definition.
+#[derive(Debug, Default)]
+pub(crate) struct SubgraphConnectors {
+    fields: HashMap<Name, HashMap<Name, Arc<Connector>>>,
+}
+
+impl SubgraphConnectors {
+    /// Reads the connectors of all subgraphs in a supergraph, keyed by subgraph name
+    pub(crate) fn from_supergraph(
+        schema: &Schema,
+        config: &Config,
+    ) -> Result<HashMap<String, SubgraphConnectors>, ConnectorError> {
+        let graphs = graph_names(schema);
+
+        let mut sources: HashMap<(String, String), Source> = HashMap::new();
+        for directive in schema
+            .schema_definition
+            .directives
+            .get_all(JOIN_DIRECTIVE)
+            .filter(|directive| join_directive_name(directive) == Some(SOURCE_DIRECTIVE))
+        {
+            let args = join_directive_args(directive);
+            for subgraph in join_directive_graphs(directive, &graphs) {
+                let name = get(args, "name")
+                    .and_then(|name| name.as_str())
+                    .ok_or_else(|| {
+                        ConnectorError::InvalidSource(
+                            String::new(),
+                            subgraph.clone(),
+                            "missing name".to_string(),
+                        )
+                    })?
+                    .to_string();
+                let source =
+                    Source::parse(args, config.source(&subgraph, &name)).map_err(|error| {
+                        ConnectorError::InvalidSource(name.clone(), subgraph.clone(), error)
+                    })?;
+                sources.insert((subgraph, name), source);
+            }
+        }
+
+        let mut connectors: HashMap<String, SubgraphConnectors> = HashMap::new();
+        for (type_name, ty) in &schema.types {
+            let fields = match ty {
+                ExtendedType::Object(object) => &object.fields,
+                ExtendedType::Interface(interface) => &interface.fields,
+                _ => continue,
+            };
+            for (field_name, field) in fields {
+                for directive in field
+                    .directives
+                    .get_all(JOIN_DIRECTIVE)
+                    .filter(|directive| join_directive_name(directive) == Some(CONNECT_DIRECTIVE))
+                {
+                    let args = join_directive_args(directive);
+                    for subgraph in join_directive_graphs(directive, &graphs) {
+                        let connector =
+                            Connector::parse(args, &subgraph, &sources).map_err(|error| {
+                                ConnectorError::InvalidConnect(
+                                    format!("{type_name}.{field_name}"),
+                                    subgraph.clone(),
+                                    error,
+                                )
+                            })?;
+                        connectors
+                            .entry(subgraph)
+                            .or_default()
+                            .fields
+                            .entry(type_name.clone())
+                            .or_default()
+                            .insert(field_name.clone(), Arc::new(connector));
+                    }
+                }
+            }
+        }
+
+        Ok(connectors)
+    }
+
+    /// The connector resolving a field
+    pub(crate) fn get(&self, type_name: &str, field_name: &str) -> Option<&Arc<Connector>> {
+        self.fields.get(type_name)?.get(field_name)
+    }
+}
+
+/// An HTTP endpoint resolving a field
+#[derive(Debug)]
+pub(crate) struct Connector {
+    pub(crate) base_url: String,
+    pub(crate) method: Method,
+    pub(crate) path: URLPathTemplate,
+    pub(crate) headers: Vec<HeaderRule>,
+    pub(crate) body: Option<JSONSelection>,
+    pub(crate) selection: JSONSelection,
+}
+
+/// A header sent with connector requests
+#[derive(Clone, Debug)]
+pub(crate) enum HeaderRule {
+    /// A static value
+    Value(HeaderName, HeaderValue),
+    /// The value of a header of the client request
+    Propagate { name: HeaderName, from: HeaderName },
+}
+
+#[derive(Debug)]
+struct Source {
+    base_url: String,
+    headers: Vec<HeaderRule>,
+}
+
+impl Source {
+    fn parse(args: &[(Name, Node<Value>)], override_url: Option<&str>) -> Result<Self, String> {
+        let http = get(args, "http")
+            .and_then(|http| http.as_object())
+            .ok_or("missing http argument")?;
+        let base_url = match override_url {
+            Some(url) => url.to_string(),
+            None => get(http, "baseURL")
+                .and_then(|url| url.as_str())
+                .ok_or("missing http.baseURL argument")?
+                .to_string(),
+        };
+        url::Url::parse(&base_url).map_err(|error| format!("invalid base URL: {error}"))?;
+        Ok(Self {
+            base_url,
+            headers: parse_headers(http)?,
+        })
+    }
+}
+
+impl Connector {
+    fn parse(
+        args: &[(Name, Node<Value>)],
+        subgraph: &str,
+        sources: &HashMap<(String, String), Source>,
+    ) -> Result<Self, String> {
+        let http = get(args, "http")
+            .and_then(|http| http.as_object())
+            .ok_or("missing http argument")?;
+
+        let mut methods = http.iter().filter_map(|(name, value)| {
+            let method = match name.as_str() {
+                "GET" => Method::GET,
+                "POST" => Method::POST,
+                "PUT" => Method::PUT,
+                "PATCH" => Method::PATCH,
+                "DELETE" => Method::DELETE,
+                _ => return None,
+            };
+            Some((method, value))
+        });
+        let (method, url) = methods
+            .next()
+            .ok_or("missing HTTP method in the http argument")?;
+        if methods.next().is_some() {
+            return Err("only one HTTP method can be set in the http argument".to_string());
+        }
+        let url = url.as_str().ok_or("the URL must be a string")?;
+
+        let (base_url, template, mut headers) = match get(args, "source")
+            .and_then(|source| source.as_str())
+        {
+            Some(source) => {
+                let source = sources
+                    .get(&(subgraph.to_string(), source.to_string()))
+                    .ok_or_else(|| format!("unknown source \"{source}\""))?;
+                (
+                    source.base_url.clone(),
+                    url.to_string(),
+                    source.headers.clone(),
+                )
+            }
+            None => {
+                let (base_url, template) = split_absolute_url(url)
+                    .ok_or("the URL must be absolute when the connector does not use a source")?;
+                (base_url.to_string(), template.to_string(), Vec::new())
+            }
+        };
+        let path = URLPathTemplate::parse(&template)
+            .map_err(|error| format!("invalid URL template: {error}"))?;
+
+        // connector headers replace the source headers with the same name
+        for header in parse_headers(http)? {
+            headers.retain(|existing| existing.name() != header.name());
+            headers.push(header);
+        }
+
+        let body = get(http, "body")
+            .map(|body| {
+                body.as_str()
+                    .ok_or("the body must be a string".to_string())
+                    .and_then(parse_selection)
+            })
+            .transpose()?;
+        let selection = get(args, "selection")
+            .and_then(|selection| selection.as_str())
+            .ok_or("missing selection argument")
+            .map_err(str::to_string)
+            .and_then(parse_selection)?;
+
+        Ok(Self {
+            base_url,
+            method,
+            path,
+            headers,
+            body,
+            selection,
+        })
+    }
+}
+
+impl HeaderRule {
+    fn name(&self) -> &HeaderName {
+        match self {
+            HeaderRule::Value(name, _) => name,
+            HeaderRule::Propagate { name, .. } => name,
+        }
+    }
+}
+
+fn parse_headers(http: &[(Name, Node<Value>)]) -> Result<Vec<HeaderRule>, String> {
+    let Some(headers) = get(http, "headers") else {
+        return Ok(Vec::new());
+    };
+    let Value::List(headers) = headers.as_ref() else {
+        return Err("headers must be a list".to_string());
+    };
+    headers
+        .iter()
+        .map(|header| {
+            let header = header.as_object().ok_or("a header must be an object")?;
+            let name = get(header, "name")
+                .and_then(|name| name.as_str())
+                .ok_or("missing header name")?;
+            let name = HeaderName::try_from(name)
+                .map_err(|error| format!("invalid header name {name}: {error}"))?;
+            if let Some(value) = get(header, "value").and_then(|value| value.as_str()) {
+                let value = HeaderValue::try_from(value)
+                    .map_err(|error| format!("invalid value for header {name}: {error}"))?;
+                Ok(HeaderRule::Value(name, value))
+            } else {
+                let from = get(header, "from")
+                    .and_then(|from| from.as_str())
+                    .map(HeaderName::try_from)
+                    .transpose()
+                    .map_err(|error| format!("invalid header name: {error}"))?
+                    .unwrap_or_else(|| name.clone());
+                Ok(HeaderRule::Propagate { name, from })
+            }
+        })
+        .collect()
+}
+
+fn parse_selection(selection: &str) -> Result<JSONSelection, String> {
+    match JSONSelection::parse(selection) {
+        Ok((remainder, selection)) if remainder.trim().is_empty() => Ok(selection),
+        Ok((remainder, _)) => Err(format!("unexpected selection content: {remainder}")),
+        Err(error) => Err(format!("invalid selection: {error}")),
+    }
+}
+
+/// Splits an absolute URL template into its origin and its path template
+fn split_absolute_url(url: &str) -> Option<(&str, &str)> {
+    let scheme_end = url.find("://")? + 3;
+    if !url[..scheme_end].starts_with("http") {
+        return None;
+    }
+    let path_start = url[scheme_end..]
+        .find(['/', '?'])
+        .map_or(url.len(), |index| scheme_end + index);
+    Some((&url[..path_start], &url[path_start..]))
+}
+
+/// Subgraph names, keyed by their `join__Graph` enum value
+fn graph_names(schema: &Schema) -> HashMap<Name, String> {
+    let Some(ExtendedType::Enum(graphs)) = schema.types.get("join__Graph") else {
+        return HashMap::new();
+    };
+    graphs
+        .values
+        .iter()
+        .filter_map(|(value, definition)| {
+            let name = definition
+                .directives
+                .get("join__graph")?
+                .argument_by_name("name")?
+                .as_str()?;
+            Some((value.clone(), name.to_string()))
+        })
+        .collect()
+}
+
+fn join_directive_name(directive: &apollo_compiler::ast::Directive) -> Option<&str> {
+    directive
+        .argument_by_name("name")
+        .and_then(|name| name.as_str())
+        .map(|name| name.trim_start_matches('@'))
+}
+
+fn join_directive_args(directive: &apollo_compiler::ast::Directive) -> &[(Name, Node<Value>)] {
+    directive
+        .argument_by_name("args")
+        .and_then(|args| args.as_object())
+        .unwrap_or_default()
+}
+
+fn join_directive_graphs(
+    directive: &apollo_compiler::ast::Directive,
+    graphs: &HashMap<Name, String>,
+) -> Vec<String> {
+    match directive
+        .argument_by_name("graphs")
+        .map(|graphs| graphs.as_ref())
+    {
+        Some(Value::List(values)) => values
+            .iter()
+            .filter_map(|value| match value.as_ref() {
+                Value::Enum(graph) => graphs.get(graph).cloned(),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn get<'a>(object: &'a [(Name, Node<Value>)], key: &str) -> Option<&'a Node<Value>> {
+    object
+        .iter()
+        .find(|(name, _)| name.as_str() == key)
+        .map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUPERGRAPH: &str = include_str!("testdata/supergraph.graphql");
+
+    #[test]
+    fn reads_connectors() {
+        let schema = Schema::parse_and_validate(SUPERGRAPH, "supergraph.graphql").unwrap();
+        let connectors = SubgraphConnectors::from_supergraph(&schema, &Config::default()).unwrap();
+
+        assert!(!connectors.contains_key("reviews"));
+        let users = connectors.get("users").unwrap();
+
+        let user = users.get("Query", "user").unwrap();
+        assert_eq!(user.method, Method::GET);
+        assert_eq!(user.base_url, "http://localhost:4001/api");
+        assert_eq!(user.path.to_string(), "/users/{args.id!}");
+        assert!(matches!(
+            &user.headers[..],
+            [
+                HeaderRule::Value(name, value),
+                HeaderRule::Propagate { name: propagated, from },
+            ] if name == "x-api-key" && value == "secret"
+                && propagated == "authorization" && from == "authorization"
+        ));
+
+        let create_user = users.get("Mutation", "createUser").unwrap();
+        assert_eq!(create_user.method, Method::POST);
+        assert!(create_user.body.is_some());
+
+        let posts = users.get("User", "posts").unwrap();
+        assert_eq!(posts.base_url, "http://localhost:4002");
+        assert_eq!(posts.path.to_string(), "/users/{this.id!}/posts");
+        assert!(posts.headers.is_empty());
+    }
+
+    #[test]
+    fn overrides_source_urls() {
+        let schema = Schema::parse_and_validate(SUPERGRAPH, "supergraph.graphql").unwrap();
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "subgraphs": {
+                "users": { "sources": { "api": { "override_url": "http://localhost:5000" } } }
+            }
+        }))
+        .unwrap();
+        let connectors = SubgraphConnectors::from_supergraph(&schema, &config).unwrap();
+        assert_eq!(
+            connectors["users"].get("Query", "user").unwrap().base_url,
+            "http://localhost:5000/"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_connectors() {
+        let sdl = SUPERGRAPH.replace("source: \"api\"", "source: \"unknown\"");
+        let schema = Schema::parse_and_validate(sdl, "supergraph.graphql").unwrap();
+        let error = SubgraphConnectors::from_supergraph(&schema, &Config::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid @connect directive on Query.user in subgraph users: unknown source \"unknown\""
+        );
+    }
+
+    #[test]
+    fn splits_absolute_urls() {
+        assert_eq!(
+            split_absolute_url("https://example.com/users/{id}"),
+            Some(("https://example.com", "/users/{id}"))
+        );
+        assert_eq!(
+            split_absolute_url("http://localhost:4000?a={b}"),
+            Some(("http://localhost:4000", "?a={b}"))
+        );
+        assert_eq!(split_absolute_url("/users/{id}"), None);
+    }
+}
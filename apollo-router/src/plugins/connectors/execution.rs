@@ -0,0 +1,771 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+
+use apollo_compiler::ast;
+use apollo_compiler::ast::Definition;
+use apollo_compiler::ast::Selection;
+use apollo_compiler::executable;
+use apollo_compiler::validation::Valid;
+use apollo_compiler::ExecutableDocument;
+use apollo_compiler::Name;
+use apollo_compiler::Schema;
+use apollo_federation::sources::connect::ApplyTo;
+use apollo_federation::sources::connect::JSONSelection;
+use futures::future::join_all;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use http::HeaderValue;
+use indexmap::IndexMap;
+use serde_json_bytes::ByteString;
+use serde_json_bytes::Map;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::Service;
+
+use super::connector::Connector;
+use super::connector::HeaderRule;
+use super::connector::SubgraphConnectors;
+use crate::graphql;
+use crate::json_ext::Object;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::services::subgraph;
+
+const CONNECTOR_FETCH_ERROR: &str = "CONNECTOR_FETCH";
+const ENTITIES: &str = "_entities";
+const TYPENAME: &str = "__typename";
+
+/// Resolves the operations sent to a subgraph by calling its connectors
+#[derive(Clone)]
+pub(crate) struct ConnectorService {
+    subgraph_name: String,
+    schema: Arc<Valid<Schema>>,
+    connectors: Arc<SubgraphConnectors>,
+    client: reqwest::Client,
+}
+
+impl ConnectorService {
+    pub(crate) fn new(
+        subgraph_name: String,
+        schema: Arc<Valid<Schema>>,
+        connectors: Arc<SubgraphConnectors>,
+        client: reqwest::Client,
+    ) -> Self {
+        Self {
+            subgraph_name,
+            schema,
+            connectors,
+            client,
+        }
+    }
+}
+
+impl Service<subgraph::Request> for ConnectorService {
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: subgraph::Request) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let body = request.subgraph_request.body();
+            let query = body.query.as_deref().unwrap_or_default();
+            let (data, errors) = match ast::Document::parse(query, "subgraph_request.graphql") {
+                Ok(document) => {
+                    let execution = Execution {
+                        schema: &service.schema,
+                        connectors: &service.connectors,
+                        client: &service.client,
+                        document: &document,
+                        variables: &body.variables,
+                        headers: request.supergraph_request.headers(),
+                        errors: Mutex::new(Vec::new()),
+                    };
+                    let data = execution.execute(body.operation_name.as_deref()).await;
+                    let errors = execution.errors.into_inner().unwrap_or_default();
+                    (data, errors)
+                }
+                Err(_) => (
+                    None,
+                    vec![graphql::Error::builder()
+                        .message(format!(
+                            "cannot parse the operation sent to subgraph '{}'",
+                            service.subgraph_name
+                        ))
+                        .extension_code(CONNECTOR_FETCH_ERROR)
+                        .build()],
+                ),
+            };
+
+            Ok(subgraph::Response::builder()
+                .and_data(data)
+                .errors(errors)
+                .extensions(Object::new())
+                .context(request.context)
+                .subgraph_name(service.subgraph_name)
+                .build())
+        })
+    }
+}
+
+/// Selections of a field, possibly merged from several occurrences of the field in the operation
+type SelectionSets<'a> = Vec<&'a [Selection]>;
+
+struct Execution<'a> {
+    schema: &'a Schema,
+    connectors: &'a SubgraphConnectors,
+    client: &'a reqwest::Client,
+    document: &'a ast::Document,
+    variables: &'a Object,
+    headers: &'a HeaderMap,
+    errors: Mutex<Vec<graphql::Error>>,
+}
+
+impl<'a> Execution<'a> {
+    async fn execute(&self, operation_name: Option<&str>) -> Option<Value> {
+        let operation = self
+            .document
+            .definitions
+            .iter()
+            .find_map(|definition| match definition {
+                Definition::OperationDefinition(operation)
+                    if operation_name.is_none() || operation.name.as_deref() == operation_name =>
+                {
+                    Some(operation)
+                }
+                _ => None,
+            });
+        let Some(operation) = operation else {
+            self.error(
+                "missing operation in the subgraph request".to_string(),
+                Path::default(),
+            );
+            return None;
+        };
+        let Some(root_type) = self.schema.root_operation(operation.operation_type) else {
+            self.error(
+                format!("missing {} root type", operation.operation_type),
+                Path::default(),
+            );
+            return None;
+        };
+
+        let fields = self.collect_fields(root_type, &[&operation.selection_set]);
+        let values = if operation.operation_type == ast::OperationType::Mutation {
+            // mutation fields are executed serially, in the order of the operation
+            let mut values = Vec::with_capacity(fields.len());
+            for (key, fields) in &fields {
+                values.push(self.resolve_root_field(root_type, key, fields).await);
+            }
+            values
+        } else {
+            join_all(
+                fields
+                    .iter()
+                    .map(|(key, fields)| self.resolve_root_field(root_type, key, fields)),
+            )
+            .await
+        };
+
+        Some(Value::Object(values.into_iter().collect()))
+    }
+
+    /// Resolves a field of the root type, by its response key
+    async fn resolve_root_field(
+        &self,
+        root_type: &'a str,
+        key: &str,
+        fields: &'a [&'a ast::Field],
+    ) -> (ByteString, Value) {
+        let path = Path(vec![PathElement::Key(key.to_string(), None)]);
+        let field = fields[0];
+        let value = if field.name == ENTITIES {
+            self.resolve_entities(field, fields, path).await
+        } else if field.name == TYPENAME {
+            Value::String(root_type.into())
+        } else if self.connectors.get(root_type, &field.name).is_some() {
+            let parent = Value::Object(Map::new());
+            self.resolve_field(root_type, &parent, fields, path).await
+        } else {
+            self.error(
+                format!("no connector resolves {}.{}", root_type, field.name),
+                path,
+            );
+            Value::Null
+        };
+        (ByteString::from(key), value)
+    }
+
+    /// Resolves an `_entities` field from its representations
+    async fn resolve_entities(
+        &self,
+        field: &ast::Field,
+        fields: &[&'a ast::Field],
+        path: Path,
+    ) -> Value {
+        let representations = field
+            .arguments
+            .iter()
+            .find(|argument| argument.name == "representations")
+            .map(|argument| self.value(&argument.value));
+        let Some(Value::Array(representations)) = representations else {
+            self.error("missing entity representations".to_string(), path);
+            return Value::Null;
+        };
+
+        let selection_sets = selection_sets(fields);
+        let entities = join_all(representations.iter().enumerate().map(
+            |(index, representation)| {
+                let mut path = path.clone();
+                path.push(PathElement::Index(index));
+                match representation.get(TYPENAME).and_then(|name| name.as_str()) {
+                    Some(type_name) => self.complete_value(
+                        representation.clone(),
+                        type_name,
+                        &selection_sets,
+                        path,
+                    ),
+                    None => {
+                        self.error(
+                            "missing __typename in entity representation".to_string(),
+                            path,
+                        );
+                        async { Value::Null }.boxed()
+                    }
+                }
+            },
+        ))
+        .await;
+
+        Value::Array(entities)
+    }
+
+    /// Resolves a field of an object, calling its connector if it has one
+    fn resolve_field(
+        &'a self,
+        parent_type: &'a str,
+        parent: &'a Value,
+        fields: &'a [&'a ast::Field],
+        path: Path,
+    ) -> BoxFuture<'a, Value> {
+        async move {
+            let field = fields[0];
+            if field.name == TYPENAME {
+                return Value::String(parent_type.into());
+            }
+            let Ok(definition) = self.schema.type_field(parent_type, &field.name) else {
+                return Value::Null;
+            };
+            let field_type = definition.ty.inner_named_type();
+            let selection_sets = selection_sets(fields);
+
+            let value = match self.connectors.get(parent_type, &field.name) {
+                Some(connector) => {
+                    let selection =
+                        self.narrow_selection(&connector.selection, field_type, &selection_sets);
+                    let mut vars = IndexMap::new();
+                    vars.insert("$args".to_string(), self.arguments(field, definition));
+                    vars.insert("$this".to_string(), parent.clone());
+                    match fetch(self.client, connector, &selection, &vars, self.headers).await {
+                        Ok((value, errors)) => {
+                            for message in errors {
+                                self.error(message, path.clone());
+                            }
+                            value
+                        }
+                        Err(message) => {
+                            self.error(message, path);
+                            return Value::Null;
+                        }
+                    }
+                }
+                None => match parent.get(field.name.as_str()) {
+                    Some(value) => value.clone(),
+                    // an entity representation only has the key fields of the entity, and a
+                    // connector response may lack some fields of its selection
+                    None => {
+                        self.error(
+                            format!(
+                                "no connector resolves {}.{}, and it is missing from its parent",
+                                parent_type, field.name
+                            ),
+                            path,
+                        );
+                        return Value::Null;
+                    }
+                },
+            };
+
+            self.complete_value(value, field_type.as_str(), &selection_sets, path)
+                .await
+        }
+        .boxed()
+    }
+
+    /// Shapes a value after the selections of its field
+    fn complete_value(
+        &'a self,
+        value: Value,
+        type_name: &'a str,
+        selection_sets: &[&'a [Selection]],
+        path: Path,
+    ) -> BoxFuture<'a, Value> {
+        let selection_sets = selection_sets.to_vec();
+        async move {
+            if selection_sets
+                .iter()
+                .all(|selections| selections.is_empty())
+            {
+                return value;
+            }
+            match value {
+                Value::Array(values) => Value::Array(
+                    join_all(values.into_iter().enumerate().map(|(index, value)| {
+                        let mut path = path.clone();
+                        path.push(PathElement::Index(index));
+                        self.complete_value(value, type_name, &selection_sets, path)
+                    }))
+                    .await,
+                ),
+                Value::Object(ref object) => {
+                    let concrete_type = object
+                        .get(TYPENAME)
+                        .and_then(|name| name.as_str())
+                        .filter(|name| self.schema.types.contains_key(*name))
+                        .unwrap_or(type_name)
+                        .to_string();
+                    let concrete_type = concrete_type.as_str();
+                    let fields = self.collect_fields(concrete_type, &selection_sets);
+                    let values = join_all(fields.iter().map(|(key, fields)| {
+                        let mut path = path.clone();
+                        path.push(PathElement::Key(key.to_string(), None));
+                        let value = &value;
+                        async move {
+                            let resolved =
+                                self.resolve_field(concrete_type, value, fields, path).await;
+                            (ByteString::from(*key), resolved)
+                        }
+                    }))
+                    .await;
+                    Value::Object(values.into_iter().collect())
+                }
+                value => value,
+            }
+        }
+        .boxed()
+    }
+
+    /// Collects the fields selected on a type, by response key
+    fn collect_fields(
+        &self,
+        type_name: &str,
+        selection_sets: &[&'a [Selection]],
+    ) -> IndexMap<&'a str, Vec<&'a ast::Field>> {
+        let mut fields = IndexMap::new();
+        for selections in selection_sets {
+            self.collect_fields_into(type_name, selections, &mut fields);
+        }
+        fields
+    }
+
+    fn collect_fields_into(
+        &self,
+        type_name: &str,
+        selections: &'a [Selection],
+        fields: &mut IndexMap<&'a str, Vec<&'a ast::Field>>,
+    ) {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) => {
+                    if self.is_included(&field.directives) {
+                        let key = field.alias.as_ref().unwrap_or(&field.name).as_str();
+                        fields.entry(key).or_default().push(field.as_ref());
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    let applies = fragment
+                        .type_condition
+                        .as_ref()
+                        .map_or(true, |condition| self.applies(condition, type_name));
+                    if applies && self.is_included(&fragment.directives) {
+                        self.collect_fields_into(type_name, &fragment.selection_set, fields);
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    if !self.is_included(&spread.directives) {
+                        continue;
+                    }
+                    if let Some(fragment) = self.fragment(&spread.fragment_name) {
+                        if self.applies(&fragment.type_condition, type_name) {
+                            self.collect_fields_into(type_name, &fragment.selection_set, fields);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn fragment(&self, name: &str) -> Option<&'a ast::FragmentDefinition> {
+        self.document
+            .definitions
+            .iter()
+            .find_map(|definition| match definition {
+                Definition::FragmentDefinition(fragment) if fragment.name == name => {
+                    Some(fragment.as_ref())
+                }
+                _ => None,
+            })
+    }
+
+    /// Narrows the selection of a connector to the fields requested on its field.
+    ///
+    /// The narrowed selection has no aliases, since values are completed from the field names of
+    /// the connector output. The whole selection is kept if a requested type has fields resolved
+    /// by other connectors, as they can read any field of their parent through `$this`.
+    fn narrow_selection(
+        &self,
+        selection: &JSONSelection,
+        field_type: &Name,
+        selection_sets: &[&'a [Selection]],
+    ) -> JSONSelection {
+        let mut requested = executable::SelectionSet::new(field_type.clone());
+        for selections in selection_sets {
+            if !self.collect_requested(field_type, selections, &mut requested) {
+                return selection.clone();
+            }
+        }
+        // fragment spreads are inlined by `collect_requested`
+        selection.apply_selection_set(&ExecutableDocument::new(), &requested)
+    }
+
+    /// Adds the fields selected on a type to `requested`, with the fields of all its fragments.
+    /// Returns false if one of them, or one of their subfields, has a connector.
+    fn collect_requested(
+        &self,
+        type_name: &str,
+        selections: &'a [Selection],
+        requested: &mut executable::SelectionSet,
+    ) -> bool {
+        for selection in selections {
+            match selection {
+                Selection::Field(field) => {
+                    if !self.is_included(&field.directives) || field.name == TYPENAME {
+                        continue;
+                    }
+                    if self.connectors.get(type_name, &field.name).is_some() {
+                        return false;
+                    }
+                    let Ok(definition) = self.schema.type_field(type_name, &field.name) else {
+                        continue;
+                    };
+                    let mut requested_field =
+                        executable::Field::new(field.name.clone(), definition.node.clone());
+                    if !self.collect_requested(
+                        definition.ty.inner_named_type(),
+                        &field.selection_set,
+                        &mut requested_field.selection_set,
+                    ) {
+                        return false;
+                    }
+                    requested.push(requested_field);
+                }
+                Selection::InlineFragment(fragment) => {
+                    if !self.is_included(&fragment.directives) {
+                        continue;
+                    }
+                    let type_name = fragment.type_condition.as_deref().unwrap_or(type_name);
+                    if !self.collect_requested(type_name, &fragment.selection_set, requested) {
+                        return false;
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    if !self.is_included(&spread.directives) {
+                        continue;
+                    }
+                    if let Some(fragment) = self.fragment(&spread.fragment_name) {
+                        if !self.collect_requested(
+                            &fragment.type_condition,
+                            &fragment.selection_set,
+                            requested,
+                        ) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn applies(&self, condition: &str, type_name: &str) -> bool {
+        condition == type_name || self.schema.is_subtype(condition, type_name)
+    }
+
+    /// Evaluates the `@skip` and `@include` directives of a selection
+    fn is_included(&self, directives: &ast::DirectiveList) -> bool {
+        let condition = |name: &str| {
+            directives
+                .get(name)
+                .and_then(|directive| directive.argument_by_name("if"))
+                .map(|value| self.value(value) == Value::Bool(true))
+        };
+        condition("skip") != Some(true) && condition("include") != Some(false)
+    }
+
+    /// The arguments of a field, with the default values of the missing ones
+    fn arguments(&self, field: &ast::Field, definition: &ast::FieldDefinition) -> Value {
+        let mut arguments = Map::new();
+        for argument in &definition.arguments {
+            let value = field
+                .arguments
+                .iter()
+                .find(|provided| provided.name == argument.name)
+                .map(|provided| self.value(&provided.value))
+                .or_else(|| {
+                    argument
+                        .default_value
+                        .as_ref()
+                        .map(|value| self.value(value))
+                });
+            if let Some(value) = value {
+                arguments.insert(argument.name.as_str(), value);
+            }
+        }
+        Value::Object(arguments)
+    }
+
+    /// Converts a GraphQL value to JSON, replacing its variables with their values
+    fn value(&self, value: &ast::Value) -> Value {
+        match value {
+            ast::Value::Null => Value::Null,
+            ast::Value::Enum(name) => Value::String(name.as_str().into()),
+            ast::Value::Variable(name) => self
+                .variables
+                .get(name.as_str())
+                .cloned()
+                .unwrap_or_default(),
+            ast::Value::String(string) => Value::String(string.as_str().into()),
+            ast::Value::Float(float) => float
+                .try_to_f64()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(Value::Number)
+                .unwrap_or_default(),
+            ast::Value::Int(int) => match int.as_str().parse::<i64>() {
+                Ok(int) => Value::Number(int.into()),
+                Err(_) => int
+                    .try_to_f64()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+                    .unwrap_or_default(),
+            },
+            ast::Value::Boolean(boolean) => Value::Bool(*boolean),
+            ast::Value::List(values) => {
+                Value::Array(values.iter().map(|value| self.value(value)).collect())
+            }
+            ast::Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| (ByteString::from(name.as_str()), self.value(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn error(&self, message: String, path: Path) {
+        let error = graphql::Error::builder()
+            .message(message)
+            .path(path)
+            .extension_code(CONNECTOR_FETCH_ERROR)
+            .build();
+        self.errors.lock().expect("lock poisoned").push(error);
+    }
+}
+
+fn selection_sets<'a>(fields: &[&'a ast::Field]) -> SelectionSets<'a> {
+    fields
+        .iter()
+        .map(|field| field.selection_set.as_slice())
+        .collect()
+}
+
+/// Calls a connector and maps its response with the connector selection, narrowed to the
+/// requested fields. Returns the mapped value with the errors of the selection.
+///
+/// The request is sent with the client of the plugin, not with the subgraph HTTP client.
+async fn fetch(
+    client: &reqwest::Client,
+    connector: &Connector,
+    selection: &JSONSelection,
+    vars: &IndexMap<String, Value>,
+    headers: &HeaderMap,
+) -> Result<(Value, Vec<String>), String> {
+    let mut url_vars = Map::new();
+    for (name, value) in vars {
+        flatten(name.trim_start_matches('$'), value, &mut url_vars);
+    }
+    let path = connector
+        .path
+        .generate_path(&Value::Object(url_vars))
+        .map_err(|error| format!("cannot build the connector URL: {error}"))?;
+    let url = format!("{}{}", connector.base_url.trim_end_matches('/'), path);
+
+    let mut request = client.request(connector.method.clone(), &url);
+    for rule in &connector.headers {
+        match rule {
+            HeaderRule::Value(name, value) => request = request.header(name, value),
+            HeaderRule::Propagate { name, from } => {
+                for value in headers.get_all(from) {
+                    request = request.header(name, value);
+                }
+            }
+        }
+    }
+    if let Some(body) = &connector.body {
+        let (body, _) = body.apply_with_vars(&Value::Object(Map::new()), vars);
+        let body = serde_json::to_vec(&body.unwrap_or_default())
+            .map_err(|error| format!("cannot serialize the connector request body: {error}"))?;
+        request = request
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .body(body);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|error| format!("HTTP fetch failed from '{url}': {error}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("HTTP fetch failed from '{url}': {status}"));
+    }
+    let body = response
+        .bytes()
+        .await
+        .map_err(|error| format!("HTTP fetch failed from '{url}': {error}"))?;
+    let data: Value = serde_json::from_slice(&body)
+        .map_err(|error| format!("invalid JSON response from '{url}': {error}"))?;
+
+    let (value, errors) = selection.apply_with_vars(&data, vars);
+    let errors = errors
+        .iter()
+        .map(|error| {
+            format!(
+                "connector selection error for '{url}': {}",
+                error.message().unwrap_or_default()
+            )
+        })
+        .collect();
+    Ok((value.unwrap_or_default(), errors))
+}
+
+/// Flattens nested objects to the dotted variable names used by URL templates
+fn flatten(prefix: &str, value: &Value, vars: &mut Map<ByteString, Value>) {
+    match value {
+        Value::Null => {}
+        Value::Object(object) => {
+            for (key, value) in object {
+                flatten(&format!("{prefix}.{}", key.as_str()), value, vars);
+            }
+            vars.insert(prefix, value.clone());
+        }
+        _ => {
+            vars.insert(prefix, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+    use crate::plugins::connectors::Config;
+
+    const SUPERGRAPH: &str = include_str!("testdata/supergraph.graphql");
+
+    // Narrows the connector selection of the first root field of query
+    fn narrow(query: &str) -> JSONSelection {
+        let schema = Schema::parse_and_validate(SUPERGRAPH, "supergraph.graphql").unwrap();
+        let connectors = SubgraphConnectors::from_supergraph(&schema, &Config::default())
+            .unwrap()
+            .remove("users")
+            .unwrap();
+        let document = ast::Document::parse(query, "query.graphql").unwrap();
+        let client = reqwest::Client::new();
+        let variables = Object::new();
+        let headers = HeaderMap::new();
+        let execution = Execution {
+            schema: &schema,
+            connectors: &connectors,
+            client: &client,
+            document: &document,
+            variables: &variables,
+            headers: &headers,
+            errors: Mutex::new(Vec::new()),
+        };
+
+        let Some(Definition::OperationDefinition(operation)) = document.definitions.first() else {
+            panic!("expected an operation");
+        };
+        let root_type = schema.root_operation(operation.operation_type).unwrap();
+        let fields = execution.collect_fields(root_type, &[&operation.selection_set]);
+        let fields = &fields[0];
+        let definition = schema.type_field(root_type, &fields[0].name).unwrap();
+        let connector = connectors.get(root_type, &fields[0].name).unwrap();
+        execution.narrow_selection(
+            &connector.selection,
+            definition.ty.inner_named_type(),
+            &selection_sets(fields),
+        )
+    }
+
+    fn selection(selection: &str) -> JSONSelection {
+        JSONSelection::parse(selection).unwrap().1
+    }
+
+    #[test]
+    fn narrows_connector_selections() {
+        assert_eq!(narrow("{ user(id: 1) { name } }"), selection("name"));
+        // aliases are left out, and fragments are merged
+        assert_eq!(
+            narrow("{ user(id: 1) { login: username ...on User { id } ...F } } fragment F on User { name }"),
+            selection("username: login id name")
+        );
+        // skipped fields are left out
+        assert_eq!(
+            narrow("{ user(id: 1) { id name @skip(if: true) } }"),
+            selection("id")
+        );
+        assert_eq!(
+            narrow("{ users { name } }"),
+            selection("$.results { name }")
+        );
+        // posts is resolved by a connector reading the user id
+        assert_eq!(
+            narrow("{ users { name posts { title } } }"),
+            selection("$.results { id name }")
+        );
+    }
+
+    #[test]
+    fn flattens_variables() {
+        let mut vars = Map::new();
+        flatten(
+            "args",
+            &json!({ "id": 1, "filter": { "name": "a", "tag": null } }),
+            &mut vars,
+        );
+        assert_eq!(vars.get("args.id"), Some(&json!(1)));
+        assert_eq!(vars.get("args.filter.name"), Some(&json!("a")));
+        assert_eq!(vars.get("args.filter.tag"), None);
+        assert!(vars.contains_key("args"));
+    }
+}
@@ -0,0 +1,139 @@
+//! Runtime for REST connectors.
+//!
+//! Fields of a subgraph annotated with `@connect` are resolved by calling HTTP endpoints instead of
+//! sending a GraphQL request to the subgraph.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use apollo_compiler::validation::Valid;
+use apollo_compiler::Schema;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use self::connector::SubgraphConnectors;
+use self::execution::ConnectorService;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::services::subgraph;
+
+mod connector;
+mod execution;
+
+register_plugin!("apollo", "preview_connectors", Connectors);
+
+/// Configuration for REST connectors
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct Config {
+    /// Enable REST connectors. When disabled, `@connect` directives are ignored and the fields
+    /// are fetched from the subgraph
+    enabled: bool,
+
+    /// Per subgraph configuration
+    subgraphs: HashMap<String, SubgraphConfig>,
+
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_timeout"
+    )]
+    #[schemars(with = "String")]
+    /// Timeout for connector HTTP requests (default: 30s)
+    timeout: Duration,
+}
+
+/// Connectors configuration of a subgraph
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+struct SubgraphConfig {
+    /// Configuration of the `@source` directives of the subgraph, by name
+    sources: HashMap<String, SourceConfig>,
+}
+
+/// Configuration of a `@source` directive
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+struct SourceConfig {
+    /// Replaces the base URL of the source
+    override_url: Option<url::Url>,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            subgraphs: HashMap::new(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+impl Config {
+    fn source(&self, subgraph: &str, source: &str) -> Option<&str> {
+        self.subgraphs
+            .get(subgraph)?
+            .sources
+            .get(source)?
+            .override_url
+            .as_ref()
+            .map(|url| url.as_str())
+    }
+}
+
+struct Connectors {
+    schema: Arc<Valid<Schema>>,
+    connectors: HashMap<String, Arc<SubgraphConnectors>>,
+    client: reqwest::Client,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Connectors {
+    type Config = Config;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        // without connectors, every subgraph service is kept as is
+        let connectors = if init.config.enabled {
+            SubgraphConnectors::from_supergraph(&init.supergraph_schema, &init.config)?
+                .into_iter()
+                .map(|(subgraph, connectors)| (subgraph, Arc::new(connectors)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        // Connector requests do not go through the subgraph HTTP client: the `tls`, `apq` and
+        // `traffic_shaping` settings of subgraphs do not apply to them, and they do not create
+        // `http_request` spans
+        let client = reqwest::Client::builder()
+            .timeout(init.config.timeout)
+            .build()?;
+        Ok(Self {
+            schema: init.supergraph_schema,
+            connectors,
+            client,
+        })
+    }
+
+    fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        match self.connectors.get(name) {
+            Some(connectors) => ConnectorService::new(
+                name.to_string(),
+                self.schema.clone(),
+                connectors.clone(),
+                self.client.clone(),
+            )
+            .boxed(),
+            None => service,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,395 @@
+use std::sync::Arc;
+
+use http::header::AUTHORIZATION;
+use serde_json_bytes::json;
+use tower::ServiceExt;
+use wiremock::matchers::body_json;
+use wiremock::matchers::header;
+use wiremock::matchers::method;
+use wiremock::matchers::path;
+use wiremock::matchers::query_param;
+use wiremock::Mock;
+use wiremock::MockServer;
+use wiremock::ResponseTemplate;
+
+use super::*;
+use crate::graphql;
+use crate::json_ext::Path;
+use crate::plugin::test::MockSubgraphService;
+use crate::query_planner::fetch::OperationKind;
+
+const SUPERGRAPH: &str = include_str!("testdata/supergraph.graphql");
+
+async fn connectors(server: &MockServer) -> Connectors {
+    let config = serde_json::from_value(serde_json::json!({
+        "enabled": true,
+        "subgraphs": {
+            "users": { "sources": { "api": { "override_url": format!("{}/api", server.uri()) } } }
+        }
+    }))
+    .unwrap();
+    let sdl = SUPERGRAPH.replace("http://localhost:4002", &server.uri());
+    Connectors::new(PluginInit::fake_new(config, Arc::new(sdl)))
+        .await
+        .unwrap()
+}
+
+async fn execute(
+    plugin: &Connectors,
+    operation_kind: OperationKind,
+    query: &str,
+    variables: serde_json_bytes::Value,
+) -> graphql::Response {
+    let supergraph_request = http::Request::builder()
+        .header(AUTHORIZATION, "Bearer token")
+        .body(graphql::Request::default())
+        .unwrap();
+    let subgraph_request = http::Request::builder()
+        .body(
+            graphql::Request::builder()
+                .query(query)
+                .variables(variables.as_object().cloned().unwrap_or_default())
+                .build(),
+        )
+        .unwrap();
+    let request = subgraph::Request::fake_builder()
+        .supergraph_request(Arc::new(supergraph_request))
+        .subgraph_request(subgraph_request)
+        .operation_kind(operation_kind)
+        .subgraph_name("users")
+        .build();
+
+    plugin
+        .subgraph_service("users", MockSubgraphService::new().boxed())
+        .oneshot(request)
+        .await
+        .unwrap()
+        .response
+        .into_body()
+}
+
+#[tokio::test]
+async fn resolves_root_fields() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/users/1"))
+        .and(header("x-api-key", "secret"))
+        .and(header("authorization", "Bearer token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": 1,
+            "name": "Ada",
+            "login": "ada",
+            "email": "ada@example.com"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let plugin = connectors(&server).await;
+
+    let response = execute(
+        &plugin,
+        OperationKind::Query,
+        "query($id: ID!) { user(id: $id) { __typename id login: username ...on User { name } } }",
+        json!({ "id": "1" }),
+    )
+    .await;
+
+    assert_eq!(response.errors, vec![]);
+    assert_eq!(
+        response.data,
+        Some(json!({
+            "user": { "__typename": "User", "id": 1, "login": "ada", "name": "Ada" }
+        }))
+    );
+}
+
+#[tokio::test]
+async fn resolves_nested_connectors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/users"))
+        .and(query_param("limit", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "results": [{ "id": 1, "name": "Ada" }, { "id": 2, "name": "Alan" }]
+        })))
+        .mount(&server)
+        .await;
+    for id in [1, 2] {
+        Mock::given(method("GET"))
+            .and(path(format!("/users/{id}/posts")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "id": id * 10, "title": format!("post {id}"), "draft": false }
+            ])))
+            .mount(&server)
+            .await;
+    }
+    let plugin = connectors(&server).await;
+
+    let response = execute(
+        &plugin,
+        OperationKind::Query,
+        "{ users(limit: 2) { name posts { title } } }",
+        json!({}),
+    )
+    .await;
+
+    assert_eq!(response.errors, vec![]);
+    assert_eq!(
+        response.data,
+        Some(json!({
+            "users": [
+                { "name": "Ada", "posts": [{ "title": "post 1" }] },
+                { "name": "Alan", "posts": [{ "title": "post 2" }] }
+            ]
+        }))
+    );
+}
+
+#[tokio::test]
+async fn resolves_entities() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/users/3/posts"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "id": 30, "title": "post 3" }
+        ])))
+        .mount(&server)
+        .await;
+    let plugin = connectors(&server).await;
+
+    let response = execute(
+        &plugin,
+        OperationKind::Query,
+        "query($representations: [_Any!]!) { _entities(representations: $representations) { ...on User { id posts { id title } } } }",
+        json!({ "representations": [{ "__typename": "User", "id": 3 }] }),
+    )
+    .await;
+
+    assert_eq!(response.errors, vec![]);
+    assert_eq!(
+        response.data,
+        Some(json!({
+            "_entities": [{ "id": 3, "posts": [{ "id": 30, "title": "post 3" }] }]
+        }))
+    );
+}
+
+#[tokio::test]
+async fn sends_request_bodies() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/users"))
+        .and(header("content-type", "application/json"))
+        .and(body_json(serde_json::json!({ "name": "Grace" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": 4,
+            "name": "Grace"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let plugin = connectors(&server).await;
+
+    let response = execute(
+        &plugin,
+        OperationKind::Mutation,
+        "mutation { createUser(name: \"Grace\") { id name } }",
+        json!({}),
+    )
+    .await;
+
+    assert_eq!(response.errors, vec![]);
+    assert_eq!(
+        response.data,
+        Some(json!({ "createUser": { "id": 4, "name": "Grace" } }))
+    );
+}
+
+#[tokio::test]
+async fn executes_mutation_fields_serially() {
+    let server = MockServer::start().await;
+    let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for (name, delay) in [("Ada", 200), ("Alan", 0)] {
+        let calls = calls.clone();
+        Mock::given(method("POST"))
+            .and(path("/api/users"))
+            .and(body_json(serde_json::json!({ "name": name })))
+            .respond_with(move |_: &wiremock::Request| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push((name, std::time::Instant::now()));
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": 1, "name": name }))
+                    .set_delay(std::time::Duration::from_millis(delay))
+            })
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    let plugin = connectors(&server).await;
+
+    let response = execute(
+        &plugin,
+        OperationKind::Mutation,
+        r#"mutation { first: createUser(name: "Ada") { name } second: createUser(name: "Alan") { name } }"#,
+        json!({}),
+    )
+    .await;
+
+    assert_eq!(response.errors, vec![]);
+    assert_eq!(
+        response.data,
+        Some(json!({ "first": { "name": "Ada" }, "second": { "name": "Alan" } }))
+    );
+    // the second field is only sent once the response of the first one is received
+    let calls = calls.lock().unwrap();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].0, "Ada");
+    assert!(calls[1].1 - calls[0].1 >= std::time::Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn reports_missing_fields() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/users/1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "id": 1 })))
+        .mount(&server)
+        .await;
+    let plugin = connectors(&server).await;
+
+    // the connector response has no name
+    let response = execute(
+        &plugin,
+        OperationKind::Query,
+        "{ user(id: 1) { id name } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(
+        response.data,
+        Some(json!({ "user": { "id": 1, "name": null } }))
+    );
+    assert_eq!(response.errors.len(), 2);
+    assert_eq!(response.errors[0].path, Some(Path::from("user")));
+    assert_eq!(response.errors[1].path, Some(Path::from("user/name")));
+
+    // entity representations only have the key fields
+    let response = execute(
+        &plugin,
+        OperationKind::Query,
+        "query($representations: [_Any!]!) { _entities(representations: $representations) { ...on User { id name } } }",
+        json!({ "representations": [{ "__typename": "User", "id": 3 }] }),
+    )
+    .await;
+    assert_eq!(
+        response.data,
+        Some(json!({ "_entities": [{ "id": 3, "name": null }] }))
+    );
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(
+        response.errors[0].message,
+        "no connector resolves User.name, and it is missing from its parent"
+    );
+    assert_eq!(
+        response.errors[0].path,
+        Some(Path::from("_entities/0/name"))
+    );
+    assert_eq!(
+        response.errors[0].extensions.get("code"),
+        Some(&json!("CONNECTOR_FETCH"))
+    );
+}
+
+#[tokio::test]
+async fn reports_fetch_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/users/1"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    let plugin = connectors(&server).await;
+
+    let response = execute(
+        &plugin,
+        OperationKind::Query,
+        "{ user(id: 1) { id } }",
+        json!({}),
+    )
+    .await;
+
+    assert_eq!(response.data, Some(json!({ "user": null })));
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(
+        response.errors[0].message,
+        format!(
+            "HTTP fetch failed from '{}/api/users/1': 500 Internal Server Error",
+            server.uri()
+        )
+    );
+    assert_eq!(response.errors[0].path, Some(Path::from("user")));
+    assert_eq!(
+        response.errors[0].extensions.get("code"),
+        Some(&json!("CONNECTOR_FETCH"))
+    );
+}
+
+#[tokio::test]
+async fn keeps_graphql_subgraphs() {
+    let server = MockServer::start().await;
+    let plugin = connectors(&server).await;
+
+    let mut subgraph = MockSubgraphService::new();
+    subgraph.expect_call().times(1).returning(|request| {
+        Ok(subgraph::Response::fake_builder()
+            .context(request.context)
+            .data(json!({ "reviews": [] }))
+            .build())
+    });
+
+    let response = plugin
+        .subgraph_service("reviews", subgraph.boxed())
+        .oneshot(subgraph::Request::fake_builder().build())
+        .await
+        .unwrap()
+        .response
+        .into_body();
+    assert_eq!(response.data, Some(json!({ "reviews": [] })));
+}
+
+#[tokio::test]
+async fn disabled_by_default() {
+    // connectors are not parsed when disabled, so an invalid one does not prevent startup
+    let sdl = SUPERGRAPH.replace(
+        r#"args: {source: "api", http: {GET: "/users/{args.id}"}"#,
+        r#"args: {source: "unknown", http: {GET: "/users/{args.id}"}"#,
+    );
+    assert!(Connectors::new(PluginInit::fake_new(
+        serde_json::from_value(serde_json::json!({ "enabled": true })).unwrap(),
+        Arc::new(sdl.clone())
+    ))
+    .await
+    .is_err());
+    let plugin = Connectors::new(PluginInit::fake_new(Config::default(), Arc::new(sdl)))
+        .await
+        .unwrap();
+
+    let mut subgraph = MockSubgraphService::new();
+    subgraph.expect_call().times(1).returning(|request| {
+        Ok(subgraph::Response::fake_builder()
+            .context(request.context)
+            .data(json!({ "user": null }))
+            .build())
+    });
+
+    let response = plugin
+        .subgraph_service("users", subgraph.boxed())
+        .oneshot(subgraph::Request::fake_builder().build())
+        .await
+        .unwrap()
+        .response
+        .into_body();
+    assert_eq!(response.data, Some(json!({ "user": null })));
+}
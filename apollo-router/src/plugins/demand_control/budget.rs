@@ -0,0 +1,411 @@
+//! Cost budgets shared by the requests of a client
+//!
+//! Each request draws its estimated cost from the budget of its client, and the difference is
+//! refunded once the actual cost is known, if it turns out lower.
+
+use std::num::NonZeroU64;
+use std::time::Duration;
+
+use http::header::RETRY_AFTER;
+use http::HeaderName;
+use http::HeaderValue;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json_bytes::json;
+use tower::BoxError;
+
+use crate::cache::redis::RedisCacheStorage;
+use crate::configuration::RedisCache;
+use crate::error::ConfigurationError;
+use crate::plugins::demand_control::CostContext;
+use crate::plugins::demand_control::DemandControlError;
+use crate::plugins::traffic_shaping::rate::ClientKey;
+use crate::plugins::traffic_shaping::rate::KeyedRateLimiter;
+use crate::plugins::traffic_shaping::rate::Permit;
+use crate::plugins::traffic_shaping::rate::Rate;
+use crate::plugins::traffic_shaping::rate::RateLimitStorage;
+use crate::services::execution;
+use crate::services::supergraph;
+use crate::Context;
+
+static COST_BUDGET_LIMIT_HEADER: HeaderName = HeaderName::from_static("apollo-cost-budget-limit");
+static COST_BUDGET_REMAINING_HEADER: HeaderName =
+    HeaderName::from_static("apollo-cost-budget-remaining");
+const COST_BUDGET_EXTENSION: &str = "costBudget";
+
+/// Cost budget of each client
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BudgetConfig {
+    /// Total cost allowed for each client
+    capacity: NonZeroU64,
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    /// Per interval
+    interval: Duration,
+    /// How clients are identified. Requests without a value for this key are not limited
+    key: ClientKey,
+    /// Where the budgets are stored. With `redis`, they are shared between router instances
+    /// and the `redis` configuration is required (default: memory)
+    #[serde(default)]
+    storage: RateLimitStorage,
+    /// Redis configuration, used by the budgets stored in Redis
+    redis: Option<RedisCache>,
+    /// How the remaining budget is reported to clients (default: headers)
+    #[serde(default)]
+    report: BudgetReport,
+}
+
+/// How the remaining budget is reported to clients
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BudgetReport {
+    /// In the `apollo-cost-budget-limit` and `apollo-cost-budget-remaining` response headers
+    #[default]
+    Headers,
+    /// In the `costBudget` response extension
+    Extensions,
+    /// Not reported
+    Disabled,
+}
+
+/// The budget drawn by a request, stored in the context extensions
+#[derive(Clone, Debug)]
+struct BudgetContext {
+    key: String,
+    /// The amount drawn from the budget and the permit it was drawn with, `None` if the budget
+    /// was exceeded
+    drawn: Option<(u64, Permit)>,
+    /// Time until the budget allows the request, if it was exceeded
+    retry_after: Option<HeaderValue>,
+    /// The request was rejected because the budget was exceeded
+    rejected: bool,
+}
+
+pub(crate) struct CostBudget {
+    key: ClientKey,
+    limit: u64,
+    limiter: KeyedRateLimiter,
+    report: BudgetReport,
+}
+
+impl CostBudget {
+    pub(crate) async fn new(config: &BudgetConfig) -> Result<Self, BoxError> {
+        let redis = match (config.storage, config.redis.clone()) {
+            (RateLimitStorage::Memory, _) => None,
+            (RateLimitStorage::Redis, Some(redis_config)) => {
+                let required_to_start = redis_config.required_to_start;
+                match RedisCacheStorage::new(redis_config).await {
+                    Ok(storage) => Some(storage),
+                    Err(e) => {
+                        tracing::error!(
+                            e,
+                            "could not open connection to Redis for cost budgets, using in memory budgets",
+                        );
+                        if required_to_start {
+                            return Err(e);
+                        }
+                        None
+                    }
+                }
+            }
+            (RateLimitStorage::Redis, None) => {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "bad configuration for demand control plugin",
+                    error: "cost budgets stored in Redis require the redis configuration"
+                        .to_string(),
+                }
+                .into());
+            }
+        };
+
+        Ok(Self {
+            key: config.key.clone(),
+            limit: config.capacity.get(),
+            limiter: KeyedRateLimiter::new(
+                "cost_budget".to_string(),
+                Rate::new(config.capacity, config.interval),
+                redis,
+            ),
+            report: config.report,
+        })
+    }
+
+    /// Draws the estimated cost of the request from the budget of its client.
+    ///
+    /// With `enforce`, an exceeded budget rejects the request.
+    pub(crate) async fn draw(
+        &self,
+        request: &execution::Request,
+        enforce: bool,
+    ) -> Result<(), DemandControlError> {
+        // requests without a client key are not limited
+        let Some(key) = self.key.extract(request) else {
+            return Ok(());
+        };
+        let estimated = request.context.extensions().with_lock(|lock| {
+            lock.get::<CostContext>()
+                .map_or(0.0, |cost_context| cost_context.estimated)
+        });
+        let amount = estimated.max(0.0).ceil() as u64;
+
+        let (result, budget_context) = match self.limiter.acquire_many(&key, amount).await {
+            Ok(permit) => (
+                Ok(()),
+                BudgetContext {
+                    key,
+                    drawn: Some((amount, permit)),
+                    retry_after: None,
+                    rejected: false,
+                },
+            ),
+            Err(rate_limited) => (
+                Err(DemandControlError::CostBudgetExceeded {
+                    estimated_cost: estimated,
+                }),
+                BudgetContext {
+                    key,
+                    drawn: None,
+                    retry_after: rate_limited.retry_after(),
+                    rejected: enforce,
+                },
+            ),
+        };
+
+        request.context.extensions().with_lock(|mut lock| {
+            if let Err(error) = &result {
+                lock.get_or_default_mut::<CostContext>().result = error.code();
+            }
+            lock.insert(budget_context);
+        });
+        if enforce {
+            result
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gives back the part of the drawn budget that was not used by the actual cost
+    pub(crate) async fn refund(&self, context: &Context) {
+        let Some((key, amount, permit, actual)) = context.extensions().with_lock(|lock| {
+            let budget_context = lock.get::<BudgetContext>()?;
+            let (amount, permit) = budget_context.drawn?;
+            let actual = lock
+                .get::<CostContext>()
+                .map_or(0.0, |cost_context| cost_context.actual);
+            Some((budget_context.key.clone(), amount, permit, actual))
+        }) else {
+            return;
+        };
+
+        let unused = amount.saturating_sub(actual.max(0.0).ceil() as u64);
+        self.limiter.release(&key, &permit, unused).await;
+    }
+
+    /// Reports the remaining budget to the client, and sets the status and `Retry-After` header
+    /// of rejected requests. The reported budget does not include the refund of this request
+    pub(crate) fn report(&self, mut response: supergraph::Response) -> supergraph::Response {
+        let Some(budget_context) = response
+            .context
+            .extensions()
+            .with_lock(|lock| lock.get::<BudgetContext>().cloned())
+        else {
+            return response;
+        };
+
+        if budget_context.rejected {
+            *response.response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            if let Some(retry_after) = budget_context.retry_after {
+                response
+                    .response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, retry_after);
+            }
+        }
+
+        // the remaining budget is the one computed when the estimated cost was drawn: the unused
+        // cost is only refunded once the response is complete, after the headers are sent. It is
+        // unknown when the budget was exceeded
+        let remaining = budget_context.drawn.map(|(_, permit)| permit.remaining);
+        match self.report {
+            BudgetReport::Headers => {
+                let headers = response.response.headers_mut();
+                headers.insert(
+                    COST_BUDGET_LIMIT_HEADER.clone(),
+                    HeaderValue::from(self.limit),
+                );
+                if let Some(remaining) = remaining {
+                    headers.insert(
+                        COST_BUDGET_REMAINING_HEADER.clone(),
+                        HeaderValue::from(remaining),
+                    );
+                }
+            }
+            BudgetReport::Extensions => {
+                let limit = self.limit;
+                let mut first = true;
+                response = response.map_stream(move |mut graphql_response| {
+                    if std::mem::take(&mut first) {
+                        graphql_response.extensions.insert(
+                            COST_BUDGET_EXTENSION,
+                            json!({ "limit": limit, "remaining": remaining }),
+                        );
+                    }
+                    graphql_response
+                });
+            }
+            BudgetReport::Disabled => {}
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphql;
+
+    async fn budget(report: &str) -> CostBudget {
+        let config = serde_json::from_value::<BudgetConfig>(serde_json::json!({
+            "capacity": 10,
+            "interval": "60s",
+            "key": { "header": "x-client-id" },
+            "report": report,
+        }))
+        .unwrap();
+        CostBudget::new(&config).await.unwrap()
+    }
+
+    fn request(client: Option<&str>, estimated: f64) -> execution::Request {
+        let context = Context::new();
+        context.extensions().with_lock(|mut lock| {
+            lock.insert(CostContext {
+                estimated,
+                ..Default::default()
+            });
+        });
+        let mut supergraph_request = http::Request::builder();
+        if let Some(client) = client {
+            supergraph_request = supergraph_request.header("x-client-id", client);
+        }
+        execution::Request::fake_builder()
+            .supergraph_request(
+                supergraph_request
+                    .body(graphql::Request::default())
+                    .unwrap(),
+            )
+            .context(context)
+            .build()
+    }
+
+    fn result(request: &execution::Request) -> &'static str {
+        request
+            .context
+            .extensions()
+            .with_lock(|lock| lock.get::<CostContext>().unwrap().result)
+    }
+
+    #[tokio::test]
+    async fn budget_exhaustion() {
+        let budget = budget("headers").await;
+
+        assert!(budget.draw(&request(Some("a"), 6.0), true).await.is_ok());
+        // the budget of the client is exhausted
+        let rejected = request(Some("a"), 6.0);
+        assert!(matches!(
+            budget.draw(&rejected, true).await,
+            Err(DemandControlError::CostBudgetExceeded { estimated_cost }) if estimated_cost == 6.0
+        ));
+        assert_eq!(result(&rejected), "COST_BUDGET_EXCEEDED");
+        // in measure mode, the request is not rejected, but the result is recorded
+        let measured = request(Some("a"), 6.0);
+        assert!(budget.draw(&measured, false).await.is_ok());
+        assert_eq!(result(&measured), "COST_BUDGET_EXCEEDED");
+        // a smaller request still fits
+        assert!(budget.draw(&request(Some("a"), 4.0), true).await.is_ok());
+
+        // each client has its own budget
+        assert!(budget.draw(&request(Some("b"), 10.0), true).await.is_ok());
+        // a request costing more than the whole budget is always rejected
+        assert!(budget.draw(&request(Some("c"), 11.0), true).await.is_err());
+        // requests without a client key are not limited
+        assert!(budget.draw(&request(None, 100.0), true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refunds_unused_cost() {
+        let budget = budget("headers").await;
+
+        let first = request(Some("a"), 8.0);
+        assert!(budget.draw(&first, true).await.is_ok());
+        assert!(budget.draw(&request(Some("a"), 5.0), true).await.is_err());
+
+        first.context.extensions().with_lock(|mut lock| {
+            lock.get_or_default_mut::<CostContext>().actual = 3.0;
+        });
+        budget.refund(&first.context).await;
+        assert!(budget.draw(&request(Some("a"), 5.0), true).await.is_ok());
+
+        // nothing is refunded for rejected requests
+        let rejected = request(Some("a"), 5.0);
+        assert!(budget.draw(&rejected, true).await.is_err());
+        budget.refund(&rejected.context).await;
+        assert!(budget.draw(&request(Some("a"), 5.0), true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reports_the_remaining_budget() {
+        let budget = budget("headers").await;
+
+        let accepted = request(Some("a"), 6.0);
+        budget.draw(&accepted, true).await.unwrap();
+        let response = budget.report(
+            supergraph::Response::fake_builder()
+                .context(accepted.context)
+                .build()
+                .unwrap(),
+        );
+        assert_eq!(response.response.status(), StatusCode::OK);
+        let headers = response.response.headers();
+        assert_eq!(headers[&COST_BUDGET_LIMIT_HEADER], "10");
+        assert_eq!(headers[&COST_BUDGET_REMAINING_HEADER], "4");
+
+        let rejected = request(Some("a"), 6.0);
+        assert!(budget.draw(&rejected, true).await.is_err());
+        let response = budget.report(
+            supergraph::Response::fake_builder()
+                .context(rejected.context)
+                .build()
+                .unwrap(),
+        );
+        assert_eq!(response.response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.response.headers();
+        assert!(headers.contains_key(RETRY_AFTER));
+        assert_eq!(headers[&COST_BUDGET_LIMIT_HEADER], "10");
+        assert!(!headers.contains_key(&COST_BUDGET_REMAINING_HEADER));
+    }
+
+    #[tokio::test]
+    async fn reports_in_extensions() {
+        let budget = budget("extensions").await;
+
+        let accepted = request(Some("a"), 6.0);
+        budget.draw(&accepted, true).await.unwrap();
+        let mut response = budget.report(
+            supergraph::Response::fake_builder()
+                .context(accepted.context)
+                .build()
+                .unwrap(),
+        );
+        assert!(!response
+            .response
+            .headers()
+            .contains_key(&COST_BUDGET_LIMIT_HEADER));
+        let graphql_response = response.next_response().await.unwrap();
+        assert_eq!(
+            graphql_response.extensions.get(COST_BUDGET_EXTENSION),
+            Some(&json!({ "limit": 10, "remaining": 4 }))
+        );
+    }
+}
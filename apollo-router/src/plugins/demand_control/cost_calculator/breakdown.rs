@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+/// The estimated cost of a query plan, broken down by subgraph fetch and field so clients can
+/// find out what makes their queries expensive
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub(crate) struct CostBreakdown {
+    /// The estimated cost of the query plan
+    pub(crate) estimated: f64,
+    /// The estimated cost of each subgraph fetch of the query plan
+    pub(crate) fetches: Vec<FetchCost>,
+}
+
+/// The estimated cost of a subgraph fetch of a query plan
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub(crate) struct FetchCost {
+    /// The name of the subgraph
+    pub(crate) subgraph: String,
+    /// The estimated cost of the subgraph operation
+    pub(crate) cost: f64,
+    /// The estimated cost of each field of the subgraph operation
+    pub(crate) fields: Vec<FieldCost>,
+}
+
+/// The estimated cost of a field of an operation
+///
+/// `cost = multiplier * (weight + the cost of its selections) + arguments + requirements`
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub(crate) struct FieldCost {
+    /// The path of the field in the response, such as `products.reviews`
+    pub(crate) path: String,
+    /// The number of instances of the field: the expected size of the list it returns, or 1
+    pub(crate) multiplier: f64,
+    /// The weight of each instance, from `@cost` or the default weight of its type
+    pub(crate) weight: f64,
+    /// The cost of the arguments of the field
+    pub(crate) arguments: f64,
+    /// The cost of the fields required by `@requires`
+    pub(crate) requirements: f64,
+    /// The total cost of the field, including its selections
+    pub(crate) cost: f64,
+}
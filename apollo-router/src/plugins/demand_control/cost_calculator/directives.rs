@@ -1,3 +1,4 @@
+use apollo_compiler::ast::Directive;
 use apollo_compiler::ast::NamedType;
 use apollo_compiler::executable::Field;
 use apollo_compiler::executable::SelectionSet;
@@ -7,6 +8,107 @@ use apollo_compiler::Schema;
 use tower::BoxError;
 
 use super::DemandControlError;
+use crate::json_ext::Object;
+
+/// `@cost(weight: Int!)`, which sets the cost of a field, type, argument or input field
+#[derive(Clone, Debug, PartialEq)]
+pub(in crate::plugins::demand_control) struct CostDirective {
+    pub(in crate::plugins::demand_control) weight: f64,
+}
+
+impl CostDirective {
+    pub(in crate::plugins::demand_control) fn from_directive(
+        directive: &Directive,
+    ) -> Option<Self> {
+        directive
+            .argument_by_name("weight")
+            .and_then(|weight| weight.to_i32())
+            .map(|weight| Self {
+                weight: weight as f64,
+            })
+    }
+}
+
+/// `@listSize(assumedSize: Int, slicingArguments: [String!], sizedFields: [String!], requireOneSlicingArgument: Boolean = true)`,
+/// which sets the expected size of the lists returned by a field
+#[derive(Clone, Debug, PartialEq)]
+pub(in crate::plugins::demand_control) struct ListSizeDirective {
+    pub(in crate::plugins::demand_control) assumed_size: Option<f64>,
+    pub(in crate::plugins::demand_control) slicing_arguments: Vec<String>,
+    pub(in crate::plugins::demand_control) sized_fields: Vec<String>,
+    pub(in crate::plugins::demand_control) require_one_slicing_argument: bool,
+}
+
+impl ListSizeDirective {
+    pub(in crate::plugins::demand_control) fn from_directive(directive: &Directive) -> Self {
+        let strings = |name: &str| {
+            directive
+                .argument_by_name(name)
+                .and_then(|value| value.as_list())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_string))
+                .collect()
+        };
+        Self {
+            assumed_size: directive
+                .argument_by_name("assumedSize")
+                .and_then(|size| size.to_i32())
+                .map(|size| size as f64),
+            slicing_arguments: strings("slicingArguments"),
+            sized_fields: strings("sizedFields"),
+            require_one_slicing_argument: directive
+                .argument_by_name("requireOneSlicingArgument")
+                .and_then(|require| require.to_bool())
+                .unwrap_or(true),
+        }
+    }
+
+    /// The expected size of the list returned by a field: the largest value of its slicing
+    /// arguments if it has some, or the assumed size
+    pub(in crate::plugins::demand_control) fn expected_size(
+        &self,
+        field: &Field,
+        variables: &Object,
+    ) -> Result<Option<f64>, DemandControlError> {
+        if self.slicing_arguments.is_empty() {
+            return Ok(self.assumed_size);
+        }
+
+        let sizes = self
+            .slicing_arguments
+            .iter()
+            .filter_map(|name| {
+                let value = field
+                    .argument_by_name(name)
+                    .map(|argument| &argument.value)
+                    .or_else(|| {
+                        field
+                            .definition
+                            .argument_by_name(name)
+                            .and_then(|definition| definition.default_value.as_ref())
+                    })?;
+                match value.as_variable() {
+                    Some(variable) => variables
+                        .get(variable.as_str())
+                        .and_then(|value| value.as_i64())
+                        .map(|size| size as f64),
+                    None => value.to_i32().map(|size| size as f64),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if self.require_one_slicing_argument && sizes.len() != 1 {
+            return Err(DemandControlError::QueryParseFailure(format!(
+                "Exactly one slicing argument is required on field {}, but {} were provided",
+                field.name,
+                sizes.len()
+            )));
+        }
+
+        Ok(sizes.into_iter().reduce(f64::max).or(self.assumed_size))
+    }
+}
 
 pub(in crate::plugins::demand_control) struct IncludeDirective {
     pub(in crate::plugins::demand_control) is_included: bool,
@@ -89,3 +191,103 @@ impl SkipDirective {
         Ok(directive)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use apollo_compiler::ExecutableDocument;
+    use serde_json_bytes::json;
+    use serde_json_bytes::Value;
+
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        directive @listSize(
+            assumedSize: Int
+            slicingArguments: [String!]
+            sizedFields: [String!]
+            requireOneSlicingArgument: Boolean = true
+        ) on FIELD_DEFINITION
+
+        type Query {
+            items(first: Int = 10, last: Int): [Int]
+                @listSize(slicingArguments: ["first", "last"], requireOneSlicingArgument: false)
+            page(first: Int, last: Int): [Int] @listSize(slicingArguments: ["first", "last"])
+            defaulted(first: Int = 3): [Int] @listSize(slicingArguments: ["first"])
+            assumed: [Int] @listSize(assumedSize: 7)
+        }
+    "#;
+
+    fn expected_size(query: &str, variables: Value) -> Result<Option<f64>, DemandControlError> {
+        let schema = Schema::parse_and_validate(SCHEMA, "schema.graphqls").unwrap();
+        let document =
+            ExecutableDocument::parse_and_validate(&schema, query, "query.graphql").unwrap();
+        let field = document
+            .get_operation(None)
+            .unwrap()
+            .selection_set
+            .selections[0]
+            .as_field()
+            .unwrap();
+        let directive = field.definition.directives.get("listSize").unwrap();
+        ListSizeDirective::from_directive(directive)
+            .expected_size(field, variables.as_object().unwrap())
+    }
+
+    #[test]
+    fn expected_size_from_slicing_arguments() {
+        // the largest slicing argument is used
+        assert_eq!(
+            expected_size("{ items(first: 5, last: 8) }", json!({})).unwrap(),
+            Some(8.0)
+        );
+        assert_eq!(
+            expected_size(
+                "query($last: Int) { items(first: 2, last: $last) }",
+                json!({ "last": 20 })
+            )
+            .unwrap(),
+            Some(20.0)
+        );
+        // arguments that are not set use their default value
+        assert_eq!(expected_size("{ items }", json!({})).unwrap(), Some(10.0));
+        assert_eq!(
+            expected_size("query($last: Int) { items(last: $last) }", json!({})).unwrap(),
+            Some(10.0)
+        );
+        assert_eq!(
+            expected_size("{ defaulted }", json!({})).unwrap(),
+            Some(3.0)
+        );
+        // without slicing arguments, the assumed size is used
+        assert_eq!(expected_size("{ assumed }", json!({})).unwrap(), Some(7.0));
+    }
+
+    #[test]
+    fn expected_size_requires_one_slicing_argument() {
+        assert_eq!(
+            expected_size("{ page(first: 3) }", json!({})).unwrap(),
+            Some(3.0)
+        );
+        assert_eq!(
+            expected_size(
+                "query($last: Int) { page(last: $last) }",
+                json!({ "last": 4 })
+            )
+            .unwrap(),
+            Some(4.0)
+        );
+
+        for (query, variables) in [
+            ("{ page }", json!({})),
+            ("{ page(first: 1, last: 2) }", json!({})),
+            // a variable without a value does not count as a slicing argument
+            ("query($last: Int) { page(last: $last) }", json!({})),
+        ] {
+            assert!(matches!(
+                expected_size(query, variables),
+                Err(DemandControlError::QueryParseFailure(message))
+                    if message.starts_with("Exactly one slicing argument is required on field page")
+            ));
+        }
+    }
+}
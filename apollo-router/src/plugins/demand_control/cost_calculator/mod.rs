@@ -1,4 +1,6 @@
+pub(crate) mod breakdown;
 mod directives;
+pub(crate) mod schema;
 pub(crate) mod static_cost;
 
 use crate::plugins::demand_control::DemandControlError;
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use apollo_compiler::ast::Argument;
+use apollo_compiler::ast::Directive;
+use apollo_compiler::name;
+use apollo_compiler::schema::ExtendedType;
+use apollo_compiler::Name;
+use apollo_compiler::Node;
+use apollo_compiler::Schema;
+use apollo_federation::link::database::links_metadata;
+use apollo_federation::link::spec::Identity;
+
+use super::directives::CostDirective;
+use super::directives::ListSizeDirective;
+
+const COST_DIRECTIVE_NAME: Name = name!("cost");
+const LIST_SIZE_DIRECTIVE_NAME: Name = name!("listSize");
+
+/// The `@cost` and `@listSize` directives of a supergraph, indexed by type and field names.
+///
+/// They are read when applied directly in the supergraph, imported from the cost specification,
+/// or when carried by composition in `@join__directive(name: "cost" | "listSize")` applications.
+#[derive(Debug, Default)]
+pub(crate) struct DemandControlledSchema {
+    type_costs: HashMap<Name, CostDirective>,
+    fields: HashMap<Name, HashMap<Name, FieldDirectives>>,
+}
+
+#[derive(Debug, Default)]
+struct FieldDirectives {
+    cost: Option<CostDirective>,
+    list_size: Option<ListSizeDirective>,
+    argument_costs: HashMap<Name, CostDirective>,
+}
+
+impl DemandControlledSchema {
+    pub(crate) fn new(schema: &Schema) -> Self {
+        let names = DirectiveNames::new(schema);
+        let mut demand_controlled_schema = Self::default();
+
+        for (type_name, ty) in &schema.types {
+            let type_directives = match ty {
+                ExtendedType::Object(object) => {
+                    names.parse(object.directives.iter().map(|d| &d.node))
+                }
+                ExtendedType::Scalar(scalar) => {
+                    names.parse(scalar.directives.iter().map(|d| &d.node))
+                }
+                ExtendedType::Enum(enum_type) => {
+                    names.parse(enum_type.directives.iter().map(|d| &d.node))
+                }
+                _ => Default::default(),
+            };
+            if let Some(cost) = type_directives.cost {
+                demand_controlled_schema
+                    .type_costs
+                    .insert(type_name.clone(), cost);
+            }
+
+            let fields = match ty {
+                ExtendedType::Object(object) => object
+                    .fields
+                    .iter()
+                    .map(|(name, field)| (name, &field.directives, field.arguments.as_slice()))
+                    .collect::<Vec<_>>(),
+                ExtendedType::Interface(interface) => interface
+                    .fields
+                    .iter()
+                    .map(|(name, field)| (name, &field.directives, field.arguments.as_slice()))
+                    .collect(),
+                ExtendedType::InputObject(input_object) => input_object
+                    .fields
+                    .iter()
+                    .map(|(name, field)| (name, &field.directives, &[][..]))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for (field_name, directives, arguments) in fields {
+                let mut field_directives = names.parse(directives.iter());
+                for argument in arguments {
+                    if let Some(cost) = names.parse(argument.directives.iter()).cost {
+                        field_directives
+                            .argument_costs
+                            .insert(argument.name.clone(), cost);
+                    }
+                }
+                if field_directives.cost.is_some()
+                    || field_directives.list_size.is_some()
+                    || !field_directives.argument_costs.is_empty()
+                {
+                    demand_controlled_schema
+                        .fields
+                        .entry(type_name.clone())
+                        .or_default()
+                        .insert(field_name.clone(), field_directives);
+                }
+            }
+        }
+
+        demand_controlled_schema
+    }
+
+    /// The weight of a type, from `@cost` on an object, scalar or enum type
+    pub(crate) fn type_cost(&self, type_name: &str) -> Option<f64> {
+        self.type_costs.get(type_name).map(|cost| cost.weight)
+    }
+
+    /// The weight of a field or input field, from `@cost` on its definition
+    pub(crate) fn field_cost(&self, type_name: &str, field_name: &str) -> Option<f64> {
+        self.field(type_name, field_name)?
+            .cost
+            .as_ref()
+            .map(|cost| cost.weight)
+    }
+
+    /// The weight of an argument, from `@cost` on its definition
+    pub(crate) fn argument_cost(
+        &self,
+        type_name: &str,
+        field_name: &str,
+        argument_name: &str,
+    ) -> Option<f64> {
+        self.field(type_name, field_name)?
+            .argument_costs
+            .get(argument_name)
+            .map(|cost| cost.weight)
+    }
+
+    pub(in crate::plugins::demand_control) fn list_size(
+        &self,
+        type_name: &str,
+        field_name: &str,
+    ) -> Option<&ListSizeDirective> {
+        self.field(type_name, field_name)?.list_size.as_ref()
+    }
+
+    fn field(&self, type_name: &str, field_name: &str) -> Option<&FieldDirectives> {
+        self.fields.get(type_name)?.get(field_name)
+    }
+}
+
+/// The names of the `@cost` and `@listSize` directives in a schema, which depend on how the cost
+/// specification is imported
+struct DirectiveNames {
+    cost: Name,
+    list_size: Name,
+}
+
+impl DirectiveNames {
+    fn new(schema: &Schema) -> Self {
+        let link = links_metadata(schema)
+            .ok()
+            .flatten()
+            .and_then(|metadata| metadata.for_identity(&Identity::cost_identity()));
+        Self {
+            cost: link
+                .as_ref()
+                .map(|link| link.directive_name_in_schema(&COST_DIRECTIVE_NAME))
+                .unwrap_or(COST_DIRECTIVE_NAME),
+            list_size: link
+                .as_ref()
+                .map(|link| link.directive_name_in_schema(&LIST_SIZE_DIRECTIVE_NAME))
+                .unwrap_or(LIST_SIZE_DIRECTIVE_NAME),
+        }
+    }
+
+    fn parse<'a>(&self, directives: impl Iterator<Item = &'a Node<Directive>>) -> FieldDirectives {
+        let mut parsed = FieldDirectives::default();
+        for directive in directives {
+            let Some(directive) = self.normalize(directive) else {
+                continue;
+            };
+            if directive.name == COST_DIRECTIVE_NAME {
+                if let Some(cost) = CostDirective::from_directive(&directive) {
+                    // when several subgraphs set a cost, the highest one is used
+                    if parsed
+                        .cost
+                        .as_ref()
+                        .map_or(true, |current| current.weight < cost.weight)
+                    {
+                        parsed.cost = Some(cost);
+                    }
+                }
+            } else if parsed.list_size.is_none() {
+                parsed.list_size = Some(ListSizeDirective::from_directive(&directive));
+            }
+        }
+        parsed
+    }
+
+    /// Returns the directive with its name in the specification if it is a `@cost` or
+    /// `@listSize` directive, directly applied or carried by `@join__directive`
+    fn normalize(&self, directive: &Node<Directive>) -> Option<Node<Directive>> {
+        if directive.name == self.cost {
+            return Some(Node::new(Directive {
+                name: COST_DIRECTIVE_NAME,
+                arguments: directive.arguments.clone(),
+            }));
+        }
+        if directive.name == self.list_size {
+            return Some(Node::new(Directive {
+                name: LIST_SIZE_DIRECTIVE_NAME,
+                arguments: directive.arguments.clone(),
+            }));
+        }
+        if directive.name != "join__directive" {
+            return None;
+        }
+
+        let name = directive
+            .argument_by_name("name")
+            .and_then(|name| name.as_str())?;
+        let name = match name.trim_start_matches('@') {
+            "cost" => COST_DIRECTIVE_NAME,
+            "listSize" => LIST_SIZE_DIRECTIVE_NAME,
+            _ => return None,
+        };
+        let arguments = directive
+            .argument_by_name("args")
+            .and_then(|args| args.as_object())
+            .unwrap_or_default()
+            .iter()
+            .map(|(name, value)| {
+                Node::new(Argument {
+                    name: name.clone(),
+                    value: value.clone(),
+                })
+            })
+            .collect();
+        Some(Node::new(Directive { name, arguments }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cost_directives() {
+        let schema = Schema::parse(
+            r#"
+            schema
+              @link(url: "https://specs.apollo.dev/link/v1.0")
+              @link(url: "https://specs.apollo.dev/cost/v0.1", import: [{ name: "@cost", as: "@weight" }])
+            {
+              query: Query
+            }
+            directive @link(url: String, as: String, import: [link__Import]) repeatable on SCHEMA
+            directive @weight(weight: Int!) on ARGUMENT_DEFINITION | ENUM | FIELD_DEFINITION | INPUT_FIELD_DEFINITION | OBJECT | SCALAR
+            directive @cost__listSize(assumedSize: Int, slicingArguments: [String!], sizedFields: [String!], requireOneSlicingArgument: Boolean = true) on FIELD_DEFINITION
+            directive @join__directive(graphs: [String!], name: String!, args: String) repeatable on SCHEMA | OBJECT | INTERFACE | FIELD_DEFINITION
+            scalar link__Import
+
+            type Query {
+              products(first: Int @weight(weight: 2)): [Product] @cost__listSize(slicingArguments: ["first"], requireOneSlicingArgument: false)
+              reviews: [Review] @join__directive(graphs: ["REVIEWS"], name: "listSize", args: { assumedSize: 5 })
+            }
+
+            type Product @weight(weight: 5) {
+              name: String @join__directive(graphs: ["A"], name: "cost", args: { weight: 3 }) @join__directive(graphs: ["B"], name: "cost", args: { weight: 7 })
+            }
+
+            type Review {
+              body: String
+            }
+            "#,
+            "schema.graphql",
+        )
+        .unwrap();
+
+        let schema = DemandControlledSchema::new(&schema);
+        assert_eq!(schema.type_cost("Product"), Some(5.0));
+        assert_eq!(schema.type_cost("Review"), None);
+        assert_eq!(schema.field_cost("Product", "name"), Some(7.0));
+        assert_eq!(schema.field_cost("Review", "body"), None);
+        assert_eq!(
+            schema.argument_cost("Query", "products", "first"),
+            Some(2.0)
+        );
+        assert_eq!(
+            schema.list_size("Query", "products"),
+            Some(&ListSizeDirective {
+                assumed_size: None,
+                slicing_arguments: vec!["first".to_string()],
+                sized_fields: vec![],
+                require_one_slicing_argument: false,
+            })
+        );
+        assert_eq!(
+            schema
+                .list_size("Query", "reviews")
+                .and_then(|list_size| list_size.assumed_size),
+            Some(5.0)
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use std::cell::RefCell;
 use std::sync::Arc;
 
+use apollo_compiler::ast;
 use apollo_compiler::ast::NamedType;
 use apollo_compiler::executable::ExecutableDocument;
 use apollo_compiler::executable::Field;
@@ -9,15 +11,21 @@ use apollo_compiler::executable::Operation;
 use apollo_compiler::executable::Selection;
 use apollo_compiler::executable::SelectionSet;
 use apollo_compiler::validation::Valid;
+use apollo_compiler::Name;
+use apollo_compiler::Node;
 use apollo_compiler::Schema;
 use serde_json_bytes::Value;
 
+use super::breakdown::FetchCost;
+use super::breakdown::FieldCost;
 use super::directives::IncludeDirective;
 use super::directives::RequiresDirective;
 use super::directives::SkipDirective;
+use super::schema::DemandControlledSchema;
 use super::DemandControlError;
 use crate::graphql::Response;
 use crate::graphql::ResponseVisitor;
+use crate::json_ext::Object;
 use crate::query_planner::fetch::SubgraphOperation;
 use crate::query_planner::fetch::SubgraphSchemas;
 use crate::query_planner::DeferredNode;
@@ -27,13 +35,81 @@ use crate::query_planner::QueryPlan;
 
 pub(crate) struct StaticCostCalculator {
     list_size: u32,
+    supergraph_schema: Arc<DemandControlledSchema>,
     subgraph_schemas: Arc<SubgraphSchemas>,
 }
 
+/// The operation being scored, and the schema it executes against
+struct ScoringContext<'a> {
+    schema: &'a Valid<Schema>,
+    query: &'a ExecutableDocument,
+    variables: &'a Object,
+    should_estimate_requires: bool,
+    /// The costs of the fields, recorded when a breakdown is requested
+    breakdown: Option<RefCell<FieldBreakdown>>,
+}
+
+#[derive(Default)]
+struct FieldBreakdown {
+    /// The response path of the field being scored
+    path: Vec<Name>,
+    fields: Vec<FieldCost>,
+}
+
+impl ScoringContext<'_> {
+    /// Records that a field is being scored, returns where its cost will be recorded
+    fn start_field(&self, field: &Field) -> Option<usize> {
+        let mut breakdown = self.breakdown.as_ref()?.borrow_mut();
+        breakdown.path.push(field.response_key().clone());
+        let path = breakdown
+            .path
+            .iter()
+            .map(|key| key.as_str())
+            .collect::<Vec<_>>()
+            .join(".");
+        breakdown.fields.push(FieldCost {
+            path,
+            ..Default::default()
+        });
+        Some(breakdown.fields.len() - 1)
+    }
+
+    fn finish_field(&self, index: Option<usize>, cost: FieldCost) {
+        if let (Some(breakdown), Some(index)) = (self.breakdown.as_ref(), index) {
+            let mut breakdown = breakdown.borrow_mut();
+            breakdown.path.pop();
+            let field = &mut breakdown.fields[index];
+            *field = FieldCost {
+                path: std::mem::take(&mut field.path),
+                ..cost
+            };
+        }
+    }
+}
+
+/// The query plan being scored
+struct PlanScoringContext<'a> {
+    variables: &'a Object,
+    /// The costs of the fetches, recorded when a breakdown is requested
+    fetches: Option<RefCell<Vec<FetchCost>>>,
+}
+
+/// The sizes of the lists returned by the fields of a selection set, set by the `sizedFields` of
+/// the `@listSize` directive of the parent field
+struct SizedFields<'a> {
+    size: f64,
+    fields: &'a [String],
+}
+
 impl StaticCostCalculator {
-    pub(crate) fn new(subgraph_schemas: Arc<SubgraphSchemas>, list_size: u32) -> Self {
+    pub(crate) fn new(
+        supergraph_schema: Arc<DemandControlledSchema>,
+        subgraph_schemas: Arc<SubgraphSchemas>,
+        list_size: u32,
+    ) -> Self {
         Self {
             list_size,
+            supergraph_schema,
             subgraph_schemas,
         }
     }
@@ -56,192 +132,316 @@ impl StaticCostCalculator {
     /// This should be okay, as we don't want this implementation to have to know about
     /// any deduplication happening in the query planner, and we're estimating an upper
     /// bound for cost anyway.
+    ///
+    /// The `@cost` and `@listSize` directives of the supergraph override the default weight of
+    /// the field and the assumed size of the lists it returns.
     fn score_field(
         &self,
+        ctx: &ScoringContext,
         field: &Field,
         parent_type: &NamedType,
-        schema: &Valid<Schema>,
-        executable: &ExecutableDocument,
-        should_estimate_requires: bool,
+        sized_fields: Option<&SizedFields>,
     ) -> Result<f64, DemandControlError> {
         if StaticCostCalculator::skipped_by_directives(field) {
             return Ok(0.0);
         }
 
-        let ty = field.inner_type_def(schema).ok_or_else(|| {
+        let breakdown_index = ctx.start_field(field);
+        let ty = field.inner_type_def(ctx.schema).ok_or_else(|| {
             DemandControlError::QueryParseFailure(format!(
                 "Field {} was found in query, but its type is missing from the schema.",
                 field.name
             ))
         })?;
 
-        // Determine how many instances we're scoring. If there's no user-provided
-        // information, assume lists have 100 items.
-        let instance_count = if field.ty().is_list() {
-            self.list_size as f64
-        } else {
+        let list_size_directive = self.supergraph_schema.list_size(parent_type, &field.name);
+        let expected_size = list_size_directive
+            .map(|directive| directive.expected_size(field, ctx.variables))
+            .transpose()?
+            .flatten();
+
+        // Determine how many instances we're scoring. The size of the list can be set by the
+        // parent field, or by the `@listSize` directive of this field. If there's no
+        // user-provided information, assume lists have the configured size.
+        let upstream_size = sized_fields
+            .filter(|sized_fields| {
+                sized_fields
+                    .fields
+                    .iter()
+                    .any(|name| name == field.name.as_str())
+            })
+            .map(|sized_fields| sized_fields.size);
+        let instance_count = if !field.ty().is_list() {
             1.0
+        } else if let Some(size) = upstream_size {
+            size
+        } else if let Some(size) = expected_size.filter(|_| {
+            list_size_directive.is_some_and(|directive| directive.sized_fields.is_empty())
+        }) {
+            size
+        } else {
+            self.list_size as f64
         };
 
-        // Determine the cost for this particular field. Scalars are free, non-scalars are not.
+        // Determine the cost for this particular field. Scalars are free, non-scalars are not,
+        // unless `@cost` is set on the field or on its type.
         // For fields with selections, add in the cost of the selections as well.
-        let mut type_cost = if ty.is_interface() || ty.is_object() || ty.is_union() {
+        let mut type_cost = if let Some(cost) = self
+            .supergraph_schema
+            .field_cost(parent_type, &field.name)
+            .or_else(|| self.supergraph_schema.type_cost(ty.name()))
+        {
+            cost
+        } else if ty.is_interface() || ty.is_object() || ty.is_union() {
             1.0
         } else {
             0.0
         };
+        let weight = type_cost;
+        let child_sized_fields = list_size_directive
+            .filter(|directive| !directive.sized_fields.is_empty())
+            .map(|directive| SizedFields {
+                size: expected_size.unwrap_or(self.list_size as f64),
+                fields: &directive.sized_fields,
+            });
         type_cost += self.score_selection_set(
+            ctx,
             &field.selection_set,
             field.ty().inner_named_type(),
-            schema,
-            executable,
-            should_estimate_requires,
+            child_sized_fields.as_ref(),
         )?;
 
+        let mut arguments_cost = 0.0;
+        for argument in &field.arguments {
+            arguments_cost += self.score_argument(ctx, argument, field, parent_type);
+        }
+
         let mut requirements_cost = 0.0;
-        if should_estimate_requires {
+        if ctx.should_estimate_requires {
             // If the field is marked with `@requires`, the required selection may not be included
             // in the query's selection. Adding that requirement's cost to the field ensures it's
             // accounted for.
             let requirements =
-                RequiresDirective::from_field(field, parent_type, schema)?.map(|d| d.fields);
+                RequiresDirective::from_field(field, parent_type, ctx.schema)?.map(|d| d.fields);
             if let Some(selection_set) = requirements {
-                requirements_cost = self.score_selection_set(
-                    &selection_set,
-                    parent_type,
-                    schema,
-                    executable,
-                    should_estimate_requires,
-                )?;
+                requirements_cost =
+                    self.score_selection_set(ctx, &selection_set, parent_type, None)?;
             }
         }
 
-        let cost = instance_count * type_cost + requirements_cost;
+        let cost = instance_count * type_cost + arguments_cost + requirements_cost;
         tracing::debug!(
-            "Field {} cost breakdown: (count) {} * (type cost) {} + (requirements) {} = {}",
+            "Field {} cost breakdown: (count) {} * (type cost) {} + (arguments) {} + (requirements) {} = {}",
             field.name,
             instance_count,
             type_cost,
+            arguments_cost,
             requirements_cost,
             cost
         );
+        ctx.finish_field(
+            breakdown_index,
+            FieldCost {
+                path: String::new(),
+                multiplier: instance_count,
+                weight,
+                arguments: arguments_cost,
+                requirements: requirements_cost,
+                cost,
+            },
+        );
 
         Ok(cost)
     }
 
+    /// Scores an argument set in the operation, with the `@cost` directive of its definition and
+    /// of the input fields it sets
+    fn score_argument(
+        &self,
+        ctx: &ScoringContext,
+        argument: &Node<ast::Argument>,
+        field: &Field,
+        parent_type: &NamedType,
+    ) -> f64 {
+        let Some(definition) = field.definition.argument_by_name(&argument.name) else {
+            return 0.0;
+        };
+        let input_cost =
+            self.score_input_value(ctx, definition.ty.inner_named_type(), &argument.value);
+        if input_cost.is_none() {
+            // the argument is null or its variable is not set
+            return 0.0;
+        }
+        self.supergraph_schema
+            .argument_cost(parent_type, &field.name, &argument.name)
+            .unwrap_or(0.0)
+            + input_cost.unwrap_or(0.0)
+    }
+
+    /// Returns `None` if the value is null
+    fn score_input_value(
+        &self,
+        ctx: &ScoringContext,
+        ty: &NamedType,
+        value: &ast::Value,
+    ) -> Option<f64> {
+        match value {
+            ast::Value::Null => None,
+            ast::Value::Variable(name) => ctx
+                .variables
+                .get(name.as_str())
+                .and_then(|value| self.score_input_json_value(ctx, ty, value)),
+            ast::Value::List(items) => Some(
+                items
+                    .iter()
+                    .filter_map(|item| self.score_input_value(ctx, ty, item))
+                    .sum(),
+            ),
+            ast::Value::Object(fields) => Some(
+                fields
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        let field_type = ctx
+                            .schema
+                            .get_input_object(ty)?
+                            .fields
+                            .get(name)?
+                            .ty
+                            .inner_named_type();
+                        let value_cost = self.score_input_value(ctx, field_type, value)?;
+                        Some(
+                            self.supergraph_schema.field_cost(ty, name).unwrap_or(0.0) + value_cost,
+                        )
+                    })
+                    .sum(),
+            ),
+            _ => Some(0.0),
+        }
+    }
+
+    /// Returns `None` if the value is null
+    fn score_input_json_value(
+        &self,
+        ctx: &ScoringContext,
+        ty: &NamedType,
+        value: &Value,
+    ) -> Option<f64> {
+        match value {
+            Value::Null => None,
+            Value::Array(items) => Some(
+                items
+                    .iter()
+                    .filter_map(|item| self.score_input_json_value(ctx, ty, item))
+                    .sum(),
+            ),
+            Value::Object(fields) => Some(
+                fields
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        let field_type = ctx
+                            .schema
+                            .get_input_object(ty)?
+                            .fields
+                            .get(name.as_str())?
+                            .ty
+                            .inner_named_type();
+                        let value_cost = self.score_input_json_value(ctx, field_type, value)?;
+                        Some(
+                            self.supergraph_schema
+                                .field_cost(ty, name.as_str())
+                                .unwrap_or(0.0)
+                                + value_cost,
+                        )
+                    })
+                    .sum(),
+            ),
+            _ => Some(0.0),
+        }
+    }
+
     fn score_fragment_spread(
         &self,
+        ctx: &ScoringContext,
         fragment_spread: &FragmentSpread,
-        parent_type: &NamedType,
-        schema: &Valid<Schema>,
-        executable: &ExecutableDocument,
-        should_estimate_requires: bool,
+        sized_fields: Option<&SizedFields>,
     ) -> Result<f64, DemandControlError> {
-        let fragment = fragment_spread.fragment_def(executable).ok_or_else(|| {
+        let fragment = fragment_spread.fragment_def(ctx.query).ok_or_else(|| {
             DemandControlError::QueryParseFailure(format!(
                 "Parsed operation did not have a definition for fragment {}",
                 fragment_spread.fragment_name
             ))
         })?;
         self.score_selection_set(
+            ctx,
             &fragment.selection_set,
-            parent_type,
-            schema,
-            executable,
-            should_estimate_requires,
+            fragment.type_condition(),
+            sized_fields,
         )
     }
 
     fn score_inline_fragment(
         &self,
+        ctx: &ScoringContext,
         inline_fragment: &InlineFragment,
         parent_type: &NamedType,
-        schema: &Valid<Schema>,
-        executable: &ExecutableDocument,
-        should_estimate_requires: bool,
+        sized_fields: Option<&SizedFields>,
     ) -> Result<f64, DemandControlError> {
         self.score_selection_set(
+            ctx,
             &inline_fragment.selection_set,
             parent_type,
-            schema,
-            executable,
-            should_estimate_requires,
+            sized_fields,
         )
     }
 
     fn score_operation(
         &self,
+        ctx: &ScoringContext,
         operation: &Operation,
-        schema: &Valid<Schema>,
-        executable: &ExecutableDocument,
-        should_estimate_requires: bool,
     ) -> Result<f64, DemandControlError> {
         let mut cost = if operation.is_mutation() { 10.0 } else { 0.0 };
 
-        let Some(root_type_name) = schema.root_operation(operation.operation_type) else {
+        let Some(root_type_name) = ctx.schema.root_operation(operation.operation_type) else {
             return Err(DemandControlError::QueryParseFailure(format!(
                 "Cannot cost {} operation because the schema does not support this root type",
                 operation.operation_type
             )));
         };
 
-        cost += self.score_selection_set(
-            &operation.selection_set,
-            root_type_name,
-            schema,
-            executable,
-            should_estimate_requires,
-        )?;
+        cost += self.score_selection_set(ctx, &operation.selection_set, root_type_name, None)?;
 
         Ok(cost)
     }
 
     fn score_selection(
         &self,
+        ctx: &ScoringContext,
         selection: &Selection,
         parent_type: &NamedType,
-        schema: &Valid<Schema>,
-        executable: &ExecutableDocument,
-        should_estimate_requires: bool,
+        sized_fields: Option<&SizedFields>,
     ) -> Result<f64, DemandControlError> {
         match selection {
-            Selection::Field(f) => {
-                self.score_field(f, parent_type, schema, executable, should_estimate_requires)
-            }
-            Selection::FragmentSpread(s) => self.score_fragment_spread(
-                s,
-                parent_type,
-                schema,
-                executable,
-                should_estimate_requires,
-            ),
+            Selection::Field(f) => self.score_field(ctx, f, parent_type, sized_fields),
+            Selection::FragmentSpread(s) => self.score_fragment_spread(ctx, s, sized_fields),
             Selection::InlineFragment(i) => self.score_inline_fragment(
+                ctx,
                 i,
                 i.type_condition.as_ref().unwrap_or(parent_type),
-                schema,
-                executable,
-                should_estimate_requires,
+                sized_fields,
             ),
         }
     }
 
     fn score_selection_set(
         &self,
+        ctx: &ScoringContext,
         selection_set: &SelectionSet,
         parent_type_name: &NamedType,
-        schema: &Valid<Schema>,
-        executable: &ExecutableDocument,
-        should_estimate_requires: bool,
+        sized_fields: Option<&SizedFields>,
     ) -> Result<f64, DemandControlError> {
         let mut cost = 0.0;
         for selection in selection_set.selections.iter() {
-            cost += self.score_selection(
-                selection,
-                parent_type_name,
-                schema,
-                executable,
-                should_estimate_requires,
-            )?;
+            cost += self.score_selection(ctx, selection, parent_type_name, sized_fields)?;
         }
         Ok(cost)
     }
@@ -260,24 +460,30 @@ impl StaticCostCalculator {
         false
     }
 
-    fn score_plan_node(&self, plan_node: &PlanNode) -> Result<f64, DemandControlError> {
+    fn score_plan_node(
+        &self,
+        plan_node: &PlanNode,
+        ctx: &PlanScoringContext,
+    ) -> Result<f64, DemandControlError> {
         match plan_node {
-            PlanNode::Sequence { nodes } => self.summed_score_of_nodes(nodes),
-            PlanNode::Parallel { nodes } => self.summed_score_of_nodes(nodes),
-            PlanNode::Flatten(flatten_node) => self.score_plan_node(&flatten_node.node),
+            PlanNode::Sequence { nodes } => self.summed_score_of_nodes(nodes, ctx),
+            PlanNode::Parallel { nodes } => self.summed_score_of_nodes(nodes, ctx),
+            PlanNode::Flatten(flatten_node) => self.score_plan_node(&flatten_node.node, ctx),
             PlanNode::Condition {
                 condition: _,
                 if_clause,
                 else_clause,
-            } => self.max_score_of_nodes(if_clause, else_clause),
+            } => self.max_score_of_nodes(if_clause, else_clause, ctx),
             PlanNode::Defer { primary, deferred } => {
-                self.summed_score_of_deferred_nodes(primary, deferred)
-            }
-            PlanNode::Fetch(fetch_node) => {
-                self.estimated_cost_of_operation(&fetch_node.service_name, &fetch_node.operation)
+                self.summed_score_of_deferred_nodes(primary, deferred, ctx)
             }
+            PlanNode::Fetch(fetch_node) => self.estimated_cost_of_operation(
+                &fetch_node.service_name,
+                &fetch_node.operation,
+                ctx,
+            ),
             PlanNode::Subscription { primary, rest: _ } => {
-                self.estimated_cost_of_operation(&primary.service_name, &primary.operation)
+                self.estimated_cost_of_operation(&primary.service_name, &primary.operation, ctx)
             }
         }
     }
@@ -286,6 +492,7 @@ impl StaticCostCalculator {
         &self,
         subgraph: &str,
         operation: &SubgraphOperation,
+        ctx: &PlanScoringContext,
     ) -> Result<f64, DemandControlError> {
         tracing::debug!("On subgraph {}, scoring operation: {}", subgraph, operation);
 
@@ -299,21 +506,47 @@ impl StaticCostCalculator {
         let operation = operation
             .as_parsed()
             .map_err(DemandControlError::SubgraphOperationNotInitialized)?;
-        self.estimated(operation, schema, false)
+        let Some(fetches) = ctx.fetches.as_ref() else {
+            return self.estimated(operation, schema, ctx.variables, false);
+        };
+        let (cost, fields) =
+            self.estimated_with_breakdown(operation, schema, ctx.variables, false)?;
+        fetches.borrow_mut().push(FetchCost {
+            subgraph: subgraph.to_string(),
+            cost,
+            fields,
+        });
+        Ok(cost)
     }
 
     fn max_score_of_nodes(
         &self,
         left: &Option<Box<PlanNode>>,
         right: &Option<Box<PlanNode>>,
+        ctx: &PlanScoringContext,
     ) -> Result<f64, DemandControlError> {
         match (left, right) {
             (None, None) => Ok(0.0),
-            (None, Some(right)) => self.score_plan_node(right),
-            (Some(left), None) => self.score_plan_node(left),
+            (None, Some(right)) => self.score_plan_node(right, ctx),
+            (Some(left), None) => self.score_plan_node(left, ctx),
             (Some(left), Some(right)) => {
-                let left_score = self.score_plan_node(left)?;
-                let right_score = self.score_plan_node(right)?;
+                // only the fetches of the most expensive branch are part of the breakdown
+                let start = ctx.fetches.as_ref().map_or(0, |f| f.borrow().len());
+                let left_score = self.score_plan_node(left, ctx)?;
+                let left_fetches = ctx
+                    .fetches
+                    .as_ref()
+                    .map(|f| f.borrow_mut().split_off(start));
+                let right_score = self.score_plan_node(right, ctx)?;
+                if left_score >= right_score {
+                    if let (Some(fetches), Some(left_fetches)) =
+                        (ctx.fetches.as_ref(), left_fetches)
+                    {
+                        let mut fetches = fetches.borrow_mut();
+                        fetches.truncate(start);
+                        fetches.extend(left_fetches);
+                    }
+                }
                 Ok(left_score.max(right_score))
             }
         }
@@ -323,23 +556,28 @@ impl StaticCostCalculator {
         &self,
         primary: &Primary,
         deferred: &Vec<DeferredNode>,
+        ctx: &PlanScoringContext,
     ) -> Result<f64, DemandControlError> {
         let mut score = 0.0;
         if let Some(node) = &primary.node {
-            score += self.score_plan_node(node)?;
+            score += self.score_plan_node(node, ctx)?;
         }
         for d in deferred {
             if let Some(node) = &d.node {
-                score += self.score_plan_node(node)?;
+                score += self.score_plan_node(node, ctx)?;
             }
         }
         Ok(score)
     }
 
-    fn summed_score_of_nodes(&self, nodes: &Vec<PlanNode>) -> Result<f64, DemandControlError> {
+    fn summed_score_of_nodes(
+        &self,
+        nodes: &Vec<PlanNode>,
+        ctx: &PlanScoringContext,
+    ) -> Result<f64, DemandControlError> {
         let mut sum = 0.0;
         for node in nodes {
-            sum += self.score_plan_node(node)?;
+            sum += self.score_plan_node(node, ctx)?;
         }
         Ok(sum)
     }
@@ -348,20 +586,81 @@ impl StaticCostCalculator {
         &self,
         query: &ExecutableDocument,
         schema: &Valid<Schema>,
+        variables: &Object,
         should_estimate_requires: bool,
     ) -> Result<f64, DemandControlError> {
+        let ctx = ScoringContext {
+            schema,
+            query,
+            variables,
+            should_estimate_requires,
+            breakdown: None,
+        };
+        self.score_document(&ctx)
+    }
+
+    /// Like `estimated`, also returns the cost of each field of the operation
+    pub(crate) fn estimated_with_breakdown(
+        &self,
+        query: &ExecutableDocument,
+        schema: &Valid<Schema>,
+        variables: &Object,
+        should_estimate_requires: bool,
+    ) -> Result<(f64, Vec<FieldCost>), DemandControlError> {
+        let ctx = ScoringContext {
+            schema,
+            query,
+            variables,
+            should_estimate_requires,
+            breakdown: Some(Default::default()),
+        };
+        let cost = self.score_document(&ctx)?;
+        let fields = ctx
+            .breakdown
+            .map(|breakdown| breakdown.into_inner().fields)
+            .unwrap_or_default();
+        Ok((cost, fields))
+    }
+
+    fn score_document(&self, ctx: &ScoringContext) -> Result<f64, DemandControlError> {
         let mut cost = 0.0;
-        if let Some(op) = &query.anonymous_operation {
-            cost += self.score_operation(op, schema, query, should_estimate_requires)?;
+        if let Some(op) = &ctx.query.anonymous_operation {
+            cost += self.score_operation(ctx, op)?;
         }
-        for (_name, op) in query.named_operations.iter() {
-            cost += self.score_operation(op, schema, query, should_estimate_requires)?;
+        for (_name, op) in ctx.query.named_operations.iter() {
+            cost += self.score_operation(ctx, op)?;
         }
         Ok(cost)
     }
 
-    pub(crate) fn planned(&self, query_plan: &QueryPlan) -> Result<f64, DemandControlError> {
-        self.score_plan_node(&query_plan.root)
+    pub(crate) fn planned(
+        &self,
+        query_plan: &QueryPlan,
+        variables: &Object,
+    ) -> Result<f64, DemandControlError> {
+        let ctx = PlanScoringContext {
+            variables,
+            fetches: None,
+        };
+        self.score_plan_node(&query_plan.root, &ctx)
+    }
+
+    /// Like `planned`, also returns the cost of each subgraph fetch of the query plan
+    pub(crate) fn planned_with_breakdown(
+        &self,
+        query_plan: &QueryPlan,
+        variables: &Object,
+    ) -> Result<(f64, Vec<FetchCost>), DemandControlError> {
+        let ctx = PlanScoringContext {
+            variables,
+            fetches: Some(Default::default()),
+        };
+        let cost = self.score_plan_node(&query_plan.root, &ctx)?;
+        let fetches = ctx
+            .fetches
+            .map(|fetches| fetches.into_inner())
+            .unwrap_or_default();
+        Ok((cost, fetches))
     }
 
     pub(crate) fn actual(
@@ -369,39 +668,47 @@ impl StaticCostCalculator {
         request: &ExecutableDocument,
         response: &Response,
     ) -> Result<f64, DemandControlError> {
-        let mut visitor = ResponseCostCalculator::new();
+        let mut visitor = ResponseCostCalculator::new(&self.supergraph_schema);
         visitor.visit(request, response);
         Ok(visitor.cost)
     }
 }
 
-pub(crate) struct ResponseCostCalculator {
+pub(crate) struct ResponseCostCalculator<'a> {
     pub(crate) cost: f64,
+    schema: &'a DemandControlledSchema,
 }
 
-impl ResponseCostCalculator {
-    pub(crate) fn new() -> Self {
-        Self { cost: 0.0 }
+impl<'a> ResponseCostCalculator<'a> {
+    pub(crate) fn new(schema: &'a DemandControlledSchema) -> Self {
+        Self { cost: 0.0, schema }
     }
 }
 
-impl ResponseVisitor for ResponseCostCalculator {
+impl<'a> ResponseVisitor for ResponseCostCalculator<'a> {
     fn visit_field(
         &mut self,
         request: &ExecutableDocument,
-        _ty: &NamedType,
+        ty: &NamedType,
         field: &Field,
         value: &Value,
     ) {
+        let weight = self
+            .schema
+            .field_cost(ty, &field.name)
+            .or_else(|| self.schema.type_cost(field.ty().inner_named_type()));
         match value {
-            Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => {}
+            Value::Null => {}
+            Value::Bool(_) | Value::Number(_) | Value::String(_) => {
+                self.cost += weight.unwrap_or(0.0);
+            }
             Value::Array(items) => {
                 for item in items {
-                    self.visit_field(request, field.ty().inner_named_type(), field, item);
+                    self.visit_field(request, ty, field, item);
                 }
             }
             Value::Object(children) => {
-                self.cost += 1.0;
+                self.cost += weight.unwrap_or(1.0);
                 self.visit_selections(request, &field.selection_set, children);
             }
         }
@@ -440,13 +747,28 @@ mod tests {
     fn estimated_cost(schema_str: &str, query_str: &str) -> f64 {
         let (schema, query) =
             parse_schema_and_operation(schema_str, query_str, &Default::default());
-        StaticCostCalculator::new(Default::default(), 100)
-            .estimated(&query.executable, schema.supergraph_schema(), true)
+        let demand_controlled_schema =
+            DemandControlledSchema::new(schema.supergraph_schema().as_ref());
+        StaticCostCalculator::new(Arc::new(demand_controlled_schema), Default::default(), 100)
+            .estimated(
+                &query.executable,
+                schema.supergraph_schema(),
+                &Default::default(),
+                true,
+            )
             .unwrap()
     }
 
     /// Estimate cost of an operation on a plain, non-federated schema.
     fn basic_estimated_cost(schema_str: &str, query_str: &str) -> f64 {
+        basic_estimated_cost_with_variables(schema_str, query_str, Default::default()).unwrap()
+    }
+
+    fn basic_estimated_cost_with_variables(
+        schema_str: &str,
+        query_str: &str,
+        variables: Object,
+    ) -> Result<f64, DemandControlError> {
         let schema =
             apollo_compiler::Schema::parse_and_validate(schema_str, "schema.graphqls").unwrap();
         let query = apollo_compiler::ExecutableDocument::parse_and_validate(
@@ -455,9 +777,9 @@ mod tests {
             "query.graphql",
         )
         .unwrap();
-        StaticCostCalculator::new(Default::default(), 100)
-            .estimated(&query, &schema, true)
-            .unwrap()
+        let demand_controlled_schema = DemandControlledSchema::new(&schema);
+        StaticCostCalculator::new(Arc::new(demand_controlled_schema), Default::default(), 100)
+            .estimated(&query, &schema, &variables, true)
     }
 
     async fn planned_cost(schema_str: &str, query_str: &str) -> f64 {
@@ -482,18 +804,21 @@ mod tests {
         };
 
         let calculator = StaticCostCalculator {
+            supergraph_schema: Default::default(),
             subgraph_schemas: planner.subgraph_schemas(),
             list_size: 100,
         };
 
-        calculator.planned(&query_plan).unwrap()
+        calculator
+            .planned(&query_plan, &Default::default())
+            .unwrap()
     }
 
     fn actual_cost(schema_str: &str, query_str: &str, response_bytes: &'static [u8]) -> f64 {
         let (_schema, query) =
             parse_schema_and_operation(schema_str, query_str, &Default::default());
         let response = Response::from_bytes("test", Bytes::from(response_bytes)).unwrap();
-        StaticCostCalculator::new(Default::default(), 100)
+        StaticCostCalculator::new(Default::default(), Default::default(), 100)
             .actual(&query.executable, &response)
             .unwrap()
     }
@@ -638,14 +963,129 @@ mod tests {
         let query = include_str!("./fixtures/federated_ships_deferred_query.graphql");
         let (schema, query) = parse_schema_and_operation(schema, query, &Default::default());
 
-        let conservative_estimate = StaticCostCalculator::new(Default::default(), 100)
-            .estimated(&query.executable, schema.supergraph_schema(), true)
-            .unwrap();
-        let narrow_estimate = StaticCostCalculator::new(Default::default(), 5)
-            .estimated(&query.executable, schema.supergraph_schema(), true)
+        let conservative_estimate =
+            StaticCostCalculator::new(Default::default(), Default::default(), 100)
+                .estimated(
+                    &query.executable,
+                    schema.supergraph_schema(),
+                    &Default::default(),
+                    true,
+                )
+                .unwrap();
+        let narrow_estimate = StaticCostCalculator::new(Default::default(), Default::default(), 5)
+            .estimated(
+                &query.executable,
+                schema.supergraph_schema(),
+                &Default::default(),
+                true,
+            )
             .unwrap();
 
         assert_eq!(conservative_estimate, 10200.0);
         assert_eq!(narrow_estimate, 35.0);
     }
+
+    #[test]
+    fn custom_cost_directives() {
+        let schema = include_str!("./fixtures/custom_cost_schema.graphql");
+
+        // `@cost` on the `Product` type and on the `reviews` field, and `first` sets the size of
+        // the list
+        let query = "{ products(first: 5) { name reviews { body } } }";
+        assert_eq!(
+            basic_estimated_cost(schema, query),
+            5.0 * (5.0 + 3.0 * 10.0)
+        );
+
+        // `@cost` on an argument and on a scalar
+        let query = "{ search(term: \"hat\") { name price } }";
+        assert_eq!(
+            basic_estimated_cost(schema, query),
+            2.0 + 20.0 * (5.0 + 1.0)
+        );
+    }
+
+    #[test]
+    fn list_size_slicing_arguments_from_variables() {
+        let schema = include_str!("./fixtures/custom_cost_schema.graphql");
+        let query = "query($first: Int) { products(first: $first) { name } }";
+
+        let variables = serde_json_bytes::json!({ "first": 2 });
+        assert_eq!(
+            basic_estimated_cost_with_variables(
+                schema,
+                query,
+                variables.as_object().unwrap().clone()
+            )
+            .unwrap(),
+            10.0
+        );
+
+        // `first` is the only slicing argument, and it is required
+        assert!(basic_estimated_cost_with_variables(schema, query, Object::new()).is_err());
+    }
+
+    #[test]
+    fn list_size_sized_fields() {
+        let schema = include_str!("./fixtures/custom_cost_schema.graphql");
+
+        let query = "{ productConnection(first: 3) { edges { name } } }";
+        assert_eq!(basic_estimated_cost(schema, query), 1.0 + 3.0 * 5.0);
+
+        // `assumedSize` is used when the slicing argument is not set
+        let query = "{ productConnection { edges { name } } }";
+        assert_eq!(basic_estimated_cost(schema, query), 1.0 + 50.0 * 5.0);
+    }
+
+    #[test]
+    fn estimated_breakdown() {
+        let schema = include_str!("./fixtures/custom_cost_schema.graphql");
+        let schema =
+            apollo_compiler::Schema::parse_and_validate(schema, "schema.graphqls").unwrap();
+        let query = apollo_compiler::ExecutableDocument::parse_and_validate(
+            &schema,
+            "{ search(term: \"hat\") { name cost: price } }",
+            "query.graphql",
+        )
+        .unwrap();
+        let calculator = StaticCostCalculator::new(
+            Arc::new(DemandControlledSchema::new(&schema)),
+            Default::default(),
+            100,
+        );
+
+        let (cost, fields) = calculator
+            .estimated_with_breakdown(&query, &schema, &Default::default(), true)
+            .unwrap();
+        assert_eq!(cost, 122.0);
+        assert_eq!(
+            fields,
+            vec![
+                FieldCost {
+                    path: "search".to_string(),
+                    multiplier: 20.0,
+                    weight: 5.0,
+                    arguments: 2.0,
+                    requirements: 0.0,
+                    cost: 122.0,
+                },
+                FieldCost {
+                    path: "search.name".to_string(),
+                    multiplier: 1.0,
+                    weight: 0.0,
+                    arguments: 0.0,
+                    requirements: 0.0,
+                    cost: 0.0,
+                },
+                FieldCost {
+                    path: "search.cost".to_string(),
+                    multiplier: 1.0,
+                    weight: 1.0,
+                    arguments: 0.0,
+                    requirements: 0.0,
+                    cost: 1.0,
+                },
+            ]
+        );
+    }
 }
@@ -11,6 +11,7 @@ use apollo_compiler::ExecutableDocument;
 use displaydoc::Display;
 use futures::future::Either;
 use futures::stream;
+use futures::FutureExt;
 use futures::StreamExt;
 use schemars::JsonSchema;
 use serde::Deserialize;
@@ -27,17 +28,27 @@ use crate::json_ext::Object;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::demand_control::budget::BudgetConfig;
+use crate::plugins::demand_control::budget::CostBudget;
+use crate::plugins::demand_control::cost_calculator::breakdown::CostBreakdown;
 use crate::plugins::demand_control::strategy::Strategy;
 use crate::plugins::demand_control::strategy::StrategyFactory;
 use crate::register_plugin;
 use crate::services::execution;
 use crate::services::execution::BoxService;
 use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::Context;
 
+mod budget;
 pub(crate) mod cost_calculator;
 pub(crate) mod strategy;
 
+/// Header requesting a breakdown of the estimated cost
+const COST_EXPLAIN_HEADER: &str = "apollo-cost-explain";
+/// Request and response extension of the estimated cost breakdown
+const COST_EXTENSION: &str = "cost";
+
 /// The cost calculation information stored in context for use in telemetry and other plugins that need to know what cost was calculated.
 #[derive(Debug, Clone)]
 pub(crate) struct CostContext {
@@ -69,6 +80,24 @@ impl CostContext {
     }
 }
 
+/// Whether the client asked for a breakdown of the estimated cost, with the
+/// `apollo-cost-explain: true` header or the `"cost": { "explain": true }` request extension
+pub(crate) fn explain_requested(request: &execution::Request) -> bool {
+    let supergraph_request = &request.supergraph_request;
+    supergraph_request
+        .headers()
+        .get(COST_EXPLAIN_HEADER)
+        .is_some_and(|value| value == "true")
+        || supergraph_request
+            .body()
+            .extensions
+            .get(COST_EXTENSION)
+            .and_then(|cost| cost.as_object())
+            .and_then(|cost| cost.get("explain"))
+            .and_then(|explain| explain.as_bool())
+            .unwrap_or_default()
+}
+
 /// Algorithm for calculating the cost of an incoming query.
 #[derive(Clone, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, rename_all = "snake_case")]
@@ -119,6 +148,17 @@ pub(crate) struct DemandControlConfig {
     mode: Mode,
     /// The strategy used to reject requests.
     strategy: StrategyConfig,
+    /// Budget of cost for each client over time. Each request draws its estimated cost from the
+    /// budget of its client, and is refunded if the actual cost is lower.
+    /// In measure mode, requests exceeding the budget are not rejected.
+    #[serde(default)]
+    budget: Option<BudgetConfig>,
+    /// Allow clients to request a breakdown of the estimated cost of their queries, by subgraph
+    /// fetch and field, with the `apollo-cost-explain: true` header or the
+    /// `"cost": { "explain": true }` request extension.
+    /// The breakdown is returned in the `cost` response extension.
+    #[serde(default)]
+    explain: bool,
 }
 
 #[derive(Debug, Display, Error)]
@@ -138,6 +178,11 @@ pub(crate) enum DemandControlError {
         /// The maximum cost of the query
         max_cost: f64,
     },
+    /// query estimated cost {estimated_cost} exceeded the remaining cost budget
+    CostBudgetExceeded {
+        /// The estimated cost of the query
+        estimated_cost: f64,
+    },
     /// Query could not be parsed: {0}
     QueryParseFailure(String),
     /// {0}
@@ -173,6 +218,15 @@ impl IntoGraphQLErrors for DemandControlError {
                     .message(self.to_string())
                     .build()])
             }
+            DemandControlError::CostBudgetExceeded { estimated_cost } => {
+                let mut extensions = Object::new();
+                extensions.insert("cost.estimated", estimated_cost.into());
+                Ok(vec![graphql::Error::builder()
+                    .extension_code(self.code())
+                    .extensions(extensions)
+                    .message(self.to_string())
+                    .build()])
+            }
             DemandControlError::QueryParseFailure(_) => Ok(vec![graphql::Error::builder()
                 .extension_code(self.code())
                 .message(self.to_string())
@@ -187,6 +241,7 @@ impl DemandControlError {
         match self {
             DemandControlError::EstimatedCostTooExpensive { .. } => "COST_ESTIMATED_TOO_EXPENSIVE",
             DemandControlError::ActualCostTooExpensive { .. } => "COST_ACTUAL_TOO_EXPENSIVE",
+            DemandControlError::CostBudgetExceeded { .. } => "COST_BUDGET_EXCEEDED",
             DemandControlError::QueryParseFailure(_) => "COST_QUERY_PARSE_FAILURE",
             DemandControlError::SubgraphOperationNotInitialized(e) => e.code(),
         }
@@ -202,9 +257,31 @@ impl<T> From<WithErrors<T>> for DemandControlError {
 pub(crate) struct DemandControl {
     config: DemandControlConfig,
     strategy_factory: StrategyFactory,
+    budget: Option<Arc<CostBudget>>,
 }
 
 impl DemandControl {
+    /// Returns the breakdown of the estimated cost in the `cost` extension of the first response
+    fn report_cost_breakdown(resp: supergraph::Response) -> supergraph::Response {
+        let Some(breakdown) = resp
+            .context
+            .extensions()
+            .with_lock(|lock| lock.get::<CostBreakdown>().cloned())
+        else {
+            return resp;
+        };
+        let Ok(breakdown) = serde_json_bytes::to_value(breakdown) else {
+            return resp;
+        };
+        let mut first = true;
+        resp.map_stream(move |mut resp| {
+            if std::mem::take(&mut first) {
+                resp.extensions.insert(COST_EXTENSION, breakdown.clone());
+            }
+            resp
+        })
+    }
+
     fn report_operation_metric(context: Context) {
         let result = context
             .extensions()
@@ -223,7 +300,12 @@ impl Plugin for DemandControl {
     type Config = DemandControlConfig;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let budget = match &init.config.budget {
+            Some(budget) if init.config.enabled => Some(Arc::new(CostBudget::new(budget).await?)),
+            _ => None,
+        };
         Ok(DemandControl {
+            budget,
             strategy_factory: StrategyFactory::new(
                 init.config.clone(),
                 init.supergraph_schema.clone(),
@@ -233,11 +315,32 @@ impl Plugin for DemandControl {
         })
     }
 
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if !self.config.enabled || (self.budget.is_none() && !self.config.explain) {
+            service
+        } else {
+            let budget = self.budget.clone();
+            ServiceBuilder::new()
+                .map_response(move |resp: supergraph::Response| {
+                    let resp = match &budget {
+                        Some(budget) => budget.report(resp),
+                        None => resp,
+                    };
+                    Self::report_cost_breakdown(resp)
+                })
+                .service(service)
+                .boxed()
+        }
+    }
+
     fn execution_service(&self, service: BoxService) -> BoxService {
         if !self.config.enabled {
             service
         } else {
             let strategy = self.strategy_factory.create();
+            let budget = self.budget.clone();
+            let budget_map_response = self.budget.clone();
+            let enforce = self.config.mode == Mode::Enforce;
             ServiceBuilder::new()
                 .checkpoint(move |req: execution::Request| {
                     req.context
@@ -258,7 +361,30 @@ impl Plugin for DemandControl {
                         ),
                     })
                 })
-                .map_response(|mut resp: execution::Response| {
+                .oneshot_checkpoint_async(move |req: execution::Request| {
+                    let budget = budget.clone();
+                    async move {
+                        // The estimated cost is drawn from the budget of the client once the strategy accepted the request.
+                        let Some(budget) = budget else {
+                            return Ok(ControlFlow::Continue(req));
+                        };
+                        Ok(match budget.draw(&req, enforce).await {
+                            Ok(_) => ControlFlow::Continue(req),
+                            Err(err) => ControlFlow::Break(
+                                execution::Response::builder()
+                                    .errors(
+                                        err.into_graphql_errors()
+                                            .expect("must be able to convert to graphql error"),
+                                    )
+                                    .context(req.context.clone())
+                                    .build()
+                                    .expect("Must be able to build response"),
+                            ),
+                        })
+                    }
+                    .boxed()
+                })
+                .map_response(move |mut resp: execution::Response| {
                     let req = resp
                         .context
                         .unsupported_executable_document()
@@ -270,10 +396,17 @@ impl Plugin for DemandControl {
 
                     // We want to sequence this code to run after all the subgraph responses have been scored.
                     // To do so without collecting all the results, we chain this "empty" stream onto the end.
+                    let budget = budget_map_response.clone();
                     let report_operation_metric =
-                        futures::stream::unfold(resp.context.clone(), |ctx| async move {
-                            Self::report_operation_metric(ctx);
-                            None
+                        futures::stream::unfold(resp.context.clone(), move |ctx| {
+                            let budget = budget.clone();
+                            async move {
+                                if let Some(budget) = budget {
+                                    budget.refund(&ctx).await;
+                                }
+                                Self::report_operation_metric(ctx);
+                                None
+                            }
                         });
 
                     resp.response = resp.response.map(move |resp| {
@@ -393,10 +526,13 @@ mod test {
     use futures::StreamExt;
     use schemars::JsonSchema;
     use serde::Deserialize;
+    use serde_json_bytes::json;
 
     use crate::graphql;
     use crate::graphql::Response;
     use crate::metrics::FutureMetricsExt;
+    use crate::plugins::demand_control::explain_requested;
+    use crate::plugins::demand_control::CostContext;
     use crate::plugins::demand_control::DemandControl;
     use crate::plugins::demand_control::DemandControlError;
     use crate::plugins::test::PluginTestHarness;
@@ -405,6 +541,7 @@ mod test {
     use crate::services::layers::query_analysis::ParsedDocument;
     use crate::services::layers::query_analysis::ParsedDocumentInner;
     use crate::services::subgraph;
+    use crate::services::supergraph;
     use crate::Context;
 
     #[tokio::test]
@@ -521,6 +658,143 @@ mod test {
         .await
     }
 
+    #[tokio::test]
+    async fn test_enforce_budget() {
+        let plugin = PluginTestHarness::<DemandControl>::builder()
+            .config(include_str!("fixtures/enforce_budget.router.yaml"))
+            .build()
+            .await;
+
+        // draws 6 from the budget of 10, and gets 1 back once the actual cost is known
+        let ctx = budget_context(6.0, 5.0);
+        let body = test_budget_on_execution(&plugin, ctx.clone()).await;
+        assert!(body[0].errors.is_empty());
+        let resp = test_budget_on_supergraph(&plugin, ctx).await;
+        assert_eq!(resp.response.status(), http::StatusCode::OK);
+        assert_eq!(resp.response.headers()["apollo-cost-budget-limit"], "10");
+        assert_eq!(resp.response.headers()["apollo-cost-budget-remaining"], "4");
+
+        // only 5 remain
+        let ctx = budget_context(6.0, 5.0);
+        let body = test_budget_on_execution(&plugin, ctx.clone()).await;
+        assert_eq!(
+            body[0].errors[0].extensions.get("code").unwrap(),
+            "COST_BUDGET_EXCEEDED"
+        );
+        let resp = test_budget_on_supergraph(&plugin, ctx).await;
+        assert_eq!(resp.response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp
+            .response
+            .headers()
+            .contains_key(http::header::RETRY_AFTER));
+        assert!(!resp
+            .response
+            .headers()
+            .contains_key("apollo-cost-budget-remaining"));
+
+        let body = test_budget_on_execution(&plugin, budget_context(5.0, 5.0)).await;
+        assert!(body[0].errors.is_empty());
+    }
+
+    #[test]
+    fn test_explain_requested() {
+        let request = |headers: &[(&str, &str)], extensions: serde_json_bytes::Value| {
+            let mut supergraph_request = http::Request::builder();
+            for (name, value) in headers {
+                supergraph_request = supergraph_request.header(*name, *value);
+            }
+            execution::Request::fake_builder()
+                .supergraph_request(
+                    supergraph_request
+                        .body(
+                            graphql::Request::builder()
+                                .extensions(extensions.as_object().unwrap().clone())
+                                .build(),
+                        )
+                        .unwrap(),
+                )
+                .build()
+        };
+
+        assert!(!explain_requested(&request(&[], json!({}))));
+        assert!(explain_requested(&request(
+            &[("apollo-cost-explain", "true")],
+            json!({})
+        )));
+        assert!(!explain_requested(&request(
+            &[("apollo-cost-explain", "false")],
+            json!({})
+        )));
+        assert!(explain_requested(&request(
+            &[],
+            json!({ "cost": { "explain": true } })
+        )));
+    }
+
+    fn budget_context(estimated: f64, actual: f64) -> Context {
+        let ctx = context();
+        ctx.extensions().with_lock(|mut lock| {
+            lock.insert(CostContext {
+                estimated,
+                actual,
+                ..Default::default()
+            })
+        });
+        ctx
+    }
+
+    async fn test_budget_on_execution(
+        plugin: &PluginTestHarness<DemandControl>,
+        ctx: Context,
+    ) -> Vec<Response> {
+        let resp = plugin
+            .call_execution(
+                execution::Request::fake_builder()
+                    .context(ctx)
+                    .supergraph_request(
+                        http::Request::builder()
+                            .header("x-client", "client")
+                            .body(graphql::Request::default())
+                            .unwrap(),
+                    )
+                    .build(),
+                |req| {
+                    execution::Response::fake_builder()
+                        .context(req.context)
+                        .build()
+                        .unwrap()
+                },
+            )
+            .await
+            .unwrap();
+
+        resp.response
+            .into_body()
+            .collect::<Vec<graphql::Response>>()
+            .await
+    }
+
+    async fn test_budget_on_supergraph(
+        plugin: &PluginTestHarness<DemandControl>,
+        ctx: Context,
+    ) -> supergraph::Response {
+        plugin
+            .call_supergraph(
+                supergraph::Request::fake_builder()
+                    .context(ctx)
+                    .build()
+                    .unwrap(),
+                |req| {
+                    supergraph::Response::fake_builder()
+                        .context(req.context)
+                        .build()
+                        .unwrap()
+                },
+            )
+            .await
+            .unwrap()
+    }
+
     async fn test_on_execution(config: &'static str) -> Vec<Response> {
         let plugin = PluginTestHarness::<DemandControl>::builder()
             .config(config)
@@ -6,6 +6,7 @@ use apollo_compiler::ExecutableDocument;
 use apollo_compiler::Schema;
 
 use crate::graphql;
+use crate::plugins::demand_control::cost_calculator::schema::DemandControlledSchema;
 use crate::plugins::demand_control::cost_calculator::static_cost::StaticCostCalculator;
 use crate::plugins::demand_control::strategy::static_estimated::StaticEstimated;
 use crate::plugins::demand_control::DemandControlConfig;
@@ -74,8 +75,7 @@ impl Strategy {
 
 pub(crate) struct StrategyFactory {
     config: DemandControlConfig,
-    #[allow(dead_code)]
-    supergraph_schema: Arc<Valid<Schema>>,
+    supergraph_schema: Arc<DemandControlledSchema>,
     subgraph_schemas: Arc<HashMap<String, Arc<Valid<Schema>>>>,
 }
 
@@ -87,7 +87,7 @@ impl StrategyFactory {
     ) -> Self {
         Self {
             config,
-            supergraph_schema,
+            supergraph_schema: Arc::new(DemandControlledSchema::new(&supergraph_schema)),
             subgraph_schemas,
         }
     }
@@ -96,7 +96,9 @@ impl StrategyFactory {
         let strategy: Arc<dyn StrategyImpl> = match &self.config.strategy {
             StrategyConfig::StaticEstimated { list_size, max } => Arc::new(StaticEstimated {
                 max: *max,
+                explain: self.config.explain,
                 cost_calculator: StaticCostCalculator::new(
+                    self.supergraph_schema.clone(),
                     self.subgraph_schemas.clone(),
                     *list_size,
                 ),
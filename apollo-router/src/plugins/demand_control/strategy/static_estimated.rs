@@ -1,7 +1,9 @@
 use apollo_compiler::ExecutableDocument;
 
 use crate::graphql;
+use crate::plugins::demand_control::cost_calculator::breakdown::CostBreakdown;
 use crate::plugins::demand_control::cost_calculator::static_cost::StaticCostCalculator;
+use crate::plugins::demand_control::explain_requested;
 use crate::plugins::demand_control::strategy::StrategyImpl;
 use crate::plugins::demand_control::CostContext;
 use crate::plugins::demand_control::DemandControlError;
@@ -12,30 +14,47 @@ use crate::services::subgraph;
 pub(crate) struct StaticEstimated {
     // The estimated value of the demand
     pub(crate) max: f64,
+    /// Clients can request a breakdown of the estimated cost
+    pub(crate) explain: bool,
     pub(crate) cost_calculator: StaticCostCalculator,
 }
 
 impl StrategyImpl for StaticEstimated {
     fn on_execution_request(&self, request: &execution::Request) -> Result<(), DemandControlError> {
-        self.cost_calculator
-            .planned(&request.query_plan)
-            .and_then(|cost| {
-                request.context.extensions().with_lock(|mut lock| {
-                    let cost_result = lock.get_or_default_mut::<CostContext>();
-                    cost_result.strategy = "static_estimated";
-                    cost_result.estimated = cost;
-                    if cost > self.max {
-                        Err(
-                            cost_result.result(DemandControlError::EstimatedCostTooExpensive {
-                                estimated_cost: cost,
-                                max_cost: self.max,
-                            }),
-                        )
-                    } else {
-                        Ok(())
-                    }
+        let query_plan = &request.query_plan;
+        let variables = &request.supergraph_request.body().variables;
+        let cost = if self.explain && explain_requested(request) {
+            self.cost_calculator
+                .planned_with_breakdown(query_plan, variables)
+                .map(|(cost, fetches)| {
+                    request.context.extensions().with_lock(|mut lock| {
+                        lock.insert(CostBreakdown {
+                            estimated: cost,
+                            fetches,
+                        })
+                    });
+                    cost
                 })
+        } else {
+            self.cost_calculator.planned(query_plan, variables)
+        };
+        cost.and_then(|cost| {
+            request.context.extensions().with_lock(|mut lock| {
+                let cost_result = lock.get_or_default_mut::<CostContext>();
+                cost_result.strategy = "static_estimated";
+                cost_result.estimated = cost;
+                if cost > self.max {
+                    Err(
+                        cost_result.result(DemandControlError::EstimatedCostTooExpensive {
+                            estimated_cost: cost,
+                            max_cost: self.max,
+                        }),
+                    )
+                } else {
+                    Ok(())
+                }
             })
+        })
     }
 
     fn on_subgraph_request(&self, _request: &subgraph::Request) -> Result<(), DemandControlError> {
@@ -1,3 +1,4 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -5,9 +6,14 @@ use std::task::Context;
 use std::task::Poll;
 
 use access_json::JSONQuery;
+use futures::future::ready;
+use futures::stream::once;
+use futures::FutureExt;
+use futures::StreamExt;
 use http::header::HeaderName;
 use http::header::ACCEPT;
 use http::header::ACCEPT_ENCODING;
+use http::header::CACHE_CONTROL;
 use http::header::CONNECTION;
 use http::header::CONTENT_ENCODING;
 use http::header::CONTENT_LENGTH;
@@ -19,6 +25,7 @@ use http::header::TE;
 use http::header::TRAILER;
 use http::header::TRANSFER_ENCODING;
 use http::header::UPGRADE;
+use http::HeaderMap;
 use http::HeaderValue;
 use regex::Regex;
 use schemars::JsonSchema;
@@ -30,6 +37,8 @@ use tower::ServiceBuilder;
 use tower::ServiceExt;
 use tower_service::Service;
 
+use crate::error::ConfigurationError;
+use crate::layers::ServiceBuilderExt;
 use crate::plugin::serde::deserialize_header_name;
 use crate::plugin::serde::deserialize_header_value;
 use crate::plugin::serde::deserialize_json_query;
@@ -38,9 +47,14 @@ use crate::plugin::serde::deserialize_option_header_value;
 use crate::plugin::serde::deserialize_regex;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::cache::cache_control::CacheControl;
+use crate::plugins::telemetry::config_new::conditions::Condition;
+use crate::plugins::telemetry::config_new::selectors::SupergraphSelector;
 use crate::register_plugin;
 use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::services::SubgraphRequest;
+use crate::services::SubgraphResponse;
 
 register_plugin!("apollo", "headers", Headers);
 
@@ -48,9 +62,11 @@ register_plugin!("apollo", "headers", Headers);
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 struct HeadersLocation {
     /// Propagate/Insert/Remove headers from request
+    #[serde(default)]
     request: Vec<Operation>,
-    // Propagate/Insert/Remove headers from response
-    // response: Option<Operation>
+    /// Propagate/Insert/Remove headers from subgraph responses to the client response
+    #[serde(default)]
+    response: Vec<ResponseOperation>,
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -180,6 +196,200 @@ enum Propagate {
     },
 }
 
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ResponseOperation {
+    Insert(InsertStatic),
+    Remove(Remove),
+    Propagate(ResponsePropagate),
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+#[serde(untagged)]
+/// Propagate header from a subgraph response to the client response
+enum ResponsePropagate {
+    /// Propagate header given a header name
+    Named {
+        /// The source header name
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_header_name")]
+        named: HeaderName,
+
+        /// An optional target header name
+        #[schemars(with = "Option<String>", default)]
+        #[serde(deserialize_with = "deserialize_option_header_name", default)]
+        rename: Option<HeaderName>,
+
+        /// Default value for the header.
+        #[schemars(with = "Option<String>", default)]
+        #[serde(deserialize_with = "deserialize_option_header_value", default)]
+        default: Option<HeaderValue>,
+
+        /// How to merge the header when several subgraphs return it
+        #[serde(default)]
+        merge: MergeStrategy,
+    },
+    /// Propagate header given a regex to match header name
+    Matching {
+        /// The regex on header name
+        #[schemars(schema_with = "propagate_matching")]
+        #[serde(deserialize_with = "deserialize_regex")]
+        matching: Regex,
+
+        /// How to merge the headers when several subgraphs return them
+        #[serde(default)]
+        merge: MergeStrategy,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How to merge the values of a header returned by several subgraphs
+enum MergeStrategy {
+    /// Keep the values from the first subgraph response
+    First,
+    /// Keep the values from the last subgraph response
+    #[default]
+    Last,
+    /// Keep the values from all subgraph responses
+    Append,
+    /// Keep the most restrictive value. `Cache-Control` directives are merged, keeping the
+    /// lowest TTL, `no-store` and `private`. Only valid for the `Cache-Control` header
+    MostRestrictive,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+struct RouterHeaders {
+    /// Insert/Remove headers on the client response
+    #[serde(default)]
+    response: Vec<RouterResponseOperation>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum RouterResponseOperation {
+    Insert(RouterResponseInsert),
+    Remove(RouterResponseRemove),
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+#[serde(untagged)]
+/// Insert header on the client response
+enum RouterResponseInsert {
+    /// Insert static header
+    Static(RouterInsertStatic),
+    /// Insert header with a value coming from context key (works only for a string in the context)
+    FromContext(RouterInsertFromContext),
+    /// Insert header with a value coming from the response body
+    FromBody(RouterInsertFromBody),
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Insert static header on the client response
+struct RouterInsertStatic {
+    /// The name of the header
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+
+    /// The value for the header
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_value")]
+    value: HeaderValue,
+
+    /// Condition to insert the header
+    condition: Option<Condition<SupergraphSelector>>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Insert header on the client response with a value coming from context key
+struct RouterInsertFromContext {
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    /// Specify header name
+    name: HeaderName,
+    /// Specify context key to fetch value
+    from_context: String,
+
+    /// Condition to insert the header
+    condition: Option<Condition<SupergraphSelector>>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Insert header on the client response with a value coming from the response body
+struct RouterInsertFromBody {
+    /// The target header name
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+
+    /// The path in the response body
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_json_query")]
+    path: JSONQuery,
+
+    /// The default if the path in the body did not resolve to an element
+    #[schemars(with = "Option<String>", default)]
+    #[serde(deserialize_with = "deserialize_option_header_value", default)]
+    default: Option<HeaderValue>,
+
+    /// Condition to insert the header
+    condition: Option<Condition<SupergraphSelector>>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+#[serde(untagged)]
+/// Remove header from the client response
+enum RouterResponseRemove {
+    /// Remove a header given a header name
+    Named {
+        /// The header name
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_header_name")]
+        named: HeaderName,
+
+        /// Condition to remove the header
+        condition: Option<Condition<SupergraphSelector>>,
+    },
+    /// Remove headers given a regex matching header name
+    Matching {
+        /// The regex on header name
+        #[schemars(schema_with = "remove_matching")]
+        #[serde(deserialize_with = "deserialize_regex")]
+        matching: Regex,
+
+        /// Condition to remove the headers
+        condition: Option<Condition<SupergraphSelector>>,
+    },
+}
+
+impl RouterResponseOperation {
+    fn condition(&self) -> Option<&Condition<SupergraphSelector>> {
+        match self {
+            RouterResponseOperation::Insert(RouterResponseInsert::Static(insert)) => {
+                insert.condition.as_ref()
+            }
+            RouterResponseOperation::Insert(RouterResponseInsert::FromContext(insert)) => {
+                insert.condition.as_ref()
+            }
+            RouterResponseOperation::Insert(RouterResponseInsert::FromBody(insert)) => {
+                insert.condition.as_ref()
+            }
+            RouterResponseOperation::Remove(RouterResponseRemove::Named { condition, .. })
+            | RouterResponseOperation::Remove(RouterResponseRemove::Matching {
+                condition, ..
+            }) => condition.as_ref(),
+        }
+    }
+}
+
 /// Configuration for header propagation
 #[derive(Clone, JsonSchema, Default, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields, default)]
@@ -188,11 +398,16 @@ struct Config {
     all: Option<HeadersLocation>,
     /// Rules to specific subgraphs
     subgraphs: HashMap<String, HeadersLocation>,
+    /// Rules to apply to the client response
+    router: Option<RouterHeaders>,
 }
 
 struct Headers {
     all_operations: Arc<Vec<Operation>>,
     subgraph_operations: HashMap<String, Arc<Vec<Operation>>>,
+    all_response_operations: Arc<Vec<ResponseOperation>>,
+    subgraph_response_operations: HashMap<String, Arc<Vec<ResponseOperation>>>,
+    router_response_operations: Arc<Vec<RouterResponseOperation>>,
 }
 
 #[async_trait::async_trait]
@@ -200,6 +415,12 @@ impl Plugin for Headers {
     type Config = Config;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        init.config
+            .all
+            .iter()
+            .chain(init.config.subgraphs.values())
+            .try_for_each(|headers| validate_response_operations(&headers.response))?;
+
         let operations: Vec<Operation> = init
             .config
             .all
@@ -217,13 +438,99 @@ impl Plugin for Headers {
             })
             .collect();
 
+        let response_operations: Vec<ResponseOperation> = init
+            .config
+            .all
+            .as_ref()
+            .map(|a| a.response.clone())
+            .unwrap_or_default();
+        let subgraph_response_operations = init
+            .config
+            .subgraphs
+            .iter()
+            .map(|(subgraph_name, op)| {
+                let mut operations = response_operations.clone();
+                operations.append(&mut op.response.clone());
+                (subgraph_name.clone(), Arc::new(operations))
+            })
+            .collect();
+
         Ok(Headers {
             all_operations: Arc::new(operations),
             subgraph_operations,
+            all_response_operations: Arc::new(response_operations),
+            subgraph_response_operations,
+            router_response_operations: Arc::new(
+                init.config
+                    .router
+                    .map(|router| router.response)
+                    .unwrap_or_default(),
+            ),
         })
     }
 
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.all_response_operations.is_empty()
+            && self
+                .subgraph_response_operations
+                .values()
+                .all(|operations| operations.is_empty())
+            && self.router_response_operations.is_empty()
+        {
+            return service;
+        }
+
+        let operations = self.router_response_operations.clone();
+        ServiceBuilder::new()
+            .map_future_with_request_data(
+                move |request: &supergraph::Request| {
+                    // conditions are evaluated on the request first, and on the response if they
+                    // could not be decided yet
+                    operations
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, operation)| {
+                            let mut condition = operation.condition().cloned().unwrap_or_default();
+                            (condition.evaluate_request(request) != Some(false))
+                                .then_some((index, condition))
+                        })
+                        .collect::<Vec<_>>()
+                },
+                {
+                    let operations = self.router_response_operations.clone();
+                    move |pending: Vec<(usize, Condition<SupergraphSelector>)>, future| {
+                        let operations = operations.clone();
+                        async move {
+                            let mut response: supergraph::Response = future.await?;
+                            let collected = response
+                                .context
+                                .extensions()
+                                .with_lock(|mut lock| lock.remove::<ResponseHeaders>());
+                            if let Some(collected) = collected {
+                                collected.apply(response.response.headers_mut());
+                            }
+
+                            let applicable: Vec<&RouterResponseOperation> = pending
+                                .iter()
+                                .filter(|(_, condition)| condition.evaluate_response(&response))
+                                .map(|(index, _)| &operations[*index])
+                                .collect();
+                            modify_client_response(&applicable, response).await
+                        }
+                        .boxed()
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        let response_operations = self
+            .subgraph_response_operations
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| self.all_response_operations.clone());
         ServiceBuilder::new()
             .layer(HeadersLayer::new(
                 self.subgraph_operations
@@ -232,10 +539,284 @@ impl Plugin for Headers {
                     .unwrap_or_else(|| self.all_operations.clone()),
             ))
             .service(service)
+            .map_response(move |response: SubgraphResponse| {
+                collect_response_headers(&response_operations, &response);
+                response
+            })
             .boxed()
     }
 }
 
+/// Applies the router response rules to the client response
+async fn modify_client_response(
+    operations: &[&RouterResponseOperation],
+    response: supergraph::Response,
+) -> Result<supergraph::Response, BoxError> {
+    if operations.is_empty() {
+        return Ok(response);
+    }
+
+    let supergraph::Response { response, context } = response;
+    let (mut parts, body) = response.into_parts();
+    // values coming from the response body are read from its first part
+    let (first, body) = if operations.iter().any(|operation| {
+        matches!(
+            operation,
+            RouterResponseOperation::Insert(RouterResponseInsert::FromBody(_))
+        )
+    }) {
+        body.into_future().await
+    } else {
+        (None, body)
+    };
+
+    let headers = &mut parts.headers;
+    for operation in operations {
+        match operation {
+            RouterResponseOperation::Insert(RouterResponseInsert::Static(insert)) => {
+                headers.insert(&insert.name, insert.value.clone());
+            }
+            RouterResponseOperation::Insert(RouterResponseInsert::FromContext(insert)) => {
+                if let Some(val) = context
+                    .get::<_, String>(&insert.from_context)
+                    .ok()
+                    .flatten()
+                {
+                    match HeaderValue::from_str(&val) {
+                        Ok(header_value) => {
+                            headers.insert(&insert.name, header_value);
+                        }
+                        Err(err) => {
+                            tracing::error!("cannot convert from the context into a header value for header name '{}': {:?}", insert.name, err);
+                        }
+                    }
+                }
+            }
+            RouterResponseOperation::Insert(RouterResponseInsert::FromBody(insert)) => {
+                let output = first
+                    .as_ref()
+                    .and_then(|first| insert.path.execute(first).ok().flatten());
+                if let Some(val) = output {
+                    let header_value = if let Value::String(val_str) = val {
+                        val_str
+                    } else {
+                        val.to_string()
+                    };
+                    match HeaderValue::from_str(&header_value) {
+                        Ok(header_value) => {
+                            headers.insert(&insert.name, header_value);
+                        }
+                        Err(err) => {
+                            tracing::error!("cannot convert from the body into a header value for header name '{}': {:?}", insert.name, err);
+                        }
+                    }
+                } else if let Some(default_val) = &insert.default {
+                    headers.insert(&insert.name, default_val.clone());
+                }
+            }
+            RouterResponseOperation::Remove(RouterResponseRemove::Named { named, .. }) => {
+                headers.remove(named);
+            }
+            RouterResponseOperation::Remove(RouterResponseRemove::Matching {
+                matching, ..
+            }) => {
+                let new_headers = headers
+                    .drain()
+                    .filter_map(|(name, value)| {
+                        name.and_then(|name| {
+                            (RESERVED_HEADERS.contains(&name) || !matching.is_match(name.as_str()))
+                                .then_some((name, value))
+                        })
+                    })
+                    .collect();
+
+                let _ = std::mem::replace(headers, new_headers);
+            }
+        }
+    }
+
+    let body = match first {
+        Some(first) => once(ready(first)).chain(body).boxed(),
+        None => body,
+    };
+    Ok(supergraph::Response {
+        response: http::Response::from_parts(parts, body),
+        context,
+    })
+}
+
+/// Checks that `most_restrictive` is only used for the `Cache-Control` header, since there is
+/// no way to compare the values of other headers
+fn validate_response_operations(operations: &[ResponseOperation]) -> Result<(), BoxError> {
+    for operation in operations {
+        let headers = match operation {
+            ResponseOperation::Propagate(ResponsePropagate::Named {
+                named,
+                rename,
+                merge: MergeStrategy::MostRestrictive,
+                ..
+            }) => match rename.as_ref().unwrap_or(named) {
+                name if name == CACHE_CONTROL => continue,
+                name => format!("`{name}`"),
+            },
+            ResponseOperation::Propagate(ResponsePropagate::Matching {
+                matching,
+                merge: MergeStrategy::MostRestrictive,
+            }) => format!("the headers matching `{matching}`"),
+            _ => continue,
+        };
+        return Err(ConfigurationError::InvalidConfiguration {
+            message: "bad configuration for headers plugin",
+            error: format!(
+                "the `most_restrictive` merge strategy only applies to `cache-control`, not to {headers}"
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Collects the headers of a subgraph response to set on the client response
+fn collect_response_headers(operations: &[ResponseOperation], response: &SubgraphResponse) {
+    if operations.is_empty() {
+        return;
+    }
+
+    let source = response.response.headers();
+    let mut already_propagated: HashSet<&HeaderName> = HashSet::new();
+    let mut headers: Vec<(HeaderName, HeaderValue, MergeStrategy)> = Vec::new();
+    for operation in operations {
+        match operation {
+            ResponseOperation::Insert(insert) => {
+                headers.push((
+                    insert.name.clone(),
+                    insert.value.clone(),
+                    MergeStrategy::Last,
+                ));
+            }
+            ResponseOperation::Remove(Remove::Named(name)) => {
+                headers.retain(|(header_name, _, _)| header_name != name);
+            }
+            ResponseOperation::Remove(Remove::Matching(matching)) => {
+                headers.retain(|(header_name, _, _)| !matching.is_match(header_name.as_str()));
+            }
+            ResponseOperation::Propagate(ResponsePropagate::Named {
+                named,
+                rename,
+                default,
+                merge,
+            }) => {
+                if already_propagated.insert(named) {
+                    let name = rename.as_ref().unwrap_or(named);
+                    let values = source.get_all(named);
+                    if values.iter().count() == 0 {
+                        if let Some(default) = default {
+                            headers.push((name.clone(), default.clone(), *merge));
+                        }
+                    } else {
+                        for value in values {
+                            headers.push((name.clone(), value.clone(), *merge));
+                        }
+                    }
+                }
+            }
+            ResponseOperation::Propagate(ResponsePropagate::Matching { matching, merge }) => {
+                let names: HashSet<&HeaderName> = source
+                    .keys()
+                    .filter(|name| {
+                        !RESERVED_HEADERS.contains(name)
+                            && matching.is_match(name.as_str())
+                            && !already_propagated.contains(name)
+                    })
+                    .collect();
+                for (name, value) in source.iter().filter(|(name, _)| names.contains(name)) {
+                    headers.push((name.clone(), value.clone(), *merge));
+                }
+                already_propagated.extend(names);
+            }
+        }
+    }
+
+    if !headers.is_empty() {
+        response
+            .context
+            .extensions()
+            .with_lock(|mut lock| lock.get_or_default_mut::<ResponseHeaders>().merge(headers));
+    }
+}
+
+/// Headers collected from subgraph responses, set on the client response
+#[derive(Default)]
+struct ResponseHeaders {
+    headers: HashMap<HeaderName, (MergeStrategy, Vec<HeaderValue>)>,
+}
+
+impl ResponseHeaders {
+    /// Adds the headers of a subgraph response
+    fn merge(&mut self, headers: Vec<(HeaderName, HeaderValue, MergeStrategy)>) {
+        let mut from_response: HashMap<HeaderName, (MergeStrategy, Vec<HeaderValue>)> =
+            HashMap::new();
+        for (name, value, merge) in headers {
+            from_response
+                .entry(name)
+                .or_insert_with(|| (merge, Vec::new()))
+                .1
+                .push(value);
+        }
+
+        for (name, (merge, values)) in from_response {
+            match self.headers.entry(name) {
+                Entry::Vacant(entry) => {
+                    entry.insert((merge, values));
+                }
+                Entry::Occupied(mut entry) => match entry.get().0 {
+                    MergeStrategy::First => {}
+                    MergeStrategy::Last => entry.get_mut().1 = values,
+                    MergeStrategy::Append | MergeStrategy::MostRestrictive => {
+                        entry.get_mut().1.extend(values)
+                    }
+                },
+            }
+        }
+    }
+
+    /// Sets the headers on the client response, replacing the existing values
+    fn apply(self, headers: &mut HeaderMap) {
+        for (name, (merge, values)) in self.headers {
+            headers.remove(&name);
+            if merge == MergeStrategy::MostRestrictive {
+                if let Some(value) = most_restrictive(&name, &values) {
+                    headers.insert(name, value);
+                }
+            } else {
+                for value in values {
+                    headers.append(&name, value);
+                }
+            }
+        }
+    }
+}
+
+fn most_restrictive(name: &HeaderName, values: &[HeaderValue]) -> Option<HeaderValue> {
+    // rejected by `validate_response_operations`
+    if name != CACHE_CONTROL {
+        return values.first().cloned();
+    }
+
+    let merged = values
+        .iter()
+        .map(|value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CACHE_CONTROL, value.clone());
+            // a value we cannot parse could forbid caching
+            CacheControl::new(&headers, None).unwrap_or_else(|_| CacheControl::no_store())
+        })
+        .reduce(|merged, cache_control| merged.merge(&cache_control))?;
+    let mut headers = HeaderMap::new();
+    merged.to_headers(&mut headers).ok()?;
+    headers.remove(CACHE_CONTROL)
+}
+
 struct HeadersLayer {
     operations: Arc<Vec<Operation>>,
     reserved_headers: Arc<HashSet<&'static HeaderName>>,
@@ -928,6 +1509,248 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_response_config() {
+        serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "set-cookie"
+                    merge: append
+                - propagate:
+                    matching: "x-.*"
+                - remove:
+                    named: "x-internal"
+                - insert:
+                    name: "test"
+                    value: "test"
+        subgraphs:
+          products:
+            response:
+                - propagate:
+                    named: "cache-control"
+                    merge: most_restrictive
+        "#,
+        )
+        .unwrap();
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "test"
+                    merge: unknown
+        "#,
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_most_restrictive_only_for_cache_control() {
+        for config in [
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "x-test"
+                    merge: most_restrictive
+        "#,
+            r#"
+        subgraphs:
+          products:
+            response:
+                - propagate:
+                    named: "cache-control"
+                    rename: "x-cache-control"
+                    merge: most_restrictive
+        "#,
+            r#"
+        all:
+            response:
+                - propagate:
+                    matching: ".*"
+                    merge: most_restrictive
+        "#,
+        ] {
+            let config = serde_yaml::from_str::<Config>(config).unwrap();
+            assert!(
+                Headers::new(PluginInit::fake_new(config, Default::default()))
+                    .await
+                    .is_err()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propagate_response_headers() -> Result<(), BoxError> {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "set-cookie"
+                    merge: append
+                - propagate:
+                    named: "cache-control"
+                    merge: most_restrictive
+                - propagate:
+                    named: "x-first"
+                    merge: first
+                - propagate:
+                    matching: "x-.*"
+                - remove:
+                    named: "x-internal"
+                - insert:
+                    name: "x-router"
+                    value: "router"
+        "#,
+        )?;
+        let plugin = Headers::new(PluginInit::fake_new(config, Default::default())).await?;
+        let context = Context::new();
+
+        for (subgraph, cache_control) in [("a", "max-age=60, public"), ("b", "max-age=10")] {
+            let subgraph_service = plugin.subgraph_service(
+                subgraph,
+                tower::service_fn(move |request: SubgraphRequest| async move {
+                    let mut headers = HeaderMap::new();
+                    headers.insert("set-cookie", format!("{subgraph}=1").try_into()?);
+                    headers.insert("cache-control", cache_control.try_into()?);
+                    headers.insert("x-first", subgraph.try_into()?);
+                    headers.insert("x-last", subgraph.try_into()?);
+                    headers.insert("x-internal", "secret".try_into()?);
+                    headers.insert(CONTENT_TYPE, "application/json".try_into()?);
+                    Ok(SubgraphResponse::fake_builder()
+                        .context(request.context)
+                        .headers(headers)
+                        .build())
+                })
+                .boxed(),
+            );
+            subgraph_service
+                .oneshot(
+                    SubgraphRequest::fake_builder()
+                        .context(context.clone())
+                        .build(),
+                )
+                .await?;
+        }
+
+        let response = plugin
+            .supergraph_service(
+                tower::service_fn(|request: supergraph::Request| async move {
+                    supergraph::Response::fake_builder()
+                        .context(request.context)
+                        .header(CONTENT_TYPE, "application/json")
+                        .build()
+                })
+                .boxed(),
+            )
+            .oneshot(
+                supergraph::Request::fake_builder()
+                    .context(context)
+                    .build()?,
+            )
+            .await?;
+
+        let headers = response.response.headers();
+        assert_eq!(
+            headers
+                .get_all("set-cookie")
+                .iter()
+                .map(|value| value.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["a=1", "b=1"]
+        );
+        assert_eq!(headers.get("cache-control").unwrap(), "max-age=10,public");
+        assert_eq!(headers.get("x-first").unwrap(), "a");
+        assert_eq!(headers.get("x-last").unwrap(), "b");
+        assert_eq!(headers.get("x-router").unwrap(), "router");
+        assert!(headers.get("x-internal").is_none());
+        assert_eq!(headers.get_all(CONTENT_TYPE).iter().count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_router_response_rules() -> Result<(), BoxError> {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        router:
+            response:
+                - insert:
+                    name: "x-mobile"
+                    value: "true"
+                    condition:
+                        eq:
+                            - request_header: "x-client"
+                            - "mobile"
+                - insert:
+                    name: "x-from-context"
+                    from_context: "my_key"
+                - insert:
+                    name: "x-user-id"
+                    path: ".data.me.id"
+                - insert:
+                    name: "x-missing"
+                    path: ".data.missing"
+                    default: "none"
+                - remove:
+                    matching: "x-internal-.*"
+        "#,
+        )?;
+        let plugin = Headers::new(PluginInit::fake_new(config, Default::default())).await?;
+        let call = |client: &'static str| {
+            let context = Context::new();
+            context
+                .insert("my_key", "my_value_from_context".to_string())
+                .unwrap();
+            plugin
+                .supergraph_service(
+                    tower::service_fn(|request: supergraph::Request| async move {
+                        supergraph::Response::fake_builder()
+                            .context(request.context)
+                            .data(serde_json_bytes::json!({ "me": { "id": "1" } }))
+                            .header("x-internal-a", "a")
+                            .header(CONTENT_TYPE, "application/json")
+                            .build()
+                    })
+                    .boxed(),
+                )
+                .oneshot(
+                    supergraph::Request::fake_builder()
+                        .header("x-client", client)
+                        .context(context)
+                        .build()
+                        .unwrap(),
+                )
+        };
+
+        let mut response = call("mobile").await?;
+        let headers = response.response.headers();
+        assert_eq!(headers.get("x-mobile").unwrap(), "true");
+        assert_eq!(
+            headers.get("x-from-context").unwrap(),
+            "my_value_from_context"
+        );
+        assert_eq!(headers.get("x-user-id").unwrap(), "1");
+        assert_eq!(headers.get("x-missing").unwrap(), "none");
+        assert!(headers.get("x-internal-a").is_none());
+        assert!(headers.get(CONTENT_TYPE).is_some());
+        let body = response.next_response().await.unwrap();
+        assert_eq!(
+            body.data,
+            Some(serde_json_bytes::json!({ "me": { "id": "1" } }))
+        );
+
+        let response = call("web").await?;
+        assert!(response.response.headers().get("x-mobile").is_none());
+        assert_eq!(response.response.headers().get("x-user-id").unwrap(), "1");
+
+        Ok(())
+    }
+
     fn example_response(req: SubgraphRequest) -> Result<SubgraphResponse, BoxError> {
         Ok(SubgraphResponse::new_from_response(
             http::Response::default(),
@@ -23,6 +23,7 @@ macro_rules! schemar_fn {
 pub(crate) mod authentication;
 pub(crate) mod authorization;
 pub(crate) mod cache;
+mod connectors;
 mod coprocessor;
 pub(crate) mod csrf;
 mod demand_control;
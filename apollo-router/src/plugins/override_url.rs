@@ -2,32 +2,50 @@
 
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
 
+use http::uri::InvalidUri;
 use http::Uri;
 use schemars::JsonSchema;
 use serde::Deserialize;
-use serde::Serialize;
 use tower::BoxError;
 use tower::ServiceExt;
 
+use self::load_balancing::LoadBalancedService;
+use self::load_balancing::LoadBalancer;
+use self::load_balancing::LoadBalancingConfig;
+use crate::error::ConfigurationError;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
 use crate::register_plugin;
 use crate::services::subgraph;
 use crate::services::SubgraphRequest;
 
-#[derive(Debug, Clone)]
+mod load_balancing;
+
+#[derive(Clone)]
 struct OverrideSubgraphUrl {
     urls: HashMap<String, Uri>,
+    load_balancers: HashMap<String, Arc<LoadBalancer>>,
 }
 
 /// Subgraph URL mappings
-#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 #[serde(untagged)]
 enum Conf {
     /// Subgraph URL mappings
-    Mapping(HashMap<String, String>),
+    Mapping(HashMap<String, SubgraphUrl>),
+}
+
+/// The URL of a subgraph
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum SubgraphUrl {
+    /// A single URL
+    Url(String),
+    /// Several endpoints between which requests are load balanced
+    LoadBalanced(LoadBalancingConfig),
 }
 
 #[async_trait::async_trait]
@@ -35,27 +53,47 @@ impl Plugin for OverrideSubgraphUrl {
     type Config = Conf;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
-        let Conf::Mapping(urls) = init.config;
-        Ok(OverrideSubgraphUrl {
-            urls: urls
-                .into_iter()
-                .map(|(k, url)| {
-                    #[cfg(unix)]
-                    // there is no standard for unix socket URLs apparently
-                    if let Some(path) = url.strip_prefix("unix://") {
-                        // there is no specified format for unix socket URLs (cf https://github.com/whatwg/url/issues/577)
-                        // so a unix:// URL will not be parsed by http::Uri
-                        // To fix that, hyperlocal came up with its own Uri type that can be converted to http::Uri.
-                        // It hides the socket path in a hex encoded authority that the unix socket connector will
-                        // know how to decode
-                        Ok((k, hyperlocal::Uri::new(path, "/").into()))
-                    } else {
-                        Uri::from_str(&url).map(|url| (k, url))
+        let Conf::Mapping(mappings) = init.config;
+        let mut urls = HashMap::new();
+        let mut load_balancers = HashMap::new();
+        for (subgraph_name, url) in mappings {
+            match url {
+                SubgraphUrl::Url(url) => {
+                    urls.insert(subgraph_name, parse_url(&url)?);
+                }
+                SubgraphUrl::LoadBalanced(config) => {
+                    if config.endpoints.is_empty() {
+                        return Err(ConfigurationError::InvalidConfiguration {
+                            message: "bad configuration for override_subgraph_url plugin",
+                            error: format!(
+                                "the list of endpoints of subgraph {subgraph_name} is empty"
+                            ),
+                        }
+                        .into());
                     }
-                    #[cfg(not(unix))]
-                    Uri::from_str(&url).map(|url| (k, url))
-                })
-                .collect::<Result<_, _>>()?,
+                    config.validate().map_err(|error| {
+                        ConfigurationError::InvalidConfiguration {
+                            message: "bad configuration for override_subgraph_url plugin",
+                            error: format!(
+                                "invalid load balancing of subgraph {subgraph_name}: {error}"
+                            ),
+                        }
+                    })?;
+                    let endpoints = config
+                        .endpoints
+                        .iter()
+                        .map(|url| parse_url(url))
+                        .collect::<Result<_, _>>()?;
+                    load_balancers.insert(
+                        subgraph_name.clone(),
+                        LoadBalancer::new(subgraph_name, endpoints, &config),
+                    );
+                }
+            }
+        }
+        Ok(OverrideSubgraphUrl {
+            urls,
+            load_balancers,
         })
     }
 
@@ -64,6 +102,10 @@ impl Plugin for OverrideSubgraphUrl {
         subgraph_name: &str,
         service: subgraph::BoxService,
     ) -> subgraph::BoxService {
+        if let Some(load_balancer) = self.load_balancers.get(subgraph_name) {
+            return LoadBalancedService::new(service, load_balancer.clone()).boxed();
+        }
+
         let new_url = self.urls.get(subgraph_name).cloned();
         service
             .map_request(move |mut req: SubgraphRequest| {
@@ -77,6 +119,20 @@ impl Plugin for OverrideSubgraphUrl {
     }
 }
 
+fn parse_url(url: &str) -> Result<Uri, InvalidUri> {
+    #[cfg(unix)]
+    // there is no standard for unix socket URLs apparently
+    if let Some(path) = url.strip_prefix("unix://") {
+        // there is no specified format for unix socket URLs (cf https://github.com/whatwg/url/issues/577)
+        // so a unix:// URL will not be parsed by http::Uri
+        // To fix that, hyperlocal came up with its own Uri type that can be converted to http::Uri.
+        // It hides the socket path in a hex encoded authority that the unix socket connector will
+        // know how to decode
+        return Ok(hyperlocal::Uri::new(path, "/").into());
+    }
+    Uri::from_str(url)
+}
+
 register_plugin!("apollo", "override_subgraph_url", OverrideSubgraphUrl);
 
 #[cfg(test)]
@@ -138,4 +194,63 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn load_balanced_endpoints() {
+        let mut mock_service = MockSubgraphService::new();
+        mock_service
+            .expect_call()
+            .times(4)
+            .returning(move |req: SubgraphRequest| {
+                let mut headers = http::HeaderMap::new();
+                headers.insert(
+                    "x-endpoint",
+                    req.subgraph_request.uri().to_string().parse().unwrap(),
+                );
+                Ok(SubgraphResponse::fake_builder()
+                    .context(req.context)
+                    .headers(headers)
+                    .build())
+            });
+
+        let dyn_plugin: Box<dyn DynPlugin> = crate::plugin::plugins()
+            .find(|factory| factory.name == "apollo.override_subgraph_url")
+            .expect("Plugin not found")
+            .create_instance_without_schema(
+                &Value::from_str(
+                    r#"{
+                "test_one": {
+                    "endpoints": ["http://localhost:8001/", "http://localhost:8002/"]
+                },
+                "test_two": "http://localhost:8003"
+            }"#,
+                )
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        let mut subgraph_service =
+            dyn_plugin.subgraph_service("test_one", BoxService::new(mock_service));
+
+        let mut endpoints = Vec::new();
+        for _ in 0..4 {
+            let response = subgraph_service
+                .ready()
+                .await
+                .unwrap()
+                .call(SubgraphRequest::fake_builder().build())
+                .await
+                .unwrap();
+            endpoints.push(response.response.headers()["x-endpoint"].clone());
+        }
+        assert_eq!(
+            endpoints,
+            vec![
+                "http://localhost:8001/",
+                "http://localhost:8002/",
+                "http://localhost:8001/",
+                "http://localhost:8002/"
+            ]
+        );
+    }
 }
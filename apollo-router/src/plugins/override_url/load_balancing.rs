@@ -0,0 +1,669 @@
+//! Load balancing of subgraph requests over a list of endpoints
+//!
+//! Endpoints returning errors are ejected for a while, and endpoints failing their health checks
+//! are not selected until a health check succeeds again. When no endpoint is available, all of
+//! them are considered, so that requests are still sent somewhere.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::HeaderName;
+use http::Uri;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Service;
+
+use crate::plugin::serde::deserialize_header_name;
+use crate::services::subgraph;
+
+/// Number of points of each endpoint on the consistent hashing ring
+const VIRTUAL_NODES: usize = 100;
+
+/// Load balancing of a subgraph over several endpoints
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LoadBalancingConfig {
+    /// The URLs of the subgraph endpoints
+    pub(crate) endpoints: Vec<String>,
+    /// How an endpoint is selected for each request (default: round_robin)
+    #[serde(default)]
+    strategy: Strategy,
+    /// Ejection of the endpoints returning errors
+    #[serde(default)]
+    ejection: EjectionConfig,
+    /// Active health checks of the endpoints. Without it, endpoints are only ejected when they
+    /// return errors
+    health_check: Option<HealthCheckConfig>,
+}
+
+impl LoadBalancingConfig {
+    /// Rejects the values the load balancer cannot work with
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.ejection.consecutive_errors == Some(0) {
+            return Err("ejection.consecutive_errors must be at least 1".to_string());
+        }
+        if let Some(health_check) = &self.health_check {
+            if health_check
+                .interval
+                .is_some_and(|interval| interval.is_zero())
+            {
+                return Err("health_check.interval must not be zero".to_string());
+            }
+            if health_check
+                .timeout
+                .is_some_and(|timeout| timeout.is_zero())
+            {
+                return Err("health_check.timeout must not be zero".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How an endpoint is selected for each request
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum Strategy {
+    /// Each endpoint in turn
+    #[default]
+    RoundRobin,
+    /// The endpoint with the fewest requests in flight
+    LeastRequests,
+    /// The endpoint selected by hashing the value of a request header, so that requests with the
+    /// same value are sent to the same endpoint while it is available. Requests without the
+    /// header use round robin
+    ConsistentHash {
+        /// The name of the header
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_header_name")]
+        header: HeaderName,
+    },
+}
+
+/// Ejection of the endpoints returning errors
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct EjectionConfig {
+    /// Number of consecutive errors or 5xx responses after which an endpoint is ejected
+    /// (default: 5)
+    consecutive_errors: Option<u32>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "Option<String>", default)]
+    /// How long an endpoint stays ejected (default: 30s)
+    duration: Option<Duration>,
+}
+
+/// Active health checks of the endpoints
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct HealthCheckConfig {
+    /// Path of the health check endpoint, requested with GET on the host of each endpoint. A
+    /// 2xx status marks the endpoint healthy, anything else unhealthy
+    path: String,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "Option<String>", default)]
+    /// Time between two health checks (default: 10s)
+    interval: Option<Duration>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "Option<String>", default)]
+    /// Timeout of a health check request (default: 1s)
+    timeout: Option<Duration>,
+}
+
+struct Endpoint {
+    uri: Uri,
+    in_flight: AtomicUsize,
+    consecutive_errors: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+    healthy: AtomicBool,
+}
+
+impl Endpoint {
+    fn is_available(&self, now: Instant) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+            && self
+                .ejected_until
+                .lock()
+                .map_or(true, |ejected_until| now >= ejected_until)
+    }
+}
+
+/// Load balancing state of a subgraph, shared by all its requests
+pub(crate) struct LoadBalancer {
+    subgraph_name: String,
+    endpoints: Vec<Endpoint>,
+    strategy: Strategy,
+    /// Points of the endpoints on the consistent hashing ring, sorted by hash
+    ring: Vec<(u64, usize)>,
+    next: AtomicUsize,
+    consecutive_errors: u32,
+    ejection_duration: Duration,
+}
+
+impl LoadBalancer {
+    pub(crate) fn new(
+        subgraph_name: String,
+        endpoints: Vec<Uri>,
+        config: &LoadBalancingConfig,
+    ) -> Arc<Self> {
+        let ring = match config.strategy {
+            Strategy::ConsistentHash { .. } => {
+                let mut ring = endpoints
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, uri)| {
+                        (0..VIRTUAL_NODES).map(move |node| (hash(&(uri.to_string(), node)), index))
+                    })
+                    .collect::<Vec<_>>();
+                ring.sort_unstable();
+                ring
+            }
+            _ => Vec::new(),
+        };
+
+        let load_balancer = Arc::new(Self {
+            subgraph_name,
+            endpoints: endpoints
+                .into_iter()
+                .map(|uri| Endpoint {
+                    uri,
+                    in_flight: AtomicUsize::new(0),
+                    consecutive_errors: AtomicU32::new(0),
+                    ejected_until: Mutex::new(None),
+                    healthy: AtomicBool::new(true),
+                })
+                .collect(),
+            strategy: config.strategy.clone(),
+            ring,
+            next: AtomicUsize::new(0),
+            consecutive_errors: config.ejection.consecutive_errors.unwrap_or(5),
+            ejection_duration: config
+                .ejection
+                .duration
+                .unwrap_or_else(|| Duration::from_secs(30)),
+        });
+
+        if let Some(health_check) = &config.health_check {
+            spawn_health_checks(Arc::downgrade(&load_balancer), health_check);
+        }
+        load_balancer
+    }
+
+    /// Selects the endpoint of a request
+    fn select(&self, request: &subgraph::Request) -> usize {
+        let now = Instant::now();
+        match &self.strategy {
+            Strategy::RoundRobin => self.round_robin(now),
+            Strategy::LeastRequests => self.least_requests(now),
+            Strategy::ConsistentHash { header } => {
+                match request.supergraph_request.headers().get(header) {
+                    Some(value) => self.consistent_hash(value.as_bytes(), now),
+                    None => self.round_robin(now),
+                }
+            }
+        }
+    }
+
+    fn round_robin(&self, now: Instant) -> usize {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.endpoints.len())
+            .map(|offset| (start + offset) % self.endpoints.len())
+            .find(|index| self.endpoints[*index].is_available(now))
+            .unwrap_or(start % self.endpoints.len())
+    }
+
+    fn least_requests(&self, now: Instant) -> usize {
+        // starting from a different endpoint every time spreads the requests between the
+        // endpoints with the same number of requests in flight
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let indexes =
+            (0..self.endpoints.len()).map(|offset| (start + offset) % self.endpoints.len());
+        let least = |indexes: &mut dyn Iterator<Item = usize>| {
+            indexes.min_by_key(|index| self.endpoints[*index].in_flight.load(Ordering::Relaxed))
+        };
+        least(
+            &mut indexes
+                .clone()
+                .filter(|index| self.endpoints[*index].is_available(now)),
+        )
+        .or_else(|| least(&mut indexes.clone()))
+        .unwrap_or_default()
+    }
+
+    fn consistent_hash(&self, value: &[u8], now: Instant) -> usize {
+        let key = hash(&value);
+        let start = self.ring.partition_point(|(point, _)| *point < key);
+        (0..self.ring.len())
+            .map(|offset| self.ring[(start + offset) % self.ring.len()].1)
+            .find(|index| self.endpoints[*index].is_available(now))
+            .unwrap_or(self.ring[start % self.ring.len()].1)
+    }
+
+    /// Records the outcome of a request, and ejects the endpoint after too many errors
+    fn record(&self, index: usize, success: bool) {
+        let endpoint = &self.endpoints[index];
+        if success {
+            endpoint.consecutive_errors.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let errors = endpoint.consecutive_errors.fetch_add(1, Ordering::Relaxed) + 1;
+        if errors >= self.consecutive_errors {
+            endpoint.consecutive_errors.store(0, Ordering::Relaxed);
+            *endpoint.ejected_until.lock() = Some(Instant::now() + self.ejection_duration);
+            tracing::warn!(
+                "ejecting endpoint {} of subgraph {} for {:?} after {} consecutive errors",
+                endpoint.uri,
+                self.subgraph_name,
+                self.ejection_duration,
+                errors
+            );
+            u64_counter!(
+                "apollo.router.operations.load_balancing.ejections",
+                "Number of subgraph endpoints ejected after consecutive errors",
+                1u64,
+                "subgraph.name" = self.subgraph_name.clone()
+            );
+        }
+    }
+
+    fn set_healthy(&self, index: usize, healthy: bool) {
+        let endpoint = &self.endpoints[index];
+        if endpoint.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                tracing::info!(
+                    "endpoint {} of subgraph {} is healthy",
+                    endpoint.uri,
+                    self.subgraph_name
+                );
+            } else {
+                tracing::warn!(
+                    "endpoint {} of subgraph {} failed its health check",
+                    endpoint.uri,
+                    self.subgraph_name
+                );
+            }
+        }
+    }
+}
+
+fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Checks the health of the endpoints periodically, until the load balancer is dropped
+fn spawn_health_checks(load_balancer: Weak<LoadBalancer>, config: &HealthCheckConfig) {
+    let interval = config.interval.unwrap_or_else(|| Duration::from_secs(10));
+    let timeout = config.timeout.unwrap_or_else(|| Duration::from_secs(1));
+    let path = if config.path.starts_with('/') {
+        config.path.clone()
+    } else {
+        format!("/{}", config.path)
+    };
+
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder().timeout(timeout).build() {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!("could not create the health check client: {e}");
+                return;
+            }
+        };
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            let Some(load_balancer) = load_balancer.upgrade() else {
+                return;
+            };
+            let checks =
+                load_balancer
+                    .endpoints
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, endpoint)| {
+                        // only HTTP endpoints can be checked
+                        let scheme = endpoint.uri.scheme_str()?;
+                        if scheme != "http" && scheme != "https" {
+                            return None;
+                        }
+                        let url = format!("{scheme}://{}{path}", endpoint.uri.authority()?);
+                        let request = client.get(url).send();
+                        Some(async move {
+                            let healthy = request
+                                .await
+                                .map_or(false, |response| response.status().is_success());
+                            (index, healthy)
+                        })
+                    });
+            for (index, healthy) in futures::future::join_all(checks).await {
+                load_balancer.set_healthy(index, healthy);
+            }
+        }
+    });
+}
+
+/// Sends each request to the endpoint selected by the load balancer
+pub(crate) struct LoadBalancedService<S> {
+    inner: S,
+    load_balancer: Arc<LoadBalancer>,
+}
+
+impl<S> LoadBalancedService<S> {
+    pub(crate) fn new(inner: S, load_balancer: Arc<LoadBalancer>) -> Self {
+        Self {
+            inner,
+            load_balancer,
+        }
+    }
+}
+
+/// A request in flight to an endpoint
+struct InFlight {
+    load_balancer: Arc<LoadBalancer>,
+    index: usize,
+}
+
+impl InFlight {
+    fn new(load_balancer: Arc<LoadBalancer>, index: usize) -> Self {
+        load_balancer.endpoints[index]
+            .in_flight
+            .fetch_add(1, Ordering::Relaxed);
+        Self {
+            load_balancer,
+            index,
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.load_balancer.endpoints[self.index]
+            .in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S> Service<subgraph::Request> for LoadBalancedService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response, Error = BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: subgraph::Request) -> Self::Future {
+        let index = self.load_balancer.select(&request);
+        *request.subgraph_request.uri_mut() = self.load_balancer.endpoints[index].uri.clone();
+        let in_flight = InFlight::new(self.load_balancer.clone(), index);
+        let response = self.inner.call(request);
+
+        async move {
+            let response = response.await;
+            let success = response.as_ref().map_or(false, |response| {
+                !response.response.status().is_server_error()
+            });
+            in_flight.load_balancer.record(in_flight.index, success);
+            response
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    use http::StatusCode;
+    use tower::ServiceExt;
+    use wiremock::matchers::method;
+    use wiremock::matchers::path;
+    use wiremock::Mock;
+    use wiremock::MockServer;
+    use wiremock::ResponseTemplate;
+
+    use super::*;
+
+    fn config(strategy: Strategy) -> LoadBalancingConfig {
+        LoadBalancingConfig {
+            endpoints: vec![],
+            strategy,
+            ejection: EjectionConfig {
+                consecutive_errors: Some(2),
+                duration: None,
+            },
+            health_check: None,
+        }
+    }
+
+    fn endpoints(count: usize) -> Vec<Uri> {
+        (0..count)
+            .map(|index| Uri::from_str(&format!("http://endpoint-{index}:4000")).unwrap())
+            .collect()
+    }
+
+    fn request(header: Option<&str>) -> subgraph::Request {
+        let mut supergraph_request = http::Request::builder();
+        if let Some(header) = header {
+            supergraph_request = supergraph_request.header("x-user", header);
+        }
+        subgraph::Request::fake_builder()
+            .supergraph_request(Arc::new(
+                supergraph_request.body(Default::default()).unwrap(),
+            ))
+            .build()
+    }
+
+    // responds with the selected endpoint, and fails for the first endpoint
+    fn service(
+        load_balancer: Arc<LoadBalancer>,
+    ) -> LoadBalancedService<
+        impl Service<
+            subgraph::Request,
+            Response = subgraph::Response,
+            Error = BoxError,
+            Future = BoxFuture<'static, Result<subgraph::Response, BoxError>>,
+        >,
+    > {
+        LoadBalancedService::new(
+            tower::service_fn(|request: subgraph::Request| {
+                async move {
+                    let uri = request.subgraph_request.uri().to_string();
+                    let mut headers = http::HeaderMap::new();
+                    headers.insert("x-endpoint", uri.parse().unwrap());
+                    let mut response = subgraph::Response::fake_builder()
+                        .context(request.context)
+                        .headers(headers)
+                        .build();
+                    if uri.contains("endpoint-0") {
+                        *response.response.status_mut() = StatusCode::BAD_GATEWAY;
+                    }
+                    Ok(response)
+                }
+                .boxed()
+            }),
+            load_balancer,
+        )
+    }
+
+    #[test]
+    fn round_robin() {
+        let load_balancer = LoadBalancer::new(
+            "test".to_string(),
+            endpoints(3),
+            &config(Strategy::RoundRobin),
+        );
+        let selected = (0..6)
+            .map(|_| load_balancer.select(&request(None)))
+            .collect::<Vec<_>>();
+        assert_eq!(selected, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn least_requests() {
+        let load_balancer = LoadBalancer::new(
+            "test".to_string(),
+            endpoints(3),
+            &config(Strategy::LeastRequests),
+        );
+        let _first = InFlight::new(load_balancer.clone(), 0);
+        let _second = InFlight::new(load_balancer.clone(), 1);
+        let _third = InFlight::new(load_balancer.clone(), 1);
+        for _ in 0..3 {
+            assert_eq!(load_balancer.select(&request(None)), 2);
+        }
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let load_balancer = LoadBalancer::new(
+            "test".to_string(),
+            endpoints(3),
+            &config(Strategy::ConsistentHash {
+                header: HeaderName::from_static("x-user"),
+            }),
+        );
+        let mut selected = HashSet::new();
+        for user in 0..50 {
+            let user = user.to_string();
+            let index = load_balancer.select(&request(Some(&user)));
+            assert_eq!(load_balancer.select(&request(Some(&user))), index);
+            selected.insert(index);
+        }
+        assert_eq!(selected.len(), 3);
+
+        // the requests of an ejected endpoint go to the other endpoints
+        let user = (0..50)
+            .map(|user| user.to_string())
+            .find(|user| load_balancer.select(&request(Some(user))) == 0)
+            .unwrap();
+        load_balancer.set_healthy(0, false);
+        assert_ne!(load_balancer.select(&request(Some(&user))), 0);
+        load_balancer.set_healthy(0, true);
+        assert_eq!(load_balancer.select(&request(Some(&user))), 0);
+    }
+
+    #[test]
+    fn validates_config() {
+        assert!(config(Strategy::RoundRobin).validate().is_ok());
+
+        let mut invalid = config(Strategy::RoundRobin);
+        invalid.ejection.consecutive_errors = Some(0);
+        assert!(invalid.validate().is_err());
+
+        let health_check = |interval: u64, timeout: u64| HealthCheckConfig {
+            path: "/health".to_string(),
+            interval: Some(Duration::from_millis(interval)),
+            timeout: Some(Duration::from_millis(timeout)),
+        };
+        let mut invalid = config(Strategy::RoundRobin);
+        invalid.health_check = Some(health_check(0, 100));
+        assert!(invalid.validate().is_err());
+        invalid.health_check = Some(health_check(100, 0));
+        assert!(invalid.validate().is_err());
+        invalid.health_check = Some(health_check(100, 100));
+        assert!(invalid.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn ejects_failing_endpoints() {
+        let load_balancer = LoadBalancer::new(
+            "test".to_string(),
+            endpoints(2),
+            &config(Strategy::RoundRobin),
+        );
+        let mut service = service(load_balancer.clone());
+
+        let mut statuses = Vec::new();
+        for _ in 0..6 {
+            let response = service
+                .ready()
+                .await
+                .unwrap()
+                .call(request(None))
+                .await
+                .unwrap();
+            statuses.push(response.response.status().as_u16());
+        }
+        // the first endpoint is ejected after two errors
+        assert_eq!(statuses, vec![502, 200, 502, 200, 200, 200]);
+        assert!(!load_balancer.endpoints[0].is_available(Instant::now()));
+        assert!(load_balancer.endpoints[0].is_available(Instant::now() + Duration::from_secs(31)));
+    }
+
+    #[tokio::test]
+    async fn uses_all_endpoints_when_none_is_available() {
+        let load_balancer = LoadBalancer::new(
+            "test".to_string(),
+            endpoints(2),
+            &config(Strategy::RoundRobin),
+        );
+        load_balancer.set_healthy(0, false);
+        load_balancer.set_healthy(1, false);
+        let selected = (0..4)
+            .map(|_| load_balancer.select(&request(None)))
+            .collect::<Vec<_>>();
+        assert_eq!(selected, vec![0, 1, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn health_checks() {
+        let healthy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&healthy)
+            .await;
+        let unhealthy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/health"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&unhealthy)
+            .await;
+
+        let load_balancer = LoadBalancer::new(
+            "test".to_string(),
+            vec![
+                Uri::from_str(&format!("{}/graphql", unhealthy.uri())).unwrap(),
+                Uri::from_str(&format!("{}/graphql", healthy.uri())).unwrap(),
+            ],
+            &LoadBalancingConfig {
+                health_check: Some(HealthCheckConfig {
+                    path: "health".to_string(),
+                    interval: Some(Duration::from_millis(50)),
+                    timeout: None,
+                }),
+                ..config(Strategy::RoundRobin)
+            },
+        );
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!load_balancer.endpoints[0].healthy.load(Ordering::Relaxed));
+        assert!(load_balancer.endpoints[1].healthy.load(Ordering::Relaxed));
+        for _ in 0..3 {
+            assert_eq!(load_balancer.select(&request(None)), 1);
+        }
+    }
+}
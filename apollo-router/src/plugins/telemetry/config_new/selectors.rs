@@ -25,6 +25,7 @@ use crate::plugins::telemetry::config_new::trace_id;
 use crate::plugins::telemetry::config_new::DatadogId;
 use crate::plugins::telemetry::config_new::Selector;
 use crate::plugins::telemetry::config_new::ToOtelValue;
+use crate::plugins::traffic_shaping::circuit_breaker::CircuitBreakerContextKey;
 use crate::query_planner::APOLLO_OPERATION_ID;
 use crate::services::router;
 use crate::services::subgraph;
@@ -626,6 +627,10 @@ pub(crate) enum SubgraphSelector {
         /// Specify the entity type on which you want the cache data. (default: all)
         entity_type: Option<EntityType>,
     },
+    CircuitBreakerState {
+        /// The state of the circuit breaker of the subgraph: `closed`, `open` or `half_open`
+        circuit_breaker_state: bool,
+    },
 }
 
 #[derive(Deserialize, JsonSchema, Clone, PartialEq, Debug)]
@@ -1408,6 +1413,16 @@ impl Selector for SubgraphSelector {
             } if *on_graphql_error => Some((!response.response.body().errors.is_empty()).into()),
             SubgraphSelector::Static(val) => Some(val.clone().into()),
             SubgraphSelector::StaticField { r#static } => Some(r#static.clone().into()),
+            SubgraphSelector::CircuitBreakerState {
+                circuit_breaker_state,
+            } if *circuit_breaker_state => response
+                .context
+                .get::<_, String>(CircuitBreakerContextKey::new(
+                    response.subgraph_name.clone()?,
+                ))
+                .ok()
+                .flatten()
+                .map(opentelemetry::Value::from),
             SubgraphSelector::Cache { cache, entity_type } => {
                 let cache_info: CacheSubgraph = response
                     .context
@@ -1521,6 +1536,7 @@ mod test {
     use crate::plugins::telemetry::config_new::selectors::TraceIdFormat;
     use crate::plugins::telemetry::config_new::Selector;
     use crate::plugins::telemetry::otel;
+    use crate::plugins::traffic_shaping::circuit_breaker::CircuitBreakerContextKey;
     use crate::query_planner::APOLLO_OPERATION_ID;
     use crate::services::FIRST_EVENT_CONTEXT_KEY;
     use crate::spec::operation_limits::OperationLimits;
@@ -2586,6 +2602,36 @@ mod test {
         );
     }
 
+    #[test]
+    fn subgraph_circuit_breaker_state() {
+        let selector = SubgraphSelector::CircuitBreakerState {
+            circuit_breaker_state: true,
+        };
+        let context = crate::context::Context::new();
+        let _ = context.insert(
+            CircuitBreakerContextKey::new("test".to_string()),
+            "open".to_string(),
+        );
+        assert_eq!(
+            selector.on_response(
+                &crate::services::SubgraphResponse::fake_builder()
+                    .subgraph_name("test".to_string())
+                    .context(context.clone())
+                    .build(),
+            ),
+            Some("open".into())
+        );
+        assert_eq!(
+            selector.on_response(
+                &crate::services::SubgraphResponse::fake_builder()
+                    .subgraph_name("other".to_string())
+                    .context(context)
+                    .build(),
+            ),
+            None
+        );
+    }
+
     #[test]
     fn subgraph_cache_hit_all_entities() {
         let selector = SubgraphSelector::Cache {
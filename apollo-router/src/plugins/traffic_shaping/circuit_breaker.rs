@@ -0,0 +1,537 @@
+//! Circuit breaker for subgraph requests
+//!
+//! The circuit breaker of a subgraph is closed by default, letting all requests through. It opens
+//! when too many requests fail or are too slow, and then rejects requests immediately. After
+//! `open_duration`, it is half open: a few probe requests are sent to the subgraph, and it closes
+//! if they all succeed, or opens again if one of them fails.
+//!
+//! A request fails if it gets an error or a 5xx response, or if it times out. Requests cancelled
+//! before their response, like when the client disconnects, are not counted.
+
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use crate::graphql;
+use crate::services::subgraph;
+
+/// Context key prefix of the state of the circuit breaker of a subgraph
+const CIRCUIT_BREAKER_CONTEXT_KEY: &str = "apollo::traffic_shaping::circuit_breaker_state";
+
+/// Circuit breaker configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CircuitBreakerConfig {
+    /// Proportion of failed requests, between 0 and 1, from which the circuit breaker opens (default: 0.5)
+    #[serde(default = "default_error_rate")]
+    error_rate: f64,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "Option<String>", default)]
+    /// Requests taking longer than this are counted as failed (disabled by default)
+    latency: Option<Duration>,
+    /// Minimum number of requests in the window before the error rate is evaluated (default: 20)
+    #[serde(default = "default_minimum_requests")]
+    minimum_requests: u32,
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_window"
+    )]
+    #[schemars(with = "String", default)]
+    /// Duration of the window over which the error rate is computed (default: 10s)
+    window: Duration,
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_open_duration"
+    )]
+    #[schemars(with = "String", default)]
+    /// How long the circuit breaker rejects requests before sending probe requests (default: 30s)
+    open_duration: Duration,
+    /// Number of successful probe requests needed to close the circuit breaker (default: 3)
+    #[serde(default = "default_probe_requests")]
+    probe_requests: u32,
+}
+
+impl CircuitBreakerConfig {
+    /// Checks the error rate and the number of probe requests
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !(self.error_rate > 0.0 && self.error_rate <= 1.0) {
+            return Err(format!(
+                "the error_rate of the circuit breaker ({}) must be greater than 0, and at most 1",
+                self.error_rate
+            ));
+        }
+        if self.probe_requests == 0 {
+            return Err("the probe_requests of the circuit breaker must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_error_rate() -> f64 {
+    0.5
+}
+
+fn default_minimum_requests() -> u32 {
+    20
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_open_duration() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_probe_requests() -> u32 {
+    3
+}
+
+/// State of a circuit breaker
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CircuitState {
+    /// Requests are sent to the subgraph
+    Closed,
+    /// Requests are rejected
+    Open,
+    /// Only probe requests are sent to the subgraph
+    HalfOpen,
+}
+
+impl CircuitState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// Context key of the state of the circuit breaker of a subgraph, as seen by the last request
+pub(crate) struct CircuitBreakerContextKey(String);
+
+impl CircuitBreakerContextKey {
+    pub(crate) fn new(subgraph_name: String) -> Self {
+        Self(subgraph_name)
+    }
+}
+
+impl From<CircuitBreakerContextKey> for String {
+    fn from(val: CircuitBreakerContextKey) -> Self {
+        format!("{CIRCUIT_BREAKER_CONTEXT_KEY}_{}", val.0)
+    }
+}
+
+/// Error returned when the circuit breaker rejects a request
+#[derive(Clone, Debug)]
+pub(crate) struct CircuitOpen {
+    subgraph_name: String,
+}
+
+impl CircuitOpen {
+    pub(crate) fn new(subgraph_name: String) -> Self {
+        CircuitOpen { subgraph_name }
+    }
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the circuit breaker of subgraph '{}' is open",
+            self.subgraph_name
+        )
+    }
+}
+
+impl From<CircuitOpen> for graphql::Error {
+    fn from(error: CircuitOpen) -> Self {
+        graphql::Error::builder()
+            .message(format!(
+                "Subgraph '{}' is unavailable: too many requests failed",
+                error.subgraph_name
+            ))
+            .extension_code("SUBGRAPH_CIRCUIT_OPEN")
+            .build()
+    }
+}
+
+impl error::Error for CircuitOpen {}
+
+struct State {
+    state: CircuitState,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+    opened_at: Instant,
+    probes_in_flight: u32,
+    probe_successes: u32,
+}
+
+/// Circuit breaker of a subgraph, shared by all its requests
+#[derive(Clone)]
+pub(crate) struct CircuitBreaker {
+    subgraph_name: String,
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(subgraph_name: String, config: CircuitBreakerConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            subgraph_name,
+            config,
+            state: Arc::new(Mutex::new(State {
+                state: CircuitState::Closed,
+                window_start: now,
+                requests: 0,
+                failures: 0,
+                opened_at: now,
+                probes_in_flight: 0,
+                probe_successes: 0,
+            })),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        self.state.lock().state
+    }
+
+    /// Checks whether a request can be sent to the subgraph
+    fn acquire(&self) -> Result<Permit, CircuitOpen> {
+        let mut state = self.state.lock();
+        if state.state == CircuitState::Open
+            && state.opened_at.elapsed() >= self.config.open_duration
+        {
+            self.transition(&mut state, CircuitState::HalfOpen);
+        }
+
+        match state.state {
+            CircuitState::Closed => Ok(Permit::new(self.clone(), false)),
+            CircuitState::HalfOpen
+                if state.probes_in_flight + state.probe_successes < self.config.probe_requests =>
+            {
+                state.probes_in_flight += 1;
+                Ok(Permit::new(self.clone(), true))
+            }
+            _ => Err(CircuitOpen::new(self.subgraph_name.clone())),
+        }
+    }
+
+    /// Records a request that timed out. Its permit was dropped without an outcome when the
+    /// timeout cancelled it, so it is counted as a failed probe if the circuit breaker is half open
+    pub(crate) fn record_timeout(&self) {
+        self.record(true, false);
+    }
+
+    /// Frees the probe slot of a request cancelled before its outcome was known
+    fn cancel(&self, probe: bool) {
+        let mut state = self.state.lock();
+        if probe && state.state == CircuitState::HalfOpen {
+            state.probes_in_flight = state.probes_in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Records the outcome of a request
+    fn record(&self, probe: bool, success: bool) {
+        let mut state = self.state.lock();
+        match state.state {
+            CircuitState::HalfOpen if probe => {
+                // probes sent during a previous half open period can still be in flight
+                state.probes_in_flight = state.probes_in_flight.saturating_sub(1);
+                if !success {
+                    self.transition(&mut state, CircuitState::Open);
+                } else {
+                    state.probe_successes += 1;
+                    if state.probe_successes >= self.config.probe_requests {
+                        self.transition(&mut state, CircuitState::Closed);
+                    }
+                }
+            }
+            CircuitState::Closed => {
+                if state.window_start.elapsed() > self.config.window {
+                    state.window_start = Instant::now();
+                    state.requests = 0;
+                    state.failures = 0;
+                }
+                state.requests += 1;
+                if !success {
+                    state.failures += 1;
+                }
+
+                if state.requests >= self.config.minimum_requests
+                    && state.failures as f64 >= state.requests as f64 * self.config.error_rate
+                {
+                    self.transition(&mut state, CircuitState::Open);
+                }
+            }
+            // requests sent before the circuit breaker opened do not affect it
+            _ => {}
+        }
+    }
+
+    fn transition(&self, state: &mut State, to: CircuitState) {
+        let now = Instant::now();
+        state.state = to;
+        state.window_start = now;
+        state.requests = 0;
+        state.failures = 0;
+        state.opened_at = now;
+        state.probes_in_flight = 0;
+        state.probe_successes = 0;
+
+        tracing::info!(
+            subgraph = %self.subgraph_name,
+            state = to.as_str(),
+            "circuit breaker state changed"
+        );
+        u64_counter!(
+            "apollo.router.operations.circuit_breaker.state_change",
+            "Number of state changes of the subgraph circuit breakers",
+            1u64,
+            "subgraph.name" = self.subgraph_name.clone(),
+            "state" = to.as_str()
+        );
+    }
+}
+
+/// Allows a request to be sent to the subgraph. If it is dropped before its outcome is recorded,
+/// the request is not counted: timeouts are recorded by `CircuitBreaker::record_timeout`
+struct Permit {
+    breaker: CircuitBreaker,
+    probe: bool,
+    started_at: Instant,
+    recorded: bool,
+}
+
+impl Permit {
+    fn new(breaker: CircuitBreaker, probe: bool) -> Self {
+        Self {
+            breaker,
+            probe,
+            started_at: Instant::now(),
+            recorded: false,
+        }
+    }
+
+    fn record(mut self, success: bool) {
+        let too_slow = self
+            .breaker
+            .config
+            .latency
+            .map(|latency| self.started_at.elapsed() > latency)
+            .unwrap_or(false);
+        self.breaker.record(self.probe, success && !too_slow);
+        self.recorded = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.cancel(self.probe);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CircuitBreakerLayer {
+    breaker: CircuitBreaker,
+}
+
+impl CircuitBreakerLayer {
+    pub(crate) fn new(breaker: CircuitBreaker) -> Self {
+        Self { breaker }
+    }
+
+    pub(crate) fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        CircuitBreakerService {
+            inner: service,
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CircuitBreakerService<S> {
+    inner: S,
+    breaker: CircuitBreaker,
+}
+
+impl<S> Service<subgraph::Request> for CircuitBreakerService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: subgraph::Request) -> Self::Future {
+        let context = request.context.clone();
+        let key = CircuitBreakerContextKey::new(self.breaker.subgraph_name.clone());
+
+        let permit = match self.breaker.acquire() {
+            Ok(permit) => permit,
+            Err(error) => {
+                let _ = context.insert(key, self.breaker.state());
+                return futures::future::ready(Err(error.into())).boxed();
+            }
+        };
+
+        let breaker = self.breaker.clone();
+        let response = self.inner.call(request);
+        async move {
+            let response = response.await.map_err(Into::into);
+            let success = match &response {
+                Ok(response) => !response.response.status().is_server_error(),
+                Err(_) => false,
+            };
+            permit.record(success);
+            let _ = context.insert(key, breaker.state());
+
+            response
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test".to_string(),
+            CircuitBreakerConfig {
+                error_rate: 0.5,
+                latency: None,
+                minimum_requests: 4,
+                window: Duration::from_secs(10),
+                open_duration: Duration::from_millis(50),
+                probe_requests: 2,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn transitions() {
+        let breaker = breaker();
+
+        breaker.acquire().unwrap().record(true);
+        breaker.acquire().unwrap().record(true);
+        breaker.acquire().unwrap().record(false);
+        // cancelled requests are not counted
+        drop(breaker.acquire().unwrap());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_timeout();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.acquire().is_err());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let first_probe = breaker.acquire().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        let second_probe = breaker.acquire().unwrap();
+        // only `probe_requests` requests are sent while half open
+        assert!(breaker.acquire().is_err());
+        first_probe.record(true);
+        second_probe.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn cancelled_and_timed_out_probes() {
+        let breaker = breaker();
+        for _ in 0..4 {
+            breaker.acquire().unwrap().record(false);
+        }
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        // a cancelled probe frees its slot
+        drop(breaker.acquire().unwrap());
+        let first_probe = breaker.acquire().unwrap();
+        let second_probe = breaker.acquire().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        drop(first_probe);
+        breaker.record_timeout();
+        assert_eq!(breaker.state(), CircuitState::Open);
+        // probes sent before the circuit breaker opened again are not counted
+        second_probe.record(true);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn validates_config() {
+        assert!(breaker().config.validate().is_ok());
+        for (error_rate, probe_requests) in [(0.0, 2), (1.5, 2), (f64::NAN, 2), (0.5, 0)] {
+            let config = CircuitBreakerConfig {
+                error_rate,
+                probe_requests,
+                ..breaker().config
+            };
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_probe() {
+        let breaker = breaker();
+        for _ in 0..4 {
+            breaker.acquire().unwrap().record(false);
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        breaker.acquire().unwrap().record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.acquire().is_err());
+    }
+
+    #[tokio::test]
+    async fn slow_requests() {
+        let breaker = CircuitBreaker::new(
+            "test".to_string(),
+            CircuitBreakerConfig {
+                latency: Some(Duration::from_millis(10)),
+                minimum_requests: 1,
+                ..breaker().config
+            },
+        );
+
+        let permit = breaker.acquire().unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        permit.record(true);
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+}
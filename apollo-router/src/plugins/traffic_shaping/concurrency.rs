@@ -0,0 +1,614 @@
+//! Adaptive concurrency limit for subgraph requests
+//!
+//! The number of requests in flight to a subgraph is limited, and the limit is adjusted from the
+//! latency of the requests: it grows while the latency stays stable, and shrinks when the latency
+//! increases or requests fail, which happens when the subgraph gets overloaded. Requests over the
+//! limit wait in a bounded queue, or are rejected immediately.
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry::metrics::ObservableGauge;
+use opentelemetry::KeyValue;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::metrics::meter_provider;
+use crate::services::subgraph;
+
+/// Number of samples averaged before the gradient algorithm adjusts the limit
+const WARMUP_SAMPLES: u32 = 10;
+/// Number of samples over which the long term latency of the gradient algorithm is averaged
+const LONG_WINDOW: f64 = 600.0;
+/// Latency increase tolerated by the gradient algorithm before reducing the limit
+const TOLERANCE: f64 = 1.5;
+/// Weight of a new limit computed by the gradient algorithm
+const SMOOTHING: f64 = 0.2;
+
+/// Adaptive concurrency limit configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConcurrencyLimitConfig {
+    /// How the limit is adjusted (default: gradient)
+    #[serde(default)]
+    algorithm: ConcurrencyAlgorithm,
+    /// Limit used until enough latencies were observed (default: 20)
+    #[serde(default = "default_initial_limit")]
+    initial_limit: u32,
+    /// Lowest limit (default: 1)
+    #[serde(default = "default_min_limit")]
+    min_limit: u32,
+    /// Highest limit (default: 1000)
+    #[serde(default = "default_max_limit")]
+    max_limit: u32,
+    /// Queue for the requests over the limit. Without it, requests over the limit are rejected
+    /// immediately
+    queue: Option<QueueConfig>,
+}
+
+/// How the concurrency limit is adjusted
+#[derive(PartialEq, Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum ConcurrencyAlgorithm {
+    /// Compares the latency of each request to the long term latency of the subgraph, and
+    /// reduces the limit in proportion when it increases
+    #[default]
+    Gradient,
+    /// Increases the limit by one while requests succeed, and multiplies it by `backoff_ratio`
+    /// when a request fails or is slower than `latency_threshold`
+    Aimd {
+        /// Factor applied to the limit on failure, between 0.5 and 1 (default: 0.9)
+        #[serde(default = "default_backoff_ratio")]
+        backoff_ratio: f64,
+        #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+        #[schemars(with = "Option<String>", default)]
+        /// Requests slower than this are counted as failed (disabled by default)
+        latency_threshold: Option<Duration>,
+    },
+}
+
+/// Queue for the requests over the concurrency limit
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct QueueConfig {
+    /// Maximum number of queued requests. Requests are rejected when the queue is full
+    max_size: usize,
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    /// How long a request waits in the queue before it is rejected
+    timeout: Duration,
+}
+
+impl ConcurrencyLimitConfig {
+    /// Checks that the initial limit is between the lowest and the highest limits
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.max_limit == 0 {
+            return Err("the max_limit of the concurrency limit must be at least 1".to_string());
+        }
+        if self.min_limit > self.max_limit {
+            return Err(format!(
+                "the min_limit of the concurrency limit ({}) is greater than its max_limit ({})",
+                self.min_limit, self.max_limit
+            ));
+        }
+        if !(self.min_limit..=self.max_limit).contains(&self.initial_limit) {
+            return Err(format!(
+                "the initial_limit of the concurrency limit ({}) is not between its min_limit ({}) and its max_limit ({})",
+                self.initial_limit, self.min_limit, self.max_limit
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_initial_limit() -> u32 {
+    20
+}
+
+fn default_min_limit() -> u32 {
+    1
+}
+
+fn default_max_limit() -> u32 {
+    1000
+}
+
+fn default_backoff_ratio() -> f64 {
+    0.9
+}
+
+/// Error returned when a request is over the concurrency limit of a subgraph
+#[derive(Clone, Debug)]
+pub(crate) struct ConcurrencyLimited {
+    subgraph_name: String,
+}
+
+impl ConcurrencyLimited {
+    pub(crate) fn new(subgraph_name: String) -> Self {
+        ConcurrencyLimited { subgraph_name }
+    }
+}
+
+impl fmt::Display for ConcurrencyLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the concurrency limit of subgraph '{}' was reached",
+            self.subgraph_name
+        )
+    }
+}
+
+impl From<ConcurrencyLimited> for graphql::Error {
+    fn from(error: ConcurrencyLimited) -> Self {
+        graphql::Error::builder()
+            .message(format!(
+                "Subgraph '{}' is overloaded: too many requests in flight",
+                error.subgraph_name
+            ))
+            .extension_code("SUBGRAPH_CONCURRENCY_LIMITED")
+            .build()
+    }
+}
+
+impl error::Error for ConcurrencyLimited {}
+
+struct State {
+    limit: f64,
+    in_flight: u32,
+    queue: VecDeque<oneshot::Sender<Permit>>,
+    /// Number of latencies observed by the gradient algorithm
+    samples: u32,
+    /// Long term latency, in seconds, of the gradient algorithm
+    long_latency: f64,
+}
+
+/// Concurrency limiter of a subgraph, shared by all its requests
+#[derive(Clone)]
+pub(crate) struct ConcurrencyLimiter {
+    subgraph_name: String,
+    config: ConcurrencyLimitConfig,
+    state: Arc<Mutex<State>>,
+    _limit_gauge: Arc<ObservableGauge<u64>>,
+}
+
+impl ConcurrencyLimiter {
+    pub(crate) fn new(subgraph_name: String, config: ConcurrencyLimitConfig) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            limit: config
+                .initial_limit
+                .clamp(config.min_limit.max(1), config.max_limit.max(1)) as f64,
+            in_flight: 0,
+            queue: VecDeque::new(),
+            samples: 0,
+            long_latency: 0.0,
+        }));
+
+        let gauge_state = Arc::downgrade(&state);
+        let attributes = [KeyValue::new("subgraph.name", subgraph_name.clone())];
+        let limit_gauge = meter_provider()
+            .meter("apollo/router")
+            .u64_observable_gauge("apollo.router.operations.traffic_shaping.concurrency_limit")
+            .with_description("Number of requests allowed in flight to a subgraph")
+            .with_callback(move |m| {
+                if let Some(state) = gauge_state.upgrade() {
+                    m.observe(state.lock().limit as u64, &attributes);
+                }
+            })
+            .init();
+
+        Self {
+            subgraph_name,
+            config,
+            state,
+            _limit_gauge: Arc::new(limit_gauge),
+        }
+    }
+
+    /// Waits until a request can be sent to the subgraph
+    async fn acquire(&self) -> Result<Permit, ConcurrencyLimited> {
+        let receiver = {
+            let mut state = self.state.lock();
+            if (state.in_flight as f64) < state.limit.floor() {
+                state.in_flight += 1;
+                return Ok(Permit::new(self.clone()));
+            }
+            match &self.config.queue {
+                Some(queue) if state.queue.len() < queue.max_size => {
+                    let (sender, receiver) = oneshot::channel();
+                    state.queue.push_back(sender);
+                    receiver
+                }
+                _ => return Err(self.rejected()),
+            }
+        };
+
+        let timeout = self
+            .config
+            .queue
+            .as_ref()
+            .map(|queue| queue.timeout)
+            .unwrap_or_default();
+        let mut receiver = receiver;
+        match tokio::time::timeout(timeout, &mut receiver).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => {
+                // permits are handed to queued requests with the lock held, so this request
+                // either got one before timing out, or will not get one after the receiver is
+                // dropped
+                let mut state = self.state.lock();
+                if let Ok(permit) = receiver.try_recv() {
+                    return Ok(permit);
+                }
+                drop(receiver);
+                state.queue.retain(|sender| !sender.is_closed());
+                drop(state);
+                Err(self.rejected())
+            }
+        }
+    }
+
+    fn rejected(&self) -> ConcurrencyLimited {
+        u64_counter!(
+            "apollo.router.operations.traffic_shaping.concurrency_rejected",
+            "Number of subgraph requests rejected by the concurrency limit",
+            1u64,
+            "subgraph.name" = self.subgraph_name.clone()
+        );
+        ConcurrencyLimited::new(self.subgraph_name.clone())
+    }
+
+    /// Adjusts the limit from the outcome of a request, and lets queued requests through
+    fn release(&self, latency: Duration, success: bool) {
+        let mut state = self.state.lock();
+        let min_limit = self.config.min_limit.max(1) as f64;
+        let max_limit = self.config.max_limit.max(1) as f64;
+        let in_flight = state.in_flight as f64;
+
+        let limit = match &self.config.algorithm {
+            ConcurrencyAlgorithm::Aimd {
+                backoff_ratio,
+                latency_threshold,
+            } => {
+                let too_slow = latency_threshold.map_or(false, |threshold| latency > threshold);
+                if !success || too_slow {
+                    state.limit * backoff_ratio.clamp(0.5, 1.0)
+                } else {
+                    state.limit + 1.0
+                }
+            }
+            ConcurrencyAlgorithm::Gradient => {
+                let latency = latency.as_secs_f64();
+                state.samples = state.samples.saturating_add(1);
+                if state.samples <= WARMUP_SAMPLES {
+                    state.long_latency += (latency - state.long_latency) / state.samples as f64;
+                    state.limit
+                } else {
+                    state.long_latency +=
+                        (latency - state.long_latency) * 2.0 / (LONG_WINDOW + 1.0);
+                    // recover faster when the latency drops after a long period of overload
+                    if state.long_latency / latency > 2.0 {
+                        state.long_latency *= 0.95;
+                    }
+
+                    let gradient = if success && latency > 0.0 {
+                        (TOLERANCE * state.long_latency / latency).clamp(0.5, 1.0)
+                    } else {
+                        0.5
+                    };
+                    let new_limit = state.limit * gradient + state.limit.sqrt();
+                    state.limit * (1.0 - SMOOTHING) + new_limit * SMOOTHING
+                }
+            }
+        };
+        // the limit only grows when at least half of it is used
+        if limit < state.limit || in_flight * 2.0 >= state.limit {
+            state.limit = limit.clamp(min_limit, max_limit);
+        }
+
+        self.free_slot(&mut state);
+    }
+
+    /// Takes a slot for an additional request, like a hedged request, if one is available
+    /// without waiting. Releasing the slot does not adjust the limit
+    pub(crate) fn try_acquire_slot(&self) -> Option<Slot> {
+        let mut state = self.state.lock();
+        if (state.in_flight as f64) < state.limit.floor() {
+            state.in_flight += 1;
+            Some(Slot {
+                limiter: self.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Frees the slot of a request, and lets queued requests through
+    fn free_slot(&self, state: &mut State) {
+        state.in_flight -= 1;
+        while (state.in_flight as f64) < state.limit.floor() {
+            let Some(sender) = state.queue.pop_front() else {
+                break;
+            };
+            state.in_flight += 1;
+            if let Err(mut permit) = sender.send(Permit::new(self.clone())) {
+                // the request timed out in the queue
+                permit.recorded = true;
+                state.in_flight -= 1;
+            }
+        }
+    }
+}
+
+/// A slot taken by [`ConcurrencyLimiter::try_acquire_slot`], freed when dropped
+pub(crate) struct Slot {
+    limiter: ConcurrencyLimiter,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock();
+        self.limiter.free_slot(&mut state);
+    }
+}
+
+/// Allows a request to be sent to the subgraph. If it is dropped before its outcome is recorded,
+/// for example on timeout, the request is counted as failed
+struct Permit {
+    limiter: ConcurrencyLimiter,
+    started_at: Instant,
+    recorded: bool,
+}
+
+impl Permit {
+    fn new(limiter: ConcurrencyLimiter) -> Self {
+        Self {
+            limiter,
+            started_at: Instant::now(),
+            recorded: false,
+        }
+    }
+
+    fn record(mut self, success: bool) {
+        self.limiter.release(self.started_at.elapsed(), success);
+        self.recorded = true;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.limiter.release(self.started_at.elapsed(), false);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ConcurrencyLimitLayer {
+    limiter: ConcurrencyLimiter,
+}
+
+impl ConcurrencyLimitLayer {
+    pub(crate) fn new(limiter: ConcurrencyLimiter) -> Self {
+        Self { limiter }
+    }
+
+    pub(crate) fn limiter(&self) -> &ConcurrencyLimiter {
+        &self.limiter
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimitService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ConcurrencyLimitService {
+            inner: service,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ConcurrencyLimitService<S> {
+    inner: S,
+    limiter: ConcurrencyLimiter,
+}
+
+impl<S> Service<subgraph::Request> for ConcurrencyLimitService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: subgraph::Request) -> Self::Future {
+        // the request may wait in the queue, so the ready service is kept for it
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+
+        async move {
+            let permit = limiter.acquire().await?;
+            let response = inner.oneshot(request).await.map_err(Into::into);
+            let success = match &response {
+                Ok(response) => !response.response.status().is_server_error(),
+                Err(_) => false,
+            };
+            permit.record(success);
+            response
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::FutureMetricsExt;
+
+    fn config(algorithm: ConcurrencyAlgorithm) -> ConcurrencyLimitConfig {
+        ConcurrencyLimitConfig {
+            algorithm,
+            initial_limit: 4,
+            min_limit: 1,
+            max_limit: 10,
+            queue: None,
+        }
+    }
+
+    fn aimd() -> ConcurrencyAlgorithm {
+        ConcurrencyAlgorithm::Aimd {
+            backoff_ratio: 0.5,
+            latency_threshold: None,
+        }
+    }
+
+    fn limit(limiter: &ConcurrencyLimiter) -> u32 {
+        limiter.state.lock().limit as u32
+    }
+
+    // sends `concurrency` requests taking `latency` at the same time
+    async fn sample(limiter: &ConcurrencyLimiter, concurrency: usize, latency: Duration) {
+        let mut permits = Vec::new();
+        for _ in 0..concurrency {
+            permits.push(limiter.acquire().await.unwrap());
+        }
+        for mut permit in permits {
+            permit.started_at = Instant::now() - latency;
+            permit.record(true);
+        }
+    }
+
+    #[test]
+    fn validates_limits() {
+        assert!(config(aimd()).validate().is_ok());
+        for (initial_limit, min_limit, max_limit) in [(4, 5, 2), (0, 1, 10), (11, 1, 10), (0, 0, 0)]
+        {
+            let config = ConcurrencyLimitConfig {
+                initial_limit,
+                min_limit,
+                max_limit,
+                ..config(aimd())
+            };
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_over_limit() {
+        let limiter = ConcurrencyLimiter::new("test".to_string(), config(aimd()));
+        let mut permits = Vec::new();
+        for _ in 0..4 {
+            permits.push(limiter.acquire().await.unwrap());
+        }
+        assert!(limiter.acquire().await.is_err());
+        permits.pop().unwrap().record(true);
+        assert!(limiter.acquire().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn queues_over_limit() {
+        let limiter = ConcurrencyLimiter::new(
+            "test".to_string(),
+            ConcurrencyLimitConfig {
+                initial_limit: 1,
+                queue: Some(QueueConfig {
+                    max_size: 1,
+                    timeout: Duration::from_millis(100),
+                }),
+                ..config(aimd())
+            },
+        );
+        let permit = limiter.acquire().await.unwrap();
+
+        // queued requests time out
+        let started_at = Instant::now();
+        assert!(limiter.acquire().await.is_err());
+        assert!(started_at.elapsed() >= Duration::from_millis(100));
+
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire().await.map(|permit| permit.record(true)) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the queue is full
+        assert!(limiter.acquire().await.is_err());
+        permit.record(true);
+        assert!(queued.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn aimd_adjusts_limit() {
+        let limiter = ConcurrencyLimiter::new("test".to_string(), config(aimd()));
+        sample(&limiter, 2, Duration::from_millis(10)).await;
+        assert_eq!(limit(&limiter), 5);
+        // the limit does not grow when less than half of it is used
+        sample(&limiter, 1, Duration::from_millis(10)).await;
+        assert_eq!(limit(&limiter), 5);
+
+        // dropped requests are counted as failed
+        drop(limiter.acquire().await.unwrap());
+        assert_eq!(limit(&limiter), 2);
+    }
+
+    #[tokio::test]
+    async fn gradient_adjusts_limit() {
+        let limiter =
+            ConcurrencyLimiter::new("test".to_string(), config(ConcurrencyAlgorithm::Gradient));
+        for _ in 0..WARMUP_SAMPLES {
+            sample(&limiter, 1, Duration::from_millis(10)).await;
+        }
+        assert_eq!(limit(&limiter), 4);
+
+        // the limit grows while the latency is stable
+        for _ in 0..10 {
+            sample(&limiter, 4, Duration::from_millis(10)).await;
+        }
+        let stable_limit = limit(&limiter);
+        assert!(stable_limit > 4);
+
+        // and shrinks when it increases
+        for _ in 0..10 {
+            sample(&limiter, 1, Duration::from_millis(100)).await;
+        }
+        assert!(limit(&limiter) < stable_limit);
+    }
+
+    #[tokio::test]
+    async fn reports_limit() {
+        async {
+            let _limiter = ConcurrencyLimiter::new("test".to_string(), config(aimd()));
+            assert_gauge!(
+                "apollo.router.operations.traffic_shaping.concurrency_limit",
+                4,
+                "subgraph.name" = "test"
+            );
+        }
+        .with_metrics()
+        .await;
+    }
+}
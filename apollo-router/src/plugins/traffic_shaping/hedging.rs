@@ -0,0 +1,431 @@
+//! Hedged subgraph requests
+//!
+//! When a query fetch takes longer than a delay, a second identical request is sent to the
+//! subgraph, and the first successful response is used while the other request is cancelled.
+//! This reduces the tail latency caused by slow subgraph instances, at the cost of sending more
+//! requests, limited by a budget. Mutations are never hedged.
+//!
+//! Hedging runs inside the circuit breaker and the concurrency limit, which see a hedged query
+//! fetch as a single request, but each hedged request takes its own slot of the concurrency limit.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::select;
+use futures::future::BoxFuture;
+use futures::future::Either;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::retry::budget::Budget;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+use tower::ServiceExt;
+
+use super::concurrency::ConcurrencyLimiter;
+use crate::query_planner::OperationKind;
+use crate::services::subgraph;
+
+/// Number of latencies kept to compute the percentile delay
+const LATENCY_SAMPLES: usize = 1000;
+/// Requests are not hedged until this number of latencies was observed
+const MINIMUM_LATENCY_SAMPLES: usize = 20;
+/// The percentile delay is computed again after this number of new latencies
+const LATENCY_UPDATE_INTERVAL: u32 = 100;
+
+/// Hedging configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HedgingConfig {
+    /// How long to wait for a response before sending the hedged request
+    delay: HedgingDelay,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// Window over which the query fetches to the subgraph are counted to compute the hedging
+    /// budget, between 1 and 60 seconds (default: 10s)
+    ttl: Option<Duration>,
+    /// Number of hedged requests allowed per second regardless of the number of query fetches,
+    /// for subgraphs that do not receive many requests (default: 10)
+    min_per_sec: Option<u32>,
+    /// Hedged requests allowed for each query fetch in the `ttl` window, on top of `min_per_sec`:
+    /// 0.1 allows one hedged request every 10 query fetches (default: 0.1)
+    hedge_percent: Option<f32>,
+}
+
+/// How long to wait for a response before sending the hedged request
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+enum HedgingDelay {
+    /// A fixed delay
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    Fixed(Duration),
+    /// A percentile, between 0 and 100, of the latency observed for the subgraph. Requests are
+    /// not hedged until enough latencies were observed
+    Percentile(f64),
+}
+
+/// Hedging state of a subgraph, shared by all its requests
+#[derive(Clone)]
+pub(crate) struct Hedging {
+    subgraph_name: String,
+    delay: HedgingDelay,
+    budget: Arc<Budget>,
+    latencies: Arc<Mutex<Latencies>>,
+    concurrency_limiter: Option<ConcurrencyLimiter>,
+}
+
+/// The latest latencies of the subgraph, and the percentile delay computed from them
+#[derive(Default)]
+struct Latencies {
+    samples: VecDeque<Duration>,
+    since_update: u32,
+    percentile: Option<Duration>,
+}
+
+impl Hedging {
+    pub(crate) fn new(subgraph_name: String, config: &HedgingConfig) -> Self {
+        Self {
+            subgraph_name,
+            delay: config.delay.clone(),
+            budget: Arc::new(Budget::new(
+                config.ttl.unwrap_or_else(|| Duration::from_secs(10)),
+                config.min_per_sec.unwrap_or(10),
+                config.hedge_percent.unwrap_or(0.1),
+            )),
+            latencies: Default::default(),
+            concurrency_limiter: None,
+        }
+    }
+
+    /// Hedged requests take a slot of this concurrency limiter, and are not sent when it has
+    /// none available
+    pub(crate) fn with_concurrency_limiter(mut self, limiter: Option<ConcurrencyLimiter>) -> Self {
+        self.concurrency_limiter = limiter;
+        self
+    }
+
+    /// The delay before the hedged request is sent, `None` if requests cannot be hedged yet
+    fn delay(&self) -> Option<Duration> {
+        match &self.delay {
+            HedgingDelay::Fixed(delay) => Some(*delay),
+            HedgingDelay::Percentile(_) => self.latencies.lock().percentile,
+        }
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let HedgingDelay::Percentile(percentile) = self.delay else {
+            return;
+        };
+
+        let mut latencies = self.latencies.lock();
+        if latencies.samples.len() == LATENCY_SAMPLES {
+            latencies.samples.pop_front();
+        }
+        latencies.samples.push_back(latency);
+        latencies.since_update += 1;
+
+        if latencies.samples.len() >= MINIMUM_LATENCY_SAMPLES
+            && (latencies.percentile.is_none() || latencies.since_update >= LATENCY_UPDATE_INTERVAL)
+        {
+            let mut samples = latencies.samples.iter().copied().collect::<Vec<_>>();
+            samples.sort_unstable();
+            let rank = (percentile.clamp(0.0, 100.0) / 100.0 * (samples.len() - 1) as f64).round();
+            latencies.percentile = Some(samples[rank as usize]);
+            latencies.since_update = 0;
+        }
+    }
+
+    fn count(&self, status: &'static str) {
+        u64_counter!(
+            "apollo.router.operations.traffic_shaping.hedging",
+            "Number of hedged subgraph requests",
+            1u64,
+            "subgraph.name" = self.subgraph_name.clone(),
+            "status" = status
+        );
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HedgingLayer {
+    hedging: Hedging,
+}
+
+impl HedgingLayer {
+    pub(crate) fn new(hedging: Hedging) -> Self {
+        Self { hedging }
+    }
+}
+
+impl<S> Layer<S> for HedgingLayer {
+    type Service = HedgingService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HedgingService {
+            inner: service,
+            hedging: self.hedging.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HedgingService<S> {
+    inner: S,
+    hedging: Hedging,
+}
+
+impl<S> Service<subgraph::Request> for HedgingService<S>
+where
+    S: Service<subgraph::Request, Response = subgraph::Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = subgraph::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: subgraph::Request) -> Self::Future {
+        // only query fetches are idempotent
+        if request.operation_kind != OperationKind::Query {
+            return self
+                .inner
+                .call(request)
+                .map(|r| r.map_err(Into::into))
+                .boxed();
+        }
+
+        let hedging = self.hedging.clone();
+        hedging.budget.deposit();
+        let hedge = hedging
+            .delay()
+            .map(|delay| (delay, request.clone(), self.inner.clone()));
+        let started_at = Instant::now();
+        let primary = self.inner.call(request).map(|r| r.map_err(Into::into));
+
+        async move {
+            let Some((delay, hedged_request, hedged_service)) = hedge else {
+                let response = primary.await;
+                hedging.record_latency(started_at.elapsed());
+                return response;
+            };
+
+            let primary = match select(primary.boxed(), tokio::time::sleep(delay).boxed()).await {
+                Either::Left((response, _)) => {
+                    hedging.record_latency(started_at.elapsed());
+                    return response;
+                }
+                Either::Right((_, primary)) => primary,
+            };
+
+            let slot = hedging
+                .concurrency_limiter
+                .as_ref()
+                .map(ConcurrencyLimiter::try_acquire_slot);
+            if matches!(slot, Some(None)) || hedging.budget.withdraw().is_err() {
+                hedging.count("aborted");
+                let response = primary.await;
+                hedging.record_latency(started_at.elapsed());
+                return response;
+            }
+            hedging.count("sent");
+
+            let hedged_started_at = Instant::now();
+            let hedged = async move {
+                // the slot is freed when the hedged request completes or is cancelled
+                let _slot = slot;
+                hedged_service
+                    .oneshot(hedged_request)
+                    .await
+                    .map_err(Into::into)
+            }
+            .boxed();
+            // the first successful response is used, and the other request is cancelled when
+            // dropped. If one of the requests fails, the other one is awaited
+            match select(primary, hedged).await {
+                Either::Left((Ok(response), _)) => {
+                    hedging.record_latency(started_at.elapsed());
+                    Ok(response)
+                }
+                Either::Right((Ok(response), _)) => {
+                    hedging.count("won");
+                    hedging.record_latency(hedged_started_at.elapsed());
+                    Ok(response)
+                }
+                Either::Left((Err(_), hedged)) => {
+                    let response = hedged.await;
+                    if response.is_ok() {
+                        hedging.count("won");
+                    }
+                    response
+                }
+                Either::Right((Err(_), primary)) => primary.await,
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    fn config(delay: HedgingDelay) -> HedgingConfig {
+        HedgingConfig {
+            delay,
+            ttl: None,
+            min_per_sec: None,
+            hedge_percent: None,
+        }
+    }
+
+    // the first request takes 500ms, the next ones 10ms
+    fn slow_first_service(
+        calls: Arc<AtomicUsize>,
+    ) -> impl Service<
+        subgraph::Request,
+        Response = subgraph::Response,
+        Error = BoxError,
+        Future = BoxFuture<'static, Result<subgraph::Response, BoxError>>,
+    > + Clone {
+        tower::service_fn(move |request: subgraph::Request| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let latency = if call == 0 { 500 } else { 10 };
+                tokio::time::sleep(Duration::from_millis(latency)).await;
+                let mut headers = http::HeaderMap::new();
+                headers.insert("x-call", call.into());
+                Ok(subgraph::Response::fake_builder()
+                    .context(request.context)
+                    .headers(headers)
+                    .build())
+            }
+            .boxed()
+        })
+    }
+
+    #[tokio::test]
+    async fn hedges_slow_queries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hedging = Hedging::new(
+            "test".to_string(),
+            &config(HedgingDelay::Fixed(Duration::from_millis(50))),
+        );
+        let service = HedgingLayer::new(hedging).layer(slow_first_service(calls.clone()));
+
+        let started_at = Instant::now();
+        let response = service
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+        assert_eq!(response.response.headers()["x-call"], "1");
+        assert!(started_at.elapsed() < Duration::from_millis(500));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_hedge_mutations() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hedging = Hedging::new(
+            "test".to_string(),
+            &config(HedgingDelay::Fixed(Duration::from_millis(50))),
+        );
+        let service = HedgingLayer::new(hedging).layer(slow_first_service(calls.clone()));
+
+        let response = service
+            .oneshot(
+                subgraph::Request::fake_builder()
+                    .operation_kind(OperationKind::Mutation)
+                    .build(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.response.headers()["x-call"], "0");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn hedges_within_budget() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hedging = Hedging::new(
+            "test".to_string(),
+            &HedgingConfig {
+                min_per_sec: Some(0),
+                hedge_percent: Some(0.0),
+                ..config(HedgingDelay::Fixed(Duration::from_millis(50)))
+            },
+        );
+        let service = HedgingLayer::new(hedging).layer(slow_first_service(calls.clone()));
+
+        let response = service
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+        assert_eq!(response.response.headers()["x-call"], "0");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn hedges_within_concurrency_limit() {
+        let limiter = ConcurrencyLimiter::new(
+            "test".to_string(),
+            serde_json::from_value(serde_json::json!({
+                "initial_limit": 1,
+                "max_limit": 1
+            }))
+            .unwrap(),
+        );
+        let hedging = Hedging::new(
+            "test".to_string(),
+            &config(HedgingDelay::Fixed(Duration::from_millis(50))),
+        )
+        .with_concurrency_limiter(Some(limiter.clone()));
+
+        // no slot is available for the hedged request
+        let calls = Arc::new(AtomicUsize::new(0));
+        let slot = limiter.try_acquire_slot().unwrap();
+        let response = HedgingLayer::new(hedging.clone())
+            .layer(slow_first_service(calls.clone()))
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+        assert_eq!(response.response.headers()["x-call"], "0");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        drop(slot);
+
+        // the hedged request takes the slot, and frees it once it completes
+        let calls = Arc::new(AtomicUsize::new(0));
+        let response = HedgingLayer::new(hedging)
+            .layer(slow_first_service(calls.clone()))
+            .oneshot(subgraph::Request::fake_builder().build())
+            .await
+            .unwrap();
+        assert_eq!(response.response.headers()["x-call"], "1");
+        assert!(limiter.try_acquire_slot().is_some());
+    }
+
+    #[test]
+    fn percentile_delay() {
+        let hedging = Hedging::new("test".to_string(), &config(HedgingDelay::Percentile(90.0)));
+        for latency in 1..MINIMUM_LATENCY_SAMPLES as u64 {
+            hedging.record_latency(Duration::from_millis(latency));
+        }
+        assert_eq!(hedging.delay(), None);
+
+        hedging.record_latency(Duration::from_millis(MINIMUM_LATENCY_SAMPLES as u64));
+        assert_eq!(hedging.delay(), Some(Duration::from_millis(18)));
+    }
+}
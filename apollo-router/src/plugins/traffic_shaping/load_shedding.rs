@@ -0,0 +1,420 @@
+//! Router level load shedding
+//!
+//! The number of requests processed at the same time by the router is capped. Requests over the
+//! cap wait in a bounded queue, and are shed when the queue is full or when they waited too long.
+//! When the queue is full, the request with the lowest priority is shed: either the new request,
+//! or the most recent request with a lower priority in the queue.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use http::HeaderValue;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use super::rate::ClientKey;
+use super::rate::ClientRequest;
+use crate::graphql;
+
+/// Router level load shedding configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LoadSheddingConfig {
+    /// Maximum number of requests processed at the same time
+    max_concurrent_requests: NonZeroU32,
+    /// Maximum number of requests waiting for the requests in flight to complete. With 0,
+    /// requests over the cap are shed immediately (default: 0)
+    #[serde(default)]
+    queue_size: usize,
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_queue_timeout"
+    )]
+    #[schemars(with = "String", default)]
+    /// How long a request waits in the queue before it is shed (default: 1s)
+    queue_timeout: Duration,
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_retry_after"
+    )]
+    #[schemars(with = "String", default)]
+    /// Value of the `Retry-After` header of the responses to shed requests (default: 1s)
+    retry_after: Duration,
+    /// Priority of requests. When the queue is full, requests with a lower priority are shed
+    /// first. Without it, all requests have the same priority
+    priority: Option<PriorityConfig>,
+}
+
+fn default_queue_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_retry_after() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// Priority of requests
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct PriorityConfig {
+    /// What the priority of a request depends on
+    key: ClientKey,
+    /// Priority of each value of the key. Requests with higher priorities are shed last
+    values: HashMap<String, i32>,
+    /// Priority of the requests without a value for the key, or with a value not listed
+    /// (default: 0)
+    #[serde(default)]
+    default: i32,
+}
+
+/// Error returned when a request is shed
+#[derive(Clone, Debug)]
+pub(crate) struct LoadShed {
+    retry_after: Duration,
+}
+
+impl LoadShed {
+    /// Value of the `Retry-After` header, in seconds rounded up
+    pub(crate) fn retry_after(&self) -> HeaderValue {
+        let mut secs = self.retry_after.as_secs();
+        if self.retry_after.subsec_nanos() > 0 || secs == 0 {
+            secs += 1;
+        }
+        HeaderValue::from(secs)
+    }
+}
+
+impl fmt::Display for LoadShed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("your request has been shed because the router is overloaded")
+    }
+}
+
+impl From<LoadShed> for graphql::Error {
+    fn from(_: LoadShed) -> Self {
+        graphql::Error::builder()
+            .message(String::from(
+                "Your request has been shed because the router is overloaded",
+            ))
+            .extension_code("REQUEST_LOAD_SHED")
+            .build()
+    }
+}
+
+impl error::Error for LoadShed {}
+
+/// Position of a request in the queue: the request with the highest priority is processed
+/// first, in the order of arrival, and the most recent request with the lowest priority is shed
+/// first
+type QueueKey = (i32, Reverse<u64>);
+
+struct State {
+    in_flight: u32,
+    queue: BTreeMap<QueueKey, oneshot::Sender<Permit>>,
+    next_arrival: u64,
+}
+
+/// Load shedding state of the router, shared by all requests
+#[derive(Clone)]
+pub(crate) struct LoadShedder {
+    config: Arc<LoadSheddingConfig>,
+    state: Arc<Mutex<State>>,
+}
+
+impl LoadShedder {
+    pub(crate) fn new(config: LoadSheddingConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            state: Arc::new(Mutex::new(State {
+                in_flight: 0,
+                queue: BTreeMap::new(),
+                next_arrival: 0,
+            })),
+        }
+    }
+
+    fn priority(&self, request: &impl ClientRequest) -> i32 {
+        let Some(priority) = &self.config.priority else {
+            return 0;
+        };
+        priority
+            .key
+            .extract(request)
+            .and_then(|value| priority.values.get(&value).copied())
+            .unwrap_or(priority.default)
+    }
+
+    /// Waits until the request can be processed
+    async fn acquire(&self, priority: i32) -> Result<Permit, LoadShed> {
+        let (key, receiver) = {
+            let mut state = self.state.lock();
+            if state.in_flight < self.config.max_concurrent_requests.get() {
+                state.in_flight += 1;
+                return Ok(Permit::new(self.clone()));
+            }
+
+            if state.queue.len() >= self.config.queue_size {
+                // the queue is full: shed the most recent request with a lower priority, or this one
+                let lowest = state
+                    .queue
+                    .first_key_value()
+                    .map(|(key, _)| *key)
+                    .filter(|(lowest, _)| *lowest < priority);
+                match lowest {
+                    // dropping the sender sheds the queued request
+                    Some(lowest) => drop(state.queue.remove(&lowest)),
+                    None => return Err(self.shed("queue_full")),
+                }
+            }
+
+            let key = (priority, Reverse(state.next_arrival));
+            state.next_arrival += 1;
+            let (sender, receiver) = oneshot::channel();
+            state.queue.insert(key, sender);
+            (key, receiver)
+        };
+
+        let mut receiver = receiver;
+        match tokio::time::timeout(self.config.queue_timeout, &mut receiver).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(self.shed("evicted")),
+            Err(_) => {
+                // permits are handed to queued requests with the lock held, so this request
+                // either got one before timing out, or is still in the queue
+                let mut state = self.state.lock();
+                if let Ok(permit) = receiver.try_recv() {
+                    return Ok(permit);
+                }
+                state.queue.remove(&key);
+                drop(state);
+                Err(self.shed("timeout"))
+            }
+        }
+    }
+
+    fn shed(&self, reason: &'static str) -> LoadShed {
+        u64_counter!(
+            "apollo.router.operations.traffic_shaping.load_shed",
+            "Number of requests shed by the router",
+            1u64,
+            "reason" = reason
+        );
+        LoadShed {
+            retry_after: self.config.retry_after,
+        }
+    }
+
+    /// Lets the next queued request through
+    fn release(&self) {
+        let mut state = self.state.lock();
+        state.in_flight -= 1;
+        while let Some((_, sender)) = state.queue.pop_last() {
+            state.in_flight += 1;
+            match sender.send(Permit::new(self.clone())) {
+                Ok(()) => break,
+                Err(mut permit) => {
+                    // the client is gone
+                    permit.active = false;
+                    state.in_flight -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Allows a request to be processed, until it is dropped
+struct Permit {
+    shedder: LoadShedder,
+    active: bool,
+}
+
+impl Permit {
+    fn new(shedder: LoadShedder) -> Self {
+        Self {
+            shedder,
+            active: true,
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.active {
+            self.shedder.release();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadSheddingLayer {
+    shedder: LoadShedder,
+}
+
+impl LoadSheddingLayer {
+    pub(crate) fn new(shedder: LoadShedder) -> Self {
+        Self { shedder }
+    }
+}
+
+impl<S> Layer<S> for LoadSheddingLayer {
+    type Service = LoadShedding<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        LoadShedding {
+            inner: service,
+            shedder: self.shedder.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LoadShedding<S> {
+    inner: S,
+    shedder: LoadShedder,
+}
+
+impl<S, Request> Service<Request> for LoadShedding<S>
+where
+    Request: ClientRequest + Send + 'static,
+    S: Service<Request> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Response: Send,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let priority = self.shedder.priority(&request);
+        let shedder = self.shedder.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let _permit = shedder.acquire(priority).await?;
+            inner.call(request).await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::services::supergraph;
+
+    fn config(queue_size: usize) -> LoadSheddingConfig {
+        LoadSheddingConfig {
+            max_concurrent_requests: NonZeroU32::new(1).unwrap(),
+            queue_size,
+            queue_timeout: Duration::from_millis(100),
+            retry_after: Duration::from_secs(2),
+            priority: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn sheds_over_capacity() {
+        let shedder = LoadShedder::new(config(0));
+        let permit = shedder.acquire(0).await.unwrap();
+        let error = shedder.acquire(0).await.err().unwrap();
+        assert_eq!(error.retry_after(), "2");
+        drop(permit);
+        assert!(shedder.acquire(0).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn queues_with_deadline() {
+        let shedder = LoadShedder::new(config(1));
+        let permit = shedder.acquire(0).await.unwrap();
+
+        let queued = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.acquire(0).await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the queue is full
+        assert!(shedder.acquire(0).await.is_err());
+        drop(permit);
+        assert!(queued.await.unwrap().is_ok());
+
+        let _permit = shedder.acquire(0).await.unwrap();
+        let started_at = Instant::now();
+        assert!(shedder.acquire(0).await.is_err());
+        assert!(started_at.elapsed() >= Duration::from_millis(100));
+        assert!(shedder.state.lock().queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sheds_lowest_priority_first() {
+        let shedder = LoadShedder::new(LoadSheddingConfig {
+            queue_timeout: Duration::from_secs(10),
+            ..config(2)
+        });
+        let permit = shedder.acquire(0).await.unwrap();
+
+        let queue = |priority: i32| {
+            let shedder = shedder.clone();
+            tokio::spawn(async move { shedder.acquire(priority).await.map(drop) })
+        };
+        let low = queue(0);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let medium = queue(5);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // a request with a lower priority than all queued requests is shed
+        assert!(shedder.acquire(-1).await.is_err());
+        // a request with a higher priority replaces the queued request with the lowest priority
+        let high = queue(10);
+        assert!(low.await.unwrap().is_err());
+
+        // the request with the highest priority is processed first
+        drop(permit);
+        assert!(high.await.unwrap().is_ok());
+        assert!(medium.await.unwrap().is_ok());
+    }
+
+    #[test]
+    fn selects_priority() {
+        let shedder = LoadShedder::new(LoadSheddingConfig {
+            priority: Some(PriorityConfig {
+                key: ClientKey::Header("x-tier".to_string()),
+                values: [("gold".to_string(), 10)].into_iter().collect(),
+                default: 1,
+            }),
+            ..config(0)
+        });
+        let request = |tier: &str| {
+            supergraph::Request::fake_builder()
+                .header("x-tier", tier)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(shedder.priority(&request("gold")), 10);
+        assert_eq!(shedder.priority(&request("free")), 1);
+        assert_eq!(
+            shedder.priority(&supergraph::Request::fake_builder().build().unwrap()),
+            1
+        );
+    }
+}
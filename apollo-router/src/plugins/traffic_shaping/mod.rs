@@ -4,14 +4,23 @@
 //! * Query deduplication
 //! * Timeout
 //! * Compression
-//! * Rate limiting
+//! * Rate limiting, globally or per client
+//! * Circuit breaker
+//! * Adaptive concurrency limit
+//! * Hedging
+//! * Load shedding
 //!
+pub(crate) mod circuit_breaker;
+mod concurrency;
 mod deduplication;
+mod hedging;
+mod load_shedding;
 pub(crate) mod rate;
 mod retry;
 pub(crate) mod timeout;
 
 use std::collections::HashMap;
+use std::num::NonZeroU32;
 use std::num::NonZeroU64;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -19,6 +28,7 @@ use std::time::Duration;
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use http::header::CONTENT_ENCODING;
+use http::header::RETRY_AFTER;
 use http::HeaderValue;
 use http::StatusCode;
 use schemars::JsonSchema;
@@ -29,12 +39,37 @@ use tower::Service;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
+use self::circuit_breaker::CircuitBreaker;
+use self::circuit_breaker::CircuitBreakerConfig;
+use self::circuit_breaker::CircuitBreakerLayer;
+use self::circuit_breaker::CircuitOpen;
+use self::concurrency::ConcurrencyLimitConfig;
+use self::concurrency::ConcurrencyLimitLayer;
+use self::concurrency::ConcurrencyLimited;
+use self::concurrency::ConcurrencyLimiter;
 use self::deduplication::QueryDeduplicationLayer;
+use self::hedging::Hedging;
+use self::hedging::HedgingConfig;
+use self::hedging::HedgingLayer;
+use self::load_shedding::LoadShed;
+use self::load_shedding::LoadShedder;
+use self::load_shedding::LoadSheddingConfig;
+use self::load_shedding::LoadSheddingLayer;
+use self::rate::ClientKey;
+use self::rate::ClientRateLimitLayer;
+use self::rate::DistributedRateLimitLayer;
+use self::rate::KeyedRateLimiter;
+use self::rate::Rate;
 use self::rate::RateLimitLayer;
+use self::rate::RateLimitStorage;
 use self::rate::RateLimited;
+use self::retry::BackoffConfig;
+use self::retry::RetryConditions;
 pub(crate) use self::retry::RetryPolicy;
 use self::timeout::Elapsed;
 use self::timeout::TimeoutLayer;
+use crate::cache::redis::RedisCacheStorage;
+use crate::configuration::RedisCache;
 use crate::error::ConfigurationError;
 use crate::graphql;
 use crate::layers::ServiceBuilderExt;
@@ -63,6 +98,8 @@ struct Shaping {
     compression: Option<Compression>,
     /// Enable global rate limiting
     global_rate_limit: Option<RateLimitConf>,
+    /// Enable rate limiting per client
+    client_rate_limit: Option<ClientRateLimitConf>,
     #[serde(deserialize_with = "humantime_serde::deserialize", default)]
     #[schemars(with = "String", default)]
     /// Enable timeout for incoming requests
@@ -70,6 +107,12 @@ struct Shaping {
     /// Retry configuration
     //  *experimental feature*: Enables request retry
     experimental_retry: Option<RetryConfig>,
+    /// Stop sending requests to subgraphs that fail too often
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Limit the number of requests in flight to subgraphs, adjusted from their latency
+    concurrency_limit: Option<ConcurrencyLimitConfig>,
+    /// Send a second request for query fetches that take too long, and use the first response
+    hedging: Option<HedgingConfig>,
     /// Enable HTTP2 for subgraphs
     experimental_http2: Option<Http2Config>,
 }
@@ -97,13 +140,29 @@ impl Merge for Shaping {
                 global_rate_limit: self
                     .global_rate_limit
                     .as_ref()
-                    .or(fallback.global_rate_limit.as_ref())
+                    .map(|conf| conf.merge(fallback.global_rate_limit.as_ref()))
+                    .or_else(|| fallback.global_rate_limit.clone()),
+                client_rate_limit: self
+                    .client_rate_limit
+                    .as_ref()
+                    .or(fallback.client_rate_limit.as_ref())
                     .cloned(),
                 experimental_retry: self
                     .experimental_retry
                     .as_ref()
                     .or(fallback.experimental_retry.as_ref())
                     .cloned(),
+                circuit_breaker: self
+                    .circuit_breaker
+                    .as_ref()
+                    .or(fallback.circuit_breaker.as_ref())
+                    .cloned(),
+                concurrency_limit: self
+                    .concurrency_limit
+                    .as_ref()
+                    .or(fallback.concurrency_limit.as_ref())
+                    .cloned(),
+                hedging: self.hedging.as_ref().or(fallback.hedging.as_ref()).cloned(),
                 experimental_http2: self
                     .experimental_http2
                     .as_ref()
@@ -117,7 +176,7 @@ impl Merge for Shaping {
 /// Retry configuration
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
-struct RetryConfig {
+pub(crate) struct RetryConfig {
     #[serde(deserialize_with = "humantime_serde::deserialize", default)]
     #[schemars(with = "String", default)]
     /// how long a single deposit should be considered. Must be between 1 and 60 seconds,
@@ -134,6 +193,14 @@ struct RetryConfig {
     /// allows request retries on mutations. This should only be activated if mutations
     /// are idempotent. Disabled by default
     retry_mutations: Option<bool>,
+    /// maximum number of attempts of a request, including the first one. By default, requests
+    /// are retried as long as the budget allows it
+    max_attempts: Option<NonZeroU32>,
+    /// delay between the attempts of a request. By default, requests are retried immediately
+    backoff: Option<BackoffConfig>,
+    /// conditions under which requests are retried. By default, requests are retried on any
+    /// error, but not on responses
+    retry_on: Option<RetryConditions>,
 }
 
 impl Merge for RetryConfig {
@@ -145,6 +212,13 @@ impl Merge for RetryConfig {
                 min_per_sec: self.min_per_sec.or(fallback.min_per_sec),
                 retry_percent: self.retry_percent.or(fallback.retry_percent),
                 retry_mutations: self.retry_mutations.or(fallback.retry_mutations),
+                max_attempts: self.max_attempts.or(fallback.max_attempts),
+                backoff: self.backoff.as_ref().or(fallback.backoff.as_ref()).cloned(),
+                retry_on: self
+                    .retry_on
+                    .as_ref()
+                    .or(fallback.retry_on.as_ref())
+                    .cloned(),
             },
         }
     }
@@ -174,10 +248,15 @@ impl Merge for SubgraphShaping {
 struct RouterShaping {
     /// Enable global rate limiting
     global_rate_limit: Option<RateLimitConf>,
+    /// Enable rate limiting per client
+    client_rate_limit: Option<ClientRateLimitConf>,
     #[serde(deserialize_with = "humantime_serde::deserialize", default)]
     #[schemars(with = "String", default)]
     /// Enable timeout for incoming requests
     timeout: Option<Duration>,
+    /// Enable load shedding: cap the number of requests processed at the same time, queue the
+    /// requests over the cap, and shed them when the queue is full
+    load_shedding: Option<LoadSheddingConfig>,
 }
 
 #[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
@@ -194,6 +273,8 @@ pub(crate) struct Config {
     subgraphs: HashMap<String, SubgraphShaping>,
     /// DEPRECATED, now always enabled: Enable variable deduplication optimization when sending requests to subgraphs (https://github.com/apollographql/router/issues/87)
     deduplicate_variables: Option<bool>,
+    /// Redis configuration, used by the rate limits stored in Redis
+    redis: Option<RedisCache>,
 }
 
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
@@ -205,6 +286,32 @@ struct RateLimitConf {
     #[schemars(with = "String")]
     /// Per interval
     interval: Duration,
+    /// Where the rate limit is stored. With `redis`, it is shared between router instances
+    /// and the `redis` configuration of traffic shaping is required (default: memory)
+    storage: Option<RateLimitStorage>,
+}
+
+impl RateLimitConf {
+    fn storage(&self) -> RateLimitStorage {
+        self.storage.unwrap_or_default()
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct ClientRateLimitConf {
+    /// Number of requests allowed for each client
+    capacity: NonZeroU64,
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    /// Per interval
+    interval: Duration,
+    /// How clients are identified. Requests without a value for this key are not limited
+    key: ClientKey,
+    /// Where the rate limits are stored. With `redis`, they are shared between router instances
+    /// and the `redis` configuration of traffic shaping is required (default: memory)
+    #[serde(default)]
+    storage: RateLimitStorage,
 }
 
 impl Merge for RateLimitConf {
@@ -212,8 +319,9 @@ impl Merge for RateLimitConf {
         match fallback {
             None => self.clone(),
             Some(fallback) => Self {
-                capacity: fallback.capacity,
-                interval: fallback.interval,
+                capacity: self.capacity,
+                interval: self.interval,
+                storage: self.storage.or(fallback.storage),
             },
         }
     }
@@ -225,6 +333,15 @@ pub(crate) struct TrafficShaping {
     config: Config,
     rate_limit_router: Option<RateLimitLayer>,
     rate_limit_subgraphs: Mutex<HashMap<String, RateLimitLayer>>,
+    distributed_rate_limit_router: Option<DistributedRateLimitLayer>,
+    distributed_rate_limit_subgraphs: Mutex<HashMap<String, DistributedRateLimitLayer>>,
+    client_rate_limit_router: Option<ClientRateLimitLayer>,
+    load_shedding_router: Option<LoadSheddingLayer>,
+    client_rate_limit_subgraphs: Mutex<HashMap<String, ClientRateLimitLayer>>,
+    circuit_breakers: Mutex<HashMap<String, CircuitBreakerLayer>>,
+    concurrency_limiters: Mutex<HashMap<String, ConcurrencyLimitLayer>>,
+    hedging_subgraphs: Mutex<HashMap<String, HedgingLayer>>,
+    redis: Option<RedisCacheStorage>,
 }
 
 #[async_trait::async_trait]
@@ -232,11 +349,22 @@ impl Plugin for TrafficShaping {
     type Config = Config;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        init.config
+            .all
+            .iter()
+            .chain(init.config.subgraphs.values())
+            .try_for_each(|shaping| validate_shaping(&shaping.shaping))
+            .map_err(|error| ConfigurationError::InvalidConfiguration {
+                message: "bad configuration for traffic_shaping plugin",
+                error,
+            })?;
+
         let rate_limit_router = init
             .config
             .router
             .as_ref()
             .and_then(|r| r.global_rate_limit.as_ref())
+            .filter(|conf| conf.storage() == RateLimitStorage::Memory)
             .map(|router_rate_limit_conf| {
                 if router_rate_limit_conf.interval.as_millis() > u64::MAX as u128 {
                     Err(ConfigurationError::InvalidConfiguration {
@@ -255,16 +383,132 @@ impl Plugin for TrafficShaping {
             })
             .transpose()?;
 
+        let shapings = init
+            .config
+            .all
+            .iter()
+            .chain(init.config.subgraphs.values())
+            .map(|s| &s.shaping);
+        let uses_redis = init
+            .config
+            .router
+            .iter()
+            .flat_map(|r| {
+                [
+                    r.global_rate_limit.as_ref().map(|conf| conf.storage()),
+                    r.client_rate_limit.as_ref().map(|conf| conf.storage),
+                ]
+            })
+            .chain(shapings.flat_map(|s| {
+                [
+                    s.global_rate_limit.as_ref().map(|conf| conf.storage()),
+                    s.client_rate_limit.as_ref().map(|conf| conf.storage),
+                ]
+            }))
+            .any(|storage| storage == Some(RateLimitStorage::Redis));
+        let redis = match init.config.redis.clone() {
+            Some(redis_config) => {
+                let required_to_start = redis_config.required_to_start;
+                match RedisCacheStorage::new(redis_config).await {
+                    Ok(storage) => Some(storage),
+                    Err(e) => {
+                        tracing::error!(
+                            e,
+                            "could not open connection to Redis for rate limiting, using in memory rate limits",
+                        );
+                        if required_to_start {
+                            return Err(e);
+                        }
+                        None
+                    }
+                }
+            }
+            None if uses_redis => {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "bad configuration for traffic_shaping plugin",
+                    error: "rate limits stored in Redis require the redis configuration"
+                        .to_string(),
+                }
+                .into());
+            }
+            None => None,
+        };
+
+        let distributed_rate_limit_router = init
+            .config
+            .router
+            .as_ref()
+            .and_then(|r| r.global_rate_limit.as_ref())
+            .filter(|conf| conf.storage() == RateLimitStorage::Redis)
+            .map(|conf| {
+                DistributedRateLimitLayer::new(KeyedRateLimiter::new(
+                    "router".to_string(),
+                    Rate::new(conf.capacity, conf.interval),
+                    redis.clone(),
+                ))
+            });
+        let client_rate_limit_router = init
+            .config
+            .router
+            .as_ref()
+            .and_then(|r| r.client_rate_limit.as_ref())
+            .map(|conf| client_rate_limit_layer("router:client", conf, redis.as_ref()));
+        let load_shedding_router = init
+            .config
+            .router
+            .as_ref()
+            .and_then(|r| r.load_shedding.clone())
+            .map(|conf| LoadSheddingLayer::new(LoadShedder::new(conf)));
+
         {
             Ok(Self {
                 config: init.config,
                 rate_limit_router,
                 rate_limit_subgraphs: Mutex::new(HashMap::new()),
+                distributed_rate_limit_router,
+                distributed_rate_limit_subgraphs: Mutex::new(HashMap::new()),
+                client_rate_limit_router,
+                load_shedding_router,
+                client_rate_limit_subgraphs: Mutex::new(HashMap::new()),
+                circuit_breakers: Mutex::new(HashMap::new()),
+                concurrency_limiters: Mutex::new(HashMap::new()),
+                hedging_subgraphs: Mutex::new(HashMap::new()),
+                redis,
             })
         }
     }
 }
 
+/// Checks the subgraph settings that deserialization cannot check
+fn validate_shaping(shaping: &Shaping) -> Result<(), String> {
+    if let Some(circuit_breaker) = &shaping.circuit_breaker {
+        circuit_breaker.validate()?;
+    }
+    if let Some(concurrency_limit) = &shaping.concurrency_limit {
+        concurrency_limit.validate()?;
+    }
+    Ok(())
+}
+
+fn client_rate_limit_layer(
+    scope: &str,
+    conf: &ClientRateLimitConf,
+    redis: Option<&RedisCacheStorage>,
+) -> ClientRateLimitLayer {
+    let redis = match conf.storage {
+        RateLimitStorage::Memory => None,
+        RateLimitStorage::Redis => redis.cloned(),
+    };
+    ClientRateLimitLayer::new(
+        conf.key.clone(),
+        KeyedRateLimiter::new(
+            scope.to_string(),
+            Rate::new(conf.capacity, conf.interval),
+            redis,
+        ),
+    )
+}
+
 pub(crate) type TrafficShapingSubgraphFuture<S> = Either<
     Either<
         BoxFuture<'static, Result<subgraph::Response, BoxError>>,
@@ -317,11 +561,42 @@ impl TrafficShaping {
                                     .build()
                             }
                             Err(error) if error.is::<RateLimited>() => {
+                                let retry_after = error
+                                    .downcast_ref::<RateLimited>()
+                                    .and_then(RateLimited::retry_after);
                                 supergraph::Response::error_builder()
                                     .status_code(StatusCode::TOO_MANY_REQUESTS)
                                     .error::<graphql::Error>(RateLimited::new().into())
                                     .context(ctx)
                                     .build()
+                                    .map(|mut response| {
+                                        if let Some(retry_after) = retry_after {
+                                            response
+                                                .response
+                                                .headers_mut()
+                                                .insert(RETRY_AFTER, retry_after);
+                                        }
+                                        response
+                                    })
+                            }
+                            Err(error) if error.is::<LoadShed>() => {
+                                let load_shed = error
+                                    .downcast_ref::<LoadShed>()
+                                    .cloned()
+                                    .expect("the error type was checked; qed");
+                                let retry_after = load_shed.retry_after();
+                                supergraph::Response::error_builder()
+                                    .status_code(StatusCode::SERVICE_UNAVAILABLE)
+                                    .error::<graphql::Error>(load_shed.into())
+                                    .context(ctx)
+                                    .build()
+                                    .map(|mut response| {
+                                        response
+                                            .response
+                                            .headers_mut()
+                                            .insert(RETRY_AFTER, retry_after);
+                                        response
+                                    })
                             }
                             _ => response,
                         }
@@ -336,7 +611,10 @@ impl TrafficShaping {
                     .and_then(|r| r.timeout)
                     .unwrap_or(DEFAULT_TIMEOUT),
             ))
+            .option_layer(self.client_rate_limit_router.clone())
+            .option_layer(self.distributed_rate_limit_router.clone())
             .option_layer(self.rate_limit_router.clone())
+            .option_layer(self.load_shedding_router.clone())
             .service(service)
     }
 
@@ -371,6 +649,7 @@ impl TrafficShaping {
                 .shaping
                 .global_rate_limit
                 .as_ref()
+                .filter(|conf| conf.storage() == RateLimitStorage::Memory)
                 .map(|rate_limit_conf| {
                     self.rate_limit_subgraphs
                         .lock()
@@ -382,17 +661,95 @@ impl TrafficShaping {
                         .clone()
                 });
 
+            let distributed_rate_limit = config
+                .shaping
+                .global_rate_limit
+                .as_ref()
+                .filter(|conf| conf.storage() == RateLimitStorage::Redis)
+                .map(|conf| {
+                    self.distributed_rate_limit_subgraphs
+                        .lock()
+                        .unwrap()
+                        .entry(name.to_string())
+                        .or_insert_with(|| {
+                            DistributedRateLimitLayer::new(KeyedRateLimiter::new(
+                                format!("subgraph:{name}"),
+                                Rate::new(conf.capacity, conf.interval),
+                                self.redis.clone(),
+                            ))
+                        })
+                        .clone()
+                });
+
+            let client_rate_limit = config.shaping.client_rate_limit.as_ref().map(|conf| {
+                self.client_rate_limit_subgraphs
+                    .lock()
+                    .unwrap()
+                    .entry(name.to_string())
+                    .or_insert_with(|| {
+                        client_rate_limit_layer(
+                            &format!("subgraph:{name}:client"),
+                            conf,
+                            self.redis.as_ref(),
+                        )
+                    })
+                    .clone()
+            });
+
+            let circuit_breaker = config.shaping.circuit_breaker.as_ref().map(|conf| {
+                self.circuit_breakers
+                    .lock()
+                    .unwrap()
+                    .entry(name.to_string())
+                    .or_insert_with(|| {
+                        CircuitBreakerLayer::new(CircuitBreaker::new(
+                            name.to_string(),
+                            conf.clone(),
+                        ))
+                    })
+                    .clone()
+            });
+
+            let concurrency_limit = config.shaping.concurrency_limit.as_ref().map(|conf| {
+                self.concurrency_limiters
+                    .lock()
+                    .unwrap()
+                    .entry(name.to_string())
+                    .or_insert_with(|| {
+                        ConcurrencyLimitLayer::new(ConcurrencyLimiter::new(
+                            name.to_string(),
+                            conf.clone(),
+                        ))
+                    })
+                    .clone()
+            });
+
+            let hedging = config.shaping.hedging.as_ref().map(|conf| {
+                self.hedging_subgraphs
+                    .lock()
+                    .unwrap()
+                    .entry(name.to_string())
+                    .or_insert_with(|| {
+                        HedgingLayer::new(
+                            Hedging::new(name.to_string(), conf).with_concurrency_limiter(
+                                concurrency_limit
+                                    .as_ref()
+                                    .map(|layer| layer.limiter().clone()),
+                            ),
+                        )
+                    })
+                    .clone()
+            });
+
             let retry = config.shaping.experimental_retry.as_ref().map(|config| {
-                let retry_policy = RetryPolicy::new(
-                    config.ttl,
-                    config.min_per_sec,
-                    config.retry_percent,
-                    config.retry_mutations,
-                    name.to_string(),
-                );
+                let retry_policy = RetryPolicy::new(config, name.to_string());
                 tower::retry::RetryLayer::new(retry_policy)
             });
 
+            let subgraph_name = name.to_string();
+            let breaker = circuit_breaker
+                .as_ref()
+                .map(|layer| layer.breaker().clone());
             Either::A(ServiceBuilder::new()
 
                 .option_layer(config.shaping.deduplicate_query.unwrap_or_default().then(
@@ -401,22 +758,67 @@ impl TrafficShaping {
                     .map_future_with_request_data(
                         |req: &subgraph::Request| req.context.clone(),
                         move |ctx, future| {
+                            // rejected requests never reach the subgraph service, so the name is
+                            // set here for the selectors that need it
+                            let subgraph_name = subgraph_name.clone();
+                            let breaker = breaker.clone();
                             async {
                                 let response: Result<subgraph::Response, BoxError> = future.await;
                                 match response {
                                     Err(error) if error.is::<Elapsed>() => {
+                                        // the timeout cancelled the request without recording it
+                                        if let Some(breaker) = breaker {
+                                            breaker.record_timeout();
+                                        }
                                         subgraph::Response::error_builder()
                                             .status_code(StatusCode::GATEWAY_TIMEOUT)
                                             .error::<graphql::Error>(Elapsed::new().into())
                                             .context(ctx)
                                             .build()
                                     }
+                                    Err(error) if error.is::<CircuitOpen>() => {
+                                        let circuit_open = error
+                                            .downcast_ref::<CircuitOpen>()
+                                            .cloned()
+                                            .expect("the error type was checked; qed");
+                                        subgraph::Response::error_builder()
+                                            .status_code(StatusCode::SERVICE_UNAVAILABLE)
+                                            .error::<graphql::Error>(circuit_open.into())
+                                            .context(ctx)
+                                            .subgraph_name(subgraph_name)
+                                            .build()
+                                    }
+                                    Err(error) if error.is::<ConcurrencyLimited>() => {
+                                        let concurrency_limited = error
+                                            .downcast_ref::<ConcurrencyLimited>()
+                                            .cloned()
+                                            .expect("the error type was checked; qed");
+                                        subgraph::Response::error_builder()
+                                            .status_code(StatusCode::SERVICE_UNAVAILABLE)
+                                            .error::<graphql::Error>(concurrency_limited.into())
+                                            .context(ctx)
+                                            .subgraph_name(subgraph_name)
+                                            .build()
+                                    }
                                     Err(error) if error.is::<RateLimited>() => {
+                                        let retry_after = error
+                                            .downcast_ref::<RateLimited>()
+                                            .and_then(RateLimited::retry_after);
                                         subgraph::Response::error_builder()
                                             .status_code(StatusCode::TOO_MANY_REQUESTS)
                                             .error::<graphql::Error>(RateLimited::new().into())
                                             .context(ctx)
+                                            .subgraph_name(subgraph_name)
                                             .build()
+                                            .map(|mut response| {
+                                                if let Some(retry_after) = retry_after {
+                                                    response
+                                                        .response
+                                                        .headers_mut()
+                                                        .insert(RETRY_AFTER, retry_after);
+                                                }
+                                                response
+                                            })
                                     }
                                     _ => response,
                                 }
@@ -428,8 +830,13 @@ impl TrafficShaping {
                         .timeout
                         .unwrap_or(DEFAULT_TIMEOUT),
                     ))
+                    .option_layer(client_rate_limit)
                     .option_layer(retry)
+                    .option_layer(distributed_rate_limit)
                     .option_layer(rate_limit)
+                    .option_layer(circuit_breaker)
+                    .option_layer(concurrency_limit)
+                    .option_layer(hedging)
                 .service(service)
                 .map_request(move |mut req: SubgraphRequest| {
                     if let Some(compression) = config.shaping.compression {
@@ -474,6 +881,8 @@ mod test {
     use crate::plugin::test::MockSubgraph;
     use crate::plugin::test::MockSupergraphService;
     use crate::plugin::DynPlugin;
+    use crate::plugins::telemetry::config_new::selectors::SubgraphSelector;
+    use crate::plugins::telemetry::config_new::Selector;
     use crate::query_planner::BridgeQueryPlannerPool;
     use crate::router_factory::create_plugins;
     use crate::services::layers::persisted_queries::PersistedQueryLayer;
@@ -703,6 +1112,48 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_merge_rate_limit_storage() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+          global_rate_limit:
+            capacity: 10
+            interval: 1s
+            storage: redis
+        subgraphs:
+          products:
+            global_rate_limit:
+              capacity: 5
+              interval: 1s
+          reviews:
+            global_rate_limit:
+              capacity: 5
+              interval: 1s
+              storage: memory
+        "#,
+        )
+        .unwrap();
+
+        let products =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("products"))
+                .unwrap()
+                .shaping
+                .global_rate_limit
+                .unwrap();
+        assert_eq!(products.capacity.get(), 5);
+        assert_eq!(products.storage(), RateLimitStorage::Redis);
+
+        let reviews =
+            TrafficShaping::merge_config(config.all.as_ref(), config.subgraphs.get("reviews"))
+                .unwrap()
+                .shaping
+                .global_rate_limit
+                .unwrap();
+        assert_eq!(reviews.capacity.get(), 5);
+        assert_eq!(reviews.storage(), RateLimitStorage::Memory);
+    }
+
     #[test]
     fn test_merge_http2_all() {
         let config = serde_yaml::from_str::<Config>(
@@ -804,18 +1255,17 @@ mod test {
             .body()
             .errors
             .is_empty());
+        let response = plugin
+            .as_any()
+            .downcast_ref::<TrafficShaping>()
+            .unwrap()
+            .subgraph_service_internal("test", test_service.clone())
+            .oneshot(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap();
+        assert_eq!(response.subgraph_name.as_deref(), Some("test"));
         assert_eq!(
-            plugin
-                .as_any()
-                .downcast_ref::<TrafficShaping>()
-                .unwrap()
-                .subgraph_service_internal("test", test_service.clone())
-                .oneshot(SubgraphRequest::fake_builder().build())
-                .await
-                .unwrap()
-                .response
-                .body()
-                .errors[0]
+            response.response.body().errors[0]
                 .extensions
                 .get("code")
                 .unwrap(),
@@ -848,6 +1298,55 @@ mod test {
             .is_empty());
     }
 
+    #[tokio::test]
+    async fn it_reports_open_circuit_breakers() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        subgraphs:
+            test:
+                circuit_breaker:
+                    minimum_requests: 2
+                    open_duration: 60s
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let failing_service = tower::service_fn(|request: SubgraphRequest| async move {
+            subgraph::Response::error_builder()
+                .status_code(StatusCode::INTERNAL_SERVER_ERROR)
+                .error(
+                    graphql::Error::builder()
+                        .message("failure")
+                        .extension_code("FAILURE")
+                        .build(),
+                )
+                .context(request.context)
+                .build()
+        });
+        let call = || {
+            plugin
+                .as_any()
+                .downcast_ref::<TrafficShaping>()
+                .unwrap()
+                .subgraph_service_internal("test", failing_service.clone())
+                .oneshot(SubgraphRequest::fake_builder().build())
+        };
+
+        call().await.unwrap();
+        call().await.unwrap();
+        let response = call().await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.subgraph_name.as_deref(), Some("test"));
+        assert_eq!(
+            SubgraphSelector::CircuitBreakerState {
+                circuit_breaker_state: true,
+            }
+            .on_response(&response),
+            Some("open".into())
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn it_rate_limit_router_requests() {
         let config = serde_yaml::from_str::<serde_json::Value>(
@@ -926,4 +1425,211 @@ mod test {
             .errors
             .is_empty());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_rate_limit_subgraph_requests_per_client() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        all:
+            client_rate_limit:
+                capacity: 1
+                interval: 100ms
+                key:
+                    header: x-client-id
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let test_service = MockSubgraph::new(hashmap! {
+            graphql::Request::default() => graphql::Response::default()
+        });
+        let call = |client: Option<&str>| {
+            let mut supergraph_request = http::Request::builder();
+            if let Some(client) = client {
+                supergraph_request = supergraph_request.header("x-client-id", client);
+            }
+            plugin
+                .as_any()
+                .downcast_ref::<TrafficShaping>()
+                .unwrap()
+                .subgraph_service_internal("test", test_service.clone())
+                .oneshot(
+                    SubgraphRequest::fake_builder()
+                        .supergraph_request(Arc::new(
+                            supergraph_request
+                                .body(graphql::Request::default())
+                                .unwrap(),
+                        ))
+                        .build(),
+                )
+        };
+
+        let response = call(Some("a")).await.unwrap();
+        assert!(response.response.body().errors.is_empty());
+        let response = call(Some("a")).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.response.body().errors[0]
+                .extensions
+                .get("code")
+                .unwrap(),
+            "REQUEST_RATE_LIMITED"
+        );
+        assert_eq!(
+            response.response.headers().get(RETRY_AFTER).unwrap(),
+            HeaderValue::from_static("1")
+        );
+
+        // other clients have their own limit, and requests without a client key are not limited
+        let response = call(Some("b")).await.unwrap();
+        assert!(response.response.body().errors.is_empty());
+        let response = call(None).await.unwrap();
+        assert!(response.response.body().errors.is_empty());
+        let response = call(None).await.unwrap();
+        assert!(response.response.body().errors.is_empty());
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let response = call(Some("a")).await.unwrap();
+        assert!(response.response.body().errors.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_rate_limit_router_requests_per_client() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        router:
+            client_rate_limit:
+                capacity: 2
+                interval: 10s
+                key: operation_name
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let service = tower::service_fn(|request: SupergraphRequest| async move {
+            SupergraphResponse::fake_builder()
+                .context(request.context)
+                .data(json!({ "test": 1234_u32 }))
+                .build()
+        });
+        let call = |operation_name: &str| {
+            plugin
+                .as_any()
+                .downcast_ref::<TrafficShaping>()
+                .unwrap()
+                .supergraph_service_internal(service)
+                .oneshot(
+                    SupergraphRequest::fake_builder()
+                        .operation_name(operation_name)
+                        .build()
+                        .unwrap(),
+                )
+        };
+
+        assert_eq!(call("A").await.unwrap().response.status(), StatusCode::OK);
+        assert_eq!(call("A").await.unwrap().response.status(), StatusCode::OK);
+        let response = call("A").await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response
+            .response
+            .headers()
+            .get(RETRY_AFTER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 5);
+        assert_eq!(call("B").await.unwrap().response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_sheds_router_requests() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        router:
+            load_shedding:
+                max_concurrent_requests: 1
+                retry_after: 2s
+        "#,
+        )
+        .unwrap();
+
+        let plugin = get_traffic_shaping_plugin(&config).await;
+        let service = tower::service_fn(|request: SupergraphRequest| async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            SupergraphResponse::fake_builder()
+                .context(request.context)
+                .data(json!({ "test": 1234_u32 }))
+                .build()
+        });
+        let service = plugin
+            .as_any()
+            .downcast_ref::<TrafficShaping>()
+            .unwrap()
+            .supergraph_service_internal(service);
+
+        let in_flight = tokio::spawn(
+            service
+                .clone()
+                .oneshot(SupergraphRequest::fake_builder().build().unwrap()),
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let response = service
+            .clone()
+            .oneshot(SupergraphRequest::fake_builder().build().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.response.headers().get(RETRY_AFTER).unwrap(), "2");
+
+        assert_eq!(
+            in_flight.await.unwrap().unwrap().response.status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_inconsistent_concurrency_limits() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        subgraphs:
+            products:
+                concurrency_limit:
+                    min_limit: 10
+                    max_limit: 5
+        "#,
+        )
+        .unwrap();
+
+        assert!(
+            TrafficShaping::new(PluginInit::fake_builder().config(config).build())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn client_rate_limit_in_redis_requires_redis_configuration() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        router:
+            client_rate_limit:
+                capacity: 1
+                interval: 1s
+                key: client_name
+                storage: redis
+        "#,
+        )
+        .unwrap();
+
+        assert!(
+            TrafficShaping::new(PluginInit::fake_builder().config(config).build())
+                .await
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,305 @@
+//! Rate limiting per client
+//!
+//! Each client gets its own limit, identified by a key extracted from the request.
+
+use std::task::Context;
+use std::task::Poll;
+
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use super::KeyedRateLimiter;
+use crate::context::OPERATION_NAME;
+use crate::graphql;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::plugins::telemetry::CLIENT_NAME;
+use crate::plugins::telemetry::CLIENT_VERSION;
+use crate::services::execution;
+use crate::services::subgraph;
+use crate::services::supergraph;
+
+/// How clients are identified
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub(crate) enum ClientKey {
+    /// Value of a header of the client request
+    Header(String),
+    /// Claim of the JWT authenticating the client request
+    JwtClaim(String),
+    /// Client name, as set by the client name header configured in telemetry
+    ClientName,
+    /// Client name and version, as set by the headers configured in telemetry
+    ClientVersion,
+    /// Name of the GraphQL operation
+    OperationName,
+}
+
+/// Requests which can be limited per client
+pub(crate) trait ClientRequest {
+    fn context(&self) -> &crate::Context;
+    fn supergraph_request(&self) -> &http::Request<graphql::Request>;
+}
+
+impl ClientRequest for supergraph::Request {
+    fn context(&self) -> &crate::Context {
+        &self.context
+    }
+
+    fn supergraph_request(&self) -> &http::Request<graphql::Request> {
+        &self.supergraph_request
+    }
+}
+
+impl ClientRequest for execution::Request {
+    fn context(&self) -> &crate::Context {
+        &self.context
+    }
+
+    fn supergraph_request(&self) -> &http::Request<graphql::Request> {
+        &self.supergraph_request
+    }
+}
+
+impl ClientRequest for subgraph::Request {
+    fn context(&self) -> &crate::Context {
+        &self.context
+    }
+
+    fn supergraph_request(&self) -> &http::Request<graphql::Request> {
+        &self.supergraph_request
+    }
+}
+
+impl ClientKey {
+    /// Returns `None` if the request has no value for this key
+    pub(crate) fn extract(&self, request: &impl ClientRequest) -> Option<String> {
+        let context = request.context();
+        match self {
+            ClientKey::Header(name) => request
+                .supergraph_request()
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            ClientKey::JwtClaim(claim) => {
+                let claims = context
+                    .get::<_, serde_json::Value>(APOLLO_AUTHENTICATION_JWT_CLAIMS)
+                    .ok()??;
+                match claims.get(claim)? {
+                    serde_json::Value::String(value) => Some(value.clone()),
+                    serde_json::Value::Null => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            ClientKey::ClientName => context.get::<_, String>(CLIENT_NAME).ok()?,
+            ClientKey::ClientVersion => {
+                let name = context.get::<_, String>(CLIENT_NAME).ok()??;
+                let version = context
+                    .get::<_, String>(CLIENT_VERSION)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                Some(format!("{name}:{version}"))
+            }
+            ClientKey::OperationName => request
+                .supergraph_request()
+                .body()
+                .operation_name
+                .clone()
+                .or_else(|| context.get::<_, String>(OPERATION_NAME).ok().flatten()),
+        }
+    }
+}
+
+/// Enforces a rate limit per client on the requests the underlying service receives
+#[derive(Clone)]
+pub(crate) struct ClientRateLimitLayer {
+    key: ClientKey,
+    limiter: KeyedRateLimiter,
+}
+
+impl ClientRateLimitLayer {
+    pub(crate) fn new(key: ClientKey, limiter: KeyedRateLimiter) -> Self {
+        Self { key, limiter }
+    }
+}
+
+impl<S> Layer<S> for ClientRateLimitLayer {
+    type Service = ClientRateLimit<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        ClientRateLimit {
+            inner: service,
+            key: self.key.clone(),
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ClientRateLimit<S> {
+    inner: S,
+    key: ClientKey,
+    limiter: KeyedRateLimiter,
+}
+
+impl<S, Request> Service<Request> for ClientRateLimit<S>
+where
+    Request: ClientRequest + Send + 'static,
+    S: Service<Request> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Response: Send,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // requests without a client key are not limited
+        let client = self.key.extract(&request);
+        let limiter = self.limiter.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            if let Some(client) = client {
+                limiter.acquire(&client).await?;
+            }
+            inner.call(request).await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugins::traffic_shaping::rate::Rate;
+    use crate::Context;
+
+    fn request(context: Context) -> supergraph::Request {
+        supergraph::Request::fake_builder()
+            .header("x-client-id", "client")
+            .operation_name("Query")
+            .context(context)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn extracts_keys() {
+        let context = Context::new();
+        context.insert(CLIENT_NAME, "name".to_string()).unwrap();
+        context.insert(CLIENT_VERSION, "1.0".to_string()).unwrap();
+        context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                json!({"sub": "user", "org": 42}),
+            )
+            .unwrap();
+        let request = request(context);
+
+        let extract = |key: ClientKey| key.extract(&request);
+        assert_eq!(
+            extract(ClientKey::Header("x-client-id".to_string())).as_deref(),
+            Some("client")
+        );
+        assert_eq!(
+            extract(ClientKey::JwtClaim("sub".to_string())).as_deref(),
+            Some("user")
+        );
+        // claims that are not strings use their JSON representation
+        assert_eq!(
+            extract(ClientKey::JwtClaim("org".to_string())).as_deref(),
+            Some("42")
+        );
+        assert_eq!(extract(ClientKey::ClientName).as_deref(), Some("name"));
+        assert_eq!(
+            extract(ClientKey::ClientVersion).as_deref(),
+            Some("name:1.0")
+        );
+        assert_eq!(extract(ClientKey::OperationName).as_deref(), Some("Query"));
+    }
+
+    #[test]
+    fn missing_keys() {
+        let context = Context::new();
+        context
+            .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, json!({"sub": null}))
+            .unwrap();
+        let request = supergraph::Request::fake_builder()
+            .context(context)
+            .build()
+            .unwrap();
+
+        let extract = |key: ClientKey| key.extract(&request);
+        assert_eq!(extract(ClientKey::Header("x-client-id".to_string())), None);
+        assert_eq!(extract(ClientKey::JwtClaim("sub".to_string())), None);
+        assert_eq!(extract(ClientKey::JwtClaim("org".to_string())), None);
+        assert_eq!(extract(ClientKey::ClientName), None);
+        assert_eq!(extract(ClientKey::ClientVersion), None);
+        assert_eq!(extract(ClientKey::OperationName), None);
+
+        // without JWT claims in the context
+        assert_eq!(
+            ClientKey::JwtClaim("sub".to_string()).extract(&request(Context::new())),
+            None
+        );
+
+        // the version is optional
+        let context = Context::new();
+        context.insert(CLIENT_NAME, "name".to_string()).unwrap();
+        assert_eq!(
+            ClientKey::ClientVersion
+                .extract(&request(context))
+                .as_deref(),
+            Some("name:")
+        );
+    }
+
+    #[tokio::test]
+    async fn limits_each_client() {
+        let layer = ClientRateLimitLayer::new(
+            ClientKey::Header("x-client-id".to_string()),
+            KeyedRateLimiter::new(
+                "test".to_string(),
+                Rate::new(NonZeroU64::new(1).unwrap(), Duration::from_secs(60)),
+                None,
+            ),
+        );
+        let service = layer.layer(tower::service_fn(|_request: supergraph::Request| async {
+            Ok::<_, BoxError>(())
+        }));
+        let call = |client: Option<&str>| {
+            let request = match client {
+                Some(client) => supergraph::Request::fake_builder()
+                    .header("x-client-id", client)
+                    .build(),
+                None => supergraph::Request::fake_builder().build(),
+            };
+            service.clone().oneshot(request.unwrap())
+        };
+
+        assert!(call(Some("a")).await.is_ok());
+        assert!(call(Some("a")).await.is_err());
+        assert!(call(Some("b")).await.is_ok());
+
+        // requests without a client key are not limited
+        assert!(call(None).await.is_ok());
+        assert!(call(None).await.is_ok());
+    }
+}
@@ -0,0 +1,72 @@
+//! Rate limit shared by all the requests, stored in Redis to apply it across router instances
+
+use std::task::Context;
+use std::task::Poll;
+
+use futures::future::BoxFuture;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use super::KeyedRateLimiter;
+
+/// Key of the single limit shared by all requests
+const GLOBAL_KEY: &str = "global";
+
+/// Enforces a rate limit on the number of requests the underlying service can handle over a
+/// period of time, across all the router instances
+#[derive(Clone)]
+pub(crate) struct DistributedRateLimitLayer {
+    limiter: KeyedRateLimiter,
+}
+
+impl DistributedRateLimitLayer {
+    pub(crate) fn new(limiter: KeyedRateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for DistributedRateLimitLayer {
+    type Service = DistributedRateLimit<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        DistributedRateLimit {
+            inner: service,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DistributedRateLimit<S> {
+    inner: S,
+    limiter: KeyedRateLimiter,
+}
+
+impl<S, Request> Service<Request> for DistributedRateLimit<S>
+where
+    Request: Send + 'static,
+    S: Service<Request> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Response: Send,
+    S::Future: Send,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let limiter = self.limiter.clone();
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            limiter.acquire(GLOBAL_KEY).await?;
+            inner.call(request).await.map_err(Into::into)
+        })
+    }
+}
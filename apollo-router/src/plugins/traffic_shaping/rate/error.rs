@@ -2,17 +2,40 @@
 
 use std::error;
 use std::fmt;
+use std::time::Duration;
+
+use http::HeaderValue;
 
 use crate::graphql;
 
 /// The rate limit error.
 #[derive(Debug, Default)]
-pub(crate) struct RateLimited;
+pub(crate) struct RateLimited {
+    retry_after: Option<Duration>,
+}
 
 impl RateLimited {
     /// Construct a new RateLimited error
     pub(crate) fn new() -> Self {
-        RateLimited {}
+        RateLimited { retry_after: None }
+    }
+
+    /// Construct a new RateLimited error, with the time after which the request can be retried
+    pub(crate) fn with_retry_after(retry_after: Duration) -> Self {
+        RateLimited {
+            retry_after: Some(retry_after),
+        }
+    }
+
+    /// Value of the `Retry-After` header, in seconds rounded up
+    pub(crate) fn retry_after(&self) -> Option<HeaderValue> {
+        self.retry_after.map(|retry_after| {
+            let mut secs = retry_after.as_secs();
+            if retry_after.subsec_nanos() > 0 || secs == 0 {
+                secs += 1;
+            }
+            HeaderValue::from(secs)
+        })
     }
 }
 
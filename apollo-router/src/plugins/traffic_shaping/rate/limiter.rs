@@ -0,0 +1,431 @@
+//! Rate limits shared by several requests, identified by a key
+//!
+//! The state is kept in memory, or in Redis to share it between router instances. When Redis
+//! cannot be reached, the in memory state is used instead, so each router instance applies the
+//! limits on its own.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::Rate;
+use super::RateLimited;
+use crate::cache::redis::ttl_secs;
+use crate::cache::redis::RedisCacheStorage;
+use crate::cache::redis::RedisKey;
+
+/// Maximum number of keys tracked in memory. The least recently seen keys are forgotten first.
+///
+/// A forgotten key starts again with a full bucket, so a client able to send requests with more
+/// than `MAX_KEYS` other keys within an interval can reset its own limit. This bounds the memory
+/// used by client chosen keys; the keys stored in Redis are not limited, they expire instead.
+const MAX_KEYS: usize = 10_000;
+
+/// Adds `ARGV[1]` to the counter of the current window at `KEYS[1]` if the sliding window leaves
+/// room for it: the counter of the previous window at `KEYS[2]` is weighted by `ARGV[3]`, and the
+/// total must not exceed `ARGV[2]`. A new counter expires after `ARGV[4]` seconds.
+///
+/// Checking and incrementing in one script ensures that concurrent requests cannot exceed the
+/// limit, and that rejected amounts are never counted.
+///
+/// Returns whether the amount was acquired, the counter of the current window and the counter of
+/// the previous window
+const ACQUIRE_SCRIPT: &str = r#"
+local current = tonumber(redis.call('GET', KEYS[1]) or 0)
+local previous = math.max(tonumber(redis.call('GET', KEYS[2]) or 0), 0)
+local amount = tonumber(ARGV[1])
+if previous * tonumber(ARGV[3]) + current + amount > tonumber(ARGV[2]) then
+    return {0, current, previous}
+end
+current = redis.call('INCRBY', KEYS[1], amount)
+if redis.call('TTL', KEYS[1]) == -1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[4])
+end
+return {1, current, previous}
+"#;
+
+/// Where the rate limiting state is stored
+#[derive(PartialEq, Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitStorage {
+    /// In the memory of each router instance
+    #[default]
+    Memory,
+    /// In Redis, shared between router instances
+    Redis,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// What was counted against the limit of a key by [`KeyedRateLimiter::acquire_many`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Permit {
+    /// What is left of the limit after the acquisition
+    pub(crate) remaining: u64,
+    // the Redis window the amount was counted in, `None` if it was counted in memory
+    window: Option<u64>,
+}
+
+#[derive(Clone)]
+pub(crate) struct KeyedRateLimiter {
+    scope: String,
+    rate: Rate,
+    buckets: Arc<Mutex<LruCache<String, Bucket>>>,
+    redis: Option<RedisCacheStorage>,
+}
+
+impl KeyedRateLimiter {
+    /// Create a new rate limiter. The scope separates the Redis keys of different limiters.
+    /// If `redis` is `None`, the state is stored in memory
+    pub(crate) fn new(scope: String, rate: Rate, redis: Option<RedisCacheStorage>) -> Self {
+        Self {
+            scope,
+            rate,
+            buckets: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(MAX_KEYS).expect("MAX_KEYS is not zero; qed"),
+            ))),
+            redis,
+        }
+    }
+
+    /// Counts a request against the limit of `key`
+    pub(crate) async fn acquire(&self, key: &str) -> Result<(), RateLimited> {
+        self.acquire_many(key, 1).await.map(|_| ())
+    }
+
+    /// Counts `amount` against the limit of `key`. Nothing is counted when the limit is exceeded
+    pub(crate) async fn acquire_many(&self, key: &str, amount: u64) -> Result<Permit, RateLimited> {
+        if let Some(redis) = self.redis.as_ref() {
+            if let Some(result) = self.acquire_in_redis(redis, key, amount).await {
+                return result;
+            }
+            tracing::debug!("Redis is not available, rate limiting in memory");
+        }
+
+        self.acquire_in_memory(key, amount)
+    }
+
+    /// Gives back `amount` of what was counted against the limit of `key` when `permit` was
+    /// acquired
+    pub(crate) async fn release(&self, key: &str, permit: &Permit, amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        match (self.redis.as_ref(), permit.window) {
+            (Some(redis), Some(window)) => {
+                redis
+                    .incr(
+                        RedisKey(self.redis_key(key, window)),
+                        -(amount as i64),
+                        self.rate.per() * 2,
+                    )
+                    .await;
+            }
+            _ => self.release_in_memory(key, amount),
+        }
+    }
+
+    // in memory, each key has a token bucket refilled continuously
+    fn acquire_in_memory(&self, key: &str, amount: u64) -> Result<Permit, RateLimited> {
+        let now = Instant::now();
+        let capacity = self.rate.num() as f64;
+        let refill_per_sec = capacity / self.rate.per().as_secs_f64();
+        let amount = amount as f64;
+        if amount > capacity {
+            return Err(RateLimited::new());
+        }
+
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.get_or_insert_mut(key.to_string(), || Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated_at).as_secs_f64() * refill_per_sec)
+            .min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= amount {
+            bucket.tokens -= amount;
+            Ok(Permit {
+                remaining: bucket.tokens as u64,
+                window: None,
+            })
+        } else {
+            Err(RateLimited::with_retry_after(Duration::from_secs_f64(
+                (amount - bucket.tokens) / refill_per_sec,
+            )))
+        }
+    }
+
+    fn release_in_memory(&self, key: &str, amount: u64) {
+        let capacity = self.rate.num() as f64;
+        if let Some(bucket) = self.buckets.lock().get_mut(key) {
+            bucket.tokens = (bucket.tokens + amount as f64).min(capacity);
+        }
+    }
+
+    // Redis uses a sliding window: the amounts counted in the current window are added to the
+    // amounts of the previous window, weighted by the part of the previous window that is still
+    // in the sliding window.
+    //
+    // Returns `None` if Redis cannot be reached
+    async fn acquire_in_redis(
+        &self,
+        redis: &RedisCacheStorage,
+        key: &str,
+        amount: u64,
+    ) -> Option<Result<Permit, RateLimited>> {
+        let capacity = self.rate.num() as i64;
+        let amount = amount as i64;
+        if amount > capacity {
+            return Some(Err(RateLimited::new()));
+        }
+
+        let interval = (self.rate.per().as_millis() as u64).max(1);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time must be after EPOCH")
+            .as_millis() as u64;
+        let window = now / interval;
+        let elapsed = now - window * interval;
+
+        // the counter of the current window is still used as previous counter in the next window
+        let weight = (interval - elapsed) as f64 / interval as f64;
+        let result: Vec<i64> = redis
+            .eval(
+                ACQUIRE_SCRIPT,
+                vec![
+                    RedisKey(self.redis_key(key, window)),
+                    RedisKey(self.redis_key(key, window.saturating_sub(1))),
+                ],
+                vec![
+                    amount.to_string(),
+                    capacity.to_string(),
+                    weight.to_string(),
+                    ttl_secs(self.rate.per() * 2).to_string(),
+                ],
+            )
+            .await?;
+        let &[acquired, current, previous] = result.as_slice() else {
+            tracing::error!("unexpected result of the rate limiting script: {result:?}");
+            return None;
+        };
+
+        if acquired == 1 {
+            let used = previous as f64 * weight + current as f64;
+            return Some(Ok(Permit {
+                remaining: (capacity as f64 - used).max(0.0) as u64,
+                window: Some(window),
+            }));
+        }
+
+        // time until the weighted previous counter leaves room for the amount
+        let needed = current + amount;
+        let retry_after = if needed >= capacity || previous == 0 {
+            interval - elapsed
+        } else {
+            let allowed_weight = (capacity - needed) as f64 / previous as f64;
+            (((1.0 - allowed_weight) * interval as f64) as u64).saturating_sub(elapsed)
+        };
+        Some(Err(RateLimited::with_retry_after(Duration::from_millis(
+            retry_after.max(1),
+        ))))
+    }
+
+    // the scope and key are a hash tag, so that the counters of all windows are in the same
+    // cluster slot and can be used by the same script
+    fn redis_key(&self, key: &str, window: u64) -> String {
+        format!("rate_limit:{{{}:{key}}}:{window}", self.scope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::num::NonZeroU64;
+
+    use fred::error::RedisErrorKind;
+    use fred::mocks::MockCommand;
+    use fred::mocks::Mocks;
+    use fred::prelude::RedisError;
+    use fred::prelude::RedisValue;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockCounters {
+        counters: Mutex<HashMap<String, i64>>,
+        unavailable: bool,
+    }
+
+    impl Mocks for MockCounters {
+        fn process_command(&self, command: MockCommand) -> Result<RedisValue, RedisError> {
+            if self.unavailable {
+                return Err(RedisError::new(RedisErrorKind::IO, "connection refused"));
+            }
+
+            let arg = |index: usize| command.args.get(index);
+            let string = |index: usize| arg(index).and_then(|arg| arg.as_string());
+            let number = |index: usize| arg(index).and_then(|arg| arg.as_f64());
+            let mut counters = self.counters.lock();
+            match (&*command.cmd, arg(1).and_then(|keys| keys.as_i64())) {
+                // the increment script: EVAL script 1 key amount ttl
+                ("EVAL", Some(1)) => {
+                    let counter = counters.entry(string(2).unwrap_or_default()).or_default();
+                    *counter += number(3).unwrap_or_default() as i64;
+                    Ok(RedisValue::Integer(*counter))
+                }
+                // the acquisition script: EVAL script 2 current previous amount capacity weight ttl
+                ("EVAL", Some(2)) => {
+                    let current_key = string(2).unwrap_or_default();
+                    let current = counters.get(&current_key).copied().unwrap_or_default();
+                    let previous = counters
+                        .get(&string(3).unwrap_or_default())
+                        .copied()
+                        .unwrap_or_default()
+                        .max(0);
+                    let amount = number(4).unwrap_or_default();
+                    let capacity = number(5).unwrap_or_default();
+                    let weight = number(6).unwrap_or_default();
+                    if previous as f64 * weight + current as f64 + amount > capacity {
+                        return Ok(RedisValue::Array(vec![
+                            RedisValue::Integer(0),
+                            RedisValue::Integer(current),
+                            RedisValue::Integer(previous),
+                        ]));
+                    }
+                    let current = counters.entry(current_key).or_default();
+                    *current += amount as i64;
+                    Ok(RedisValue::Array(vec![
+                        RedisValue::Integer(1),
+                        RedisValue::Integer(*current),
+                        RedisValue::Integer(previous),
+                    ]))
+                }
+                _ => Ok(RedisValue::Integer(1)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_buckets() {
+        let limiter = KeyedRateLimiter::new(
+            "test".to_string(),
+            Rate::new(NonZeroU64::new(2).unwrap(), Duration::from_millis(100)),
+            None,
+        );
+
+        assert!(limiter.acquire("a").await.is_ok());
+        assert!(limiter.acquire("a").await.is_ok());
+        assert!(limiter.acquire("a").await.is_err());
+        assert!(limiter.acquire("b").await.is_ok());
+
+        // a token is added every 50ms
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(limiter.acquire("a").await.is_ok());
+        assert!(limiter.acquire("a").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn redis_sliding_window() {
+        let mocks = Arc::new(MockCounters::default());
+        let redis = RedisCacheStorage::from_mocks(mocks.clone()).await.unwrap();
+        let limiter = KeyedRateLimiter::new(
+            "test".to_string(),
+            Rate::new(NonZeroU64::new(2).unwrap(), Duration::from_secs(3600)),
+            Some(redis),
+        );
+
+        // the previous window was full, so its requests still count in the sliding window
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let window = now / 3_600_000;
+        mocks
+            .counters
+            .lock()
+            .insert(limiter.redis_key("a", window - 1), 1_000_000);
+
+        let error = limiter.acquire("a").await.unwrap_err();
+        assert!(error.retry_after().is_some());
+        assert!(limiter.acquire("b").await.is_ok());
+        assert!(limiter.acquire("b").await.is_ok());
+        assert!(limiter.acquire("b").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn weighted_acquisitions() {
+        let limiter = KeyedRateLimiter::new(
+            "test".to_string(),
+            Rate::new(NonZeroU64::new(100).unwrap(), Duration::from_secs(3600)),
+            None,
+        );
+
+        assert_eq!(limiter.acquire_many("a", 60).await.unwrap().remaining, 40);
+        assert!(limiter.acquire_many("a", 60).await.is_err());
+        // more than the capacity can never be acquired
+        assert!(limiter
+            .acquire_many("a", 200)
+            .await
+            .unwrap_err()
+            .retry_after()
+            .is_none());
+
+        let permit = limiter.acquire_many("a", 40).await.unwrap();
+        assert_eq!(permit.remaining, 0);
+        limiter.release("a", &permit, 30).await;
+        assert_eq!(limiter.acquire_many("a", 30).await.unwrap().remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn redis_weighted_acquisitions() {
+        let mocks = Arc::new(MockCounters::default());
+        let redis = RedisCacheStorage::from_mocks(mocks.clone()).await.unwrap();
+        let limiter = KeyedRateLimiter::new(
+            "test".to_string(),
+            Rate::new(NonZeroU64::new(100).unwrap(), Duration::from_secs(3600)),
+            Some(redis),
+        );
+
+        let permit = limiter.acquire_many("a", 60).await.unwrap();
+        assert_eq!(permit.remaining, 40);
+        // the rejected amount is not counted
+        assert!(limiter.acquire_many("a", 60).await.is_err());
+        assert_eq!(limiter.acquire_many("a", 20).await.unwrap().remaining, 20);
+
+        limiter.release("a", &permit, 50).await;
+        assert_eq!(limiter.acquire_many("a", 70).await.unwrap().remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn redis_unavailable() {
+        let redis = RedisCacheStorage::from_mocks(Arc::new(MockCounters {
+            unavailable: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let limiter = KeyedRateLimiter::new(
+            "test".to_string(),
+            Rate::new(NonZeroU64::new(1).unwrap(), Duration::from_secs(3600)),
+            Some(redis),
+        );
+
+        // the limit is applied in memory
+        assert!(limiter.acquire("a").await.is_ok());
+        assert!(limiter.acquire("a").await.is_err());
+    }
+}
@@ -1,13 +1,23 @@
 //! Limit the rate at which requests are processed.
 
+mod client;
+mod distributed;
 mod error;
 pub(crate) mod future;
 mod layer;
+mod limiter;
 #[allow(clippy::module_inception)]
 mod rate;
 pub(crate) mod service;
 
+pub(crate) use self::client::ClientKey;
+pub(crate) use self::client::ClientRateLimitLayer;
+pub(crate) use self::client::ClientRequest;
+pub(crate) use self::distributed::DistributedRateLimitLayer;
 pub(crate) use self::error::RateLimited;
 pub(crate) use self::layer::RateLimitLayer;
+pub(crate) use self::limiter::KeyedRateLimiter;
+pub(crate) use self::limiter::Permit;
+pub(crate) use self::limiter::RateLimitStorage;
 pub(crate) use self::rate::Rate;
 pub(crate) use self::service::RateLimit;
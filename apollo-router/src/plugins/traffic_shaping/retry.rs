@@ -1,78 +1,550 @@
-use std::future;
+use std::num::NonZeroU32;
 use std::sync::Arc;
 use std::time::Duration;
 
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::header::RETRY_AFTER;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
 use tower::retry::budget::Budget;
 use tower::retry::Policy;
+use tower::BoxError;
 
+use super::circuit_breaker::CircuitOpen;
+use super::concurrency::ConcurrencyLimited;
+use super::RetryConfig;
+use crate::error::FetchError;
 use crate::query_planner::OperationKind;
 use crate::services::subgraph;
 
+/// Highest `Retry-After` delay honored when no backoff is configured
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Delay between the attempts of a request
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BackoffConfig {
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_initial_delay"
+    )]
+    #[schemars(with = "String", default)]
+    /// Delay before the first retry (default: 100ms)
+    initial_delay: Duration,
+    #[serde(
+        deserialize_with = "humantime_serde::deserialize",
+        default = "default_max_delay"
+    )]
+    #[schemars(with = "String", default)]
+    /// Highest delay between two attempts. A subgraph asking with `Retry-After` to wait longer
+    /// than this is not retried (default: 5s)
+    max_delay: Duration,
+    /// Factor applied to the delay after each retry (default: 2)
+    #[serde(default = "default_multiplier")]
+    multiplier: f64,
+    /// Randomize each delay between half and all of its value, so that the requests failing at
+    /// the same time are not all retried at the same time (default: true)
+    #[serde(default = "default_jitter")]
+    jitter: bool,
+}
+
+fn default_initial_delay() -> Duration {
+    Duration::from_millis(100)
+}
+
+fn default_max_delay() -> Duration {
+    DEFAULT_MAX_DELAY
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+/// Conditions under which a request is retried. A request is retried if it matches any of them
+#[derive(PartialEq, Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct RetryConditions {
+    /// HTTP status codes of subgraph responses, such as 502, 503 or 504
+    status_codes: Vec<u16>,
+    /// Kinds of connection errors
+    connection_errors: Vec<ConnectionError>,
+    /// Extension codes of the GraphQL errors of subgraph responses
+    graphql_error_codes: Vec<String>,
+}
+
+/// Kind of error that prevented a subgraph response from being received
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ConnectionError {
+    /// The connection could not be established
+    Connect,
+    /// The connection was closed or reset before the response was received
+    Closed,
+    /// Any other error
+    Other,
+}
+
+impl ConnectionError {
+    /// The kind of a subgraph request error, `None` if it is not a transport error
+    fn of(error: &BoxError) -> Option<Self> {
+        let Some(FetchError::SubrequestHttpError {
+            status_code: None,
+            reason,
+            ..
+        }) = error.downcast_ref::<FetchError>()
+        else {
+            return None;
+        };
+        // the error from the HTTP client is only available as a message
+        let reason = reason.to_lowercase();
+        if ["error trying to connect", "dns error", "connection refused"]
+            .iter()
+            .any(|message| reason.contains(message))
+        {
+            Some(ConnectionError::Connect)
+        } else if [
+            "connection closed",
+            "connection reset",
+            "broken pipe",
+            "incomplete message",
+        ]
+        .iter()
+        .any(|message| reason.contains(message))
+        {
+            Some(ConnectionError::Closed)
+        } else {
+            Some(ConnectionError::Other)
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionError::Connect => "connect",
+            ConnectionError::Closed => "closed",
+            ConnectionError::Other => "other",
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct RetryPolicy {
     budget: Arc<Budget>,
     retry_mutations: bool,
     subgraph_name: String,
+    max_attempts: Option<NonZeroU32>,
+    backoff: Option<BackoffConfig>,
+    retry_on: Option<RetryConditions>,
+    /// Number of retries of the current request
+    retries: u32,
 }
 
 impl RetryPolicy {
-    pub(crate) fn new(
-        duration: Option<Duration>,
-        min_per_sec: Option<u32>,
-        retry_percent: Option<f32>,
-        retry_mutations: Option<bool>,
-        subgraph_name: String,
-    ) -> Self {
+    pub(crate) fn new(config: &RetryConfig, subgraph_name: String) -> Self {
         Self {
             budget: Arc::new(Budget::new(
-                duration.unwrap_or_else(|| Duration::from_secs(10)),
-                min_per_sec.unwrap_or(10),
-                retry_percent.unwrap_or(0.2),
+                config.ttl.unwrap_or_else(|| Duration::from_secs(10)),
+                config.min_per_sec.unwrap_or(10),
+                config.retry_percent.unwrap_or(0.2),
             )),
-            retry_mutations: retry_mutations.unwrap_or(false),
+            retry_mutations: config.retry_mutations.unwrap_or(false),
             subgraph_name,
+            max_attempts: config.max_attempts,
+            backoff: config.backoff.clone(),
+            retry_on: config.retry_on.clone(),
+            retries: 0,
         }
     }
-}
 
-impl<Res, E> Policy<subgraph::Request, Res, E> for RetryPolicy {
-    type Future = future::Ready<Self>;
+    /// Why a request should be retried, `None` if it should not
+    fn retry_reason(&self, result: Result<&subgraph::Response, &BoxError>) -> Option<String> {
+        // the circuit breaker and the concurrency limit reject requests when the subgraph is
+        // unavailable or overloaded: retrying would only drain the budget
+        if let Err(error) = result {
+            if error.is::<CircuitOpen>() || error.is::<ConcurrencyLimited>() {
+                return None;
+            }
+        }
+
+        let Some(retry_on) = &self.retry_on else {
+            // without conditions, all errors are retried
+            return result.is_err().then(|| "error".to_string());
+        };
 
-    fn retry(&self, req: &subgraph::Request, result: Result<&Res, &E>) -> Option<Self::Future> {
         match result {
-            Ok(_) => {
-                // Treat all `Response`s as success,
-                // so deposit budget and don't retry...
-                self.budget.deposit();
-                None
-            }
-            Err(_e) => {
-                if req.operation_kind == OperationKind::Mutation && !self.retry_mutations {
-                    return None;
+            Ok(response) => {
+                let status = response.response.status().as_u16();
+                if retry_on.status_codes.contains(&status) {
+                    return Some(format!("status {status}"));
                 }
+                response
+                    .response
+                    .body()
+                    .errors
+                    .iter()
+                    .filter_map(|error| error.extensions.get("code")?.as_str())
+                    .find(|code| retry_on.graphql_error_codes.iter().any(|c| c == code))
+                    .map(|code| format!("GraphQL error {code}"))
+            }
+            Err(error) => ConnectionError::of(error)
+                .filter(|kind| retry_on.connection_errors.contains(kind))
+                .map(|kind| format!("{} connection error", kind.as_str())),
+        }
+    }
+
+    /// The delay before the next retry, from the backoff configuration
+    fn backoff_delay(&self) -> Duration {
+        let Some(backoff) = &self.backoff else {
+            return Duration::ZERO;
+        };
+        let delay = Duration::from_secs_f64(
+            (backoff.initial_delay.as_secs_f64()
+                * backoff.multiplier.max(1.0).powi(self.retries as i32))
+            .min(backoff.max_delay.as_secs_f64()),
+        );
+        if backoff.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+}
 
-                let withdrew = self.budget.withdraw();
-                if withdrew.is_err() {
-                    tracing::info!(
-                        monotonic_counter.apollo_router_http_request_retry_total = 1u64,
-                        status = "aborted",
-                        subgraph = %self.subgraph_name,
-                    );
+/// The delay requested by the `Retry-After` header of a subgraph response, in seconds
+fn retry_after(response: &subgraph::Response) -> Option<Duration> {
+    response
+        .response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
 
-                    return None;
-                }
+impl Policy<subgraph::Request, subgraph::Response, BoxError> for RetryPolicy {
+    type Future = BoxFuture<'static, Self>;
 
-                tracing::info!(
-                    monotonic_counter.apollo_router_http_request_retry_total = 1u64,
-                    subgraph = %self.subgraph_name,
-                );
+    fn retry(
+        &self,
+        req: &subgraph::Request,
+        result: Result<&subgraph::Response, &BoxError>,
+    ) -> Option<Self::Future> {
+        let Some(reason) = self.retry_reason(result) else {
+            if result.is_ok() {
+                // successful responses deposit to the budget
+                self.budget.deposit();
+            }
+            return None;
+        };
 
-                Some(future::ready(self.clone()))
+        if req.operation_kind == OperationKind::Mutation && !self.retry_mutations {
+            return None;
+        }
+        if self
+            .max_attempts
+            .map_or(false, |max_attempts| self.retries + 1 >= max_attempts.get())
+        {
+            return None;
+        }
+
+        let mut delay = self.backoff_delay();
+        if let Some(retry_after) = result.ok().and_then(retry_after) {
+            let max_delay = self
+                .backoff
+                .as_ref()
+                .map_or(DEFAULT_MAX_DELAY, |backoff| backoff.max_delay);
+            if retry_after > max_delay {
+                return None;
             }
+            delay = delay.max(retry_after);
+        }
+
+        let withdrew = self.budget.withdraw();
+        if withdrew.is_err() {
+            tracing::info!(
+                monotonic_counter.apollo_router_http_request_retry_total = 1u64,
+                status = "aborted",
+                subgraph = %self.subgraph_name,
+            );
+
+            return None;
         }
+
+        tracing::info!(
+            monotonic_counter.apollo_router_http_request_retry_total = 1u64,
+            subgraph = %self.subgraph_name,
+        );
+
+        let mut policy = self.clone();
+        policy.retries += 1;
+        // recorded as an event of the subgraph request span
+        tracing::info!(
+            subgraph.name = %self.subgraph_name,
+            retry.attempt = policy.retries + 1,
+            retry.reason = %reason,
+            retry.delay_ms = delay.as_millis() as u64,
+            "retrying subgraph request"
+        );
+
+        Some(
+            async move {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                policy
+            }
+            .boxed(),
+        )
     }
 
     fn clone_request(&self, req: &subgraph::Request) -> Option<subgraph::Request> {
         Some(req.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::time::Instant;
+
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::graphql;
+
+    fn config() -> RetryConfig {
+        RetryConfig {
+            ttl: None,
+            min_per_sec: None,
+            retry_percent: None,
+            retry_mutations: None,
+            max_attempts: None,
+            backoff: None,
+            retry_on: None,
+        }
+    }
+
+    fn connection_error(reason: &str) -> BoxError {
+        FetchError::SubrequestHttpError {
+            status_code: None,
+            service: "test".to_string(),
+            reason: reason.to_string(),
+        }
+        .into()
+    }
+
+    // returns the results in order, then successful responses
+    async fn attempts(
+        config: RetryConfig,
+        results: Vec<Result<subgraph::Response, BoxError>>,
+    ) -> (Result<subgraph::Response, BoxError>, usize) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let results = Arc::new(parking_lot::Mutex::new(results.into_iter()));
+        let service = tower::service_fn({
+            let calls = calls.clone();
+            move |_request: subgraph::Request| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let result = results
+                    .lock()
+                    .next()
+                    .unwrap_or_else(|| Ok(subgraph::Response::fake_builder().build()));
+                async move { result }
+            }
+        });
+        let response =
+            tower::retry::Retry::new(RetryPolicy::new(&config, "test".to_string()), service)
+                .oneshot(subgraph::Request::fake_builder().build())
+                .await;
+        (response, calls.load(Ordering::SeqCst))
+    }
+
+    fn response(status: StatusCode) -> Result<subgraph::Response, BoxError> {
+        Ok(subgraph::Response::fake_builder()
+            .status_code(status)
+            .build())
+    }
+
+    #[test]
+    fn classifies_connection_errors() {
+        assert_eq!(
+            ConnectionError::of(&connection_error(
+                "error trying to connect: tcp connect error: Connection refused (os error 111)"
+            )),
+            Some(ConnectionError::Connect)
+        );
+        assert_eq!(
+            ConnectionError::of(&connection_error(
+                "connection closed before message completed"
+            )),
+            Some(ConnectionError::Closed)
+        );
+        assert_eq!(
+            ConnectionError::of(&connection_error("invalid certificate")),
+            Some(ConnectionError::Other)
+        );
+        assert_eq!(
+            ConnectionError::of(&BoxError::from("the circuit breaker is open")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_errors_by_default() {
+        let (response, calls) = attempts(
+            config(),
+            vec![
+                Err(connection_error("connection reset")),
+                response(StatusCode::SERVICE_UNAVAILABLE),
+            ],
+        )
+        .await;
+        assert_eq!(
+            response.unwrap().response.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(calls, 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_rejections() {
+        for error in [
+            BoxError::from(CircuitOpen::new("test".to_string())),
+            BoxError::from(ConcurrencyLimited::new("test".to_string())),
+        ] {
+            let (response, calls) = attempts(config(), vec![Err(error)]).await;
+            assert!(response.is_err());
+            assert_eq!(calls, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_matching_conditions() {
+        let config = RetryConfig {
+            retry_on: Some(RetryConditions {
+                status_codes: vec![503],
+                connection_errors: vec![ConnectionError::Connect],
+                graphql_error_codes: vec!["UNAVAILABLE".to_string()],
+            }),
+            ..config()
+        };
+
+        let (response, calls) = attempts(
+            config.clone(),
+            vec![
+                response(StatusCode::SERVICE_UNAVAILABLE),
+                Err(connection_error("error trying to connect: dns error")),
+                Ok(subgraph::Response::fake_builder()
+                    .error(
+                        graphql::Error::builder()
+                            .message("unavailable")
+                            .extension_code("UNAVAILABLE")
+                            .build(),
+                    )
+                    .build()),
+                response(StatusCode::BAD_GATEWAY),
+            ],
+        )
+        .await;
+        assert_eq!(response.unwrap().response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(calls, 4);
+
+        let (response, calls) = attempts(
+            config,
+            vec![Err(connection_error(
+                "connection closed before message completed",
+            ))],
+        )
+        .await;
+        assert!(response.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn limits_attempts() {
+        let config = RetryConfig {
+            max_attempts: NonZeroU32::new(3),
+            ..config()
+        };
+        let (response, calls) = attempts(
+            config,
+            (0..5)
+                .map(|_| Err(connection_error("connection reset")))
+                .collect(),
+        )
+        .await;
+        assert!(response.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn backs_off() {
+        let mut policy = RetryPolicy::new(
+            &RetryConfig {
+                backoff: Some(BackoffConfig {
+                    initial_delay: Duration::from_millis(100),
+                    max_delay: Duration::from_millis(300),
+                    multiplier: 2.0,
+                    jitter: false,
+                }),
+                ..config()
+            },
+            "test".to_string(),
+        );
+        let mut delays = Vec::new();
+        for retries in 0..4 {
+            policy.retries = retries;
+            delays.push(policy.backoff_delay().as_millis());
+        }
+        assert_eq!(delays, vec![100, 200, 300, 300]);
+
+        policy.backoff.as_mut().unwrap().jitter = true;
+        policy.retries = 0;
+        let delay = policy.backoff_delay();
+        assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn honors_retry_after() {
+        let config = RetryConfig {
+            retry_on: Some(RetryConditions {
+                status_codes: vec![503],
+                ..Default::default()
+            }),
+            ..config()
+        };
+        let retry_after = |seconds: &str| {
+            let mut response = subgraph::Response::fake_builder()
+                .status_code(StatusCode::SERVICE_UNAVAILABLE)
+                .build();
+            response
+                .response
+                .headers_mut()
+                .insert(RETRY_AFTER, seconds.parse().unwrap());
+            Ok(response)
+        };
+
+        let started_at = Instant::now();
+        let (response, calls) = attempts(config.clone(), vec![retry_after("1")]).await;
+        assert_eq!(response.unwrap().response.status(), StatusCode::OK);
+        assert_eq!(calls, 2);
+        assert!(started_at.elapsed() >= Duration::from_secs(1));
+
+        // longer than the maximum delay
+        let (response, calls) = attempts(config, vec![retry_after("60")]).await;
+        assert_eq!(
+            response.unwrap().response.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(calls, 1);
+    }
+}
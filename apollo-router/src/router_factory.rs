@@ -697,6 +697,8 @@ pub(crate) async fn create_plugins(
     add_optional_apollo_plugin!("coprocessor");
     add_optional_apollo_plugin!("preview_demand_control");
     add_user_plugins!();
+    // Connectors replace the subgraph service, so they must be the innermost subgraph plugin
+    add_mandatory_apollo_plugin!("preview_connectors");
 
     // Macros above remove from `apollo_plugin_factories`, so anything left at the end
     // indicates a missing macro call.